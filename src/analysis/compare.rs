@@ -0,0 +1,243 @@
+//! A/B comparison of two circuits driven by the same signal.
+//!
+//! Both simulators render the test signal, and the outputs are compared
+//! sample by sample and spectrally. Spectral differences are reported as
+//! the level of `b` relative to `a`, so a positive value means `b` is louder
+//! at that frequency.
+
+use std::f64::consts::PI;
+
+use super::fft::{fft, Complex};
+use crate::error::{PedalerError, Result};
+use crate::Simulator;
+
+/// Magnitudes more than this far below the spectral peak are clamped to it,
+/// so that near-silent bins do not produce meaningless dB ratios.
+const SPECTRAL_FLOOR_DB: f64 = -80.0;
+
+/// Result of comparing two circuits' responses to the same input.
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    /// RMS of the per-sample difference `b - a`
+    pub rms_difference: f64,
+    /// Largest absolute per-sample difference
+    pub peak_divergence: f64,
+    /// Sample index of the largest difference
+    pub peak_index: usize,
+    /// Overall RMS level of `b` relative to `a`, in dB
+    pub gain_difference_db: f64,
+    /// Per-bin `(frequency, dB)` level of `b` relative to `a`, for bins
+    /// where either output is above the spectral floor
+    pub spectral_difference: Vec<(f64, f64)>,
+}
+
+impl Comparison {
+    /// The spectral bin with the largest absolute level difference.
+    pub fn max_spectral_difference(&self) -> Option<(f64, f64)> {
+        self.spectral_difference
+            .iter()
+            .copied()
+            .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+    }
+
+    /// Average spectral difference in octave bands.
+    ///
+    /// Returns `(center frequency, dB)` for each band from 31.25 Hz upwards
+    /// that contains at least one compared bin.
+    pub fn octave_bands(&self) -> Vec<(f64, f64)> {
+        let mut bands = Vec::new();
+        let mut center = 31.25;
+        let top = self
+            .spectral_difference
+            .last()
+            .map(|&(f, _)| f)
+            .unwrap_or(0.0);
+
+        while center / 2f64.sqrt() <= top {
+            let (lo, hi) = (center / 2f64.sqrt(), center * 2f64.sqrt());
+            let (sum, count) = self
+                .spectral_difference
+                .iter()
+                .filter(|(f, _)| *f >= lo && *f < hi)
+                .fold((0.0, 0usize), |(sum, count), (_, db)| (sum + db, count + 1));
+            if count > 0 {
+                bands.push((center, sum / count as f64));
+            }
+            center *= 2.0;
+        }
+        bands
+    }
+}
+
+/// Render `test_signal` through both simulators and compare their outputs.
+///
+/// The simulators must run at the same sample rate.
+pub fn compare_responses(
+    sim_a: &mut Simulator,
+    sim_b: &mut Simulator,
+    test_signal: &[f32],
+) -> Result<Comparison> {
+    if sim_a.sample_rate() != sim_b.sample_rate() {
+        return Err(PedalerError::InvalidSimulationParam {
+            message: format!(
+                "cannot compare circuits at different sample rates ({} Hz vs {} Hz)",
+                sim_a.sample_rate(),
+                sim_b.sample_rate()
+            ),
+        });
+    }
+
+    let mut out_a = vec![0.0f32; test_signal.len()];
+    let mut out_b = vec![0.0f32; test_signal.len()];
+    sim_a.process_block(test_signal, &mut out_a)?;
+    sim_b.process_block(test_signal, &mut out_b)?;
+
+    Ok(compare_signals(&out_a, &out_b, sim_a.sample_rate() as f64))
+}
+
+/// Compare two rendered signals of equal length.
+pub fn compare_signals(a: &[f32], b: &[f32], sample_rate: f64) -> Comparison {
+    let n = a.len().min(b.len());
+
+    let mut sum_sq = 0.0;
+    let mut peak_divergence = 0.0;
+    let mut peak_index = 0;
+    for (i, (&x, &y)) in a.iter().zip(b).enumerate() {
+        let diff = (y - x) as f64;
+        sum_sq += diff * diff;
+        if diff.abs() > peak_divergence {
+            peak_divergence = diff.abs();
+            peak_index = i;
+        }
+    }
+    let rms_difference = if n > 0 {
+        (sum_sq / n as f64).sqrt()
+    } else {
+        0.0
+    };
+
+    let gain_difference_db = level_ratio_db(rms(&b[..n]), rms(&a[..n]));
+
+    Comparison {
+        rms_difference,
+        peak_divergence,
+        peak_index,
+        gain_difference_db,
+        spectral_difference: spectral_difference(&a[..n], &b[..n], sample_rate),
+    }
+}
+
+/// Per-bin level difference between the Hann-windowed spectra of `a` and `b`.
+fn spectral_difference(a: &[f32], b: &[f32], sample_rate: f64) -> Vec<(f64, f64)> {
+    if a.is_empty() {
+        return Vec::new();
+    }
+    let spec_a = magnitude_spectrum(a);
+    let spec_b = magnitude_spectrum(b);
+
+    let peak = spec_a.iter().chain(&spec_b).fold(0.0f64, |m, &v| m.max(v));
+    if peak == 0.0 {
+        return Vec::new();
+    }
+    let floor = peak * 10f64.powf(SPECTRAL_FLOOR_DB / 20.0);
+    let bin_hz = sample_rate / (2 * (spec_a.len() - 1)) as f64;
+
+    spec_a
+        .iter()
+        .zip(&spec_b)
+        .enumerate()
+        .skip(1) // DC
+        .filter(|(_, (&ma, &mb))| ma > floor || mb > floor)
+        .map(|(k, (&ma, &mb))| {
+            (
+                k as f64 * bin_hz,
+                level_ratio_db(mb.max(floor), ma.max(floor)),
+            )
+        })
+        .collect()
+}
+
+/// Magnitudes of the non-negative frequency bins of a Hann-windowed signal.
+fn magnitude_spectrum(signal: &[f32]) -> Vec<f64> {
+    let size = signal.len().next_power_of_two().max(2);
+    let len = signal.len() as f64;
+    let mut buf = vec![Complex::default(); size];
+    for (i, (b, &s)) in buf.iter_mut().zip(signal).enumerate() {
+        let window = 0.5 - 0.5 * (2.0 * PI * i as f64 / len).cos();
+        b.re = s as f64 * window;
+    }
+    fft(&mut buf);
+    buf[..=size / 2].iter().map(|c| c.abs()).collect()
+}
+
+fn rms(signal: &[f32]) -> f64 {
+    if signal.is_empty() {
+        return 0.0;
+    }
+    (signal.iter().map(|&s| (s as f64) * (s as f64)).sum::<f64>() / signal.len() as f64).sqrt()
+}
+
+/// `20 log10(num / den)`, treating two silent signals as equal.
+fn level_ratio_db(num: f64, den: f64) -> f64 {
+    if num == den {
+        0.0
+    } else {
+        20.0 * (num / den).log10()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dsl, Circuit};
+
+    fn divider(r_bottom: &str) -> Simulator {
+        let src = format!(
+            ".input in\n.output out\nV_IN in 0 AC\nR1 in out 10k\nR2 out 0 {}\nC1 out 0 1n\n",
+            r_bottom
+        );
+        Simulator::new(
+            Circuit::from_ast(dsl::parse(&src).unwrap()).unwrap(),
+            48000.0,
+        )
+    }
+
+    fn test_signal() -> Vec<f32> {
+        // Two tones, so the spectrum has content in several bands
+        (0..8192)
+            .map(|i| {
+                let t = i as f32 / 48000.0;
+                0.5 * (2.0 * std::f32::consts::PI * 220.0 * t).sin()
+                    + 0.25 * (2.0 * std::f32::consts::PI * 3000.0 * t).sin()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_identical_circuits_have_no_difference() {
+        let cmp =
+            compare_responses(&mut divider("10k"), &mut divider("10k"), &test_signal()).unwrap();
+        assert_eq!(cmp.rms_difference, 0.0);
+        assert_eq!(cmp.peak_divergence, 0.0);
+        assert_eq!(cmp.gain_difference_db, 0.0);
+        assert!(!cmp.spectral_difference.is_empty());
+        assert!(cmp.spectral_difference.iter().all(|&(_, db)| db == 0.0));
+    }
+
+    #[test]
+    fn test_scaled_circuit_reports_gain_difference() {
+        // Divider ratio 0.5 versus 0.75 is +3.52 dB
+        let expected = 20.0 * 1.5f64.log10();
+        let cmp =
+            compare_responses(&mut divider("10k"), &mut divider("30k"), &test_signal()).unwrap();
+
+        assert!(
+            (cmp.gain_difference_db - expected).abs() < 0.1,
+            "gain {}",
+            cmp.gain_difference_db
+        );
+        assert!(cmp.rms_difference > 0.05);
+        let (_, db) = cmp.max_spectral_difference().unwrap();
+        assert!((db - expected).abs() < 0.2, "spectral {}", db);
+    }
+}
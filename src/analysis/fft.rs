@@ -0,0 +1,162 @@
+//! Complex numbers and an in-place radix-2 FFT.
+
+use std::f64::consts::PI;
+use std::ops::{Add, Div, Mul, Sub};
+
+/// A complex number in rectangular form.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Complex {
+    /// Real part
+    pub re: f64,
+    /// Imaginary part
+    pub im: f64,
+}
+
+impl Complex {
+    /// Create a complex number from real and imaginary parts.
+    pub const fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    /// Create `e^(jθ)`.
+    pub fn from_angle(theta: f64) -> Self {
+        Self::new(theta.cos(), theta.sin())
+    }
+
+    /// Complex conjugate.
+    pub fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    /// Squared magnitude.
+    pub fn norm_sqr(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    /// Magnitude.
+    pub fn abs(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    /// Phase angle in radians.
+    pub fn arg(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    /// Multiply by a real scalar.
+    pub fn scale(self, k: f64) -> Self {
+        Self::new(self.re * k, self.im * k)
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self {
+        let d = rhs.norm_sqr();
+        let n = self * rhs.conj();
+        Self::new(n.re / d, n.im / d)
+    }
+}
+
+/// Forward FFT in place. The length must be a power of two.
+pub fn fft(data: &mut [Complex]) {
+    transform(data, false);
+}
+
+/// Inverse FFT in place, including the 1/N normalization.
+/// The length must be a power of two.
+pub fn ifft(data: &mut [Complex]) {
+    transform(data, true);
+    let scale = 1.0 / data.len() as f64;
+    for x in data.iter_mut() {
+        *x = x.scale(scale);
+    }
+}
+
+/// Iterative Cooley-Tukey radix-2 transform.
+fn transform(data: &mut [Complex], inverse: bool) {
+    let n = data.len();
+    assert!(
+        n.is_power_of_two(),
+        "FFT length must be a power of two, got {}",
+        n
+    );
+    if n <= 1 {
+        return;
+    }
+
+    // Bit-reversal permutation
+    let bits = n.trailing_zeros();
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if j > i {
+            data.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let w_len = Complex::from_angle(sign * 2.0 * PI / len as f64);
+        for chunk in data.chunks_mut(len) {
+            let mut w = Complex::new(1.0, 0.0);
+            let (lo, hi) = chunk.split_at_mut(len / 2);
+            for (a, b) in lo.iter_mut().zip(hi.iter_mut()) {
+                let t = *b * w;
+                *b = *a - t;
+                *a = *a + t;
+                w = w * w_len;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fft_roundtrip_and_tone() {
+        let n = 64;
+        let signal: Vec<Complex> = (0..n)
+            .map(|i| Complex::new((2.0 * PI * 5.0 * i as f64 / n as f64).cos(), 0.0))
+            .collect();
+
+        let mut spectrum = signal.clone();
+        fft(&mut spectrum);
+        // A cosine in bin 5 splits evenly between bins 5 and n-5
+        assert!((spectrum[5].abs() - n as f64 / 2.0).abs() < 1e-9);
+        assert!((spectrum[n - 5].abs() - n as f64 / 2.0).abs() < 1e-9);
+        assert!(spectrum[6].abs() < 1e-9);
+
+        ifft(&mut spectrum);
+        for (a, b) in spectrum.iter().zip(&signal) {
+            assert!((*a - *b).abs() < 1e-12);
+        }
+    }
+}
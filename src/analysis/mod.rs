@@ -0,0 +1,16 @@
+//! Signal analysis utilities.
+//!
+//! Offline helpers for measuring circuits rather than running them in real
+//! time:
+//! - [`Complex`] numbers and a radix-2 [`fft`] / [`ifft`]
+//! - Exponential sine sweep generation and deconvolution for large-signal
+//!   frequency-response measurement
+//! - A/B comparison of two circuits' responses to the same signal
+
+mod compare;
+mod fft;
+mod sweep;
+
+pub use compare::{compare_responses, compare_signals, Comparison};
+pub use fft::{fft, ifft, Complex};
+pub use sweep::{deconvolve_sweep, log_sweep};
@@ -0,0 +1,147 @@
+//! Exponential sine sweep (ESS) measurement.
+//!
+//! A logarithmic chirp is played through the system under test and the
+//! recorded response is deconvolved against it. Because the sweep's
+//! instantaneous frequency rises exponentially, the impulse responses of the
+//! harmonic distortion products land at negative times. Keeping only the
+//! causal part of the deconvolved response isolates the linear (fundamental)
+//! response at the level the sweep was played at.
+
+use std::f64::consts::PI;
+
+use super::fft::{fft, ifft, Complex};
+
+/// Fraction of the sweep faded in and out to limit band-edge ripple.
+const FADE_FRACTION: f64 = 0.01;
+
+/// Regularization of the spectral division outside the swept band, relative
+/// to the peak sweep power. Inside the band the division is exact.
+const REGULARIZATION: f64 = 1e-6;
+
+/// Generate an exponential sine sweep.
+///
+/// `x(t) = A sin(2π f₁ L (e^(t/L) − 1))` with `L = T / ln(f₂/f₁)`, with a
+/// short raised-cosine fade at either end.
+pub fn log_sweep(
+    f_start: f64,
+    f_end: f64,
+    amplitude: f64,
+    duration: f64,
+    sample_rate: f64,
+) -> Vec<f64> {
+    let n = (duration * sample_rate).round().max(1.0) as usize;
+    let l = duration / (f_end / f_start).ln();
+    let fade = ((n as f64 * FADE_FRACTION) as usize).max(1);
+
+    (0..n)
+        .map(|i| {
+            let t = i as f64 / sample_rate;
+            let phase = 2.0 * PI * f_start * l * ((t / l).exp() - 1.0);
+            let edge = i.min(n - 1 - i);
+            let gain = if edge < fade {
+                0.5 - 0.5 * (PI * edge as f64 / fade as f64).cos()
+            } else {
+                1.0
+            };
+            amplitude * gain * phase.sin()
+        })
+        .collect()
+}
+
+/// Deconvolve a recorded response against the sweep that produced it.
+///
+/// Returns `(frequency, magnitude)` pairs for every FFT bin between
+/// `f_start` and `f_end`, where magnitude is the linear gain of the
+/// fundamental response.
+pub fn deconvolve_sweep(
+    sweep: &[f64],
+    response: &[f64],
+    f_start: f64,
+    f_end: f64,
+    sample_rate: f64,
+) -> Vec<(f64, f64)> {
+    let size = (sweep.len() + response.len()).next_power_of_two();
+
+    let mut x = padded(sweep, size);
+    let mut y = padded(response, size);
+    fft(&mut x);
+    fft(&mut y);
+
+    // Spectral division H = Y X* / (|X|² + ε), regularized out of band
+    let peak = x.iter().map(|c| c.norm_sqr()).fold(0.0, f64::max);
+    let bin_hz = sample_rate / size as f64;
+    let mut h: Vec<Complex> = x
+        .iter()
+        .zip(&y)
+        .enumerate()
+        .map(|(k, (&xk, &yk))| {
+            let f = k.min(size - k) as f64 * bin_hz;
+            let eps = if f >= f_start && f <= f_end {
+                0.0
+            } else {
+                REGULARIZATION * peak
+            };
+            (yk * xk.conj()).scale(1.0 / (xk.norm_sqr() + eps))
+        })
+        .collect();
+    ifft(&mut h);
+
+    // Keep the causal half plus a short stretch before t = 0 for the
+    // pre-ringing of the band-limited response. The 2nd harmonic response
+    // sits L ln 2 before t = 0, so stop halfway there.
+    let l = sweep.len() as f64 / sample_rate / (f_end / f_start).ln();
+    let pre = ((0.5 * l * 2f64.ln() * sample_rate) as usize).clamp(1, size / 4);
+    let keep = size / 2;
+    let taper = keep / 10;
+    for (i, v) in h.iter_mut().enumerate() {
+        let gain = if i < keep - taper {
+            1.0
+        } else if i < keep {
+            let pos = (i - (keep - taper)) as f64 / taper as f64;
+            0.5 + 0.5 * (PI * pos).cos()
+        } else if i >= size - pre {
+            let pos = (size - i) as f64 / pre as f64;
+            0.5 + 0.5 * (PI * pos).cos()
+        } else {
+            0.0
+        };
+        *v = v.scale(gain);
+    }
+    fft(&mut h);
+
+    let first = (f_start / bin_hz).ceil() as usize;
+    let last = ((f_end / bin_hz).floor() as usize).min(size / 2);
+    (first..=last)
+        .map(|k| (k as f64 * bin_hz, h[k].abs()))
+        .collect()
+}
+
+/// Copy a real signal into a zero-padded complex buffer.
+fn padded(signal: &[f64], size: usize) -> Vec<Complex> {
+    let mut buf = vec![Complex::default(); size];
+    for (b, &s) in buf.iter_mut().zip(signal) {
+        b.re = s;
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deconvolve_pure_gain_and_delay() {
+        let fs = 48000.0;
+        let sweep = log_sweep(50.0, 10000.0, 1.0, 0.5, fs);
+
+        // A delayed, attenuated copy is a flat 0.5 response
+        let mut response = vec![0.0; 10];
+        response.extend(sweep.iter().map(|s| 0.5 * s));
+
+        let bins = deconvolve_sweep(&sweep, &response, 100.0, 8000.0, fs);
+        assert!(!bins.is_empty());
+        for (f, mag) in bins {
+            assert!((mag - 0.5).abs() < 0.01, "{} Hz: {}", f, mag);
+        }
+    }
+}
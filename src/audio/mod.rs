@@ -1,8 +1,24 @@
 //! Audio I/O for the CLI frontend.
 //!
-//! Handles reading raw PCM audio from stdin and writing to stdout.
+//! Handles reading raw PCM audio from stdin, or a decoded WAV file, and
+//! writing to stdout or a file.
+//!
+//! Output can optionally be quantized to a fixed number of decimal places, or
+//! written as one decimal sample per line, so golden-file comparisons are not
+//! sensitive to last-bit differences between platforms. WAV output is
+//! streamed through [`WavWriter`], and WAV files are decoded by [`read_wav`].
+//!
+//! A circuit with several `.input` or `.output` channels reads and writes
+//! interleaved frames, one sample per channel in declaration order.
+
+mod wav;
 
-use std::io::{self, Read, Write};
+pub use wav::{read_wav, WavAudio, WavEncoding, WavWriter};
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
 
 use crate::error::{PedalerError, Result};
 
@@ -23,16 +39,30 @@ impl AudioInput {
     }
 
     /// Read a block of samples from stdin.
-    /// Returns the number of samples read, or 0 on EOF.
+    ///
+    /// Reads until `samples` is full or the input ends, so that interleaved
+    /// frames stay aligned from one block to the next. Returns the number of
+    /// samples read, or 0 on EOF.
     pub fn read_block(&mut self, samples: &mut [f32]) -> Result<usize> {
         let bytes_to_read = samples.len() * 4;
+        if self.buffer.len() < bytes_to_read {
+            self.buffer.resize(bytes_to_read, 0);
+        }
         let buffer = &mut self.buffer[..bytes_to_read];
 
-        let bytes_read = io::stdin()
-            .read(buffer)
-            .map_err(|e| PedalerError::AudioInputError {
-                message: e.to_string(),
+        let mut stdin = io::stdin();
+        let mut bytes_read = 0;
+        while bytes_read < bytes_to_read {
+            let n = stdin.read(&mut buffer[bytes_read..]).map_err(|e| {
+                PedalerError::AudioInputError {
+                    message: e.to_string(),
+                }
             })?;
+            if n == 0 {
+                break;
+            }
+            bytes_read += n;
+        }
 
         if bytes_read == 0 {
             return Ok(0);
@@ -60,50 +90,258 @@ impl Default for AudioInput {
     }
 }
 
-/// Audio output writer to stdout.
+/// Output sample encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Raw 32-bit little-endian IEEE-754 floats
+    #[default]
+    F32le,
+    /// One decimal frame per line, channels separated by spaces
+    Text,
+    /// 32-bit float WAV, streamed with sizes backfilled on seekable outputs
+    Wav,
+    /// 16-bit integer PCM WAV, clipped to full scale
+    Wav16,
+    /// 24-bit integer PCM WAV, clipped to full scale
+    Wav24,
+}
+
+impl OutputFormat {
+    /// Sample encoding of the WAV formats, or `None` for headerless output.
+    pub fn wav_encoding(self) -> Option<WavEncoding> {
+        match self {
+            OutputFormat::F32le | OutputFormat::Text => None,
+            OutputFormat::Wav => Some(WavEncoding::Float32),
+            OutputFormat::Wav16 => Some(WavEncoding::Pcm16),
+            OutputFormat::Wav24 => Some(WavEncoding::Pcm24),
+        }
+    }
+}
+
+/// Destination for encoded output.
+enum Sink {
+    Stdout(io::Stdout),
+    File(BufWriter<File>),
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::Stdout(s) => s.write(buf),
+            Sink::File(f) => f.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Stdout(s) => s.flush(),
+            Sink::File(f) => f.flush(),
+        }
+    }
+}
+
+impl Seek for Sink {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Sink::Stdout(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "stdout is not seekable",
+            )),
+            Sink::File(f) => f.seek(pos),
+        }
+    }
+}
+
+/// Audio output writer to stdout or a file.
 pub struct AudioOutput {
     buffer: Vec<u8>,
+    format: OutputFormat,
+    /// Number of decimal places to round samples to, if any
+    round: Option<usize>,
+    /// Sample rate recorded in WAV headers
+    sample_rate: u32,
+    /// Number of interleaved channels per frame
+    channels: usize,
+    /// Output destination (moved into `wav` once the WAV header is written)
+    sink: Option<Sink>,
+    /// Active WAV stream
+    wav: Option<WavWriter<Sink>>,
 }
 
 impl AudioOutput {
-    /// Create a new audio output writer.
+    /// Create a new audio output writer producing raw f32le samples.
     pub fn new() -> Self {
+        Self::with_format(OutputFormat::F32le, None)
+    }
+
+    /// Create an output writer with the given encoding and optional rounding.
+    ///
+    /// # Arguments
+    /// * `format` - Sample encoding
+    /// * `round` - Round each sample to this many decimal places
+    pub fn with_format(format: OutputFormat, round: Option<usize>) -> Self {
         Self {
             buffer: vec![0u8; BUFFER_SIZE * 4],
+            format,
+            round,
+            sample_rate: crate::DEFAULT_SAMPLE_RATE as u32,
+            channels: 1,
+            sink: Some(Sink::Stdout(io::stdout())),
+            wav: None,
         }
     }
 
-    /// Write a block of samples to stdout.
-    pub fn write_block(&mut self, samples: &[f32]) -> Result<()> {
-        let bytes_needed = samples.len() * 4;
-        if self.buffer.len() < bytes_needed {
-            self.buffer.resize(bytes_needed, 0);
+    /// Set the sample rate recorded in WAV headers.
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    /// Set the number of interleaved channels in each frame.
+    ///
+    /// This sets the WAV header's channel count and how many samples share
+    /// a line of text output. The processing functions in this module set it
+    /// to the simulator's output channel count.
+    pub fn with_channels(mut self, channels: usize) -> Self {
+        self.channels = channels.max(1);
+        self
+    }
+
+    /// Write to a file instead of stdout.
+    ///
+    /// WAV files written this way get their chunk sizes backfilled by
+    /// [`AudioOutput::finish`].
+    pub fn with_output_file(mut self, path: &Path) -> Result<Self> {
+        let file = File::create(path).map_err(|e| PedalerError::FileWriteError {
+            path: path.display().to_string(),
+            source: e,
+        })?;
+        self.sink = Some(Sink::File(BufWriter::new(file)));
+        Ok(self)
+    }
+
+    /// Apply the configured rounding to a sample.
+    ///
+    /// Negative zero is folded into positive zero so that the encoded bytes
+    /// do not depend on the sign of a vanishing result.
+    fn quantize(&self, sample: f32) -> f32 {
+        let value = match self.round {
+            Some(places) => {
+                let scale = 10f64.powi(places as i32);
+                ((sample as f64 * scale).round() / scale) as f32
+            }
+            None => sample,
+        };
+        if value == 0.0 {
+            0.0
+        } else {
+            value
         }
+    }
+
+    /// Encode a block of samples into the internal buffer and return the bytes.
+    pub fn encode_block(&mut self, samples: &[f32]) -> &[u8] {
+        match self.format {
+            OutputFormat::F32le | OutputFormat::Wav | OutputFormat::Wav16 | OutputFormat::Wav24 => {
+                // Raw f32le is the payload of a float WAV without the header
+                let encoding = self.format.wav_encoding().unwrap_or_default();
+                let width = encoding.bytes_per_sample();
+                let bytes_needed = samples.len() * width;
+                if self.buffer.len() < bytes_needed {
+                    self.buffer.resize(bytes_needed, 0);
+                }
 
-        for (i, &sample) in samples.iter().enumerate() {
-            let bytes = sample.to_le_bytes();
-            self.buffer[i * 4] = bytes[0];
-            self.buffer[i * 4 + 1] = bytes[1];
-            self.buffer[i * 4 + 2] = bytes[2];
-            self.buffer[i * 4 + 3] = bytes[3];
+                for (i, &sample) in samples.iter().enumerate() {
+                    let sample = self.quantize(sample);
+                    encoding.encode(sample, &mut self.buffer[i * width..(i + 1) * width]);
+                }
+
+                &self.buffer[..bytes_needed]
+            }
+            OutputFormat::Text => {
+                let mut text = String::with_capacity(samples.len() * 12);
+                for frame in samples.chunks(self.channels) {
+                    for (channel, &sample) in frame.iter().enumerate() {
+                        if channel > 0 {
+                            text.push(' ');
+                        }
+                        let value = self.quantize(sample);
+                        match self.round {
+                            Some(places) => {
+                                let _ = write!(text, "{:.*}", places, value);
+                            }
+                            None => {
+                                let _ = write!(text, "{}", value);
+                            }
+                        }
+                    }
+                    text.push('\n');
+                }
+                self.buffer.clear();
+                self.buffer.extend_from_slice(text.as_bytes());
+                &self.buffer
+            }
         }
+    }
 
-        io::stdout()
-            .write_all(&self.buffer[..bytes_needed])
-            .map_err(|e| PedalerError::AudioOutputError {
-                message: e.to_string(),
-            })?;
+    /// Write a block of samples to the output.
+    pub fn write_block(&mut self, samples: &[f32]) -> Result<()> {
+        self.start_wav()?;
+        let len = self.encode_block(samples).len();
+        let bytes = &self.buffer[..len];
 
-        Ok(())
+        let result = match (&mut self.wav, &mut self.sink) {
+            (Some(wav), _) => wav.write_encoded(bytes),
+            (None, Some(sink)) => sink.write_all(bytes),
+            (None, None) => Ok(()),
+        };
+        result.map_err(output_error)
     }
 
     /// Flush the output stream.
     pub fn flush(&mut self) -> Result<()> {
-        io::stdout()
-            .flush()
-            .map_err(|e| PedalerError::AudioOutputError {
-                message: e.to_string(),
-            })
+        let result = match (&mut self.wav, &mut self.sink) {
+            (Some(wav), _) => wav.flush(),
+            (None, Some(sink)) => sink.flush(),
+            (None, None) => Ok(()),
+        };
+        result.map_err(output_error)
+    }
+
+    /// Complete the output: flush, and for WAV files backfill the header sizes.
+    pub fn finish(&mut self) -> Result<()> {
+        self.start_wav()?;
+        if let Some(wav) = self.wav.take() {
+            let sink = match wav.get_ref() {
+                Sink::File(_) => wav.finalize(),
+                Sink::Stdout(_) => wav.into_inner(),
+            }
+            .map_err(output_error)?;
+            self.sink = Some(sink);
+        }
+        self.flush()
+    }
+
+    /// Write the WAV header the first time output is produced.
+    fn start_wav(&mut self) -> Result<()> {
+        let Some(encoding) = self.format.wav_encoding() else {
+            return Ok(());
+        };
+        if self.wav.is_none() {
+            if let Some(sink) = self.sink.take() {
+                let channels = self.channels as u16;
+                let wav = WavWriter::new(sink, self.sample_rate, channels, encoding);
+                self.wav = Some(wav.map_err(output_error)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Map an I/O error to an audio output error.
+fn output_error(e: io::Error) -> PedalerError {
+    PedalerError::AudioOutputError {
+        message: e.to_string(),
     }
 }
 
@@ -115,23 +353,181 @@ impl Default for AudioOutput {
 
 /// Process audio from stdin to stdout using the given simulator.
 pub fn process_audio(simulator: &mut crate::Simulator) -> Result<()> {
+    process_audio_with_output(simulator, AudioOutput::new())
+}
+
+/// Process audio from stdin to stdout, encoding with the given output writer.
+///
+/// Input and output are interleaved frames of the simulator's input and
+/// output channels; a trailing partial input frame is dropped.
+pub fn process_audio_with_output(
+    simulator: &mut crate::Simulator,
+    output: AudioOutput,
+) -> Result<()> {
     let mut input = AudioInput::new();
-    let mut output = AudioOutput::new();
+    let (inputs, outputs) = (simulator.input_channels(), simulator.output_channels());
+    let mut output = output.with_channels(outputs);
 
-    let mut in_samples = vec![0.0f32; BUFFER_SIZE];
-    let mut out_samples = vec![0.0f32; BUFFER_SIZE];
+    let mut in_samples = vec![0.0f32; BUFFER_SIZE * inputs];
+    let mut out_samples = vec![0.0f32; BUFFER_SIZE * outputs];
 
     loop {
-        let samples_read = input.read_block(&mut in_samples)?;
+        let frames = input.read_block(&mut in_samples)? / inputs;
 
-        if samples_read == 0 {
+        if frames == 0 {
             break;
         }
 
-        simulator.process_block(&in_samples[..samples_read], &mut out_samples[..samples_read])?;
-        output.write_block(&out_samples[..samples_read])?;
+        let out = &mut out_samples[..frames * outputs];
+        simulator.process_interleaved(&in_samples[..frames * inputs], out)?;
+        output.write_block(out)?;
+    }
+
+    output.finish()?;
+    Ok(())
+}
+
+/// Process an in-memory buffer, such as a decoded WAV file, encoding with
+/// the given output writer.
+///
+/// `samples` holds interleaved frames of the simulator's input channels,
+/// as from [`WavAudio::interleaved_for`].
+pub fn process_samples_with_output(
+    simulator: &mut crate::Simulator,
+    samples: &[f32],
+    output: AudioOutput,
+) -> Result<()> {
+    let (inputs, outputs) = (simulator.input_channels(), simulator.output_channels());
+    let mut output = output.with_channels(outputs);
+    let mut out_samples = vec![0.0f32; BUFFER_SIZE * outputs];
+
+    for block in samples.chunks(BUFFER_SIZE * inputs) {
+        let out = &mut out_samples[..block.len() / inputs * outputs];
+        simulator.process_interleaved(block, out)?;
+        output.write_block(out)?;
     }
 
-    output.flush()?;
+    output.finish()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Circuit, Simulator};
+
+    fn render(format: OutputFormat, round: Option<usize>) -> Vec<u8> {
+        let src =
+            ".input in\n.output out\nVIN in 0 AC 1.0\nR1 in out 10k\nC1 out 0 10n\nD1 out 0\n";
+        let circuit = Circuit::from_ast(crate::dsl::parse(src).unwrap()).unwrap();
+        let mut sim = Simulator::new(circuit, 48000.0);
+        let mut output = AudioOutput::with_format(format, round);
+
+        let input: Vec<f32> = (0..BUFFER_SIZE)
+            .map(|i| (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 48000.0).sin())
+            .collect();
+        let mut out = vec![0.0f32; BUFFER_SIZE];
+        sim.process_block(&input, &mut out).unwrap();
+        output.encode_block(&out).to_vec()
+    }
+
+    #[test]
+    fn test_rounded_output_is_reproducible() {
+        for format in [OutputFormat::F32le, OutputFormat::Text] {
+            let first = render(format, Some(6));
+            let second = render(format, Some(6));
+            assert!(!first.is_empty());
+            assert_eq!(first, second);
+        }
+    }
+
+    #[test]
+    fn test_wav_file_output_is_finalized() {
+        let path =
+            std::env::temp_dir().join(format!("pedaler_wav_test_{}.wav", std::process::id()));
+        for (format, bits) in [
+            (OutputFormat::Wav, 32),
+            (OutputFormat::Wav16, 16),
+            (OutputFormat::Wav24, 24),
+        ] {
+            let mut output = AudioOutput::with_format(format, None)
+                .with_sample_rate(44100)
+                .with_output_file(&path)
+                .unwrap();
+            let block = [0.25f32; BUFFER_SIZE];
+            for _ in 0..3 {
+                output.write_block(&block).unwrap();
+            }
+            output.finish().unwrap();
+
+            let reader = hound::WavReader::open(&path).unwrap();
+            assert_eq!(reader.spec().sample_rate, 44100);
+            assert_eq!(reader.spec().bits_per_sample, bits);
+            assert_eq!(reader.len() as usize, 3 * BUFFER_SIZE);
+            let audio = read_wav(File::open(&path).unwrap()).unwrap();
+            assert!(audio.samples.iter().all(|&x| x == 0.25));
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_wav_round_trip_through_pass_through_circuit() {
+        let dir = std::env::temp_dir();
+        let input_path = dir.join(format!("pedaler_wav_in_{}.wav", std::process::id()));
+        let output_path = dir.join(format!("pedaler_wav_out_{}.wav", std::process::id()));
+
+        // A 24-bit stereo sine, as a user would have it on disk
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: 24,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&input_path, spec).unwrap();
+        let sine: Vec<f32> = (0..3000)
+            .map(|i| 0.5 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 44100.0).sin())
+            .collect();
+        for &x in &sine {
+            let code = (x * 8_388_608.0).round() as i32;
+            writer.write_sample(code).unwrap();
+            writer.write_sample(code).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let file = File::open(&input_path).unwrap();
+        let input = read_wav(io::BufReader::new(file)).unwrap();
+        assert_eq!(input.sample_rate, 44100);
+        assert_eq!(input.samples.len(), sine.len());
+
+        let src = ".input in\n.output out\nV_IN in 0 AC\nR1 in out 1\nR2 out 0 1G\n";
+        let circuit = Circuit::from_ast(crate::dsl::parse(src).unwrap()).unwrap();
+        let mut sim = Simulator::new(circuit, input.sample_rate as f32);
+        let output = AudioOutput::with_format(OutputFormat::Wav, None)
+            .with_sample_rate(input.sample_rate)
+            .with_output_file(&output_path)
+            .unwrap();
+        process_samples_with_output(&mut sim, &input.samples, output).unwrap();
+
+        let rendered = read_wav(io::BufReader::new(File::open(&output_path).unwrap())).unwrap();
+        assert_eq!(rendered.sample_rate, 44100);
+        assert_eq!(rendered.samples.len(), sine.len());
+        for (y, x) in rendered.samples.iter().zip(&sine) {
+            assert!((y - x).abs() < 1e-6, "{} vs {}", y, x);
+        }
+        std::fs::remove_file(&input_path).unwrap();
+        std::fs::remove_file(&output_path).unwrap();
+    }
+
+    #[test]
+    fn test_text_output_rounding() {
+        let mut output = AudioOutput::with_format(OutputFormat::Text, Some(3));
+        let text =
+            String::from_utf8(output.encode_block(&[0.12345, -0.0, -1.0 / 3.0]).to_vec()).unwrap();
+        assert_eq!(text, "0.123\n0.000\n-0.333\n");
+
+        let mut output = AudioOutput::with_format(OutputFormat::Text, Some(1)).with_channels(2);
+        let text =
+            String::from_utf8(output.encode_block(&[0.125, -0.5, 1.0, 0.0]).to_vec()).unwrap();
+        assert_eq!(text, "0.1 -0.5\n1.0 0.0\n");
+    }
+}
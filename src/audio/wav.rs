@@ -0,0 +1,434 @@
+//! WAV encoding and decoding.
+//!
+//! [`read_wav`] decodes files with `hound`. [`WavWriter`] is our own
+//! encoder, because `hound::WavWriter` needs a seekable output and cannot
+//! stream to a pipe such as stdout.
+//!
+//! [`WavWriter`] is a streaming encoder. Samples are written as interleaved
+//! 16-bit or 24-bit integer PCM, or 32-bit IEEE float, as they arrive, so
+//! memory use stays flat however long the render is. The RIFF and `data`
+//! chunk sizes are unknown until the end. The header is first written with
+//! the conventional "unknown length" value `0xFFFFFFFF`, which most readers
+//! (ffmpeg, sox) treat as "read to end of file". Seekable outputs are
+//! patched with the real sizes by [`WavWriter::finalize`].
+//!
+//! Files larger than 4 GiB cannot be described by a plain RIFF header. In
+//! that case the sizes stay saturated at `0xFFFFFFFF`.
+
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+
+/// Size of the canonical header written by [`WavWriter`].
+pub const WAV_HEADER_LEN: u64 = 44;
+
+/// Placeholder chunk size meaning "unknown / until end of file".
+const STREAMING_SIZE: u32 = u32::MAX;
+
+/// Byte offset of the RIFF chunk size field.
+const RIFF_SIZE_OFFSET: u64 = 4;
+
+/// Byte offset of the `data` chunk size field.
+const DATA_SIZE_OFFSET: u64 = 40;
+
+/// WAVE_FORMAT_PCM
+const FORMAT_PCM: u16 = 1;
+
+/// WAVE_FORMAT_IEEE_FLOAT
+const FORMAT_IEEE_FLOAT: u16 = 3;
+
+/// Sample encoding of a WAV stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WavEncoding {
+    /// 16-bit signed integer PCM
+    Pcm16,
+    /// 24-bit signed integer PCM
+    Pcm24,
+    /// 32-bit IEEE float
+    #[default]
+    Float32,
+}
+
+impl WavEncoding {
+    /// Bytes used by one encoded sample.
+    pub fn bytes_per_sample(self) -> usize {
+        match self {
+            WavEncoding::Pcm16 => 2,
+            WavEncoding::Pcm24 => 3,
+            WavEncoding::Float32 => 4,
+        }
+    }
+
+    /// Encode one sample into `out`, which must be
+    /// [`bytes_per_sample`](Self::bytes_per_sample) long.
+    ///
+    /// Integer encodings clip samples to the full-scale range -1.0..1.0.
+    pub fn encode(self, sample: f32, out: &mut [u8]) {
+        match self {
+            WavEncoding::Pcm16 => {
+                let x = (sample as f64 * 32768.0).round().clamp(-32768.0, 32767.0) as i16;
+                out.copy_from_slice(&x.to_le_bytes());
+            }
+            WavEncoding::Pcm24 => {
+                let x = (sample as f64 * 8_388_608.0)
+                    .round()
+                    .clamp(-8_388_608.0, 8_388_607.0) as i32;
+                out.copy_from_slice(&x.to_le_bytes()[..3]);
+            }
+            WavEncoding::Float32 => out.copy_from_slice(&sample.to_le_bytes()),
+        }
+    }
+
+    fn format_tag(self) -> u16 {
+        match self {
+            WavEncoding::Pcm16 | WavEncoding::Pcm24 => FORMAT_PCM,
+            WavEncoding::Float32 => FORMAT_IEEE_FLOAT,
+        }
+    }
+}
+
+/// Streaming WAV writer.
+pub struct WavWriter<W: Write> {
+    inner: W,
+    encoding: WavEncoding,
+    /// Bytes of sample data written so far
+    data_bytes: u64,
+}
+
+impl<W: Write> WavWriter<W> {
+    /// Create a writer for `channels` interleaved channels and emit a
+    /// header with streaming (unknown) sizes.
+    pub fn new(
+        mut inner: W,
+        sample_rate: u32,
+        channels: u16,
+        encoding: WavEncoding,
+    ) -> io::Result<Self> {
+        write_header(
+            &mut inner,
+            sample_rate,
+            channels,
+            encoding,
+            STREAMING_SIZE,
+            STREAMING_SIZE,
+        )?;
+        Ok(Self {
+            inner,
+            encoding,
+            data_bytes: 0,
+        })
+    }
+
+    /// Append interleaved samples to the `data` chunk.
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        let mut bytes = [0u8; 4];
+        let bytes = &mut bytes[..self.encoding.bytes_per_sample()];
+        for &sample in samples {
+            self.encoding.encode(sample, bytes);
+            self.inner.write_all(bytes)?;
+        }
+        self.data_bytes += (samples.len() * bytes.len()) as u64;
+        Ok(())
+    }
+
+    /// Append sample bytes already encoded with this writer's
+    /// [`WavEncoding`].
+    pub fn write_encoded(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.inner.write_all(bytes)?;
+        self.data_bytes += bytes.len() as u64;
+        Ok(())
+    }
+    /// Number of sample bytes written so far.
+    pub fn data_bytes(&self) -> u64 {
+        self.data_bytes
+    }
+
+    /// Flush the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    /// Get a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Flush and return the underlying writer, leaving the streaming sizes
+    /// in the header. Use this for non-seekable outputs such as pipes.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Flush, backfill the RIFF and `data` chunk sizes, and return the
+    /// underlying writer positioned at the end of the file.
+    pub fn finalize(mut self) -> io::Result<W> {
+        let data_size = u32::try_from(self.data_bytes).unwrap_or(STREAMING_SIZE);
+        let riff_size =
+            u32::try_from(self.data_bytes + WAV_HEADER_LEN - 8).unwrap_or(STREAMING_SIZE);
+
+        self.inner.seek(SeekFrom::Start(RIFF_SIZE_OFFSET))?;
+        self.inner.write_all(&riff_size.to_le_bytes())?;
+        self.inner.seek(SeekFrom::Start(DATA_SIZE_OFFSET))?;
+        self.inner.write_all(&data_size.to_le_bytes())?;
+        self.inner.seek(SeekFrom::End(0))?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Write a 44-byte WAV header.
+fn write_header<W: Write>(
+    w: &mut W,
+    sample_rate: u32,
+    channels: u16,
+    encoding: WavEncoding,
+    riff_size: u32,
+    data_size: u32,
+) -> io::Result<()> {
+    let bits = encoding.bytes_per_sample() as u16 * 8;
+    let block_align = channels * bits / 8;
+    let byte_rate = sample_rate * block_align as u32;
+
+    w.write_all(b"RIFF")?;
+    w.write_all(&riff_size.to_le_bytes())?;
+    w.write_all(b"WAVE")?;
+
+    w.write_all(b"fmt ")?;
+    w.write_all(&16u32.to_le_bytes())?;
+    w.write_all(&encoding.format_tag().to_le_bytes())?;
+    w.write_all(&channels.to_le_bytes())?;
+    w.write_all(&sample_rate.to_le_bytes())?;
+    w.write_all(&byte_rate.to_le_bytes())?;
+    w.write_all(&block_align.to_le_bytes())?;
+    w.write_all(&bits.to_le_bytes())?;
+
+    w.write_all(b"data")?;
+    w.write_all(&data_size.to_le_bytes())?;
+    Ok(())
+}
+
+/// Decoded contents of a WAV file.
+#[derive(Debug, Clone)]
+pub struct WavAudio {
+    /// Mono samples (channels averaged)
+    pub samples: Vec<f32>,
+    /// Samples of every channel, interleaved
+    pub interleaved: Vec<f32>,
+    /// Number of channels in the file
+    pub channels: usize,
+    /// Sample rate in Hz
+    pub sample_rate: u32,
+}
+
+impl WavAudio {
+    /// Interleaved input for a circuit with `inputs` input channels.
+    ///
+    /// One input gets the mono mixdown. Several inputs get the file's own
+    /// channels when the counts match, and a mono file is copied to every
+    /// input. Any other combination returns `None`.
+    pub fn interleaved_for(&self, inputs: usize) -> Option<Vec<f32>> {
+        if inputs == 1 {
+            Some(self.samples.clone())
+        } else if self.channels == inputs {
+            Some(self.interleaved.clone())
+        } else if self.channels == 1 {
+            Some(
+                self.samples
+                    .iter()
+                    .flat_map(|&x| std::iter::repeat_n(x, inputs))
+                    .collect(),
+            )
+        } else {
+            None
+        }
+    }
+}
+
+/// Read a WAV stream, keeping its channels and mixing them down to mono.
+///
+/// Supports everything `hound` decodes: 8/16/24/32-bit integer PCM and
+/// 32-bit float. A `data` chunk with the streaming size `0xFFFFFFFF`, as
+/// [`WavWriter`] leaves on a pipe, is read to the end of input.
+pub fn read_wav<R: Read>(mut reader: R) -> io::Result<WavAudio> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    fill_streaming_size(&mut bytes);
+
+    let reader = hound::WavReader::new(Cursor::new(bytes)).map_err(hound_error)?;
+    let spec = reader.spec();
+    let interleaved = match spec.sample_format {
+        hound::SampleFormat::Float => reader
+            .into_samples::<f32>()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(hound_error)?,
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f64;
+            reader
+                .into_samples::<i32>()
+                .map(|s| s.map(|x| (x as f64 * scale) as f32))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(hound_error)?
+        }
+    };
+
+    let channels = spec.channels as usize;
+    let samples = interleaved
+        .chunks_exact(channels)
+        .map(|frame| {
+            let sum: f64 = frame.iter().map(|&x| x as f64).sum();
+            (sum / channels as f64) as f32
+        })
+        .collect();
+    Ok(WavAudio {
+        samples,
+        interleaved,
+        channels,
+        sample_rate: spec.sample_rate,
+    })
+}
+
+/// Replace a streaming `data` chunk size with the length actually present,
+/// rounded down to whole frames, so that `hound` accepts the file.
+fn fill_streaming_size(bytes: &mut Vec<u8>) {
+    let u32_at = |bytes: &[u8], offset: usize| {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    };
+
+    let mut block_align = 1;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let size = u32_at(bytes, pos + 4);
+        let body = pos + 8;
+        match &bytes[pos..pos + 4] {
+            b"fmt " if body + 14 <= bytes.len() => {
+                block_align = u16::from_le_bytes([bytes[body + 12], bytes[body + 13]]).max(1);
+            }
+            b"data" => {
+                if size == STREAMING_SIZE {
+                    let available = (bytes.len() - body).min(u32::MAX as usize);
+                    let len = available / block_align as usize * block_align as usize;
+                    bytes.truncate(body + len);
+                    bytes[pos + 4..body].copy_from_slice(&(len as u32).to_le_bytes());
+                }
+                return;
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        pos = body.saturating_add(size as usize);
+        pos += pos % 2;
+    }
+}
+
+fn hound_error(e: hound::Error) -> io::Error {
+    match e {
+        hound::Error::IoError(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_finalized_wav_has_correct_sizes() {
+        let samples: Vec<f32> = (0..1000).map(|i| (i as f32 * 0.01).sin()).collect();
+
+        let mut writer =
+            WavWriter::new(Cursor::new(Vec::new()), 48000, 1, WavEncoding::Float32).unwrap();
+        for block in samples.chunks(256) {
+            writer.write_samples(block).unwrap();
+        }
+        let bytes = writer.finalize().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), 44 + 4000);
+        assert_eq!(u32_at(&bytes, 4), 36 + 4000);
+        assert_eq!(u32_at(&bytes, 40), 4000);
+
+        // Readable by a standard decoder
+        let reader = hound::WavReader::new(Cursor::new(bytes)).unwrap();
+        let spec = reader.spec();
+        assert_eq!(spec.sample_rate, 48000);
+        assert_eq!(spec.channels, 1);
+        assert_eq!(spec.sample_format, hound::SampleFormat::Float);
+        let decoded: Vec<f32> = reader.into_samples::<f32>().map(|s| s.unwrap()).collect();
+        assert_eq!(decoded, samples);
+    }
+
+    #[test]
+    fn test_streaming_header_uses_unknown_sizes() {
+        let mut writer = WavWriter::new(Vec::new(), 44100, 1, WavEncoding::Float32).unwrap();
+        writer.write_samples(&[0.5; 10]).unwrap();
+        let bytes = writer.into_inner().unwrap();
+
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32_at(&bytes, 4), u32::MAX);
+        assert_eq!(u32_at(&bytes, 40), u32::MAX);
+        assert_eq!(bytes.len(), 44 + 40);
+    }
+
+    #[test]
+    fn test_read_wav_mixes_pcm_to_mono() {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 22050,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut cursor = Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut cursor, spec).unwrap();
+        for (left, right) in [(16384i16, 0i16), (-32768, -32768), (8192, 8192)] {
+            writer.write_sample(left).unwrap();
+            writer.write_sample(right).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let audio = read_wav(Cursor::new(cursor.into_inner())).unwrap();
+        assert_eq!(audio.sample_rate, 22050);
+        assert_eq!(audio.samples, vec![0.25, -1.0, 0.25]);
+        assert_eq!(audio.channels, 2);
+        assert_eq!(audio.interleaved, vec![0.5, 0.0, -1.0, -1.0, 0.25, 0.25]);
+
+        // Streamed float output reads back unchanged
+        let mut writer = WavWriter::new(Vec::new(), 48000, 1, WavEncoding::Float32).unwrap();
+        writer.write_samples(&[0.5, -0.125]).unwrap();
+        let audio = read_wav(writer.into_inner().unwrap().as_slice()).unwrap();
+        assert_eq!(audio.samples, vec![0.5, -0.125]);
+    }
+
+    #[test]
+    fn test_integer_encodings_read_back() {
+        let samples = [0.5, -0.25, 1.5, -1.0];
+        for (encoding, bits, clipped) in [
+            (WavEncoding::Pcm16, 16, 32767.0 / 32768.0),
+            (WavEncoding::Pcm24, 24, 8_388_607.0 / 8_388_608.0),
+        ] {
+            let mut writer = WavWriter::new(Cursor::new(Vec::new()), 44100, 2, encoding).unwrap();
+            writer.write_samples(&samples).unwrap();
+            let bytes = writer.finalize().unwrap().into_inner();
+            assert_eq!(bytes.len(), 44 + samples.len() * bits / 8);
+
+            let reader = hound::WavReader::new(Cursor::new(bytes.clone())).unwrap();
+            assert_eq!(reader.spec().bits_per_sample as usize, bits);
+            assert_eq!(reader.spec().sample_format, hound::SampleFormat::Int);
+
+            // Out-of-range samples clip to full scale
+            let audio = read_wav(bytes.as_slice()).unwrap();
+            assert_eq!(audio.channels, 2);
+            assert_eq!(audio.interleaved, vec![0.5, -0.25, clipped, -1.0]);
+        }
+
+        // A streamed 24-bit pipe reads back too
+        let mut writer = WavWriter::new(Vec::new(), 48000, 1, WavEncoding::Pcm24).unwrap();
+        writer.write_samples(&[0.5, -0.125]).unwrap();
+        let audio = read_wav(writer.into_inner().unwrap().as_slice()).unwrap();
+        assert_eq!(audio.samples, vec![0.5, -0.125]);
+    }
+}
@@ -0,0 +1,220 @@
+//! Building blocks: common pedal stages for a [`CircuitBuilder`].
+//!
+//! Each function adds a stage's components between two nodes of the
+//! builder, so blocks compose by sharing nodes:
+//!
+//! ```
+//! use pedaler_core::circuit::{blocks, CircuitBuilder};
+//! use pedaler_core::components::DiodeParams;
+//!
+//! let mut b = CircuitBuilder::new();
+//! let [input, drive, clip, output] = ["in", "drive", "clip", "out"].map(|n| b.add_node(n));
+//! let gnd = b.add_node("0");
+//! b.input_source("V_IN", input, gnd)
+//!     .resistor("R_BIAS", input, gnd, 1e6)
+//!     .set_input(input)
+//!     .set_output(output);
+//! blocks::gain_stage(&mut b, "GAIN", input, drive, 100e3, 4.7e3).unwrap();
+//! blocks::diode_clipper(&mut b, "CLIP", drive, clip, 1e3, DiodeParams::default()).unwrap();
+//! blocks::tone_stack(&mut b, "TONE", clip, output, 0.5).unwrap();
+//! assert!(b.build().is_ok());
+//! ```
+//!
+//! `name` prefixes every component of a block and names its internal nodes
+//! (`<name>_<suffix>`), so it must be unique within the circuit. Values are
+//! in base units (ohms, farads).
+
+use super::{CircuitBuilder, NodeId};
+use crate::components::DiodeParams;
+use crate::error::{PedalerError, Result};
+
+/// Non-inverting op-amp gain stage from `input` to `output`, with gain
+/// `1 + rf / rg`.
+///
+/// The ideal op-amp drives `output`, `rf` feeds back to the inverting
+/// input and `rg` returns it to ground. `input` needs its own DC path to
+/// ground.
+pub fn gain_stage(
+    builder: &mut CircuitBuilder,
+    name: &str,
+    input: NodeId,
+    output: NodeId,
+    rf: f64,
+    rg: f64,
+) -> Result<()> {
+    positive(name, "rf", rf)?;
+    positive(name, "rg", rg)?;
+    let fb = builder.add_node(&format!("{}_fb", name));
+    builder
+        .opamp(&format!("OP{}", name), output, input, fb)
+        .resistor(&format!("R{}F", name), output, fb, rf)
+        .resistor(&format!("R{}G", name), fb, NodeId::GROUND, rg);
+    Ok(())
+}
+
+/// First-order RC low-pass filter from `input` to `output`, with corner
+/// `1 / (2π r c)`.
+pub fn rc_lowpass(
+    builder: &mut CircuitBuilder,
+    name: &str,
+    input: NodeId,
+    output: NodeId,
+    r: f64,
+    c: f64,
+) -> Result<()> {
+    positive(name, "r", r)?;
+    positive(name, "c", c)?;
+    builder
+        .resistor(&format!("R{}", name), input, output, r)
+        .capacitor(&format!("C{}", name), output, NodeId::GROUND, c);
+    Ok(())
+}
+
+/// First-order RC high-pass filter from `input` to `output`, with corner
+/// `1 / (2π r c)`. The resistor also biases `output` to ground.
+pub fn rc_highpass(
+    builder: &mut CircuitBuilder,
+    name: &str,
+    input: NodeId,
+    output: NodeId,
+    r: f64,
+    c: f64,
+) -> Result<()> {
+    positive(name, "r", r)?;
+    positive(name, "c", c)?;
+    builder
+        .capacitor(&format!("C{}", name), input, output, c)
+        .resistor(&format!("R{}", name), output, NodeId::GROUND, r);
+    Ok(())
+}
+
+/// Diode clipper: a series resistor `r` from `input` to `output` and a pair
+/// of anti-parallel `diode`s from `output` to ground, clipping both halves
+/// symmetrically.
+pub fn diode_clipper(
+    builder: &mut CircuitBuilder,
+    name: &str,
+    input: NodeId,
+    output: NodeId,
+    r: f64,
+    diode: DiodeParams,
+) -> Result<()> {
+    positive(name, "r", r)?;
+    builder
+        .resistor(&format!("R{}", name), input, output, r)
+        .diode(
+            &format!("D{}A", name),
+            output,
+            NodeId::GROUND,
+            diode.clone(),
+        )
+        .diode(&format!("D{}B", name), NodeId::GROUND, output, diode);
+    Ok(())
+}
+
+/// Passive tone stack in the style of the Big Muff: a low-pass (39k, 10n)
+/// and a high-pass (4n, 22k) branch from `input`, blended into `output` by
+/// a 100k pot (`POT<name>`) at `tone`, from 0.0 (bass) to 1.0 (treble).
+///
+/// At the middle of the pot both branches mix into the familiar mid scoop.
+pub fn tone_stack(
+    builder: &mut CircuitBuilder,
+    name: &str,
+    input: NodeId,
+    output: NodeId,
+    tone: f64,
+) -> Result<()> {
+    if !(0.0..=1.0).contains(&tone) {
+        return Err(PedalerError::invalid_parameter(
+            name,
+            "tone",
+            format!("must be between 0 and 1, got {}", tone),
+        ));
+    }
+    let lp = builder.add_node(&format!("{}_lp", name));
+    let hp = builder.add_node(&format!("{}_hp", name));
+    builder
+        .resistor(&format!("R{}L", name), input, lp, 39e3)
+        .capacitor(&format!("C{}L", name), lp, NodeId::GROUND, 10e-9)
+        .capacitor(&format!("C{}H", name), input, hp, 4e-9)
+        .resistor(&format!("R{}H", name), hp, NodeId::GROUND, 22e3)
+        .potentiometer(&format!("POT{}", name), lp, output, hp, 100e3, tone);
+    Ok(())
+}
+
+/// Check that a block value is positive and finite.
+fn positive(name: &str, param: &str, value: f64) -> Result<()> {
+    if value.is_finite() && value > 0.0 {
+        Ok(())
+    } else {
+        Err(PedalerError::invalid_parameter(
+            name,
+            param,
+            format!("must be positive, got {}", value),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::validate_circuit;
+    use crate::Simulator;
+
+    /// A builder with the audio input at `in` and the output at `out`.
+    fn builder() -> (CircuitBuilder, NodeId, NodeId) {
+        let mut b = CircuitBuilder::new();
+        let (input, output) = (b.add_node("in"), b.add_node("out"));
+        b.input_source("V_IN", input, NodeId::GROUND)
+            .set_input(input)
+            .set_output(output);
+        (b, input, output)
+    }
+
+    fn simulator(b: &CircuitBuilder) -> Simulator {
+        let circuit = b.build().unwrap();
+        validate_circuit(&circuit).unwrap();
+        Simulator::new(circuit, 48000.0)
+    }
+
+    #[test]
+    fn test_gain_stage_has_non_inverting_gain() {
+        for (rf, rg) in [(100e3, 4.7e3), (10e3, 10e3), (1e3, 1e6)] {
+            let (mut b, input, output) = builder();
+            gain_stage(&mut b, "G1", input, output, rf, rg).unwrap();
+            let mut sim = simulator(&b);
+            let out = sim.dc_sweep(&[0.01, -0.02]).unwrap();
+            let gain = 1.0 + rf / rg;
+            assert!(
+                (out[0] / 0.01 - gain).abs() < 1e-6 * gain,
+                "gain {} vs {}",
+                out[0] / 0.01,
+                gain
+            );
+            assert!((out[1] / -0.02 - gain).abs() < 1e-6 * gain);
+        }
+
+        let (mut b, input, output) = builder();
+        assert!(gain_stage(&mut b, "G1", input, output, 0.0, 1e3).is_err());
+        assert!(tone_stack(&mut b, "T1", input, output, 1.5).is_err());
+    }
+
+    #[test]
+    fn test_blocks_compose_into_a_pedal() {
+        let (mut b, input, output) = builder();
+        let [n1, n2, n3, n4] = ["a", "b", "c", "d"].map(|n| b.add_node(n));
+        rc_highpass(&mut b, "HP", input, n1, 1e6, 100e-9).unwrap();
+        gain_stage(&mut b, "G", n1, n2, 100e3, 1e3).unwrap();
+        diode_clipper(&mut b, "CLIP", n2, n3, 1e3, DiodeParams::default()).unwrap();
+        rc_lowpass(&mut b, "LP", n3, n4, 1e3, 10e-9).unwrap();
+        tone_stack(&mut b, "T", n4, output, 0.5).unwrap();
+        let mut sim = simulator(&b);
+        let input: Vec<f32> = (0..4800).map(|i| 0.5 * (i as f32 * 0.05).sin()).collect();
+        let mut output = vec![0.0f32; input.len()];
+        sim.process_block(&input, &mut output).unwrap();
+
+        // The clipper caps the drive well below the 50 V the gain asks for
+        let peak = output.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+        assert!(peak > 0.05 && peak < 1.0, "peak {}", peak);
+    }
+}
@@ -3,9 +3,12 @@
 use std::collections::HashMap;
 
 use super::types::{BranchId, ComponentId, NodeId};
-use crate::components::Component;
-use crate::dsl::{CircuitAst, ComponentType};
+use crate::components::{
+    Component, DiodeParams, IntegrationMethod, OpAmpModel, Resistor, FDN_SIZES,
+};
+use crate::dsl::{CircuitAst, ComponentDef, ComponentType, ModelDef, ModelType};
 use crate::error::{PedalerError, Result};
+use crate::solver::{stamp_linear_components, MnaMatrix, MIN_CONDUCTANCE};
 
 /// Definition of a digital delay effect (stored for later instantiation).
 #[derive(Debug, Clone)]
@@ -41,6 +44,29 @@ pub struct ReverbDef {
     pub branch: BranchId,
 }
 
+/// Definition of a mono-to-stereo widener (stored for later instantiation).
+#[derive(Debug, Clone)]
+pub struct WidenDef {
+    /// Component name
+    pub name: String,
+    /// Input node
+    pub input_node: NodeId,
+    /// Left output node
+    pub left_node: NodeId,
+    /// Right output node
+    pub right_node: NodeId,
+    /// Stereo width (0.0-1.0)
+    pub width: f32,
+    /// Inter-channel delay in seconds
+    pub delay_time: f64,
+    /// Use mid/side processing instead of a plain Haas delay
+    pub mid_side: bool,
+    /// Branch ID for the left output voltage source
+    pub left_branch: BranchId,
+    /// Branch ID for the right output voltage source
+    pub right_branch: BranchId,
+}
+
 /// Definition of an LFO (Low Frequency Oscillator) for modulation.
 #[derive(Debug, Clone)]
 pub struct LfoDef {
@@ -50,10 +76,31 @@ pub struct LfoDef {
     pub rate: f64,
     /// Waveform shape (sine, triangle, sawtooth, square)
     pub shape: String,
+    /// Phase offset in cycles (0.0 to 1.0)
+    pub phase: f64,
+    /// Output at the bottom of the waveform
+    pub min: f64,
+    /// Output at the top of the waveform
+    pub max: f64,
+}
+
+/// Definition of an envelope follower for dynamics-controlled modulation.
+#[derive(Debug, Clone)]
+pub struct EnvelopeDef {
+    /// Component name (used to reference from modulated components)
+    pub name: String,
+    /// Node whose amplitude is followed
+    pub input_node: NodeId,
+    /// Attack time in seconds
+    pub attack: f64,
+    /// Release time in seconds
+    pub release: f64,
+    /// Volts-to-control gain
+    pub gain: f64,
 }
 
 /// A complete circuit ready for simulation.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Circuit {
     /// All components in the circuit
     pub components: Vec<Component>,
@@ -70,28 +117,59 @@ pub struct Circuit {
     /// Number of branch current variables (voltage sources, inductors)
     pub num_branches: usize,
 
-    /// Input node ID (where audio signal is injected)
+    /// Input node ID (where audio signal is injected); the first of
+    /// `input_nodes`, used by mono analyses
     pub input_node: NodeId,
 
-    /// Output node ID (where audio signal is read)
+    /// Output node ID (where audio signal is read); the first of
+    /// `output_nodes`, used by mono analyses
     pub output_node: NodeId,
 
-    /// Index of the input voltage source component
+    /// Index of the input source component; the first of `input_sources`
     pub input_source_idx: Option<usize>,
 
+    /// Input node IDs, one per audio channel in `.input` order
+    pub input_nodes: Vec<NodeId>,
+
+    /// Output node IDs, one per audio channel in `.output` order
+    pub output_nodes: Vec<NodeId>,
+
+    /// Index of the voltage or current source driving each input channel
+    pub input_sources: Vec<Option<usize>>,
+
+    /// Control input node names mapped to the index of the voltage source
+    /// driving each
+    pub control_sources: HashMap<String, usize>,
+
     /// Digital delay effect definitions
     pub delay_defs: Vec<DelayDef>,
 
     /// Digital reverb effect definitions
     pub reverb_defs: Vec<ReverbDef>,
 
+    /// Stereo widener definitions
+    pub widen_defs: Vec<WidenDef>,
+
     /// LFO definitions for modulation
     pub lfo_defs: Vec<LfoDef>,
+
+    /// Envelope follower definitions for modulation
+    pub envelope_defs: Vec<EnvelopeDef>,
+
+    /// Diode models defined with `.model`, by name
+    pub diode_models: HashMap<String, DiodeParams>,
+
+    /// MNA solution frozen by `Simulator::freeze_operating_point`, which
+    /// simulators start from instead of all zeros
+    pub operating_point: Option<Vec<f64>>,
 }
 
 impl Circuit {
     /// Build a circuit from a parsed AST.
-    pub fn from_ast(ast: CircuitAst) -> Result<Self> {
+    pub fn from_ast(mut ast: CircuitAst) -> Result<Self> {
+        ast.flatten_subcircuits()?;
+        ast.resolve_params()?;
+        ast.expand_tone_stacks()?;
         let mut node_map = HashMap::new();
         let mut node_names = Vec::new();
 
@@ -118,7 +196,9 @@ impl Circuit {
                 continue;
             }
             // Also skip digital effects
-            if comp.component_type == ComponentType::Delay || comp.component_type == ComponentType::Reverb {
+            if comp.component_type == ComponentType::Delay
+                || comp.component_type == ComponentType::Reverb
+            {
                 continue;
             }
             for node_name in &comp.nodes {
@@ -129,33 +209,75 @@ impl Circuit {
                     next_id += 1;
                 }
             }
+
+            // Diode stacks get hidden nodes between the diodes of each chain
+            if comp.component_type == ComponentType::DiodeStack {
+                if STACK_DIRECTIONS
+                    .iter()
+                    .all(|d| !comp.string_params.contains_key(*d))
+                {
+                    return Err(PedalerError::invalid_component(
+                        &comp.name,
+                        comp.line,
+                        "diode stack requires fwd= and/or rev= model lists",
+                    ));
+                }
+                for direction in STACK_DIRECTIONS {
+                    for i in 1..stack_models(comp, direction)?.len() {
+                        node_map.insert(stack_node(&comp.name, direction, i), NodeId(next_id));
+                        node_names.push(stack_node(&comp.name, direction, i));
+                        next_id += 1;
+                    }
+                }
+            }
         }
 
         let num_nodes = next_id;
 
         // Get input/output nodes
-        let input_node_name = ast.input_node.as_ref().ok_or(PedalerError::MissingInput)?;
-        let output_node_name = ast.output_node.as_ref().ok_or(PedalerError::MissingOutput)?;
-
-        let input_node = *node_map
-            .get(input_node_name)
-            .ok_or_else(|| PedalerError::NodeNotFound {
-                node: input_node_name.clone(),
-            })?;
+        if ast.input_nodes.is_empty() {
+            return Err(PedalerError::MissingInput);
+        }
+        if ast.output_nodes.is_empty() {
+            return Err(PedalerError::MissingOutput);
+        }
+        let resolve = |name: &String| {
+            node_map
+                .get(name)
+                .copied()
+                .ok_or_else(|| PedalerError::NodeNotFound { node: name.clone() })
+        };
+        let input_nodes = ast
+            .input_nodes
+            .iter()
+            .map(resolve)
+            .collect::<Result<Vec<_>>>()?;
+        let output_nodes = ast
+            .output_nodes
+            .iter()
+            .map(resolve)
+            .collect::<Result<Vec<_>>>()?;
 
-        let output_node = *node_map
-            .get(output_node_name)
-            .ok_or_else(|| PedalerError::NodeNotFound {
-                node: output_node_name.clone(),
-            })?;
+        let control_nodes = ast
+            .control_nodes
+            .iter()
+            .map(|name| {
+                node_map
+                    .get(name)
+                    .map(|&node| (name.clone(), node))
+                    .ok_or_else(|| PedalerError::NodeNotFound { node: name.clone() })
+            })
+            .collect::<Result<Vec<_>>>()?;
 
         // Convert components
         let mut components = Vec::with_capacity(ast.components.len());
         let mut delay_defs = Vec::new();
         let mut reverb_defs = Vec::new();
+        let mut widen_defs = Vec::new();
         let mut lfo_defs = Vec::new();
+        let mut envelope_defs = Vec::new();
         let mut num_branches = 0usize;
-        let mut input_source_idx = None;
+        let mut input_sources = vec![None; input_nodes.len()];
 
         for (idx, comp_def) in ast.components.into_iter().enumerate() {
             // Resolve node names to IDs
@@ -164,11 +286,10 @@ impl Circuit {
                 .iter()
                 .map(|name| {
                     let normalized = if name == "GND" { "0" } else { name };
-                    node_map.get(normalized).copied().ok_or_else(|| {
-                        PedalerError::NodeNotFound {
-                            node: name.clone(),
-                        }
-                    })
+                    node_map
+                        .get(normalized)
+                        .copied()
+                        .ok_or_else(|| PedalerError::NodeNotFound { node: name.clone() })
                 })
                 .collect::<Result<Vec<_>>>()?;
 
@@ -193,6 +314,15 @@ impl Circuit {
                     continue;
                 }
                 ComponentType::Reverb => {
+                    if let Some(&lines) = comp_def.params.get("lines") {
+                        if !FDN_SIZES.iter().any(|&n| n as f64 == lines) {
+                            return Err(PedalerError::invalid_parameter(
+                                &comp_def.name,
+                                "lines",
+                                format!("must be 4, 8 or 16, got {}", lines),
+                            ));
+                        }
+                    }
                     // Assign a branch for the output voltage source
                     let branch = BranchId(num_branches);
                     num_branches += 1;
@@ -205,16 +335,130 @@ impl Circuit {
                     });
                     continue;
                 }
+                ComponentType::Widen => {
+                    let width = comp_def.params.get("width").copied().unwrap_or(1.0);
+                    if !(0.0..=1.0).contains(&width) {
+                        return Err(PedalerError::invalid_parameter(
+                            &comp_def.name,
+                            "width",
+                            format!("must be between 0 and 1, got {}", width),
+                        ));
+                    }
+                    let delay_time = comp_def.params.get("delay").copied().unwrap_or(0.015); // Default 15ms
+                    if delay_time.is_nan() || delay_time <= 0.0 {
+                        return Err(PedalerError::invalid_parameter(
+                            &comp_def.name,
+                            "delay",
+                            format!("must be positive, got {}", delay_time),
+                        ));
+                    }
+                    // One branch per output voltage source
+                    let left_branch = BranchId(num_branches);
+                    let right_branch = BranchId(num_branches + 1);
+                    num_branches += 2;
+                    widen_defs.push(WidenDef {
+                        name: comp_def.name.clone(),
+                        input_node: nodes[0],
+                        left_node: nodes[1],
+                        right_node: nodes[2],
+                        width: width as f32,
+                        delay_time,
+                        mid_side: comp_def.params.get("ms").is_some_and(|v| *v > 0.5),
+                        left_branch,
+                        right_branch,
+                    });
+                    continue;
+                }
                 ComponentType::Lfo => {
                     let rate = comp_def.value.unwrap_or(0.5); // Default 0.5 Hz
-                    let shape = comp_def.model_ref.clone().unwrap_or_else(|| "sine".to_string());
+                    let shape = comp_def
+                        .model_ref
+                        .clone()
+                        .unwrap_or_else(|| "sine".to_string());
+                    let phase = comp_def.params.get("phase").copied().unwrap_or(0.0);
+                    if !phase.is_finite() {
+                        return Err(PedalerError::invalid_parameter(
+                            &comp_def.name,
+                            "phase",
+                            format!("must be finite, got {}", phase),
+                        ));
+                    }
+                    let min = comp_def.params.get("min").copied().unwrap_or(0.0);
+                    let max = comp_def.params.get("max").copied().unwrap_or(1.0);
+                    if !(0.0..=1.0).contains(&min) || !(0.0..=1.0).contains(&max) || min > max {
+                        return Err(PedalerError::invalid_parameter(
+                            &comp_def.name,
+                            "min",
+                            format!(
+                                "min and max must satisfy 0 <= min <= max <= 1, got {} and {}",
+                                min, max
+                            ),
+                        ));
+                    }
                     lfo_defs.push(LfoDef {
                         name: comp_def.name.clone(),
                         rate,
                         shape,
+                        phase: phase.rem_euclid(1.0),
+                        min,
+                        max,
+                    });
+                    continue;
+                }
+                ComponentType::Envelope => {
+                    envelope_defs.push(EnvelopeDef {
+                        name: comp_def.name.clone(),
+                        input_node: nodes[0],
+                        attack: comp_def.params.get("attack").copied().unwrap_or(0.01), // Default 10ms
+                        release: comp_def.params.get("release").copied().unwrap_or(0.1), // Default 100ms
+                        gain: comp_def.params.get("gain").copied().unwrap_or(1.0),
                     });
                     continue;
                 }
+                ComponentType::DiodeStack => {
+                    // Expand each chain into series diodes through the hidden nodes
+                    for direction in STACK_DIRECTIONS {
+                        let models = stack_models(&comp_def, direction)?;
+                        let (from, to) = if direction == "fwd" {
+                            (nodes[0], nodes[1])
+                        } else {
+                            (nodes[1], nodes[0])
+                        };
+                        let mut chain = vec![from];
+                        for i in 1..models.len() {
+                            chain.push(node_map[&stack_node(&comp_def.name, direction, i)]);
+                        }
+                        chain.push(to);
+
+                        for (i, model_name) in models.iter().enumerate() {
+                            let model = ast.models.get(model_name).ok_or_else(|| {
+                                PedalerError::UndefinedModel {
+                                    model: model_name.clone(),
+                                    component: comp_def.name.clone(),
+                                }
+                            })?;
+                            let diode_def = ComponentDef {
+                                component_type: ComponentType::Diode,
+                                name: format!("{}.{}{}", comp_def.name, direction, i + 1),
+                                nodes: Vec::new(),
+                                value: None,
+                                model_ref: Some(model_name.clone()),
+                                params: HashMap::new(),
+                                string_params: HashMap::new(),
+                                param_refs: HashMap::new(),
+                                line: comp_def.line,
+                            };
+                            components.push(Component::from_def(
+                                ComponentId(idx),
+                                &diode_def,
+                                &chain[i..i + 2],
+                                Some(model),
+                                &mut num_branches,
+                            )?);
+                        }
+                    }
+                    continue;
+                }
                 _ => {}
             }
 
@@ -233,31 +477,79 @@ impl Circuit {
                 &mut num_branches,
             )?;
 
-            // Check if this is the input voltage source
+            // Check if this is an input voltage source
             if let Component::VoltageSource(ref vs) = component {
-                if vs.nodes[0] == input_node || vs.nodes[1] == input_node {
-                    input_source_idx = Some(components.len());
+                for (source, input) in input_sources.iter_mut().zip(&input_nodes) {
+                    if vs.nodes.contains(input) {
+                        *source = Some(components.len());
+                    }
                 }
             }
 
             components.push(component);
         }
 
+        // An input no voltage source touches can be driven by injecting a
+        // current instead, e.g. from a high-impedance pickup
+        for (source, input) in input_sources.iter_mut().zip(&input_nodes) {
+            if source.is_none() {
+                *source = components.iter().position(
+                    |c| matches!(c, Component::CurrentSource(is) if is.nodes.contains(input)),
+                );
+            }
+        }
+
+        // Each control node needs its own source to carry the control signal
+        let mut control_sources = HashMap::new();
+        for (name, node) in control_nodes {
+            let idx = (0..components.len())
+                .find(|&idx| {
+                    !input_sources.contains(&Some(idx))
+                        && matches!(&components[idx], Component::VoltageSource(vs) if vs.nodes.contains(&node))
+                })
+                .ok_or_else(|| PedalerError::UndrivenControl { node: name.clone() })?;
+            control_sources.insert(name, idx);
+        }
+
+        let mut diode_models = HashMap::new();
+        for model in ast.models.values() {
+            if model.model_type == ModelType::Diode {
+                diode_models.insert(model.name.clone(), DiodeParams::from_model(model)?);
+            }
+        }
+
         Ok(Circuit {
             components,
             node_map,
             node_names,
             num_nodes,
             num_branches,
-            input_node,
-            output_node,
-            input_source_idx,
+            input_node: input_nodes[0],
+            output_node: output_nodes[0],
+            input_source_idx: input_sources[0],
+            input_nodes,
+            output_nodes,
+            input_sources,
+            control_sources,
             delay_defs,
             reverb_defs,
+            widen_defs,
             lfo_defs,
+            envelope_defs,
+            diode_models,
+            operating_point: None,
         })
     }
 
+    /// Diode parameters for `model`: a `.model` of the netlist, or else a
+    /// built-in preset (see [`DiodeParams::preset`]).
+    pub fn diode_model(&self, model: &str) -> Option<DiodeParams> {
+        self.diode_models
+            .get(model)
+            .cloned()
+            .or_else(|| DiodeParams::preset(model))
+    }
+
     /// Get the total size of the MNA solution vector.
     pub fn matrix_size(&self) -> usize {
         // Nodes (excluding ground) + branch currents
@@ -288,4 +580,1173 @@ impl Circuit {
     pub fn node_name(&self, node: NodeId) -> &str {
         &self.node_names[node.0]
     }
+
+    /// Names of every node except ground, in node ID order. Hidden nodes
+    /// inside diode stacks are included, since they can be probed too.
+    pub fn signal_node_names(&self) -> &[String] {
+        &self.node_names[1..]
+    }
+
+    /// Names of every component, in circuit order.
+    pub fn component_names(&self) -> Vec<&str> {
+        self.components.iter().map(|c| c.name()).collect()
+    }
+
+    /// Number of nonlinear components (solved with Newton-Raphson).
+    pub fn nonlinear_component_count(&self) -> usize {
+        self.components.iter().filter(|c| c.is_nonlinear()).count()
+    }
+
+    /// Whether any component is nonlinear, so each sample needs iteration.
+    pub fn has_nonlinear(&self) -> bool {
+        self.components.iter().any(|c| c.is_nonlinear())
+    }
+
+    /// Whether the circuit has capacitors or inductors.
+    pub fn has_reactive(&self) -> bool {
+        self.components.iter().any(|c| c.is_reactive())
+    }
+
+    /// Whether any component is modulated by an LFO or envelope follower.
+    pub fn has_modulation(&self) -> bool {
+        self.components.iter().any(|c| c.is_modulated())
+    }
+
+    /// Estimate the fastest time constant of the circuit, in seconds.
+    ///
+    /// Each capacitor's time constant is its capacitance times the resistance
+    /// it sees with the rest of the circuit at DC, and each inductor's is its
+    /// inductance over that resistance. Sources are zeroed and nonlinear
+    /// devices are off, as at zero bias, so conducting clipping diodes are
+    /// not accounted for. Returns `None` if there is nothing reactive.
+    pub fn fastest_time_constant(&self) -> Option<f64> {
+        if !self.has_reactive() {
+            return None;
+        }
+
+        let size = self.matrix_size();
+        let mut matrix = MnaMatrix::new(size);
+        stamp_linear_components(
+            self,
+            &mut matrix,
+            f64::INFINITY,
+            IntegrationMethod::default(),
+        );
+        // Nodes reached only through capacitors or nonlinear devices float
+        for i in 0..(self.num_nodes - 1) {
+            matrix.add(i, i, MIN_CONDUCTANCE);
+        }
+        matrix.factor().ok()?;
+
+        let mut fastest: Option<f64> = None;
+        for component in &self.components {
+            let tau = match component {
+                Component::Capacitor(c) => {
+                    // Resistance seen by the capacitor: voltage across a 1 A test current
+                    let (n1, n2) = (self.node_index(c.nodes[0]), self.node_index(c.nodes[1]));
+                    matrix.z.fill(0.0);
+                    matrix.stamp_current_source(n1, n2, 1.0);
+                    matrix.solve().ok()?;
+                    let r = (matrix.voltage(n1) - matrix.voltage(n2)).abs();
+                    c.capacitance * r
+                }
+                Component::Inductor(l) => {
+                    // Conductance seen by the inductor: current through it as a 1 V source
+                    let br = self.branch_index(l.branch);
+                    matrix.z.fill(0.0);
+                    matrix.z[br] = 1.0;
+                    matrix.solve().ok()?;
+                    let g = matrix.x[br].abs();
+                    // An inductor with no resistive path only sees the floating-node
+                    // conductance, and has no meaningful time constant
+                    if g < 1e3 * MIN_CONDUCTANCE {
+                        continue;
+                    }
+                    l.inductance * g
+                }
+                _ => continue,
+            };
+            // A capacitor straight across a source is held, not charged
+            if tau < 1e-15 {
+                continue;
+            }
+            fastest = Some(fastest.map_or(tau, |f| f.min(tau)));
+        }
+        fastest
+    }
+
+    /// Lowest sample rate that simulates the circuit's fastest pole well.
+    ///
+    /// The trapezoidal rule warps frequencies towards Nyquist, so the fastest
+    /// pole, `1 / (2π τ)` for the [fastest time constant](Self::fastest_time_constant),
+    /// should sit at most at half the Nyquist frequency. Returns `None` if
+    /// there is nothing reactive.
+    pub fn recommended_sample_rate(&self) -> Option<f32> {
+        let tau = self.fastest_time_constant()?;
+        let pole = 1.0 / (2.0 * std::f64::consts::PI * tau);
+        Some((4.0 * pole) as f32)
+    }
+
+    /// Power-of-two oversampling factor that brings `sample_rate` up to the
+    /// [recommended rate](Self::recommended_sample_rate), or 1 if it is
+    /// already high enough.
+    pub fn oversampling_factor(&self, sample_rate: f32) -> u32 {
+        match self.recommended_sample_rate() {
+            Some(recommended) if recommended > sample_rate => {
+                let ratio = (recommended / sample_rate).ceil() as u32;
+                ratio.checked_next_power_of_two().unwrap_or(u32::MAX)
+            }
+            _ => 1,
+        }
+    }
+
+    /// Op-amp inputs with no DC path to ground, as `(op-amp name, node)`.
+    ///
+    /// The op-amp model only puts its input resistance between its two
+    /// inputs, so an input reached only through capacitors, such as an
+    /// AC-coupled input without a bias resistor, has nothing to set its DC
+    /// level. DC paths run through every component except capacitors,
+    /// current sources and op-amp inputs; op-amp outputs are driven against
+    /// ground, and digital effects join their nodes as they are stamped.
+    pub fn floating_opamp_inputs(&self) -> Vec<(String, NodeId)> {
+        let mut parent: Vec<usize> = (0..self.num_nodes).collect();
+        for component in &self.components {
+            match component {
+                Component::Capacitor(_) | Component::CurrentSource(_) | Component::Noise(_) => {}
+                Component::OpAmp(op) => union(&mut parent, op.output().0, 0),
+                // The gate, and the grid while biased negative, draw no current
+                Component::Jfet(j) => union(&mut parent, j.drain().0, j.source().0),
+                Component::Mosfet(m) => union(&mut parent, m.drain().0, m.source().0),
+                Component::Triode(t) => union(&mut parent, t.plate().0, t.cathode().0),
+                // The windings are isolated from each other
+                Component::Transformer(t) => {
+                    union(&mut parent, t.nodes[0].0, t.nodes[1].0);
+                    union(&mut parent, t.nodes[2].0, t.nodes[3].0);
+                }
+                other => {
+                    let nodes = other.nodes();
+                    for node in &nodes[1..] {
+                        union(&mut parent, nodes[0].0, node.0);
+                    }
+                }
+            }
+        }
+        self.union_effects(&mut parent);
+
+        let mut floating = Vec::new();
+        for component in &self.components {
+            if let Component::OpAmp(op) = component {
+                for node in [op.input_pos(), op.input_neg()] {
+                    if find_root(&mut parent, node.0) != 0 {
+                        floating.push((op.name.clone(), node));
+                    }
+                }
+            }
+        }
+        floating
+    }
+
+    /// A warning for each input [`floating_opamp_inputs`](Self::floating_opamp_inputs)
+    /// reports, suggesting the bias resistor that would fix it, for hosts
+    /// to show their users.
+    pub fn floating_opamp_warnings(&self) -> Vec<String> {
+        self.floating_opamp_inputs()
+            .into_iter()
+            .map(|(opamp, node)| {
+                let node = self.node_name(node);
+                format!(
+                    "input '{}' of op-amp {} has no DC path to ground; \
+                     add a bias resistor (e.g. R_BIAS_{} {} 0 1M)",
+                    node, opamp, node, node
+                )
+            })
+            .collect()
+    }
+
+    /// Give every floating op-amp input (see [`floating_opamp_inputs`](Self::floating_opamp_inputs))
+    /// a bias resistor of `resistance` ohms to ground, named `R_BIAS_<node>`.
+    ///
+    /// Returns the names of the resistors added.
+    pub fn add_opamp_bias_resistors(&mut self, resistance: f64) -> Result<Vec<String>> {
+        if resistance.is_nan() || resistance <= 0.0 {
+            return Err(PedalerError::invalid_parameter(
+                "R_BIAS",
+                "resistance",
+                format!("must be positive, got {}", resistance),
+            ));
+        }
+
+        let mut added = Vec::new();
+        for (_, node) in self.floating_opamp_inputs() {
+            let name = format!("R_BIAS_{}", self.node_name(node));
+            if added.contains(&name) {
+                continue;
+            }
+            if self.components.iter().any(|c| c.name() == name) {
+                return Err(PedalerError::DuplicateComponent { name });
+            }
+            let id = ComponentId(self.components.len());
+            self.components.push(Component::Resistor(Resistor::new(
+                id,
+                name.clone(),
+                [node, NodeId::GROUND],
+                resistance,
+            )));
+            added.push(name);
+        }
+        Ok(added)
+    }
+
+    /// Draw every toleranced resistor, capacitor and inductor (`tol=5%`)
+    /// from its tolerance, as one unit off the production line would have.
+    ///
+    /// Each value is scaled by `1 + δ`, with `δ` Gaussian of standard
+    /// deviation `tol / 3` and cut off at `±tol`. Components without a
+    /// tolerance keep their values. The same seed always draws the same
+    /// values, in component order.
+    pub fn randomize_tolerances(&mut self, seed: u64) {
+        let mut rng = SplitMix64(seed);
+        for component in &mut self.components {
+            match component {
+                Component::Resistor(r) if r.tolerance > 0.0 => {
+                    let factor = rng.deviation(r.tolerance);
+                    r.set_resistance(r.resistance * factor);
+                }
+                Component::Capacitor(c) if c.tolerance > 0.0 => {
+                    let factor = rng.deviation(c.tolerance);
+                    c.set_capacitance(c.capacitance * factor);
+                }
+                Component::Inductor(l) if l.tolerance > 0.0 => {
+                    let factor = rng.deviation(l.tolerance);
+                    l.set_inductance(l.inductance * factor);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Nodes with no path to ground, which leave the MNA matrix singular.
+    ///
+    /// Paths run through every component except current sources, transistor
+    /// gates and op-amp inputs; the input resistance of a non-nullor op-amp
+    /// joins its two inputs, and op-amp and digital effect outputs are driven
+    /// against ground. Capacitors count as paths: a node reached only
+    /// through capacitors is solvable at audio rate, and the DC solve gives
+    /// it an implicit leakage to ground (see [`cap_coupled_nodes`](Self::cap_coupled_nodes)).
+    ///
+    /// Nodes are returned in order of their IDs.
+    pub fn floating_nodes(&self) -> Vec<NodeId> {
+        let mut parent = self.ground_paths(true);
+        (1..self.num_nodes)
+            .filter(|&i| find_root(&mut parent, i) != 0)
+            .map(NodeId)
+            .collect()
+    }
+
+    /// Nodes that reach ground only through capacitors, in order of their
+    /// IDs.
+    ///
+    /// At DC an ideal capacitor is open, so these nodes have nothing to set
+    /// their voltage. Like SPICE's `rshunt` option, the operating point
+    /// solve ties each of them to ground through [`MIN_CONDUCTANCE`].
+    pub fn cap_coupled_nodes(&self) -> Vec<NodeId> {
+        let mut dc = self.ground_paths(false);
+        let mut any = self.ground_paths(true);
+        (1..self.num_nodes)
+            .filter(|&i| find_root(&mut dc, i) != 0 && find_root(&mut any, i) == 0)
+            .map(NodeId)
+            .collect()
+    }
+
+    /// Names of the components in the first loop made only of voltage
+    /// sources, inductors and transformer windings, if there is one.
+    ///
+    /// Each such loop puts a second KVL constraint on a voltage that one of
+    /// its members already fixes, which leaves the MNA matrix singular at DC.
+    /// Ammeters count as zero-volt sources, delays and reverbs as sources
+    /// from their input to their output node, and widener outputs as
+    /// sources to ground. The loop is listed in order around it, starting
+    /// from the component that closes it.
+    pub fn voltage_source_loop(&self) -> Option<Vec<String>> {
+        let sources = self
+            .components
+            .iter()
+            .filter(|c| {
+                matches!(
+                    c,
+                    Component::VoltageSource(_) | Component::Ammeter(_) | Component::Inductor(_)
+                )
+            })
+            .map(|c| (c.name(), c.nodes()[0], c.nodes()[1]));
+        let windings = self.components.iter().filter_map(|c| match c {
+            Component::Transformer(t) => Some(t),
+            _ => None,
+        });
+        let sources = sources.chain(windings.flat_map(|t| {
+            [
+                (t.name.as_str(), t.nodes[0], t.nodes[1]),
+                (t.name.as_str(), t.nodes[2], t.nodes[3]),
+            ]
+        }));
+        let effects = self
+            .delay_defs
+            .iter()
+            .map(|d| (d.name.as_str(), d.input_node, d.output_node))
+            .chain(
+                self.reverb_defs
+                    .iter()
+                    .map(|r| (r.name.as_str(), r.input_node, r.output_node)),
+            )
+            .chain(self.widen_defs.iter().flat_map(|w| {
+                [
+                    (w.name.as_str(), w.left_node, NodeId::GROUND),
+                    (w.name.as_str(), w.right_node, NodeId::GROUND),
+                ]
+            }));
+
+        // Spanning forest of the sources seen so far, by node
+        let mut parent: Vec<usize> = (0..self.num_nodes).collect();
+        let mut adjacent: Vec<Vec<(usize, &str)>> = vec![Vec::new(); self.num_nodes];
+        for (name, a, b) in sources.chain(effects) {
+            let (a, b) = (a.0, b.0);
+            if find_root(&mut parent, a) != find_root(&mut parent, b) {
+                union(&mut parent, a, b);
+                adjacent[a].push((b, name));
+                adjacent[b].push((a, name));
+                continue;
+            }
+
+            // Already joined: walk the forest from b back to a
+            let mut via: Vec<Option<(usize, &str)>> = vec![None; self.num_nodes];
+            let mut queue = std::collections::VecDeque::from([a]);
+            while let Some(node) = queue.pop_front() {
+                for &(next, edge) in &adjacent[node] {
+                    if next != a && via[next].is_none() {
+                        via[next] = Some((node, edge));
+                        queue.push_back(next);
+                    }
+                }
+            }
+            let mut names = vec![name.to_string()];
+            let mut node = b;
+            while let Some((prev, edge)) = via[node] {
+                names.push(edge.to_string());
+                node = prev;
+            }
+            return Some(names);
+        }
+        None
+    }
+
+    /// Join the nodes of each digital effect in `parent` the way the
+    /// effect is stamped: a delay or reverb as a source from its input to
+    /// its output node, each widener output as a source to ground.
+    fn union_effects(&self, parent: &mut [usize]) {
+        let delays = self
+            .delay_defs
+            .iter()
+            .map(|d| (d.input_node, d.output_node));
+        let reverbs = self
+            .reverb_defs
+            .iter()
+            .map(|r| (r.input_node, r.output_node));
+        let wideners = self.widen_defs.iter().flat_map(|w| {
+            [
+                (w.left_node, NodeId::GROUND),
+                (w.right_node, NodeId::GROUND),
+            ]
+        });
+        for (a, b) in delays.chain(reverbs).chain(wideners) {
+            union(parent, a.0, b.0);
+        }
+    }
+
+    /// Union-find forest joining nodes connected by a path that can carry
+    /// current to ground, optionally through capacitors. See
+    /// [`floating_nodes`](Self::floating_nodes).
+    fn ground_paths(&self, through_capacitors: bool) -> Vec<usize> {
+        let mut parent: Vec<usize> = (0..self.num_nodes).collect();
+        for component in &self.components {
+            match component {
+                Component::CurrentSource(_) | Component::Noise(_) => {}
+                Component::Capacitor(_) if !through_capacitors => {}
+                Component::OpAmp(op) => {
+                    union(&mut parent, op.output().0, 0);
+                    if op.params.model != OpAmpModel::Nullor {
+                        union(&mut parent, op.input_pos().0, op.input_neg().0);
+                    }
+                }
+                Component::Jfet(j) => union(&mut parent, j.drain().0, j.source().0),
+                Component::Mosfet(m) => union(&mut parent, m.drain().0, m.source().0),
+                Component::Triode(t) => union(&mut parent, t.plate().0, t.cathode().0),
+                // The windings are isolated from each other
+                Component::Transformer(t) => {
+                    union(&mut parent, t.nodes[0].0, t.nodes[1].0);
+                    union(&mut parent, t.nodes[2].0, t.nodes[3].0);
+                }
+                other => {
+                    let nodes = other.nodes();
+                    for node in &nodes[1..] {
+                        union(&mut parent, nodes[0].0, node.0);
+                    }
+                }
+            }
+        }
+        self.union_effects(&mut parent);
+        parent
+    }
+
+    /// Group the non-ground nodes into separately connected sub-circuits.
+    ///
+    /// Ground is shared by everything, so it does not join groups. Nodes are
+    /// connected by components, by digital effects (input to outputs) and by
+    /// envelope followers (followed node to the nodes of the components they
+    /// modulate), so a sidechain counts as part of the circuit it controls.
+    /// A well-formed circuit has exactly one group; more usually means two
+    /// circuits were pasted into one file without being wired together.
+    ///
+    /// Groups are ordered by their lowest node ID, and each group is sorted.
+    pub fn subcircuits(&self) -> Vec<Vec<NodeId>> {
+        let mut parent: Vec<usize> = (0..self.num_nodes).collect();
+        let mut connect = |nodes: &[NodeId]| {
+            let mut signal = nodes.iter().filter(|n| !n.is_ground());
+            if let Some(first) = signal.next() {
+                for node in signal {
+                    union(&mut parent, first.0, node.0);
+                }
+            }
+        };
+
+        for component in &self.components {
+            connect(component.nodes());
+        }
+        for delay in &self.delay_defs {
+            connect(&[delay.input_node, delay.output_node]);
+        }
+        for reverb in &self.reverb_defs {
+            connect(&[reverb.input_node, reverb.output_node]);
+        }
+        for widen in &self.widen_defs {
+            connect(&[widen.input_node, widen.left_node, widen.right_node]);
+        }
+        for env in &self.envelope_defs {
+            for component in &self.components {
+                if component.modulation_source() == Some(env.name.as_str()) {
+                    for &node in component.nodes() {
+                        connect(&[env.input_node, node]);
+                    }
+                }
+            }
+        }
+
+        let mut groups: Vec<Vec<NodeId>> = Vec::new();
+        let mut group_of_root = HashMap::new();
+        for i in 1..self.num_nodes {
+            let root = find_root(&mut parent, i);
+            let group = *group_of_root.entry(root).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[group].push(NodeId(i));
+        }
+        groups
+    }
+}
+
+/// Builds a [`Circuit`] in code, without writing DSL source.
+///
+/// The builder collects the same definitions the parser would and hands
+/// them to [`Circuit::from_ast`], so nodes and branches are numbered exactly
+/// as for the equivalent netlist. Nodes are numbered in the order they are
+/// added, like nodes in order of appearance in the DSL:
+///
+/// ```
+/// use pedaler_core::circuit::CircuitBuilder;
+///
+/// let mut builder = CircuitBuilder::new();
+/// let (input, output) = (builder.add_node("in"), builder.add_node("out"));
+/// let gnd = builder.add_node("0");
+/// builder
+///     .input_source("V_IN", input, gnd)
+///     .resistor("R1", input, output, 1e3)
+///     .capacitor("C1", output, gnd, 100e-9)
+///     .set_input(input)
+///     .set_output(output);
+/// let circuit = builder.build().unwrap();
+/// assert_eq!(circuit.matrix_size(), 3);
+/// ```
+///
+/// Like a parsed circuit, the result still needs
+/// [`validate_circuit`](super::validate_circuit) before simulation.
+#[derive(Debug, Clone, Default)]
+pub struct CircuitBuilder {
+    ast: CircuitAst,
+    /// Node names by ID, ground first
+    node_names: Vec<String>,
+    /// Diode parameters given directly rather than through a model
+    diode_params: HashMap<String, DiodeParams>,
+    /// First node passed in that this builder did not create
+    unknown_node: Option<NodeId>,
+}
+
+impl CircuitBuilder {
+    /// Create an empty builder.
+    pub fn new() -> Self {
+        Self {
+            node_names: vec!["0".to_string()],
+            ..Default::default()
+        }
+    }
+
+    /// Add a node, returning its ID.
+    ///
+    /// `"0"` and `"GND"` are ground, and adding a name twice returns the
+    /// existing node.
+    pub fn add_node(&mut self, name: &str) -> NodeId {
+        if name == "0" || name == "GND" {
+            return NodeId::GROUND;
+        }
+        if let Some(i) = self.node_names.iter().position(|n| n == name) {
+            return NodeId(i);
+        }
+        self.node_names.push(name.to_string());
+        self.ast.nodes.push(name.to_string());
+        NodeId(self.node_names.len() - 1)
+    }
+
+    /// Add a resistor of `ohms` between `a` and `b`.
+    pub fn resistor(&mut self, name: &str, a: NodeId, b: NodeId, ohms: f64) -> &mut Self {
+        self.component(
+            ComponentType::Resistor,
+            name,
+            &[a, b],
+            Some(ohms),
+            HashMap::new(),
+        )
+    }
+
+    /// Add a capacitor of `farads` between `a` and `b`.
+    pub fn capacitor(&mut self, name: &str, a: NodeId, b: NodeId, farads: f64) -> &mut Self {
+        self.component(
+            ComponentType::Capacitor,
+            name,
+            &[a, b],
+            Some(farads),
+            HashMap::new(),
+        )
+    }
+
+    /// Add an inductor of `henries` between `a` and `b`.
+    pub fn inductor(&mut self, name: &str, a: NodeId, b: NodeId, henries: f64) -> &mut Self {
+        self.component(
+            ComponentType::Inductor,
+            name,
+            &[a, b],
+            Some(henries),
+            HashMap::new(),
+        )
+    }
+
+    /// Add a diode conducting from `anode` to `cathode`.
+    pub fn diode(
+        &mut self,
+        name: &str,
+        anode: NodeId,
+        cathode: NodeId,
+        params: DiodeParams,
+    ) -> &mut Self {
+        self.diode_params.insert(name.to_string(), params);
+        self.component(
+            ComponentType::Diode,
+            name,
+            &[anode, cathode],
+            None,
+            HashMap::new(),
+        )
+    }
+
+    /// Add an ideal op-amp driving `output` from `non_inverting` and
+    /// `inverting`.
+    pub fn opamp(
+        &mut self,
+        name: &str,
+        output: NodeId,
+        non_inverting: NodeId,
+        inverting: NodeId,
+    ) -> &mut Self {
+        self.component(
+            ComponentType::OpAmp,
+            name,
+            &[output, non_inverting, inverting],
+            None,
+            HashMap::new(),
+        )
+    }
+
+    /// Add a potentiometer of `ohms` between `a` and `b`, with its wiper at
+    /// `position` (0.0 at `a`, 1.0 at `b`).
+    pub fn potentiometer(
+        &mut self,
+        name: &str,
+        a: NodeId,
+        wiper: NodeId,
+        b: NodeId,
+        ohms: f64,
+        position: f64,
+    ) -> &mut Self {
+        let params = HashMap::from([("position".to_string(), position)]);
+        self.component(
+            ComponentType::Potentiometer,
+            name,
+            &[a, wiper, b],
+            Some(ohms),
+            params,
+        )
+    }
+
+    /// Add a DC voltage source of `volts` from `pos` to `neg`.
+    pub fn voltage_source(
+        &mut self,
+        name: &str,
+        pos: NodeId,
+        neg: NodeId,
+        volts: f64,
+    ) -> &mut Self {
+        self.component(
+            ComponentType::VoltageSource,
+            name,
+            &[pos, neg],
+            Some(volts),
+            HashMap::new(),
+        )
+    }
+
+    /// Add the source carrying the audio input, like `V_IN in 0 AC`.
+    pub fn input_source(&mut self, name: &str, pos: NodeId, neg: NodeId) -> &mut Self {
+        let params = HashMap::from([("ac".to_string(), 1.0)]);
+        self.component(
+            ComponentType::VoltageSource,
+            name,
+            &[pos, neg],
+            None,
+            params,
+        )
+    }
+
+    /// Set the node audio is injected at (`.input`), making the circuit
+    /// mono on its input side.
+    pub fn set_input(&mut self, node: NodeId) -> &mut Self {
+        self.ast.input_nodes = vec![self.name_of(node)];
+        self
+    }
+
+    /// Set the node audio is read from (`.output`), making the circuit mono
+    /// on its output side.
+    pub fn set_output(&mut self, node: NodeId) -> &mut Self {
+        self.ast.output_nodes = vec![self.name_of(node)];
+        self
+    }
+
+    /// Build the circuit.
+    ///
+    /// Fails as [`Circuit::from_ast`] does, e.g. with `MissingInput` when no
+    /// input was set, and with `NodeNotFound` for a node ID that did not
+    /// come from this builder.
+    pub fn build(&self) -> Result<Circuit> {
+        if let Some(node) = self.unknown_node {
+            return Err(PedalerError::NodeNotFound {
+                node: node.to_string(),
+            });
+        }
+        let mut circuit = Circuit::from_ast(self.ast.clone())?;
+        for component in &mut circuit.components {
+            if let Component::Diode(d) = component {
+                if let Some(params) = self.diode_params.get(&d.name) {
+                    // Checked here as a .model's parameters are when parsed
+                    params.validate(&d.name)?;
+                    d.params = params.clone();
+                }
+            }
+        }
+        Ok(circuit)
+    }
+
+    /// Write the circuit as DSL text, as [`CircuitAst::to_dsl`] does.
+    ///
+    /// Each diode's parameters become a `.model` named `<diode>_MODEL`. The
+    /// model sets `vf`, which in the DSL also sets the exponential's limit,
+    /// so a diode whose `v_crit` differs from its `vf` reads back with
+    /// `v_crit = vf`.
+    pub fn to_dsl(&self) -> String {
+        let mut ast = self.ast.clone();
+        for def in &mut ast.components {
+            let Some(p) = self.diode_params.get(&def.name) else {
+                continue;
+            };
+            let name = format!("{}_MODEL", def.name);
+            let params = [
+                ("is", p.is),
+                ("n", p.n),
+                ("vf", p.vf),
+                ("vz", p.vz),
+                ("ibv", p.ibv),
+                ("eg", p.eg),
+                ("xti", p.xti),
+            ];
+            ast.models.insert(
+                name.clone(),
+                ModelDef {
+                    name: name.clone(),
+                    model_type: ModelType::Diode,
+                    params: params.iter().map(|&(k, v)| (k.to_string(), v)).collect(),
+                    param_refs: HashMap::new(),
+                    line: 0,
+                },
+            );
+            def.model_ref = Some(name);
+        }
+        ast.to_dsl()
+    }
+
+    fn component(
+        &mut self,
+        component_type: ComponentType,
+        name: &str,
+        nodes: &[NodeId],
+        value: Option<f64>,
+        params: HashMap<String, f64>,
+    ) -> &mut Self {
+        let nodes = nodes.iter().map(|&n| self.name_of(n)).collect();
+        self.ast.components.push(ComponentDef {
+            component_type,
+            name: name.to_string(),
+            nodes,
+            value,
+            model_ref: None,
+            params,
+            string_params: HashMap::new(),
+            param_refs: HashMap::new(),
+            line: 0,
+        });
+        self
+    }
+
+    /// Name of a node, noting IDs this builder did not hand out.
+    fn name_of(&mut self, node: NodeId) -> String {
+        match self.node_names.get(node.0) {
+            Some(name) => name.clone(),
+            None => {
+                self.unknown_node.get_or_insert(node);
+                node.to_string()
+            }
+        }
+    }
+}
+
+/// Root of node `i` in a union-find forest, compressing the path on the way.
+fn find_root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+/// Join the sets of nodes `a` and `b`. The lower index becomes the root, so
+/// ground always roots its own set.
+fn union(parent: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find_root(parent, a), find_root(parent, b));
+    parent[a.max(b)] = a.min(b);
+}
+
+/// Diode stack chain parameters: `fwd` conducts anode to cathode, `rev`
+/// cathode to anode.
+const STACK_DIRECTIONS: [&str; 2] = ["fwd", "rev"];
+
+/// Model names of one chain of a diode stack, in order from its anode end.
+fn stack_models(def: &ComponentDef, direction: &str) -> Result<Vec<String>> {
+    let Some(list) = def.string_params.get(direction) else {
+        if def.params.contains_key(direction) {
+            return Err(PedalerError::invalid_parameter(
+                &def.name,
+                direction,
+                "expected a comma-separated list of diode model names",
+            ));
+        }
+        return Ok(Vec::new());
+    };
+    let models: Vec<String> = list.split(',').map(|m| m.trim().to_string()).collect();
+    if models.iter().any(|m| m.is_empty()) {
+        return Err(PedalerError::invalid_parameter(
+            &def.name,
+            direction,
+            "empty model name in list",
+        ));
+    }
+    Ok(models)
+}
+
+/// Name of the hidden node after the `index`-th diode of a stack chain.
+///
+/// The `.` cannot appear in DSL node names, so these never collide.
+fn stack_node(stack: &str, direction: &str, index: usize) -> String {
+    format!("{}.{}{}", stack, direction, index)
+}
+
+/// splitmix64, for drawing component tolerances. Unlike xorshift it takes
+/// any seed, zero included, and neighbouring seeds draw unrelated values.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `(0, 1]`.
+    fn unit(&mut self) -> f64 {
+        ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A factor `1 + δ` for a value with relative tolerance `tol`: `δ` is
+    /// Gaussian (Box-Muller) with `tol` as its 3σ, cut off at `±tol`.
+    fn deviation(&mut self, tol: f64) -> f64 {
+        let gauss =
+            (-2.0 * self.unit().ln()).sqrt() * (2.0 * std::f64::consts::PI * self.unit()).cos();
+        1.0 + (gauss * tol / 3.0).clamp(-tol, tol)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl;
+
+    #[test]
+    fn test_component_introspection() {
+        let src = ".input in\n.output out\nV_IN in 0 AC\nR1 in b 10k\n\
+                   D1 out 0\nD2 0 out\nQ1 out b 0\nR2 out 0 1k\n";
+        let circuit = Circuit::from_ast(dsl::parse(src).unwrap()).unwrap();
+        assert_eq!(circuit.nonlinear_component_count(), 3);
+        assert!(circuit.has_nonlinear());
+        assert!(!circuit.has_reactive());
+        assert!(!circuit.has_modulation());
+
+        let src = ".input in\n.output out\nV_IN in 0 AC\nLFO LFO1 1 sine\n\
+                   R1 in out 10k LFO1\nC1 out 0 10n\n";
+        let circuit = Circuit::from_ast(dsl::parse(src).unwrap()).unwrap();
+        assert_eq!(circuit.nonlinear_component_count(), 0);
+        assert!(!circuit.has_nonlinear());
+        assert!(circuit.has_reactive());
+        assert!(circuit.has_modulation());
+    }
+
+    #[test]
+    fn test_modulated_resistor_keeps_lfo_name() {
+        let src = ".input in\n.output out\nV_IN in 0 AC\nLFO LFO1 2 sine\n\
+                   R1 in out 10k mod=LFO1 depth=0.8\nR2 out 0 10k lfo=LFO1\n";
+        let circuit = Circuit::from_ast(dsl::parse(src).unwrap()).unwrap();
+        for name in ["R1", "R2"] {
+            let Some(Component::Resistor(r)) = circuit.components.iter().find(|c| c.name() == name)
+            else {
+                panic!("{} missing", name);
+            };
+            assert_eq!(r.modulation.as_ref().unwrap().lfo_name, "LFO1");
+        }
+    }
+
+    #[test]
+    fn test_param_references_resolve() {
+        let src = ".input in\n.output out\n.param R 10k\n.param RMAX 50k\n.param IS 2e-14\n\
+                   V_IN in 0 AC\nR1 in out R\nR2 out 0 {R} mod=E1 rmax=RMAX\nENV E1 in\n\
+                   D1 out 0 DX\n.model DX D (is={IS})\n";
+        let circuit = Circuit::from_ast(dsl::parse(src).unwrap()).unwrap();
+        let resistor = |name| match circuit.components.iter().find(|c| c.name() == name) {
+            Some(Component::Resistor(r)) => r,
+            _ => panic!("{} missing", name),
+        };
+        assert_eq!(resistor("R1").resistance, 10_000.0);
+        assert_eq!(resistor("R2").resistance, 10_000.0);
+        assert_eq!(resistor("R2").modulation.as_ref().unwrap().r_max, 50_000.0);
+        assert_eq!(circuit.diode_models["DX"].is, 2e-14);
+
+        let err = Circuit::from_ast(dsl::parse("R1 in out RX\n").unwrap()).unwrap_err();
+        assert!(
+            matches!(err, PedalerError::ParseError { line: 1, .. }),
+            "{:?}",
+            err
+        );
+        assert!(err.to_string().contains("undefined parameter 'RX'"));
+    }
+
+    #[test]
+    fn test_non_positive_values_are_rejected() {
+        let build = |line: &str| {
+            let src = format!(
+                ".input in\n.output out\nV_IN in 0 AC\nR0 in out 1k\n{}\n",
+                line
+            );
+            Circuit::from_ast(dsl::parse(&src).unwrap())
+        };
+        for (line, param) in [
+            ("R1 in out -10k", "value"),
+            ("C1 out 0 0", "value"),
+            ("L1 out 0 -1m", "value"),
+            ("R1 out 0 1k tol=100%", "tol"),
+            ("D1 out 0 DX\n.model DX D (is=-1e-14)", "is"),
+            ("LFO LFO1 1 sine\nC1 out 0 10n mod=LFO1 depth=1.5", "depth"),
+            ("LFO LFO1 1 sine\nC1 out 0 10n mod=LFO1 range=-2", "range"),
+        ] {
+            let err = build(line).unwrap_err();
+            assert!(
+                matches!(err, PedalerError::InvalidParameter { param: ref p, .. } if p == param),
+                "{}: {:?}",
+                line,
+                err
+            );
+        }
+        // Tiny but positive values are fine
+        assert!(build("C1 out 0 1p").is_ok());
+        assert!(build("R1 out 0 1m").is_ok());
+
+        // Diode parameters from the builder are held to the same rules as
+        // a .model's
+        let bad = [
+            (
+                DiodeParams {
+                    is: -1e-14,
+                    ..DiodeParams::default()
+                },
+                "is",
+            ),
+            (
+                DiodeParams {
+                    n: 0.0,
+                    ..DiodeParams::default()
+                },
+                "n",
+            ),
+            (
+                DiodeParams {
+                    vz: -5.1,
+                    ..DiodeParams::default()
+                },
+                "vz",
+            ),
+        ];
+        for (params, param) in bad {
+            let mut b = CircuitBuilder::new();
+            let (input, output, gnd) = (b.add_node("in"), b.add_node("out"), b.add_node("0"));
+            b.input_source("V_IN", input, gnd)
+                .resistor("R1", input, output, 1e3)
+                .diode("D1", output, gnd, params)
+                .set_input(input)
+                .set_output(output);
+            let err = b.build().unwrap_err();
+            assert!(
+                matches!(err, PedalerError::InvalidParameter { param: ref p, .. } if p == param),
+                "{:?}",
+                err
+            );
+        }
+        assert!(matches!(
+            build(".model DZ D (n=0)\nD1 out 0 DZ"),
+            Err(PedalerError::InvalidParameter { param: ref p, .. }) if p == "n"
+        ));
+    }
+
+    #[test]
+    fn test_subcircuit_instances_get_their_own_nodes() {
+        let src = ".subckt DIV top\nRA top mid 10k\nRB mid 0 10k\n.ends DIV\n\
+                   .input in\n.output out\nV_IN in 0 AC\n\
+                   R1 in a 1k\nX1 a DIV\nR2 a out 1k\nX2 out DIV\n";
+        let circuit = Circuit::from_ast(dsl::parse(src).unwrap()).unwrap();
+        let resistors: Vec<&str> = circuit
+            .components
+            .iter()
+            .filter(|c| matches!(c, Component::Resistor(_)))
+            .map(|c| c.name())
+            .collect();
+        assert_eq!(resistors, ["R1", "X1.RA", "X1.RB", "R2", "X2.RA", "X2.RB"]);
+        let (mid1, mid2) = (
+            circuit.find_node("X1.mid").unwrap(),
+            circuit.find_node("X2.mid").unwrap(),
+        );
+        assert_ne!(mid1, mid2);
+        assert_eq!(circuit.find_node("mid"), None);
+
+        // Nested instances flatten in turn; a cycle is reported
+        let nested =
+            ".subckt PAIR p\nX1 p DIV\nX2 p DIV\n.ends\n".to_string() + src + "X3 in PAIR\n";
+        let circuit = Circuit::from_ast(dsl::parse(&nested).unwrap()).unwrap();
+        assert!(circuit.find_node("X3.X2.mid").is_some());
+        let cycle = ".subckt A p\nX1 p B\n.ends\n.subckt B p\nX1 p A\n.ends\nX1 in A\n";
+        let err = Circuit::from_ast(dsl::parse(cycle).unwrap()).unwrap_err();
+        assert!(err.to_string().contains("A -> B -> A"), "{}", err);
+
+        // A name after .ends must be the subcircuit's own
+        let err = dsl::parse(".subckt DIV top\nRA top 0 10k\n.ends MIX\n").unwrap_err();
+        assert!(
+            err.to_string().contains(".ends MIX does not close DIV"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_control_node_needs_its_own_source() {
+        let base =
+            ".input in\n.output out\n.control sc\nV_IN in 0 AC\nR1 in out 10k\nR2 sc out 10k\n";
+        let err = Circuit::from_ast(dsl::parse(base).unwrap()).unwrap_err();
+        assert!(matches!(err, PedalerError::UndrivenControl { ref node } if node == "sc"));
+
+        let circuit =
+            Circuit::from_ast(dsl::parse(&format!("{}V_SC sc 0 AC\n", base)).unwrap()).unwrap();
+        let idx = circuit.control_sources["sc"];
+        assert_eq!(circuit.components[idx].name(), "V_SC");
+        assert_ne!(Some(idx), circuit.input_source_idx);
+    }
+
+    #[test]
+    fn test_independent_filters_are_separate_subcircuits() {
+        // Two RC low-passes with nothing but ground in common
+        let src = ".input in\n.output out\n\
+                   V_IN in 0 AC\nR1 in out 1k\nC1 out 0 1u\n\
+                   V2 b 0 DC 1\nR2 b c 1k\nC2 c 0 1u\n";
+        let circuit = Circuit::from_ast(dsl::parse(src).unwrap()).unwrap();
+        let names: Vec<Vec<&str>> = circuit
+            .subcircuits()
+            .iter()
+            .map(|group| group.iter().map(|&n| circuit.node_name(n)).collect())
+            .collect();
+        assert_eq!(names, vec![vec!["in", "out"], vec!["b", "c"]]);
+
+        // Both still simulate as ordinary RC filters (tau = 1ms)
+        let mut sim = crate::Simulator::new(circuit, 48000.0);
+        sim.set_input(1.0);
+        for _ in 0..48 {
+            sim.step().unwrap();
+        }
+        let expected = 1.0 - (-1.0f64).exp();
+        let out = sim.node_voltage("out").unwrap();
+        let c = sim.node_voltage("c").unwrap();
+        assert!((out - expected).abs() < 0.01, "out {}", out);
+        assert!((c - expected).abs() < 0.01, "c {}", c);
+    }
+
+    #[test]
+    fn test_sidechain_joins_the_circuit_it_modulates() {
+        let src = ".input in\n.output out\n.control sc\nV_IN in 0 AC\n\
+                   V_SC sc 0 AC\nR_SC sc 0 10k\nENV ENV1 sc\n\
+                   POT1 in out 0 10k position=0.5 mod=ENV1\n";
+        let circuit = Circuit::from_ast(dsl::parse(src).unwrap()).unwrap();
+        assert_eq!(circuit.subcircuits().len(), 1);
+    }
+
+    #[test]
+    fn test_ac_coupled_opamp_input_needs_bias() {
+        // Non-inverting amp, AC-coupled into the + input
+        let amp = |bias: &str| {
+            let src = format!(
+                ".input in\n.output out\nV_IN in 0 AC\nC1 in p 100n\n{}OP1 out p n\nR1 out n 10k\nR2 n 0 1k\n",
+                bias
+            );
+            Circuit::from_ast(dsl::parse(&src).unwrap()).unwrap()
+        };
+
+        let mut floating = amp("");
+        let p = floating.find_node("p").unwrap();
+        assert_eq!(
+            floating.floating_opamp_inputs(),
+            vec![("OP1".to_string(), p)]
+        );
+        assert!(amp("R_B p 0 1M\n").floating_opamp_inputs().is_empty());
+        assert_eq!(
+            floating.floating_opamp_warnings(),
+            ["input 'p' of op-amp OP1 has no DC path to ground; add a bias resistor (e.g. R_BIAS_p p 0 1M)"]
+        );
+
+        let added = floating.add_opamp_bias_resistors(1e6).unwrap();
+        assert_eq!(added, vec!["R_BIAS_p".to_string()]);
+        assert!(floating.floating_opamp_inputs().is_empty());
+        assert!(floating.add_opamp_bias_resistors(1e6).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_fast_time_constant_recommends_oversampling() {
+        let ladder = |r1: &str| {
+            let src = format!(
+                ".input in\n.output out\nV_IN in 0 AC\nR1 in a {}\nC1 a 0 1n\nR2 a out 100k\nC2 out 0 10n\n",
+                r1
+            );
+            Circuit::from_ast(dsl::parse(&src).unwrap()).unwrap()
+        };
+
+        // 1k into 1n is a 1us time constant, a pole at 159 kHz
+        let fast = ladder("1k");
+        let tau = fast.fastest_time_constant().unwrap();
+        assert!((tau / 1e-6 - 1.0).abs() < 1e-3, "tau {}", tau);
+        assert!(fast.recommended_sample_rate().unwrap() > 10.0 * 48000.0);
+        assert_eq!(fast.oversampling_factor(48000.0), 16);
+
+        // 100k into 1n is 100us, comfortably inside 48 kHz
+        let slow = ladder("100k");
+        assert!(slow.recommended_sample_rate().unwrap() < 48000.0);
+        assert_eq!(slow.oversampling_factor(48000.0), 1);
+
+        let resistive = ".input in\n.output out\nV_IN in 0 AC\nR1 in out 1k\nR2 out 0 1k\n";
+        let resistive = Circuit::from_ast(dsl::parse(resistive).unwrap()).unwrap();
+        assert_eq!(resistive.fastest_time_constant(), None);
+    }
+
+    #[test]
+    fn test_builder_matches_parsed_circuit() {
+        let src = ".input in\n.output out\nV_IN in 0 AC\nR1 in out 1k\nC1 out 0 100n\nD1 out 0\n";
+        let parsed = Circuit::from_ast(dsl::parse(src).unwrap()).unwrap();
+
+        let mut builder = CircuitBuilder::new();
+        let input = builder.add_node("in");
+        let output = builder.add_node("out");
+        let gnd = builder.add_node("GND");
+        builder
+            .input_source("V_IN", input, gnd)
+            .resistor("R1", input, output, 1e3)
+            .capacitor("C1", output, gnd, 100e-9)
+            .diode("D1", output, gnd, DiodeParams::germanium())
+            .set_input(input)
+            .set_output(output);
+        let built = builder.build().unwrap();
+
+        assert_eq!(built.matrix_size(), parsed.matrix_size());
+        assert_eq!(built.node_map, parsed.node_map);
+        assert_eq!(built.node_names, parsed.node_names);
+        assert_eq!(built.input_source_idx, parsed.input_source_idx);
+        assert_eq!((built.input_node, built.output_node), (input, output));
+        let Some(Component::Diode(d)) = built.components.last() else {
+            panic!("D1 missing");
+        };
+        assert_eq!(d.params.is, DiodeParams::germanium().is);
+
+        // Saved as DSL, the builder's circuit reads back the same
+        let saved = Circuit::from_ast(dsl::parse(&builder.to_dsl()).unwrap()).unwrap();
+        assert_eq!(saved.node_names, built.node_names);
+        let Some(Component::Diode(d)) = saved.components.last() else {
+            panic!("D1 missing");
+        };
+        assert_eq!(
+            (d.params.is, d.params.n),
+            (DiodeParams::germanium().is, DiodeParams::germanium().n)
+        );
+
+        let mut stray = CircuitBuilder::new();
+        stray.add_node("in");
+        stray.set_input(NodeId(1)).set_output(NodeId(5));
+        assert!(matches!(
+            stray.build(),
+            Err(PedalerError::NodeNotFound { .. })
+        ));
+    }
 }
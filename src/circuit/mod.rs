@@ -4,10 +4,11 @@
 //! The [`Circuit`] struct holds all components, nodes, and their connections
 //! in a form suitable for simulation.
 
+pub mod blocks;
 mod graph;
 mod types;
 mod validate;
 
-pub use graph::{Circuit, DelayDef, LfoDef, ReverbDef};
+pub use graph::{Circuit, CircuitBuilder, DelayDef, EnvelopeDef, LfoDef, ReverbDef, WidenDef};
 pub use types::*;
 pub use validate::validate_circuit;
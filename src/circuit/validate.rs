@@ -7,18 +7,20 @@ use super::Circuit;
 /// Validate a circuit for simulation.
 ///
 /// Checks:
+/// - Each input node is driven by a voltage source of its own
 /// - All nodes have a path to ground
+/// - No delay or reverb drives its own input node
 /// - No duplicate component names
 /// - Component parameters are valid
 pub fn validate_circuit(circuit: &Circuit) -> Result<()> {
     // Check that input and output nodes exist and are not ground
-    if circuit.input_node.is_ground() {
+    if circuit.input_nodes.iter().any(|node| node.is_ground()) {
         return Err(PedalerError::InvalidTopology {
             message: "Input node cannot be ground".to_string(),
         });
     }
 
-    if circuit.output_node.is_ground() {
+    if circuit.output_nodes.iter().any(|node| node.is_ground()) {
         return Err(PedalerError::InvalidTopology {
             message: "Output node cannot be ground".to_string(),
         });
@@ -31,10 +33,156 @@ pub fn validate_circuit(circuit: &Circuit) -> Result<()> {
         });
     }
 
+    // Without a source on an input node, its audio would be silently
+    // discarded
+    for (channel, source) in circuit.input_sources.iter().enumerate() {
+        let node = circuit.node_name(circuit.input_nodes[channel]);
+        let source = source.ok_or_else(|| PedalerError::UndrivenInput {
+            node: node.to_string(),
+        })?;
+        // One source can only carry one channel
+        if circuit.input_sources[..channel].contains(&Some(source)) {
+            return Err(PedalerError::InvalidTopology {
+                message: format!("input {} shares its source with another input", node),
+            });
+        }
+    }
+
+    // A node with no path to ground leaves the matrix singular, which would
+    // only surface as a SingularMatrix error at solve time
+    if let Some(&node) = circuit.floating_nodes().first() {
+        return Err(PedalerError::FloatingNode {
+            node: circuit.node_name(node).to_string(),
+        });
+    }
+
+    // A delay or reverb drives its output relative to its input, so one
+    // whose output is its input would hold zero volts at its processed signal
+    let effects = circuit
+        .delay_defs
+        .iter()
+        .map(|d| (&d.name, d.input_node, d.output_node))
+        .chain(
+            circuit
+                .reverb_defs
+                .iter()
+                .map(|r| (&r.name, r.input_node, r.output_node)),
+        );
+    for (name, input, output) in effects {
+        if input == output {
+            return Err(PedalerError::InvalidTopology {
+                message: format!(
+                    "{} reads and drives the same node {}; give its output a node of its own",
+                    name,
+                    circuit.node_name(input)
+                ),
+            });
+        }
+    }
+
+    // Parallel sources fix the same voltage twice, which is just as singular
+    if let Some(names) = circuit.voltage_source_loop() {
+        return Err(PedalerError::InvalidTopology {
+            message: format!(
+                "voltage sources and inductors form a loop: {}",
+                names.join(", ")
+            ),
+        });
+    }
+
     // TODO: More sophisticated connectivity checks
-    // - Check for floating nodes (no DC path to ground)
-    // - Check for voltage source loops
     // - Check for current source cutsets
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl;
+
+    fn build(src: &str) -> Circuit {
+        Circuit::from_ast(dsl::parse(src).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_undriven_input_is_rejected() {
+        let circuit = build(".input in\n.output out\nR1 in out 10k\nR2 out 0 10k\n");
+        let err = validate_circuit(&circuit).unwrap_err();
+        assert!(matches!(err, PedalerError::UndrivenInput { ref node } if node == "in"));
+        assert!(err.to_string().contains("V_IN in 0 AC"));
+    }
+
+    #[test]
+    fn test_driven_input_is_accepted() {
+        let circuit = build(".input in\n.output out\nV_IN in 0 AC\nR1 in out 10k\nR2 out 0 10k\n");
+        assert!(validate_circuit(&circuit).is_ok());
+    }
+
+    #[test]
+    fn test_floating_node_is_rejected() {
+        // R3 hangs between two nodes that reach nothing else
+        let circuit =
+            build(".input in\n.output out\nV_IN in 0 AC\nR1 in out 10k\nR2 out 0 10k\nR3 a b 1k\n");
+        let err = validate_circuit(&circuit).unwrap_err();
+        assert!(matches!(err, PedalerError::FloatingNode { ref node } if node == "a"));
+    }
+
+    #[test]
+    fn test_parallel_voltage_sources_are_rejected() {
+        let circuit = build(
+            ".input in\n.output out\nV_IN in 0 AC\nV1 in 0 DC 9\nR1 in out 10k\nR2 out 0 10k\n",
+        );
+        let err = validate_circuit(&circuit).unwrap_err();
+        assert!(matches!(err, PedalerError::InvalidTopology { .. }));
+        assert!(err.to_string().contains("form a loop: V1, V_IN"), "{}", err);
+
+        // One source, and an inductor hanging off it, is fine
+        let circuit = build(".input in\n.output out\nV_IN in 0 AC\nL1 in out 10m\nR2 out 0 10k\n");
+        assert!(validate_circuit(&circuit).is_ok());
+    }
+
+    #[test]
+    fn test_cap_coupled_node_is_accepted() {
+        // The midpoint of a capacitive divider has no resistive path to
+        // ground, but its implicit leakage still solves it at DC
+        let src = ".input in\n.output out\nV_IN in 0 AC\nC1 in out 100n\nC2 out 0 100n\n";
+        let circuit = build(src);
+        assert!(validate_circuit(&circuit).is_ok());
+
+        let mut sim = crate::Simulator::new(circuit, 48000.0);
+        let out = sim.dc_sweep(&[1.0]).unwrap();
+        assert!(out[0].abs() < 1e-6, "output at {}", out[0]);
+    }
+
+    #[test]
+    fn test_effect_driving_its_own_input_is_rejected() {
+        let src = ".input in\n.output buf\nV_IN in 0 AC\nR1 in buf 1k\nR2 buf 0 10k\n";
+        for effect in ["DELAY d1 buf buf 300m", "REVERB rv1 buf buf"] {
+            let circuit = build(&format!("{}{}\n", src, effect));
+            let err = validate_circuit(&circuit).unwrap_err();
+            assert!(matches!(err, PedalerError::InvalidTopology { .. }));
+            assert!(err.to_string().contains("same node buf"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_effect_examples_run() {
+        let sources = [
+            include_str!("../../examples/circuits/slapback.ped"),
+            include_str!("../../examples/circuits/reverb.ped"),
+        ];
+        let input: Vec<f32> = (0..256)
+            .map(|i| 0.3 * (2.0 * std::f32::consts::PI * 220.0 * i as f32 / 48000.0).sin())
+            .collect();
+        for src in sources {
+            let circuit = build(src);
+            assert!(validate_circuit(&circuit).is_ok());
+
+            let mut sim = crate::Simulator::new(circuit, 48000.0);
+            let mut output = vec![0.0f32; input.len()];
+            sim.process_block(&input, &mut output).unwrap();
+            assert!(output.iter().any(|v| v.abs() > 1e-3));
+        }
+    }
+}
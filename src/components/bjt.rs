@@ -2,11 +2,20 @@
 //!
 //! Uses a simplified Ebers-Moll model for NPN and PNP transistors.
 //! The BJT is modeled as two diodes with a current-controlled current source.
+//!
+//! An optional self-heating model tracks the junction temperature from the
+//! dissipated power through a first-order thermal network (Rth, Cth). The
+//! junction temperature feeds back into the thermal voltage and saturation
+//! current, reproducing the bias drift of germanium fuzz circuits.
 
 use crate::circuit::{ComponentId, NodeId};
 use crate::dsl::{ModelDef, ModelType};
 use crate::error::{PedalerError, Result};
-use crate::THERMAL_VOLTAGE;
+use crate::{NOMINAL_TEMPERATURE, THERMAL_VOLTAGE};
+
+/// Junction voltage past which the diode currents are extrapolated linearly
+/// to prevent overflow.
+const V_CRIT: f64 = 0.8;
 
 /// BJT type (NPN or PNP).
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -30,6 +39,16 @@ pub struct BjtParams {
     pub n: f64,
     /// Early voltage (for output resistance), 0 = infinite
     pub va: f64,
+    /// Bandgap energy in eV (1.11 for silicon, 0.67 for germanium)
+    pub eg: f64,
+    /// Saturation current temperature exponent
+    pub xti: f64,
+    /// Enable the self-heating (junction temperature) model
+    pub self_heating: bool,
+    /// Junction-to-ambient thermal resistance (K/W)
+    pub rth: f64,
+    /// Junction thermal capacitance (J/K)
+    pub cth: f64,
 }
 
 impl Default for BjtParams {
@@ -41,12 +60,20 @@ impl Default for BjtParams {
             is_bc: 1e-14,
             n: 1.0,
             va: 100.0,
+            eg: 1.11,
+            xti: 3.0,
+            self_heating: false,
+            rth: 300.0,
+            cth: 5e-3,
         }
     }
 }
 
 impl BjtParams {
     /// Create parameters from a model definition.
+    ///
+    /// Returns `InvalidParameter` for out-of-range values, e.g. a
+    /// non-positive `bf`, `is` or `n`, or a negative `va`.
     pub fn from_model(model: &ModelDef) -> Result<(BjtType, Self)> {
         let bjt_type = match model.model_type {
             ModelType::BjtNpn => BjtType::Npn,
@@ -62,22 +89,37 @@ impl BjtParams {
 
         let mut params = Self::default();
 
-        if let Some(&bf) = model.params.get("bf") {
+        if let Some(bf) = super::positive_model_param(model, "bf")? {
             params.beta_f = bf;
         }
-        if let Some(&br) = model.params.get("br") {
+        if let Some(br) = super::positive_model_param(model, "br")? {
             params.beta_r = br;
         }
-        if let Some(&is) = model.params.get("is") {
+        if let Some(is) = super::positive_model_param(model, "is")? {
             params.is_be = is;
             params.is_bc = is;
         }
-        if let Some(&n) = model.params.get("n") {
+        if let Some(n) = super::positive_model_param(model, "n")? {
             params.n = n;
         }
-        if let Some(&va) = model.params.get("va") {
+        if let Some(va) = super::non_negative_model_param(model, "va")? {
             params.va = va;
         }
+        if let Some(eg) = super::positive_model_param(model, "eg")? {
+            params.eg = eg;
+        }
+        if let Some(&xti) = model.params.get("xti") {
+            params.xti = xti;
+        }
+        if let Some(&sh) = model.params.get("selfheat") {
+            params.self_heating = sh > 0.5;
+        }
+        if let Some(rth) = super::positive_model_param(model, "rth")? {
+            params.rth = rth;
+        }
+        if let Some(cth) = super::positive_model_param(model, "cth")? {
+            params.cth = cth;
+        }
 
         Ok((bjt_type, params))
     }
@@ -86,6 +128,11 @@ impl BjtParams {
     pub fn n_vt(&self) -> f64 {
         self.n * THERMAL_VOLTAGE
     }
+
+    /// Thermal time constant of the self-heating model (seconds).
+    pub fn thermal_time_constant(&self) -> f64 {
+        self.rth * self.cth
+    }
 }
 
 /// A BJT component.
@@ -100,6 +147,18 @@ pub struct Bjt {
     pub v_be_op: f64,
     /// Current base-collector voltage operating point
     pub v_bc_op: f64,
+    /// Base-emitter voltage to start from (initial condition)
+    pub initial_v_be_op: f64,
+    /// Base-collector voltage to start from (initial condition)
+    pub initial_v_bc_op: f64,
+    /// Ambient temperature (K)
+    pub ambient_temp: f64,
+    /// Junction temperature (K)
+    junction_temp: f64,
+    /// Thermal voltage at the junction temperature
+    vt: f64,
+    /// Saturation current scale factor Is(T) / Is(T_nom)
+    is_scale: f64,
 }
 
 impl Bjt {
@@ -119,6 +178,12 @@ impl Bjt {
             params,
             v_be_op: 0.0,
             v_bc_op: 0.0,
+            initial_v_be_op: 0.0,
+            initial_v_bc_op: 0.0,
+            ambient_temp: NOMINAL_TEMPERATURE,
+            junction_temp: NOMINAL_TEMPERATURE,
+            vt: THERMAL_VOLTAGE,
+            is_scale: 1.0,
         }
     }
 
@@ -137,40 +202,98 @@ impl Bjt {
         self.nodes[2]
     }
 
+    /// Thermal voltage times ideality factor at the junction temperature.
+    pub fn n_vt(&self) -> f64 {
+        self.params.n * self.vt
+    }
+
+    /// Base-emitter saturation current at the junction temperature.
+    pub fn is_be(&self) -> f64 {
+        self.params.is_be * self.is_scale
+    }
+
+    /// Base-collector saturation current at the junction temperature.
+    pub fn is_bc(&self) -> f64 {
+        self.params.is_bc * self.is_scale
+    }
+
+    /// Get the junction temperature (K).
+    pub fn junction_temperature(&self) -> f64 {
+        self.junction_temp
+    }
+
+    /// Set the junction temperature (K), updating Vt and Is accordingly.
+    ///
+    /// Uses the SPICE saturation current temperature law:
+    ///   Is(T) = Is * (T/Tnom)^XTI * exp(Eg/Vt(T) * (T/Tnom - 1))
+    pub fn set_junction_temperature(&mut self, temp: f64) {
+        self.junction_temp = temp;
+        self.vt = super::thermal_voltage(temp);
+        self.is_scale = super::saturation_current_scale(temp, self.params.eg, self.params.xti);
+    }
+
+    /// Set the ambient temperature (K), and start the junction there.
+    pub fn set_ambient_temperature(&mut self, temp: f64) {
+        self.ambient_temp = temp;
+        self.set_junction_temperature(temp);
+    }
+
+    /// Power dissipated in the device at the given junction voltages.
+    pub fn power(&self, v_be: f64, v_bc: f64) -> f64 {
+        let v_ce = v_be - v_bc;
+        self.i_c(v_be, v_bc) * v_ce + self.i_b(v_be, v_bc) * v_be
+    }
+
+    /// Advance the self-heating model by one time step.
+    ///
+    /// The junction temperature relaxes towards `T_amb + P * Rth` with the
+    /// thermal time constant `Rth * Cth`. Does nothing if self-heating is off.
+    pub fn update_thermal(&mut self, v_be: f64, v_bc: f64, dt: f64) {
+        if !self.params.self_heating {
+            return;
+        }
+        let p = self.power(v_be, v_bc).max(0.0);
+        let t_target = self.ambient_temp + p * self.params.rth;
+        let tau = self.params.thermal_time_constant().max(dt);
+        let alpha = 1.0 - (-dt / tau).exp();
+        let temp = self.junction_temp + (t_target - self.junction_temp) * alpha;
+        self.set_junction_temperature(temp);
+    }
+
     /// Calculate the base-emitter diode current.
     pub fn i_be(&self, v_be: f64) -> f64 {
-        let n_vt = self.params.n_vt();
+        let n_vt = self.n_vt();
+        let is_be = self.is_be();
         let v = match self.bjt_type {
             BjtType::Npn => v_be,
             BjtType::Pnp => -v_be,
         };
 
-        if v > 0.8 {
+        if v > V_CRIT {
             // Linear extrapolation to prevent overflow
-            let v_crit = 0.8;
-            let i_crit = self.params.is_be * ((v_crit / n_vt).exp() - 1.0);
-            let g_crit = self.params.is_be / n_vt * (v_crit / n_vt).exp();
-            i_crit + g_crit * (v - v_crit)
+            let i_crit = is_be * ((V_CRIT / n_vt).exp() - 1.0);
+            let g_crit = is_be / n_vt * (V_CRIT / n_vt).exp();
+            i_crit + g_crit * (v - V_CRIT)
         } else {
-            self.params.is_be * ((v / n_vt).exp() - 1.0)
+            is_be * ((v / n_vt).exp() - 1.0)
         }
     }
 
     /// Calculate the base-collector diode current.
     pub fn i_bc(&self, v_bc: f64) -> f64 {
-        let n_vt = self.params.n_vt();
+        let n_vt = self.n_vt();
+        let is_bc = self.is_bc();
         let v = match self.bjt_type {
             BjtType::Npn => v_bc,
             BjtType::Pnp => -v_bc,
         };
 
-        if v > 0.8 {
-            let v_crit = 0.8;
-            let i_crit = self.params.is_bc * ((v_crit / n_vt).exp() - 1.0);
-            let g_crit = self.params.is_bc / n_vt * (v_crit / n_vt).exp();
-            i_crit + g_crit * (v - v_crit)
+        if v > V_CRIT {
+            let i_crit = is_bc * ((V_CRIT / n_vt).exp() - 1.0);
+            let g_crit = is_bc / n_vt * (V_CRIT / n_vt).exp();
+            i_crit + g_crit * (v - V_CRIT)
         } else {
-            self.params.is_bc * ((v / n_vt).exp() - 1.0)
+            is_bc * ((v / n_vt).exp() - 1.0)
         }
     }
 
@@ -207,15 +330,17 @@ impl Bjt {
     /// Returns (gm, go, gpi, gmu) - transconductance, output conductance,
     /// input conductance, feedback conductance.
     pub fn linearize(&self, v_be: f64, v_bc: f64) -> (f64, f64, f64, f64) {
-        let n_vt = self.params.n_vt();
+        let n_vt = self.n_vt();
 
         // dI_be/dV_be
         let v_be_eff = match self.bjt_type {
             BjtType::Npn => v_be,
             BjtType::Pnp => -v_be,
         };
+        // Past V_CRIT the current is extrapolated linearly, and so is its
+        // slope held
         let g_be = if v_be_eff > 0.0 {
-            (self.params.is_be / n_vt * (v_be_eff / n_vt).exp()).min(1.0)
+            self.is_be() / n_vt * (v_be_eff.min(V_CRIT) / n_vt).exp()
         } else {
             1e-12
         };
@@ -226,7 +351,7 @@ impl Bjt {
             BjtType::Pnp => -v_bc,
         };
         let g_bc = if v_bc_eff > 0.0 {
-            (self.params.is_bc / n_vt * (v_bc_eff / n_vt).exp()).min(1.0)
+            self.is_bc() / n_vt * (v_bc_eff.min(V_CRIT) / n_vt).exp()
         } else {
             1e-12
         };
@@ -250,9 +375,168 @@ impl Bjt {
         (gm.max(1e-12), go.max(1e-12), gpi.max(1e-12), gmu.max(1e-12))
     }
 
+    /// Companion current sources `(i_c_eq, i_b_eq)` for the conductances of
+    /// [`linearize`](Self::linearize).
+    ///
+    /// With `gpi` stamped B-E, `gmu` B-C, `go` C-E and `gm` as a VCCS from
+    /// B-E into the collector, a source of `i_c_eq` from collector to
+    /// emitter and one of `i_b_eq` from base to emitter make the linear
+    /// model carry exactly the Ebers-Moll `Ic` and `Ib` at `(v_be, v_bc)`.
+    pub fn companion_currents(&self, v_be: f64, v_bc: f64) -> (f64, f64) {
+        let (gm, go, gpi, gmu) = self.linearize(v_be, v_bc);
+        let v_ce = v_be - v_bc;
+        // The B-C conductance carries gmu * Vbc out of the collector, and
+        // the base current returns through the emitter
+        let i_c_eq = self.i_c(v_be, v_bc) - gm * v_be - go * v_ce + gmu * v_bc;
+        let i_b_eq = self.i_b(v_be, v_bc) - gpi * v_be - gmu * v_bc;
+        (i_c_eq, i_b_eq)
+    }
+
     /// Update operating points.
     pub fn update_operating_point(&mut self, v_be: f64, v_bc: f64) {
         self.v_be_op = v_be;
         self.v_bc_op = v_bc;
     }
+
+    /// Return to the initial operating point. The junction temperature is
+    /// left as it is.
+    pub fn reset_to_initial_conditions(&mut self) {
+        self.v_be_op = self.initial_v_be_op;
+        self.v_bc_op = self.initial_v_bc_op;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn power_transistor(self_heating: bool) -> Bjt {
+        let params = BjtParams {
+            self_heating,
+            rth: 200.0,
+            cth: 1e-3, // 0.2 s thermal time constant
+            ..Default::default()
+        };
+        Bjt::new(
+            ComponentId(0),
+            "Q1".to_string(),
+            [NodeId(1), NodeId(2), NodeId(0)],
+            BjtType::Npn,
+            params,
+        )
+    }
+
+    #[test]
+    fn test_self_heating_drifts_bias() {
+        let mut q = power_transistor(true);
+        let (v_be, v_bc) = (0.65, -8.0); // ~9V across C-E, tens of mA
+        let dt = 1.0 / 48000.0;
+        let i_start = q.i_c(v_be, v_bc);
+
+        for _ in 0..24000 {
+            q.update_thermal(v_be, v_bc, dt);
+        }
+
+        assert!(q.junction_temperature() > NOMINAL_TEMPERATURE + 1.0);
+        assert!(
+            q.i_c(v_be, v_bc) > i_start * 1.1,
+            "collector current should drift upwards"
+        );
+    }
+
+    #[test]
+    fn test_self_heating_off_keeps_bias() {
+        let mut q = power_transistor(false);
+        let (v_be, v_bc) = (0.65, -8.0);
+        let dt = 1.0 / 48000.0;
+        let i_start = q.i_c(v_be, v_bc);
+
+        for _ in 0..24000 {
+            q.update_thermal(v_be, v_bc, dt);
+        }
+
+        assert_eq!(q.junction_temperature(), NOMINAL_TEMPERATURE);
+        assert_eq!(q.i_c(v_be, v_bc), i_start);
+    }
+
+    /// Collector and base currents of the linear companion model at
+    /// `(v_be, v_bc)`, linearized at `(v_be0, v_bc0)`.
+    fn companion_model(
+        q: &Bjt,
+        (v_be0, v_bc0): (f64, f64),
+        (v_be, v_bc): (f64, f64),
+    ) -> (f64, f64) {
+        let (gm, go, gpi, gmu) = q.linearize(v_be0, v_bc0);
+        let (i_c_eq, i_b_eq) = q.companion_currents(v_be0, v_bc0);
+        let i_c = gm * v_be + go * (v_be - v_bc) - gmu * v_bc + i_c_eq;
+        let i_b = gpi * v_be + gmu * v_bc + i_b_eq;
+        (i_c, i_b)
+    }
+
+    #[test]
+    fn test_companion_model_matches_ebers_moll() {
+        let npn = power_transistor(false);
+        let pnp = Bjt::new(
+            ComponentId(1),
+            "Q2".to_string(),
+            [NodeId(1), NodeId(2), NodeId(0)],
+            BjtType::Pnp,
+            BjtParams::default(),
+        );
+        // Forward active, saturated, cut off and past V_CRIT
+        for (q, sign) in [(&npn, 1.0), (&pnp, -1.0)] {
+            for (v_be, v_bc) in [(0.65, -5.0), (0.7, 0.6), (-0.5, -9.0), (0.9, -2.0)] {
+                let op = (sign * v_be, sign * v_bc);
+                // KCL: the linear model carries the device's currents at
+                // its operating point
+                let (i_c, i_b) = companion_model(q, op, op);
+                let (e_c, e_b) = (q.i_c(op.0, op.1), q.i_b(op.0, op.1));
+                assert!(
+                    (i_c - e_c).abs() <= 1e-9 * e_c.abs().max(1e-9),
+                    "Ic {} vs {}",
+                    i_c,
+                    e_c
+                );
+                assert!(
+                    (i_b - e_b).abs() <= 1e-9 * e_b.abs().max(1e-9),
+                    "Ib {} vs {}",
+                    i_b,
+                    e_b
+                );
+            }
+
+            // Away from saturation it is the tangent: a small step in Vbe
+            // moves both currents as Ebers-Moll does
+            for v_be in [0.6, 0.7, 0.9] {
+                let op = (sign * v_be, sign * -5.0);
+                let h = sign * 1e-6;
+                let (i_c, i_b) = companion_model(q, op, (op.0 + h, op.1));
+                let (e_c, e_b) = (q.i_c(op.0 + h, op.1), q.i_b(op.0 + h, op.1));
+                assert!(
+                    (i_c - e_c).abs() <= 1e-6 * e_c.abs(),
+                    "Ic {} vs {} at {}",
+                    i_c,
+                    e_c,
+                    v_be
+                );
+                assert!(
+                    (i_b - e_b).abs() <= 1e-6 * e_b.abs(),
+                    "Ib {} vs {} at {}",
+                    i_b,
+                    e_b,
+                    v_be
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_model_params_rejected() {
+        let ast = crate::dsl::parse(".model QBAD NPN (bf=0 is=1e-14)").unwrap();
+        let err = BjtParams::from_model(&ast.models["QBAD"]).unwrap_err();
+        assert!(matches!(
+            err,
+            PedalerError::InvalidParameter { ref param, .. } if param == "bf"
+        ));
+    }
 }
@@ -2,13 +2,63 @@
 
 use crate::circuit::{ComponentId, NodeId};
 
+/// Modulation configuration for a potentiometer wiper.
+#[derive(Debug, Clone)]
+pub struct PotModulation {
+    /// Name of the control source (LFO or envelope follower)
+    pub source: String,
+    /// Modulation depth - how far a full-scale control moves the wiper
+    pub depth: f64,
+}
+
+/// Potentiometer taper: how the resistance from n1 to the wiper follows
+/// the knob position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PotTaper {
+    /// Resistance proportional to position (B taper)
+    #[default]
+    Linear,
+    /// Audio taper (A): 10% of the resistance at mid-travel
+    Log,
+    /// Reverse audio taper (C): 90% of the resistance at mid-travel
+    ReverseLog,
+}
+
+impl PotTaper {
+    /// Parse a taper from its name: `linear` (`lin`), `log` (`audio`) or
+    /// `reverselog` (`revlog`).
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "linear" | "lin" => Some(Self::Linear),
+            "log" | "audio" => Some(Self::Log),
+            "reverselog" | "revlog" => Some(Self::ReverseLog),
+            _ => None,
+        }
+    }
+
+    /// Fraction of the total resistance between n1 and the wiper at
+    /// `position`.
+    ///
+    /// The log taper is the exponential `(81^p - 1) / 80`, which passes
+    /// through 10% at mid-travel like common audio-taper pots.
+    pub fn fraction(&self, position: f64) -> f64 {
+        let log = |p: f64| (81f64.powf(p) - 1.0) / 80.0;
+        match self {
+            Self::Linear => position,
+            Self::Log => log(position),
+            Self::ReverseLog => 1.0 - log(1.0 - position),
+        }
+    }
+}
+
 /// A potentiometer component.
 ///
 /// Modeled as two resistors in series with a wiper tap:
 ///   n1 ----[R1]---- wiper ----[R2]---- n2
 ///
-/// where R1 = position * total_resistance
-/// and   R2 = (1 - position) * total_resistance
+/// where R1 = taper(position) * total_resistance
+/// and   R2 = (1 - taper(position)) * total_resistance
 #[derive(Debug, Clone)]
 pub struct Potentiometer {
     pub id: ComponentId,
@@ -17,6 +67,12 @@ pub struct Potentiometer {
     pub total_resistance: f64,
     /// Position from 0.0 to 1.0
     pub position: f64,
+    /// Knob position set by the user (before modulation)
+    pub base_position: f64,
+    /// Optional wiper modulation
+    pub modulation: Option<PotModulation>,
+    /// Taper of the resistance along the travel
+    pub taper: PotTaper,
 }
 
 impl Potentiometer {
@@ -28,23 +84,54 @@ impl Potentiometer {
         total_resistance: f64,
         position: f64,
     ) -> Self {
+        let position = position.clamp(0.001, 0.999); // Avoid zero resistance
         Self {
             id,
             name,
             nodes,
             total_resistance: total_resistance.max(1.0), // Minimum 1 ohm
-            position: position.clamp(0.001, 0.999), // Avoid zero resistance
+            position,
+            base_position: position,
+            modulation: None,
+            taper: PotTaper::Linear,
+        }
+    }
+
+    /// Set the taper.
+    pub fn with_taper(mut self, taper: PotTaper) -> Self {
+        self.taper = taper;
+        self
+    }
+
+    /// Attach a control source that moves the wiper.
+    pub fn set_modulation(&mut self, source: String, depth: f64) {
+        self.modulation = Some(PotModulation { source, depth });
+    }
+
+    /// Check if this pot is modulated.
+    pub fn is_modulated(&self) -> bool {
+        self.modulation.is_some()
+    }
+
+    /// Update the wiper position from a control signal.
+    ///
+    /// # Arguments
+    /// * `mod_value` - The control value (0.0 to 1.0)
+    pub fn update_modulation(&mut self, mod_value: f64) {
+        if let Some(ref modulation) = self.modulation {
+            // position = base + depth * control, clamped to the valid range
+            self.position = (self.base_position + modulation.depth * mod_value).clamp(0.001, 0.999);
         }
     }
 
     /// Get the resistance from n1 to wiper.
     pub fn r1(&self) -> f64 {
-        (self.position * self.total_resistance).max(0.1)
+        (self.taper.fraction(self.position) * self.total_resistance).max(0.1)
     }
 
     /// Get the resistance from wiper to n2.
     pub fn r2(&self) -> f64 {
-        ((1.0 - self.position) * self.total_resistance).max(0.1)
+        ((1.0 - self.taper.fraction(self.position)) * self.total_resistance).max(0.1)
     }
 
     /// Get the conductance from n1 to wiper.
@@ -60,6 +147,7 @@ impl Potentiometer {
     /// Set the wiper position.
     pub fn set_position(&mut self, position: f64) {
         self.position = position.clamp(0.001, 0.999);
+        self.base_position = self.position;
     }
 
     /// Get node n1.
@@ -78,16 +166,29 @@ impl Potentiometer {
     }
 }
 
+/// Contact arrangement of a switch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwitchPoles {
+    /// Single throw: `n1` and `n2`, connected or not
+    Spst,
+    /// Double throw: `common` connected to `t1` or to `t2`. A DPDT
+    /// footswitch is two of these set together.
+    Spdt,
+}
+
 /// A switch component.
 ///
-/// Modeled as a resistance:
+/// Each contact is modeled as a resistance:
 /// - Closed: very small resistance (0.01 ohms)
 /// - Open: very large resistance (1e9 ohms)
+///
+/// A double-throw switch has one contact from `common` to each throw,
+/// with complementary states: open selects `t1`, closed selects `t2`.
 #[derive(Debug, Clone)]
 pub struct Switch {
     pub id: ComponentId,
     pub name: String,
-    pub nodes: [NodeId; 2],
+    pub nodes: Vec<NodeId>, // [n1, n2] or [common, t1, t2]
     pub closed: bool,
 }
 
@@ -97,8 +198,9 @@ impl Switch {
     /// Resistance when open.
     pub const R_OPEN: f64 = 1e9;
 
-    /// Create a new switch.
-    pub fn new(id: ComponentId, name: String, nodes: [NodeId; 2], closed: bool) -> Self {
+    /// Create a new switch: single throw for two nodes, double throw for
+    /// three.
+    pub fn new(id: ComponentId, name: String, nodes: Vec<NodeId>, closed: bool) -> Self {
         Self {
             id,
             name,
@@ -107,6 +209,15 @@ impl Switch {
         }
     }
 
+    /// Contact arrangement, from the number of terminals.
+    pub fn poles(&self) -> SwitchPoles {
+        if self.nodes.len() == 3 {
+            SwitchPoles::Spdt
+        } else {
+            SwitchPoles::Spst
+        }
+    }
+
     /// Get the current resistance.
     pub fn resistance(&self) -> f64 {
         if self.closed {
@@ -121,6 +232,16 @@ impl Switch {
         1.0 / self.resistance()
     }
 
+    /// Conductances from `common` to `[t1, t2]` of a double-throw switch.
+    pub fn throw_conductances(&self) -> [f64; 2] {
+        let (on, off) = (1.0 / Self::R_CLOSED, 1.0 / Self::R_OPEN);
+        if self.closed {
+            [off, on]
+        } else {
+            [on, off]
+        }
+    }
+
     /// Set the switch state.
     pub fn set_state(&mut self, closed: bool) {
         self.closed = closed;
@@ -131,3 +252,31 @@ impl Switch {
         self.closed = !self.closed;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_taper_is_low_at_mid_travel() {
+        let pot = |taper| {
+            Potentiometer::new(
+                ComponentId(0),
+                "POT1".to_string(),
+                [NodeId(1), NodeId(2), NodeId(0)],
+                100e3,
+                0.5,
+            )
+            .with_taper(taper)
+        };
+        assert!((pot(PotTaper::Linear).r1() - 50e3).abs() < 1e-6);
+
+        let log = pot(PotTaper::Log);
+        let fraction = log.r1() / log.total_resistance;
+        assert!((0.10..=0.15).contains(&fraction), "{}", fraction);
+        assert!((log.r1() + log.r2() - 100e3).abs() < 1e-6);
+
+        let reverse = pot(PotTaper::ReverseLog);
+        assert!((reverse.r2() - log.r1()).abs() < 1e-6);
+    }
+}
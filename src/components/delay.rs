@@ -3,6 +3,10 @@
 //! A digital delay line stores samples in a ring buffer and outputs
 //! the delayed signal. This is the fundamental building block for
 //! delay, echo, chorus, flanger, and reverb effects.
+//!
+//! The delay may be a fractional number of samples: the output is linearly
+//! interpolated between the two neighbouring samples, so a swept delay
+//! glides instead of stepping from one sample to the next.
 
 use crate::circuit::NodeId;
 
@@ -19,8 +23,8 @@ pub struct DelayLine {
     buffer: Vec<f32>,
     /// Current write position in the buffer
     write_pos: usize,
-    /// Delay time in samples
-    delay_samples: usize,
+    /// Delay time in samples, at least one
+    delay_samples: f64,
     /// Dry/wet mix (0.0 = dry only, 1.0 = wet only, 0.5 = equal mix)
     mix: f32,
     /// Feedback amount (0.0 = no feedback, 0.5 = 50% feedback for echo)
@@ -47,29 +51,52 @@ impl DelayLine {
         mix: f32,
         feedback: f32,
     ) -> Self {
-        let delay_samples = ((delay_time * sample_rate as f64) as usize).max(1);
-        let buffer = vec![0.0; delay_samples];
-
-        Self {
+        let mut delay = Self {
             name,
             input_node,
             output_node,
-            buffer,
+            buffer: Vec::new(),
             write_pos: 0,
-            delay_samples,
+            delay_samples: 1.0,
             mix: mix.clamp(0.0, 1.0),
             feedback: feedback.clamp(0.0, 0.95), // Limit to prevent runaway
-        }
+        };
+        delay.set_delay_time(delay_time, sample_rate);
+        delay
     }
 
     /// Get the delay time in samples.
-    pub fn delay_samples(&self) -> usize {
+    pub fn delay_samples(&self) -> f64 {
         self.delay_samples
     }
 
     /// Get the delay time in seconds at the given sample rate.
     pub fn delay_time(&self, sample_rate: f32) -> f64 {
-        self.delay_samples as f64 / sample_rate as f64
+        self.delay_samples / sample_rate as f64
+    }
+
+    /// Change the delay time, e.g. to sweep a chorus or flanger.
+    ///
+    /// The delay is at least one sample. Shortening it keeps the buffer;
+    /// lengthening it past the buffer grows the buffer, keeping what it
+    /// holds, with silence beyond the oldest sample.
+    pub fn set_delay_time(&mut self, delay_time: f64, sample_rate: f32) {
+        let delay_samples = (delay_time * sample_rate as f64).max(1.0);
+        // Reading between samples needs the one before the delayed sample too
+        let len = delay_samples.ceil() as usize;
+        if len > self.buffer.len() {
+            // Oldest sample first, so the new space sits just behind it
+            self.buffer.rotate_left(self.write_pos);
+            self.write_pos = self.buffer.len();
+            self.buffer.resize(len, 0.0);
+            self.write_pos %= len;
+        }
+        self.delay_samples = delay_samples;
+    }
+
+    /// Set the dry/wet mix, clamped to 0.0-1.0.
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
     }
 
     /// Process one sample through the delay line.
@@ -80,14 +107,20 @@ impl DelayLine {
     /// # Returns
     /// Mixed output: dry * (1-mix) + wet * mix
     pub fn process(&mut self, input: f32) -> f32 {
-        // Read the delayed sample
-        let delayed = self.buffer[self.write_pos];
+        // Read the delayed sample, between the samples `whole` and
+        // `whole + 1` back from the one about to be written
+        let len = self.buffer.len();
+        let whole = self.delay_samples.floor();
+        let frac = (self.delay_samples - whole) as f32;
+        let newer = self.buffer[(self.write_pos + len - whole as usize) % len];
+        let older = self.buffer[(self.write_pos + 2 * len - whole as usize - 1) % len];
+        let delayed = newer + frac * (older - newer);
 
         // Write input + feedback to buffer
-        self.buffer[self.write_pos] = input + delayed * self.feedback;
+        self.buffer[self.write_pos] = super::flush_denormal(input + delayed * self.feedback);
 
         // Advance the write position
-        self.write_pos = (self.write_pos + 1) % self.delay_samples;
+        self.write_pos = (self.write_pos + 1) % len;
 
         // Mix dry and wet signals
         input * (1.0 - self.mix) + delayed * self.mix
@@ -117,7 +150,7 @@ mod tests {
             0.0,    // No feedback
         );
 
-        assert_eq!(delay.delay_samples(), 10);
+        assert_eq!(delay.delay_samples(), 10.0);
 
         // First 10 samples should output 0 (buffer is empty)
         for i in 0..10 {
@@ -132,6 +165,65 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_fractional_delay_interpolates() {
+        let mut delay = DelayLine::new(
+            "D1".to_string(),
+            NodeId(1),
+            NodeId(2),
+            0.0105,
+            1000.0,
+            1.0,
+            0.0,
+        );
+        assert!((delay.delay_samples() - 10.5).abs() < 1e-9);
+
+        // A ramp delayed by 10.5 samples lands halfway between samples
+        for n in 0..40 {
+            let out = delay.process(n as f32);
+            if n >= 11 {
+                assert!(
+                    (out - (n as f32 - 10.5)).abs() < 1e-4,
+                    "sample {}: {}",
+                    n,
+                    out
+                );
+            }
+        }
+
+        // Lengthened at runtime, the 11 samples held are kept, 20 back
+        delay.set_delay_time(0.02, 1000.0);
+        let out: Vec<f32> = (40..50).map(|n| delay.process(n as f32)).collect();
+        assert_eq!(out[0], 0.0);
+        assert_eq!(out[9], 29.0);
+    }
+
+    #[test]
+    fn test_feedback_tail_decays_to_exact_zero() {
+        // Interpolation smears the echoes, so the tail thins out slowly
+        let mut delay = DelayLine::new(
+            "D1".to_string(),
+            NodeId(1),
+            NodeId(2),
+            0.0105,
+            1000.0,
+            1.0,
+            0.9,
+        );
+        delay.process(1.0);
+
+        for i in 0..100_000 {
+            let out = delay.process(0.0);
+            assert!(!out.is_subnormal(), "subnormal output after {} samples", i);
+            assert!(
+                delay.buffer.iter().all(|v| !v.is_subnormal()),
+                "subnormal state after {} samples",
+                i
+            );
+        }
+        assert!(delay.buffer.iter().all(|&v| v == 0.0));
+    }
+
     #[test]
     fn test_delay_line_with_mix() {
         // Test dry/wet mixing
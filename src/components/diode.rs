@@ -7,10 +7,21 @@
 //!   I ≈ I0 + G_d * (V - V0)
 //!
 //! where G_d = dI/dV = Is/(n*Vt) * exp(V0/(n*Vt))
+//!
+//! A Zener diode (`vz > 0`) also conducts in reverse once the voltage drops
+//! below -Vz, with a current that grows exponentially with the depth of
+//! breakdown:
+//!   I_bd = -Ibv * (exp(-(V + Vz) / (n * Vt)) - 1)
+//!
+//! Vt and Is follow the device temperature, set from the simulator's
+//! ambient temperature.
+
+use std::f64::consts::SQRT_2;
 
 use crate::circuit::{ComponentId, NodeId};
 use crate::dsl::ModelDef;
-use crate::THERMAL_VOLTAGE;
+use crate::error::{PedalerError, Result};
+use crate::{NOMINAL_TEMPERATURE, THERMAL_VOLTAGE};
 
 /// Parameters for a diode model.
 #[derive(Debug, Clone)]
@@ -23,6 +34,14 @@ pub struct DiodeParams {
     pub vf: f64,
     /// Maximum voltage for exp() calculation to prevent overflow
     pub v_crit: f64,
+    /// Reverse breakdown (Zener) voltage, 0 = no breakdown
+    pub vz: f64,
+    /// Reverse current at the breakdown voltage (A)
+    pub ibv: f64,
+    /// Bandgap energy in eV (1.11 for silicon, 0.67 for germanium)
+    pub eg: f64,
+    /// Saturation current temperature exponent
+    pub xti: f64,
 }
 
 impl Default for DiodeParams {
@@ -32,6 +51,10 @@ impl Default for DiodeParams {
             n: 1.0,
             vf: 0.7,
             v_crit: 0.7, // About 40 * Vt
+            vz: 0.0,
+            ibv: 1e-3,
+            eg: 1.11,
+            xti: 3.0,
         }
     }
 }
@@ -44,6 +67,8 @@ impl DiodeParams {
             n: 1.5,
             vf: 0.3,
             v_crit: 0.5,
+            eg: 0.67,
+            ..Self::default()
         }
     }
 
@@ -54,26 +79,86 @@ impl DiodeParams {
             n: 2.0,
             vf: color_vf, // Red ~1.8V, Green ~2.2V, Blue ~3.3V
             v_crit: color_vf,
+            ..Self::default()
+        }
+    }
+
+    /// Built-in parameters by name, ignoring case: `SI` (or `SILICON`),
+    /// `GE` (or `GERMANIUM`) and `LED` (red).
+    pub fn preset(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "SI" | "SILICON" => Some(Self::default()),
+            "GE" | "GERMANIUM" => Some(Self::germanium()),
+            "LED" => Some(Self::led(1.8)),
+            _ => None,
         }
     }
 
     /// Create parameters from a model definition.
-    pub fn from_model(model: &ModelDef) -> Self {
+    ///
+    /// Returns `InvalidParameter` if the parameters fail
+    /// [`validate`](Self::validate).
+    pub fn from_model(model: &ModelDef) -> Result<Self> {
         let mut params = Self::default();
-        if let Some(&is) = model.params.get("is") {
+        let get = |param| model.params.get(param).copied();
+        if let Some(is) = get("is") {
             params.is = is;
         }
-        if let Some(&n) = model.params.get("n") {
+        if let Some(n) = get("n") {
             params.n = n;
         }
-        if let Some(&vf) = model.params.get("vf") {
+        if let Some(vf) = get("vf") {
             params.vf = vf;
             params.v_crit = vf;
         }
-        params
+        if let Some(vz) = get("vz") {
+            params.vz = vz;
+        }
+        if let Some(ibv) = get("ibv") {
+            params.ibv = ibv;
+        }
+        if let Some(eg) = get("eg") {
+            params.eg = eg;
+        }
+        if let Some(xti) = get("xti") {
+            params.xti = xti;
+        }
+        params.validate(&model.name)?;
+        Ok(params)
     }
 
-    /// Thermal voltage times ideality factor.
+    /// Check that `is`, `n`, `vf`, `v_crit`, `ibv` and `eg` are positive
+    /// and `vz` is not negative, reporting a bad value as an
+    /// `InvalidParameter` of `name`.
+    pub fn validate(&self, name: &str) -> Result<()> {
+        let positive = [
+            ("is", self.is),
+            ("n", self.n),
+            ("vf", self.vf),
+            ("v_crit", self.v_crit),
+            ("ibv", self.ibv),
+            ("eg", self.eg),
+        ];
+        for (param, value) in positive {
+            if value.is_nan() || value <= 0.0 {
+                return Err(PedalerError::invalid_parameter(
+                    name,
+                    param,
+                    format!("must be positive, got {}", value),
+                ));
+            }
+        }
+        if self.vz.is_nan() || self.vz < 0.0 {
+            return Err(PedalerError::invalid_parameter(
+                name,
+                "vz",
+                format!("must not be negative, got {}", self.vz),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Thermal voltage times ideality factor, at the nominal temperature.
     pub fn n_vt(&self) -> f64 {
         self.n * THERMAL_VOLTAGE
     }
@@ -88,6 +173,14 @@ pub struct Diode {
     pub params: DiodeParams,
     /// Current operating point voltage (for Newton-Raphson)
     pub v_op: f64,
+    /// Operating point voltage to start from (initial condition)
+    pub initial_v_op: f64,
+    /// Device temperature (K)
+    temp: f64,
+    /// Thermal voltage at the device temperature
+    vt: f64,
+    /// Saturation current scale factor Is(T) / Is(T_nom)
+    is_scale: f64,
 }
 
 impl Diode {
@@ -99,44 +192,105 @@ impl Diode {
             nodes,
             params,
             v_op: 0.0,
+            initial_v_op: 0.0,
+            temp: NOMINAL_TEMPERATURE,
+            vt: THERMAL_VOLTAGE,
+            is_scale: 1.0,
         }
     }
 
+    /// Thermal voltage times ideality factor at the device temperature.
+    pub fn n_vt(&self) -> f64 {
+        self.params.n * self.vt
+    }
+
+    /// Saturation current at the device temperature.
+    pub fn is(&self) -> f64 {
+        self.params.is * self.is_scale
+    }
+
+    /// Get the device temperature (K).
+    pub fn temperature(&self) -> f64 {
+        self.temp
+    }
+
+    /// Set the device temperature (K), updating Vt and Is with the same
+    /// law as the BJT's junction temperature.
+    pub fn set_temperature(&mut self, temp: f64) {
+        self.temp = temp;
+        self.vt = super::thermal_voltage(temp);
+        self.is_scale = super::saturation_current_scale(temp, self.params.eg, self.params.xti);
+    }
+
+    /// Return to the initial operating point.
+    pub fn reset_to_initial_conditions(&mut self) {
+        self.v_op = self.initial_v_op;
+    }
+
     /// Calculate the diode current at a given voltage.
     pub fn current(&self, v: f64) -> f64 {
-        let n_vt = self.params.n_vt();
+        self.junction_current(v) + self.breakdown(v).0
+    }
+
+    /// Calculate the conductance (dI/dV) at a given voltage.
+    pub fn conductance(&self, v: f64) -> f64 {
+        self.junction_conductance(v) + self.breakdown(v).1
+    }
+
+    /// Reverse breakdown current and its conductance at `v`, both zero
+    /// above -Vz or without a Zener voltage.
+    ///
+    /// Past a breakdown current of 1 A the exponential is continued
+    /// linearly, like the forward current past `v_crit`.
+    fn breakdown(&self, v: f64) -> (f64, f64) {
+        let depth = -(v + self.params.vz);
+        if self.params.vz <= 0.0 || depth <= 0.0 {
+            return (0.0, 0.0);
+        }
+        let n_vt = self.n_vt();
+        let ibv = self.params.ibv;
+        let depth_crit = n_vt * (1.0 + 1.0 / ibv).ln();
+        let d = depth.min(depth_crit);
+        let g = ibv / n_vt * (d / n_vt).exp();
+        let i = ibv * ((d / n_vt).exp() - 1.0) + g * (depth - d);
+        (-i, g)
+    }
+
+    /// Forward and reverse-saturation current of the junction.
+    fn junction_current(&self, v: f64) -> f64 {
+        let n_vt = self.n_vt();
 
         // Limit voltage to prevent overflow
         let v_limited = v.min(self.params.v_crit * 2.0);
 
         if v_limited > self.params.v_crit {
             // Linear extrapolation for high forward bias
-            let i_crit = self.params.is * ((self.params.v_crit / n_vt).exp() - 1.0);
-            let g_crit = self.params.is / n_vt * (self.params.v_crit / n_vt).exp();
+            let i_crit = self.is() * ((self.params.v_crit / n_vt).exp() - 1.0);
+            let g_crit = self.is() / n_vt * (self.params.v_crit / n_vt).exp();
             i_crit + g_crit * (v_limited - self.params.v_crit)
         } else if v_limited < -5.0 * n_vt {
             // Deep reverse bias - just use saturation current
-            -self.params.is
+            -self.is()
         } else {
             // Normal Shockley equation
-            self.params.is * ((v_limited / n_vt).exp() - 1.0)
+            self.is() * ((v_limited / n_vt).exp() - 1.0)
         }
     }
 
-    /// Calculate the conductance (dI/dV) at a given voltage.
-    pub fn conductance(&self, v: f64) -> f64 {
-        let n_vt = self.params.n_vt();
+    /// Conductance of the junction, without breakdown.
+    fn junction_conductance(&self, v: f64) -> f64 {
+        let n_vt = self.n_vt();
         let v_limited = v.min(self.params.v_crit * 2.0);
 
         if v_limited > self.params.v_crit {
             // Conductance at critical point
-            self.params.is / n_vt * (self.params.v_crit / n_vt).exp()
+            self.is() / n_vt * (self.params.v_crit / n_vt).exp()
         } else if v_limited < -5.0 * n_vt {
             // Very small conductance in deep reverse bias
             1e-12
         } else {
             // dI/dV = Is/(n*Vt) * exp(V/(n*Vt))
-            self.params.is / n_vt * (v_limited / n_vt).exp()
+            self.is() / n_vt * (v_limited / n_vt).exp()
         }
     }
 
@@ -156,24 +310,61 @@ impl Diode {
         self.v_op = v;
     }
 
-    /// Limit voltage step for Newton-Raphson convergence.
-    /// Uses a larger step limit to allow faster convergence while still
-    /// preventing numerical overflow in the exponential.
+    /// Voltage above which Newton-Raphson steps are limited: SPICE's
+    /// `n*Vt * ln(n*Vt / (sqrt(2) * Is))`, where the exponential's curvature
+    /// starts to dominate, but no higher than `v_crit`, past which the
+    /// current is linear anyway.
+    pub fn critical_voltage(&self) -> f64 {
+        let n_vt = self.n_vt();
+        let v_crit = n_vt * (n_vt / (SQRT_2 * self.is())).ln();
+        v_crit.min(self.params.v_crit)
+    }
+
+    /// Limit voltage step for Newton-Raphson convergence, like SPICE's
+    /// `pnjlim`.
+    ///
+    /// Past the critical voltage, a step of more than `2 n*Vt` is cut back
+    /// logarithmically, to where the exponential's current matches the
+    /// linearization at `v_old`, rather than to a fixed step. A Zener
+    /// diode's breakdown is limited the same way, mirrored about -Vz.
     pub fn limit_voltage_step(&self, v_old: f64, v_new: f64) -> f64 {
-        // Allow larger steps (up to critical voltage) for faster convergence
-        // but prevent huge jumps that could cause numerical issues
-        let max_step = self.params.v_crit.max(0.5);  // At least 0.5V steps allowed
-
-        if (v_new - v_old).abs() > max_step {
-            if v_new > v_old {
-                v_old + max_step
-            } else {
-                v_old - max_step
-            }
-        } else {
-            v_new
+        let n_vt = self.n_vt();
+        let vz = self.params.vz;
+        if vz > 0.0 && v_new < -vz {
+            let depth_crit = (n_vt * (n_vt / (SQRT_2 * self.params.ibv)).ln()).max(0.0);
+            return -vz - pnjlim(-(v_old + vz), -(v_new + vz), n_vt, depth_crit);
+        }
+        if v_old >= self.params.v_crit {
+            // Already on the linear extension, where steps need no limit
+            return v_new;
         }
+        pnjlim(v_old, v_new, n_vt, self.critical_voltage())
+    }
+}
+
+/// SPICE's junction voltage limiting: limit the step from `v_old` to `v_new`
+/// across a junction with thermal voltage `n_vt`, above `v_crit`.
+///
+/// A step ending at or below `v_crit` is never limited, so a limited step
+/// still gets that far; coming out of reverse bias, SPICE's can stop well
+/// short of it and take several iterations to climb the knee.
+fn pnjlim(v_old: f64, v_new: f64, n_vt: f64, v_crit: f64) -> f64 {
+    if v_new <= v_crit || (v_new - v_old).abs() <= 2.0 * n_vt {
+        return v_new;
     }
+    let limited = if v_old > 0.0 {
+        // Grow the current by the factor the linearization at v_old
+        // predicts, 1 + (v_new - v_old) / n_vt
+        let arg = 1.0 + (v_new - v_old) / n_vt;
+        if arg > 0.0 {
+            v_old + n_vt * arg.ln()
+        } else {
+            v_crit
+        }
+    } else {
+        n_vt * (v_new / n_vt).ln()
+    };
+    limited.max(v_crit)
 }
 
 #[cfg(test)]
@@ -212,4 +403,106 @@ mod tests {
         assert!(i_rev < 0.0);
         assert!(i_rev > -2.0 * d.params.is);
     }
+
+    #[test]
+    fn test_zener_breakdown_conducts_in_reverse() {
+        let params = DiodeParams {
+            vz: 5.1,
+            ..DiodeParams::default()
+        };
+        let d = Diode::new(
+            ComponentId(0),
+            "DZ".to_string(),
+            [NodeId(1), NodeId(0)],
+            params.clone(),
+        );
+
+        // Blocking until -Vz, then Ibv at the knee and steeply more past it
+        assert!(d.current(-5.0) > -2.0 * d.params.is);
+        assert!((d.current(-5.1) + d.params.is).abs() < 1e-12);
+        assert!(d.current(-5.2) < -10.0 * d.params.ibv);
+        assert!(d.current(-20.0).is_finite());
+
+        // The linearization carries the breakdown conductance
+        for v in [-5.15, -5.3, -8.0] {
+            let h = 1e-7;
+            let g = (d.current(v + h) - d.current(v - h)) / (2.0 * h);
+            let (g_lin, _) = d.linearize(v);
+            assert!(
+                (g_lin / g - 1.0).abs() < 1e-4,
+                "{} vs {} at {}",
+                g_lin,
+                g,
+                v
+            );
+        }
+    }
+
+    /// Solve a source `vs` driving a diode through 1k by Newton-Raphson
+    /// from `v`, limiting each step with `limit`. Returns the iterations.
+    fn solve_clipper(d: &Diode, vs: f64, v: &mut f64, limit: impl Fn(f64, f64) -> f64) -> usize {
+        let g_r = 1e-3;
+        for iteration in 1..=100 {
+            let (g, i_eq) = d.linearize(*v);
+            // g_r * (vs - v) = g * v + i_eq
+            let v_new = limit(*v, (g_r * vs - i_eq) / (g_r + g));
+            let done = (v_new - *v).abs() < 1e-9;
+            *v = v_new;
+            if done {
+                return iteration;
+            }
+        }
+        panic!("no convergence at {} V", vs);
+    }
+
+    #[test]
+    fn test_pnjlim_converges_faster_on_led_clipper() {
+        let d = Diode::new(
+            ComponentId(0),
+            "D_LED".to_string(),
+            [NodeId(1), NodeId(0)],
+            DiodeParams::led(1.8),
+        );
+
+        // The fixed-step clamp this limiting replaced
+        let max_step = d.params.v_crit.max(0.5);
+        let fixed = |v_old: f64, v_new: f64| v_old + (v_new - v_old).clamp(-max_step, max_step);
+
+        // A 9V sine swinging the LED through its knee every few samples
+        let (mut v_fixed, mut v_pnjlim) = (0.0, 0.0);
+        let (mut n_fixed, mut n_pnjlim) = (0, 0);
+        for i in 0..480 {
+            let vs = 9.0 * (i as f64).sin();
+            n_fixed += solve_clipper(&d, vs, &mut v_fixed, fixed);
+            n_pnjlim += solve_clipper(&d, vs, &mut v_pnjlim, |v_old, v_new| {
+                d.limit_voltage_step(v_old, v_new)
+            });
+            assert!((v_fixed - v_pnjlim).abs() < 1e-6);
+        }
+        assert!(
+            n_pnjlim * 5 < n_fixed * 4,
+            "{} vs {} iterations",
+            n_pnjlim,
+            n_fixed
+        );
+    }
+
+    fn model(src: &str) -> ModelDef {
+        let ast = crate::dsl::parse(src).unwrap();
+        ast.models.into_values().next().unwrap()
+    }
+
+    #[test]
+    fn test_invalid_model_params_rejected() {
+        let err = DiodeParams::from_model(&model(".model DBAD D (n=0)")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid parameter 'n' for component 'DBAD': must be positive, got 0"
+        );
+
+        let err = DiodeParams::from_model(&model(".model DBAD D (is=-1e-14)")).unwrap_err();
+        assert!(err.to_string().contains("'is'"), "{}", err);
+
+        assert!(DiodeParams::from_model(&model(".model DOK D (is=1e-9 n=1.5)")).is_ok());
+    }
 }
@@ -0,0 +1,114 @@
+//! Envelope follower for dynamics-controlled modulation.
+//!
+//! The envelope follower tracks the amplitude of a circuit node and produces
+//! a control signal (0.0 to 1.0) that can modulate other components, e.g.
+//! sweeping a pot-based filter for auto-wah effects.
+
+use crate::circuit::NodeId;
+
+/// A peak envelope follower with separate attack and release times.
+#[derive(Debug, Clone)]
+pub struct EnvelopeFollower {
+    /// Component name (used to reference from modulated components)
+    pub name: String,
+    /// Node whose voltage is followed
+    pub input_node: NodeId,
+    /// Smoothing coefficient while the level is rising
+    attack_coeff: f64,
+    /// Smoothing coefficient while the level is falling
+    release_coeff: f64,
+    /// Gain applied to the envelope before clamping to [0, 1]
+    gain: f64,
+    /// Current envelope level (volts)
+    level: f64,
+    /// Current output value (0.0 to 1.0)
+    pub value: f64,
+}
+
+impl EnvelopeFollower {
+    /// Create a new envelope follower.
+    ///
+    /// # Arguments
+    /// * `name` - Component name
+    /// * `input_node` - Node to follow
+    /// * `attack` - Attack time constant in seconds
+    /// * `release` - Release time constant in seconds
+    /// * `gain` - Volts-to-control gain (output = clamp(gain * level, 0, 1))
+    /// * `sample_rate` - Audio sample rate in Hz
+    pub fn new(
+        name: String,
+        input_node: NodeId,
+        attack: f64,
+        release: f64,
+        gain: f64,
+        sample_rate: f64,
+    ) -> Self {
+        Self {
+            name,
+            input_node,
+            attack_coeff: Self::coefficient(attack, sample_rate),
+            release_coeff: Self::coefficient(release, sample_rate),
+            gain,
+            level: 0.0,
+            value: 0.0,
+        }
+    }
+
+    /// One-pole smoothing coefficient for a time constant.
+    fn coefficient(time: f64, sample_rate: f64) -> f64 {
+        if time <= 0.0 {
+            0.0
+        } else {
+            (-1.0 / (time * sample_rate)).exp()
+        }
+    }
+
+    /// Feed one sample of the followed voltage and return the new value.
+    ///
+    /// Returns a value in the range [0.0, 1.0].
+    pub fn process(&mut self, input: f64) -> f64 {
+        let rectified = input.abs();
+        let coeff = if rectified > self.level {
+            self.attack_coeff
+        } else {
+            self.release_coeff
+        };
+        self.level = coeff * self.level + (1.0 - coeff) * rectified;
+        self.value = (self.gain * self.level).clamp(0.0, 1.0);
+        self.value
+    }
+
+    /// Get the current output value without advancing.
+    pub fn current_value(&self) -> f64 {
+        self.value
+    }
+
+    /// Reset the envelope to silence.
+    pub fn reset(&mut self) {
+        self.level = 0.0;
+        self.value = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope_attack_and_release() {
+        let mut env =
+            EnvelopeFollower::new("ENV1".to_string(), NodeId(1), 0.001, 0.01, 1.0, 48000.0);
+
+        // After many attack time constants the envelope settles at the input level
+        for _ in 0..960 {
+            env.process(0.8);
+        }
+        assert!((env.current_value() - 0.8).abs() < 1e-3);
+
+        // One release time constant later it has decayed to ~37%
+        for _ in 0..480 {
+            env.process(0.0);
+        }
+        assert!((env.current_value() - 0.8 * (-1.0f64).exp()).abs() < 1e-2);
+    }
+}
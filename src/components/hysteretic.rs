@@ -0,0 +1,151 @@
+//! Hysteretic resistor: a resistance with memory of its drive signal.
+//!
+//! An internal state `s` (0.0 to 1.0) follows the drive with one time
+//! constant while the drive is rising and another while it is falling, like
+//! the lamp and photocell of a vactrol. The resistance is interpolated
+//! logarithmically, `R = rmax * (rmin / rmax)^s`, so equal steps of `s` are
+//! equal ratios of resistance. The drive is either a control signal (LFO or
+//! envelope follower) or the element's own terminal voltage, which gives
+//! sag and memory effects.
+
+use crate::circuit::{ComponentId, NodeId};
+
+/// A resistor whose value follows its drive with asymmetric lag.
+#[derive(Debug, Clone)]
+pub struct Hysteretic {
+    pub id: ComponentId,
+    pub name: String,
+    pub nodes: [NodeId; 2],
+    /// Resistance at full drive (ohms)
+    pub rmin: f64,
+    /// Resistance at zero drive (ohms)
+    pub rmax: f64,
+    /// Time constant while the drive is rising (seconds)
+    pub tau_rise: f64,
+    /// Time constant while the drive is falling (seconds)
+    pub tau_fall: f64,
+    /// Terminal voltage that counts as full drive when self-driven (volts)
+    pub vref: f64,
+    /// LFO or envelope follower driving the element, if not self-driven
+    pub control: Option<String>,
+    /// Current state (0.0 = rmax, 1.0 = rmin)
+    state: f64,
+}
+
+impl Hysteretic {
+    /// Create a new hysteretic resistor, starting at `rmax`.
+    pub fn new(
+        id: ComponentId,
+        name: String,
+        nodes: [NodeId; 2],
+        rmin: f64,
+        rmax: f64,
+        tau_rise: f64,
+        tau_fall: f64,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            nodes,
+            rmin,
+            rmax,
+            tau_rise,
+            tau_fall,
+            vref: 1.0,
+            control: None,
+            state: 0.0,
+        }
+    }
+
+    /// Drive from a control signal instead of the terminal voltage.
+    pub fn with_control(mut self, control: String) -> Self {
+        self.control = Some(control);
+        self
+    }
+
+    /// Set the terminal voltage that counts as full drive.
+    pub fn with_vref(mut self, vref: f64) -> Self {
+        self.vref = vref;
+        self
+    }
+
+    /// Current state (0.0 to 1.0).
+    pub fn state(&self) -> f64 {
+        self.state
+    }
+
+    /// Current resistance.
+    pub fn resistance(&self) -> f64 {
+        self.rmax * (self.rmin / self.rmax).powf(self.state)
+    }
+
+    /// Current conductance.
+    pub fn conductance(&self) -> f64 {
+        1.0 / self.resistance()
+    }
+
+    /// Drive for a given voltage across the terminals when self-driven.
+    pub fn voltage_drive(&self, v: f64) -> f64 {
+        v.abs() / self.vref
+    }
+
+    /// Advance the state by one time step towards `drive` (clamped to 0-1).
+    pub fn update(&mut self, drive: f64, dt: f64) {
+        let target = drive.clamp(0.0, 1.0);
+        let tau = if target > self.state {
+            self.tau_rise
+        } else {
+            self.tau_fall
+        };
+        self.state += (target - self.state) * (1.0 - (-dt / tau).exp());
+    }
+
+    /// Reset the state to zero drive.
+    pub fn reset(&mut self) {
+        self.state = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step_response_follows_time_constants() {
+        let dt = 1.0 / 48000.0;
+        let mut h = Hysteretic::new(
+            ComponentId(0),
+            "H1".to_string(),
+            [NodeId(1), NodeId(0)],
+            1e3,
+            1e6,
+            0.005,
+            0.05,
+        );
+        let resistance_at = |s: f64| 1e6 * (1e-3f64).powf(s);
+        assert_eq!(h.resistance(), 1e6);
+
+        // One rise time constant after a step on, the state is at 1 - 1/e
+        for _ in 0..240 {
+            h.update(1.0, dt);
+        }
+        let rise = 1.0 - (-1.0f64).exp();
+        assert!((h.state() - rise).abs() < 1e-3);
+        assert!((h.resistance() / resistance_at(rise) - 1.0).abs() < 1e-2);
+
+        // Settle, then the fall is ten times slower
+        for _ in 0..4800 {
+            h.update(1.0, dt);
+        }
+        for _ in 0..240 {
+            h.update(0.0, dt);
+        }
+        let after_short_fall = h.state();
+        assert!((after_short_fall - (-0.1f64).exp()).abs() < 1e-3);
+        for _ in 240..2400 {
+            h.update(0.0, dt);
+        }
+        assert!((h.state() - (-1.0f64).exp()).abs() < 1e-3);
+        assert!((h.resistance() / resistance_at((-1.0f64).exp()) - 1.0).abs() < 1e-2);
+    }
+}
@@ -0,0 +1,312 @@
+//! JFET (Junction Field-Effect Transistor) model.
+//!
+//! Uses the Shichman-Hodges square-law model for N- and P-channel JFETs,
+//! with channel-length modulation. The gate junction is assumed reverse
+//! biased and draws no current.
+//!
+//! For an N-channel device with `Vgst = Vgs - Vto`:
+//! - cutoff (`Vgst <= 0`): `Id = 0`
+//! - triode (`Vds < Vgst`): `Id = beta * Vds * (2 * Vgst - Vds) * (1 + lambda * Vds)`
+//! - saturation: `Id = beta * Vgst^2 * (1 + lambda * Vds)`
+//!
+//! The channel is symmetric: with `Vds < 0`, drain and source swap roles.
+//! A P-channel device is the N-channel one with every voltage and current
+//! negated.
+
+use crate::circuit::{ComponentId, NodeId};
+use crate::dsl::{ModelDef, ModelType};
+use crate::error::{PedalerError, Result};
+
+/// JFET channel type (N or P).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JfetType {
+    N,
+    P,
+}
+
+/// Parameters for a JFET model.
+#[derive(Debug, Clone)]
+pub struct JfetParams {
+    /// Threshold (pinch-off) voltage, negative for depletion devices
+    pub vto: f64,
+    /// Transconductance parameter (A/V²)
+    pub beta: f64,
+    /// Channel-length modulation (1/V)
+    pub lambda: f64,
+}
+
+impl Default for JfetParams {
+    fn default() -> Self {
+        Self {
+            vto: -2.0,
+            beta: 1e-4,
+            lambda: 0.0,
+        }
+    }
+}
+
+impl JfetParams {
+    /// Create parameters from a model definition.
+    ///
+    /// Returns `InvalidParameter` for out-of-range values, e.g. a
+    /// non-positive `beta` or a negative `lambda`.
+    pub fn from_model(model: &ModelDef) -> Result<(JfetType, Self)> {
+        let jfet_type = match model.model_type {
+            ModelType::JfetN => JfetType::N,
+            ModelType::JfetP => JfetType::P,
+            _ => {
+                return Err(PedalerError::InvalidParameter {
+                    component: model.name.clone(),
+                    param: "type".to_string(),
+                    message: "expected NJF or PJF model type".to_string(),
+                });
+            }
+        };
+
+        let mut params = Self::default();
+
+        if let Some(&vto) = model.params.get("vto") {
+            params.vto = vto;
+        }
+        if let Some(beta) = super::positive_model_param(model, "beta")? {
+            params.beta = beta;
+        }
+        if let Some(lambda) = super::non_negative_model_param(model, "lambda")? {
+            params.lambda = lambda;
+        }
+
+        Ok((jfet_type, params))
+    }
+}
+
+/// A JFET component.
+#[derive(Debug, Clone)]
+pub struct Jfet {
+    pub id: ComponentId,
+    pub name: String,
+    pub nodes: [NodeId; 3], // [drain, gate, source]
+    pub jfet_type: JfetType,
+    pub params: JfetParams,
+    /// Current gate-source voltage operating point
+    pub v_gs_op: f64,
+    /// Current drain-source voltage operating point
+    pub v_ds_op: f64,
+    /// Gate-source voltage to start from (initial condition)
+    pub initial_v_gs_op: f64,
+    /// Drain-source voltage to start from (initial condition)
+    pub initial_v_ds_op: f64,
+}
+
+impl Jfet {
+    /// Create a new JFET.
+    pub fn new(
+        id: ComponentId,
+        name: String,
+        nodes: [NodeId; 3],
+        jfet_type: JfetType,
+        params: JfetParams,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            nodes,
+            jfet_type,
+            params,
+            v_gs_op: 0.0,
+            v_ds_op: 0.0,
+            initial_v_gs_op: 0.0,
+            initial_v_ds_op: 0.0,
+        }
+    }
+
+    /// Get the drain node.
+    pub fn drain(&self) -> NodeId {
+        self.nodes[0]
+    }
+
+    /// Get the gate node.
+    pub fn gate(&self) -> NodeId {
+        self.nodes[1]
+    }
+
+    /// Get the source node.
+    pub fn source(&self) -> NodeId {
+        self.nodes[2]
+    }
+
+    /// +1 for N-channel, -1 for P-channel.
+    fn sign(&self) -> f64 {
+        match self.jfet_type {
+            JfetType::N => 1.0,
+            JfetType::P => -1.0,
+        }
+    }
+
+    /// N-channel drain current and its partial derivatives
+    /// `(Id, dId/dVgs, dId/dVds)` for `v_ds >= 0`.
+    fn forward(&self, v_gs: f64, v_ds: f64) -> (f64, f64, f64) {
+        let JfetParams { vto, beta, lambda } = self.params;
+        let v_gst = v_gs - vto;
+        if v_gst <= 0.0 {
+            return (0.0, 0.0, 0.0);
+        }
+        let clm = 1.0 + lambda * v_ds;
+        if v_ds < v_gst {
+            // Triode
+            let shape = v_ds * (2.0 * v_gst - v_ds);
+            let i_d = beta * shape * clm;
+            let gm = 2.0 * beta * v_ds * clm;
+            let gds = 2.0 * beta * (v_gst - v_ds) * clm + beta * shape * lambda;
+            (i_d, gm, gds)
+        } else {
+            // Saturation
+            let i_d = beta * v_gst * v_gst * clm;
+            let gm = 2.0 * beta * v_gst * clm;
+            let gds = beta * v_gst * v_gst * lambda;
+            (i_d, gm, gds)
+        }
+    }
+
+    /// N-channel drain current and `(dId/dVgs, dId/dVds)` at any `v_ds`,
+    /// swapping drain and source when `v_ds` is negative.
+    fn channel(&self, v_gs: f64, v_ds: f64) -> (f64, f64, f64) {
+        if v_ds >= 0.0 {
+            self.forward(v_gs, v_ds)
+        } else {
+            // Id(Vgs, Vds) = -F(Vgd, -Vds) with Vgd = Vgs - Vds
+            let (i, f_g, f_d) = self.forward(v_gs - v_ds, -v_ds);
+            (-i, -f_g, f_g + f_d)
+        }
+    }
+
+    /// Calculate the drain current (Id), flowing from drain to source.
+    pub fn i_d(&self, v_gs: f64, v_ds: f64) -> f64 {
+        let s = self.sign();
+        s * self.channel(s * v_gs, s * v_ds).0
+    }
+
+    /// Get partial derivatives for linearization.
+    /// Returns (gm, gds) - transconductance dId/dVgs and output conductance
+    /// dId/dVds.
+    pub fn linearize(&self, v_gs: f64, v_ds: f64) -> (f64, f64) {
+        // Negating both voltages and the current leaves the derivatives alone
+        let s = self.sign();
+        let (_, gm, gds) = self.channel(s * v_gs, s * v_ds);
+        (gm, gds.max(1e-12))
+    }
+
+    /// Update operating points.
+    pub fn update_operating_point(&mut self, v_gs: f64, v_ds: f64) {
+        self.v_gs_op = v_gs;
+        self.v_ds_op = v_ds;
+    }
+
+    /// Return to the initial operating point.
+    pub fn reset_to_initial_conditions(&mut self) {
+        self.v_gs_op = self.initial_v_gs_op;
+        self.v_ds_op = self.initial_v_ds_op;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jfet(jfet_type: JfetType) -> Jfet {
+        let params = JfetParams {
+            vto: -2.0,
+            beta: 1e-3,
+            lambda: 0.02,
+        };
+        Jfet::new(
+            ComponentId(0),
+            "J1".to_string(),
+            [NodeId(1), NodeId(2), NodeId(0)],
+            jfet_type,
+            params,
+        )
+    }
+
+    /// Compare `linearize` against central differences of `i_d`.
+    fn assert_derivatives(j: &Jfet, v_gs: f64, v_ds: f64) {
+        let h = 1e-6;
+        let gm = (j.i_d(v_gs + h, v_ds) - j.i_d(v_gs - h, v_ds)) / (2.0 * h);
+        let gds = (j.i_d(v_gs, v_ds + h) - j.i_d(v_gs, v_ds - h)) / (2.0 * h);
+        let (a_gm, a_gds) = j.linearize(v_gs, v_ds);
+        assert!(
+            (a_gm - gm).abs() < 1e-7,
+            "gm {} vs {} at ({}, {})",
+            a_gm,
+            gm,
+            v_gs,
+            v_ds
+        );
+        assert!(
+            (a_gds - gds).abs() < 1e-7,
+            "gds {} vs {} at ({}, {})",
+            a_gds,
+            gds,
+            v_gs,
+            v_ds
+        );
+    }
+
+    #[test]
+    fn test_cutoff() {
+        let j = jfet(JfetType::N);
+        assert_eq!(j.i_d(-2.5, 5.0), 0.0);
+        assert_eq!(j.i_d(-2.0, 5.0), 0.0);
+        let (gm, gds) = j.linearize(-3.0, 5.0);
+        assert_eq!(gm, 0.0);
+        assert!(gds > 0.0 && gds < 1e-9);
+    }
+
+    #[test]
+    fn test_triode() {
+        // Vgst = 1.5 V, Vds = 0.5 V
+        let j = jfet(JfetType::N);
+        let expected = 1e-3 * 0.5 * (2.0 * 1.5 - 0.5) * (1.0 + 0.02 * 0.5);
+        assert!((j.i_d(-0.5, 0.5) - expected).abs() < 1e-15);
+        assert_derivatives(&j, -0.5, 0.5);
+
+        // Near the origin the channel is a resistor of 1 / (2 beta Vgst)
+        let r = 1e-3 / j.i_d(0.0, 1e-3);
+        assert!(
+            (r - 1.0 / (2.0 * 1e-3 * 2.0)).abs() < 1.0,
+            "channel resistance {}",
+            r
+        );
+
+        // Reversed, drain and source swap roles, so a small Vds sees the
+        // same channel either way
+        assert_derivatives(&j, -0.5, -0.5);
+        assert!((j.i_d(0.0, -1e-3) / j.i_d(0.0, 1e-3) + 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_saturation() {
+        // Vgst = 1 V: Idss-like current beta * Vgst^2, nearly flat in Vds
+        let j = jfet(JfetType::N);
+        let expected = 1e-3 * (1.0 + 0.02 * 5.0);
+        assert!((j.i_d(-1.0, 5.0) - expected).abs() < 1e-15);
+        assert!(j.i_d(-1.0, 9.0) > j.i_d(-1.0, 5.0));
+        assert_derivatives(&j, -1.0, 5.0);
+        let (gm, gds) = j.linearize(-1.0, 5.0);
+        assert!(gm > 100.0 * gds);
+
+        // The P-channel device mirrors the N-channel one
+        let p = jfet(JfetType::P);
+        assert_eq!(p.i_d(1.0, -5.0), -j.i_d(-1.0, 5.0));
+        assert_derivatives(&p, 1.0, -5.0);
+    }
+
+    #[test]
+    fn test_invalid_model_params_rejected() {
+        let ast = crate::dsl::parse(".model JBAD NJF (vto=-1 beta=0)").unwrap();
+        let err = JfetParams::from_model(&ast.models["JBAD"]).unwrap_err();
+        assert!(matches!(
+            err,
+            PedalerError::InvalidParameter { ref param, .. } if param == "beta"
+        ));
+    }
+}
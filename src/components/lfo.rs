@@ -1,10 +1,19 @@
 //! Low Frequency Oscillator (LFO) for modulation effects.
 //!
-//! The LFO generates a control signal (0.0 to 1.0) that can be used
-//! to modulate other components like resistors in phaser/flanger circuits.
+//! The LFO generates a control signal (0.0 to 1.0, or a narrower range)
+//! that can be used to modulate other components like resistors in
+//! phaser/flanger circuits. A phase offset lets two LFOs at the same rate
+//! run out of step, e.g. for the two sides of a stereo chorus.
+//!
+//! At audio rates the jumps in the naive sawtooth and square alias, so from
+//! [`BAND_LIMITED_RATE`] up those edges are smoothed with PolyBLEP
+//! corrections. Slow modulation keeps the cheaper naive shapes.
 
 use std::f64::consts::PI;
 
+/// Rate in Hz from which the sawtooth and square are band-limited.
+pub const BAND_LIMITED_RATE: f64 = 20.0;
+
 /// LFO waveform shape.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum LfoShape {
@@ -21,6 +30,7 @@ pub enum LfoShape {
 
 impl LfoShape {
     /// Parse shape from string.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
         match s.to_lowercase().as_str() {
             "sine" | "sin" => Some(Self::Sine),
@@ -43,9 +53,17 @@ pub struct Lfo {
     shape: LfoShape,
     /// Current phase (0.0 to 1.0)
     phase: f64,
+    /// Phase to start from and reset to (0.0 to 1.0)
+    initial_phase: f64,
     /// Phase increment per sample
     phase_increment: f64,
-    /// Current output value (0.0 to 1.0)
+    /// Whether the sawtooth and square edges get PolyBLEP corrections
+    band_limited: bool,
+    /// Output at the bottom of the waveform
+    min: f64,
+    /// Output at the top of the waveform
+    max: f64,
+    /// Current output value (between `min` and `max`)
     pub value: f64,
 }
 
@@ -64,11 +82,30 @@ impl Lfo {
             rate,
             shape,
             phase: 0.0,
+            initial_phase: 0.0,
             phase_increment,
+            band_limited: rate >= BAND_LIMITED_RATE,
+            min: 0.0,
+            max: 1.0,
             value: 0.5, // Start at middle
         }
     }
 
+    /// Start the waveform `phase` cycles in, wrapped into 0.0-1.0.
+    pub fn with_phase(mut self, phase: f64) -> Self {
+        self.initial_phase = phase.rem_euclid(1.0);
+        self.phase = self.initial_phase;
+        self
+    }
+
+    /// Scale the output into `[min, max]` instead of `[0.0, 1.0]`.
+    pub fn with_range(mut self, min: f64, max: f64) -> Self {
+        self.min = min;
+        self.max = max;
+        self.value = 0.5 * (min + max);
+        self
+    }
+
     /// Get the current LFO rate in Hz.
     pub fn rate(&self) -> f64 {
         self.rate
@@ -78,14 +115,27 @@ impl Lfo {
     pub fn set_rate(&mut self, rate: f64, sample_rate: f64) {
         self.rate = rate;
         self.phase_increment = rate / sample_rate;
+        self.band_limited = rate >= BAND_LIMITED_RATE;
     }
 
     /// Advance the LFO by one sample and return the new value.
     ///
-    /// Returns a value in the range [0.0, 1.0].
+    /// Returns a value in the range [min, max], by default [0.0, 1.0].
     pub fn tick(&mut self) -> f64 {
-        // Calculate output based on shape
-        self.value = match self.shape {
+        self.value = self.min + (self.max - self.min) * self.waveform();
+
+        // Advance phase
+        self.phase += self.phase_increment;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+        }
+
+        self.value
+    }
+
+    /// The waveform at the current phase, from 0.0 to 1.0.
+    fn waveform(&self) -> f64 {
+        match self.shape {
             LfoShape::Sine => {
                 // Sine: 0.5 + 0.5 * sin(2π * phase)
                 0.5 + 0.5 * (2.0 * PI * self.phase).sin()
@@ -99,22 +149,42 @@ impl Lfo {
                 }
             }
             LfoShape::Sawtooth => {
-                // Sawtooth: rises linearly from 0 to 1
-                self.phase
+                // Sawtooth: rises linearly from 0 to 1, dropping by 1 at the wrap
+                let naive = self.phase;
+                if self.band_limited {
+                    naive - 0.5 * self.poly_blep(self.phase)
+                } else {
+                    naive
+                }
             }
             LfoShape::Square => {
                 // Square: 0 for first half, 1 for second half
-                if self.phase < 0.5 { 0.0 } else { 1.0 }
+                let naive = if self.phase < 0.5 { 0.0 } else { 1.0 };
+                if self.band_limited {
+                    // Falling at the wrap, rising halfway
+                    naive - 0.5 * self.poly_blep(self.phase)
+                        + 0.5 * self.poly_blep((self.phase + 0.5) % 1.0)
+                } else {
+                    naive
+                }
             }
-        };
-
-        // Advance phase
-        self.phase += self.phase_increment;
-        if self.phase >= 1.0 {
-            self.phase -= 1.0;
         }
+    }
 
-        self.value
+    /// PolyBLEP residual for a unit step at phase 0, in -1 to 1: subtract
+    /// half of it for a fall of one, add half for a rise. Zero more than one
+    /// sample away from the edge.
+    fn poly_blep(&self, phase: f64) -> f64 {
+        let dt = self.phase_increment;
+        if phase < dt {
+            let x = phase / dt;
+            2.0 * x - x * x - 1.0
+        } else if phase > 1.0 - dt {
+            let x = (phase - 1.0) / dt;
+            x * x + 2.0 * x + 1.0
+        } else {
+            0.0
+        }
     }
 
     /// Get the current output value without advancing.
@@ -122,15 +192,10 @@ impl Lfo {
         self.value
     }
 
-    /// Reset the LFO phase.
+    /// Reset the LFO to its initial phase.
     pub fn reset(&mut self) {
-        self.phase = 0.0;
-        self.value = match self.shape {
-            LfoShape::Sine => 0.5,
-            LfoShape::Triangle => 0.0,
-            LfoShape::Sawtooth => 0.0,
-            LfoShape::Square => 0.0,
-        };
+        self.phase = self.initial_phase;
+        self.value = self.min + (self.max - self.min) * self.waveform();
     }
 }
 
@@ -173,4 +238,72 @@ mod tests {
         let v3 = lfo.tick(); // phase 0.75: 0.5
         assert!((v3 - 0.5).abs() < 0.01);
     }
+
+    #[test]
+    fn test_lfo_phase_offset_and_range() {
+        let mut a = Lfo::new("LFO1".to_string(), 1.0, LfoShape::Sine, 4.0);
+        let mut b = Lfo::new("LFO2".to_string(), 1.0, LfoShape::Sine, 4.0)
+            .with_phase(0.25)
+            .with_range(0.2, 0.9);
+
+        // A quarter cycle ahead: b's phase 0.25 peak is a's next sample
+        let va: Vec<f64> = (0..8).map(|_| a.tick()).collect();
+        let vb: Vec<f64> = (0..8).map(|_| b.tick()).collect();
+        for n in 0..7 {
+            let expected = 0.2 + 0.7 * va[n + 1];
+            assert!(
+                (vb[n] - expected).abs() < 1e-9,
+                "sample {}: {} vs {}",
+                n,
+                vb[n],
+                expected
+            );
+        }
+
+        // The sine sweeps exactly the configured range
+        assert!((vb.iter().cloned().fold(f64::INFINITY, f64::min) - 0.2).abs() < 1e-9);
+        assert!((vb.iter().cloned().fold(0.0, f64::max) - 0.9).abs() < 1e-9);
+
+        b.reset();
+        assert_eq!(b.tick(), vb[0]);
+    }
+
+    /// Energy above `cutoff` Hz in the first 4096 samples.
+    fn energy_above(samples: &[f64], sample_rate: f64, cutoff: f64) -> f64 {
+        use crate::analysis::{fft, Complex};
+        let mut bins: Vec<Complex> = samples[..4096]
+            .iter()
+            .map(|&x| Complex::new(x - 0.5, 0.0))
+            .collect();
+        fft(&mut bins);
+        let first = (cutoff / sample_rate * 4096.0) as usize;
+        bins[first..=2048].iter().map(|b| b.norm_sqr()).sum()
+    }
+
+    #[test]
+    fn test_band_limited_saw_aliases_less() {
+        let (rate, fs) = (1234.5, 48000.0);
+        let mut lfo = Lfo::new("LFO1".to_string(), rate, LfoShape::Sawtooth, fs);
+        let band_limited: Vec<f64> = (0..4096).map(|_| lfo.tick()).collect();
+        let naive: Vec<f64> = (0..4096).map(|n| (n as f64 * rate / fs).fract()).collect();
+
+        // The edges alias into the top of the band; PolyBLEP suppresses most of it
+        let (smooth, harsh) = (
+            energy_above(&band_limited, fs, 20000.0),
+            energy_above(&naive, fs, 20000.0),
+        );
+        assert!(
+            smooth < 0.3 * harsh,
+            "band-limited {} vs naive {}",
+            smooth,
+            harsh
+        );
+
+        // Slow modulation keeps the naive ramp
+        let mut slow = Lfo::new("LFO2".to_string(), 1.0, LfoShape::Sawtooth, 4.0);
+        assert_eq!(
+            (0..4).map(|_| slow.tick()).collect::<Vec<_>>(),
+            [0.0, 0.25, 0.5, 0.75]
+        );
+    }
 }
@@ -2,6 +2,32 @@
 
 use crate::circuit::{BranchId, ComponentId, NodeId};
 
+/// Smallest resistance a resistor takes, to keep the matrix non-singular.
+pub const MIN_RESISTANCE: f64 = 1e-12;
+
+/// Numerical integration rule for the capacitor and inductor companion
+/// models.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntegrationMethod {
+    /// Second-order accurate and energy-preserving, but a sharp edge can
+    /// leave a stiff node oscillating from sample to sample
+    #[default]
+    Trapezoidal,
+    /// First-order accurate and slightly lossy at high frequencies, but it
+    /// damps the trapezoidal ringing
+    BackwardEuler,
+}
+
+impl IntegrationMethod {
+    /// Multiplier of `C/dt` and `L/dt` in the companion model.
+    pub(super) fn scale(self) -> f64 {
+        match self {
+            IntegrationMethod::Trapezoidal => 2.0,
+            IntegrationMethod::BackwardEuler => 1.0,
+        }
+    }
+}
+
 /// Modulation configuration for a resistor.
 #[derive(Debug, Clone)]
 pub struct ResistorModulation {
@@ -13,6 +39,11 @@ pub struct ResistorModulation {
     /// Modulation range - multiplier for how far resistance can vary
     /// Default is 4.0, meaning resistance can go up to 5x base value
     pub range: f64,
+    /// Lowest effective resistance the modulation may reach
+    pub r_min: f64,
+    /// Highest effective resistance the modulation may reach, so that a deep
+    /// sweep never leaves the branch effectively open
+    pub r_max: f64,
 }
 
 /// A resistor component with optional modulation.
@@ -27,12 +58,14 @@ pub struct Resistor {
     pub modulation: Option<ResistorModulation>,
     /// Current effective resistance (updated each sample for modulated resistors)
     pub effective_resistance: f64,
+    /// Relative tolerance of the resistance (0 = exact)
+    pub tolerance: f64,
 }
 
 impl Resistor {
     /// Create a new resistor.
     pub fn new(id: ComponentId, name: String, nodes: [NodeId; 2], resistance: f64) -> Self {
-        let r = resistance.max(1e-12); // Minimum resistance to avoid singularity
+        let r = resistance.max(MIN_RESISTANCE);
         Self {
             id,
             name,
@@ -40,6 +73,7 @@ impl Resistor {
             resistance: r,
             modulation: None,
             effective_resistance: r,
+            tolerance: 0.0,
         }
     }
 
@@ -53,7 +87,7 @@ impl Resistor {
         depth: f64,
         range: f64,
     ) -> Self {
-        let r = resistance.max(1e-12);
+        let r = resistance.max(MIN_RESISTANCE);
         Self {
             id,
             name,
@@ -63,9 +97,29 @@ impl Resistor {
                 lfo_name,
                 depth: depth.clamp(0.0, 1.0),
                 range: range.max(0.0),
+                r_min: MIN_RESISTANCE,
+                r_max: f64::INFINITY,
             }),
             effective_resistance: r,
+            tolerance: 0.0,
+        }
+    }
+
+    /// Clamp the modulated resistance to `[r_min, r_max]`.
+    ///
+    /// Has no effect on a resistor that is not modulated.
+    pub fn with_modulation_bounds(mut self, r_min: f64, r_max: f64) -> Self {
+        if let Some(ref mut modulation) = self.modulation {
+            modulation.r_min = r_min.max(MIN_RESISTANCE);
+            modulation.r_max = r_max.max(modulation.r_min);
         }
+        self
+    }
+
+    /// Give the resistance a relative tolerance, e.g. `0.05` for 5%.
+    pub fn with_tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
     }
 
     /// Check if this resistor is modulated.
@@ -83,10 +137,19 @@ impl Resistor {
             // When mod_value = 0: R_eff = R_base
             // When mod_value = 1 and depth = 1: R_eff = R_base * (1 + range)
             let factor = 1.0 + modulation.depth * modulation.range * mod_value;
-            self.effective_resistance = (self.resistance * factor).max(1e-12);
+            self.effective_resistance =
+                (self.resistance * factor).clamp(modulation.r_min, modulation.r_max);
         }
     }
 
+    /// Set the base resistance.
+    ///
+    /// Modulated resistors pick up the new value on their next update.
+    pub fn set_resistance(&mut self, resistance: f64) {
+        self.resistance = resistance.max(MIN_RESISTANCE);
+        self.effective_resistance = self.resistance;
+    }
+
     /// Get the conductance (1/R) using the effective resistance.
     pub fn conductance(&self) -> f64 {
         1.0 / self.effective_resistance
@@ -98,6 +161,18 @@ impl Resistor {
     }
 }
 
+/// Modulation configuration for a capacitor.
+#[derive(Debug, Clone)]
+pub struct CapacitorModulation {
+    /// Name of the control source (LFO or envelope follower)
+    pub source: String,
+    /// Modulation depth (0.0 to 1.0)
+    pub depth: f64,
+    /// How far a full-scale control multiplies the capacitance: at
+    /// depth=1.0 it varies from C_base to C_base * (1 + range)
+    pub range: f64,
+}
+
 /// A capacitor component.
 ///
 /// In discrete-time simulation, a capacitor is modeled using a companion model.
@@ -113,13 +188,25 @@ pub struct Capacitor {
     pub id: ComponentId,
     pub name: String,
     pub nodes: [NodeId; 2],
+    /// Base capacitance value (when modulation = 0)
     pub capacitance: f64,
+    /// Parallel leakage resistance (`None` means no leakage)
+    pub leakage: Option<f64>,
+    /// Optional modulation configuration
+    pub modulation: Option<CapacitorModulation>,
+    /// Current effective capacitance (updated each sample for modulated
+    /// capacitors)
+    pub effective_capacitance: f64,
+    /// Relative tolerance of the capacitance (0 = exact)
+    pub tolerance: f64,
 
     // State for discrete-time model
     /// Previous voltage across capacitor
     pub v_prev: f64,
     /// Previous current through capacitor
     pub i_prev: f64,
+    /// Voltage the capacitor starts from (initial condition)
+    pub initial_voltage: f64,
 }
 
 impl Capacitor {
@@ -130,18 +217,81 @@ impl Capacitor {
             name,
             nodes,
             capacitance,
+            leakage: None,
+            modulation: None,
+            effective_capacitance: capacitance,
+            tolerance: 0.0,
             v_prev: 0.0,
             i_prev: 0.0,
+            initial_voltage: 0.0,
         }
     }
 
-    /// Get the equivalent conductance for the trapezoidal companion model.
+    /// Return to the initial voltage, at rest.
+    pub fn reset_to_initial_conditions(&mut self) {
+        self.v_prev = self.initial_voltage;
+        self.i_prev = 0.0;
+    }
+
+    /// Add a parallel leakage resistance, as found in electrolytic capacitors.
+    pub fn with_leakage(mut self, resistance: f64) -> Self {
+        self.leakage = Some(resistance);
+        self
+    }
+
+    /// Give the capacitance a relative tolerance, e.g. `0.2` for 20%.
+    pub fn with_tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Attach a control source that sweeps the capacitance, as in a phaser.
+    pub fn with_modulation(mut self, source: String, depth: f64, range: f64) -> Self {
+        self.modulation = Some(CapacitorModulation {
+            source,
+            depth: depth.clamp(0.0, 1.0),
+            range: range.max(0.0),
+        });
+        self
+    }
+
+    /// Update the effective capacitance based on modulation signal.
+    ///
+    /// # Arguments
+    /// * `mod_value` - The control value (0.0 to 1.0)
+    pub fn update_modulation(&mut self, mod_value: f64) {
+        if let Some(ref modulation) = self.modulation {
+            // C_eff = C_base * (1 + depth * range * mod_value)
+            let factor = 1.0 + modulation.depth * modulation.range * mod_value;
+            self.effective_capacitance = (self.capacitance * factor).max(0.0);
+        }
+    }
+
+    /// Set the base capacitance.
+    ///
+    /// Modulated capacitors pick up the new value on their next update.
+    pub fn set_capacitance(&mut self, capacitance: f64) {
+        self.capacitance = capacitance;
+        self.effective_capacitance = capacitance;
+    }
+
+    /// Get the conductance of the leakage path (zero if there is none).
+    ///
+    /// This is stamped in parallel with the companion model and is not part
+    /// of the capacitor's own current history.
+    pub fn leakage_conductance(&self) -> f64 {
+        self.leakage.map(|r| 1.0 / r).unwrap_or(0.0)
+    }
+
+    /// Get the equivalent conductance for the companion model, using the
+    /// effective capacitance.
     ///
     /// For a capacitor with trapezoidal integration:
     ///   i(n) = G * v(n) - I_eq
-    /// where G = 2C/dt and I_eq = G*v(n-1) + i(n-1)
-    pub fn conductance(&self, dt: f64) -> f64 {
-        2.0 * self.capacitance / dt
+    /// where G = 2C/dt and I_eq = G*v(n-1) + i(n-1).
+    /// Backward Euler uses G = C/dt and I_eq = G*v(n-1).
+    pub fn conductance(&self, dt: f64, method: IntegrationMethod) -> f64 {
+        method.scale() * self.effective_capacitance / dt
     }
 
     /// Get the equivalent current source value for the companion model.
@@ -149,17 +299,24 @@ impl Capacitor {
     /// The companion current source represents the "history" term and
     /// should be SUBTRACTED from the current. In MNA terms, we add a
     /// negative current source (current flowing out of node n+).
-    pub fn current_source(&self, dt: f64) -> f64 {
-        // I_eq = (2C/dt) * v_prev + i_prev
+    pub fn current_source(&self, dt: f64, method: IntegrationMethod) -> f64 {
+        // I_eq = (2C/dt) * v_prev + i_prev, or (C/dt) * v_prev
         // This gets SUBTRACTED, so we return -I_eq to add as a source
-        -(self.conductance(dt) * self.v_prev + self.i_prev)
+        let history = match method {
+            IntegrationMethod::Trapezoidal => self.i_prev,
+            IntegrationMethod::BackwardEuler => 0.0,
+        };
+        -(self.conductance(dt, method) * self.v_prev + history)
     }
 
     /// Update the state after solving.
-    pub fn update_state(&mut self, v_new: f64, dt: f64) {
-        // i_new = (2C/dt) * (v_new - v_prev) - i_prev
-        let g = self.conductance(dt);
-        let i_new = g * (v_new - self.v_prev) - self.i_prev;
+    pub fn update_state(&mut self, v_new: f64, dt: f64, method: IntegrationMethod) {
+        // i_new = (2C/dt) * (v_new - v_prev) - i_prev, or (C/dt) * (v_new - v_prev)
+        let g = self.conductance(dt, method);
+        let i_new = match method {
+            IntegrationMethod::Trapezoidal => g * (v_new - self.v_prev) - self.i_prev,
+            IntegrationMethod::BackwardEuler => g * (v_new - self.v_prev),
+        };
         self.v_prev = v_new;
         self.i_prev = i_new;
     }
@@ -179,12 +336,16 @@ pub struct Inductor {
     pub nodes: [NodeId; 2],
     pub inductance: f64,
     pub branch: BranchId,
+    /// Relative tolerance of the inductance (0 = exact)
+    pub tolerance: f64,
 
     // State for discrete-time model
     /// Previous current through inductor
     pub i_prev: f64,
     /// Previous voltage across inductor
     pub v_prev: f64,
+    /// Current the inductor starts from (initial condition)
+    pub initial_current: f64,
 }
 
 impl Inductor {
@@ -202,27 +363,54 @@ impl Inductor {
             nodes,
             inductance,
             branch,
+            tolerance: 0.0,
             i_prev: 0.0,
             v_prev: 0.0,
+            initial_current: 0.0,
         }
     }
 
-    /// Get the equivalent resistance for the trapezoidal companion model.
-    pub fn resistance(&self, dt: f64) -> f64 {
-        2.0 * self.inductance / dt
+    /// Give the inductance a relative tolerance, e.g. `0.1` for 10%.
+    pub fn with_tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Set the inductance.
+    pub fn set_inductance(&mut self, inductance: f64) {
+        self.inductance = inductance;
+    }
+
+    /// Return to the initial current, at rest.
+    pub fn reset_to_initial_conditions(&mut self) {
+        self.i_prev = self.initial_current;
+        self.v_prev = 0.0;
+    }
+
+    /// Get the equivalent resistance for the companion model: 2L/dt for
+    /// trapezoidal integration, L/dt for backward Euler.
+    pub fn resistance(&self, dt: f64, method: IntegrationMethod) -> f64 {
+        method.scale() * self.inductance / dt
     }
 
     /// Get the equivalent voltage source value for the companion model.
-    pub fn voltage_source(&self, dt: f64) -> f64 {
-        // V_eq = (2L/dt) * i_prev + v_prev
-        self.resistance(dt) * self.i_prev + self.v_prev
+    pub fn voltage_source(&self, dt: f64, method: IntegrationMethod) -> f64 {
+        // V_eq = (2L/dt) * i_prev + v_prev, or (L/dt) * i_prev
+        let history = match method {
+            IntegrationMethod::Trapezoidal => self.v_prev,
+            IntegrationMethod::BackwardEuler => 0.0,
+        };
+        self.resistance(dt, method) * self.i_prev + history
     }
 
     /// Update the state after solving.
-    pub fn update_state(&mut self, i_new: f64, dt: f64) {
-        // v_new = (2L/dt) * (i_new - i_prev) - v_prev
-        let r = self.resistance(dt);
-        let v_new = r * (i_new - self.i_prev) - self.v_prev;
+    pub fn update_state(&mut self, i_new: f64, dt: f64, method: IntegrationMethod) {
+        // v_new = (2L/dt) * (i_new - i_prev) - v_prev, or (L/dt) * (i_new - i_prev)
+        let r = self.resistance(dt, method);
+        let v_new = match method {
+            IntegrationMethod::Trapezoidal => r * (i_new - self.i_prev) - self.v_prev,
+            IntegrationMethod::BackwardEuler => r * (i_new - self.i_prev),
+        };
         self.i_prev = i_new;
         self.v_prev = v_new;
     }
@@ -243,6 +431,33 @@ mod tests {
         assert!((r.conductance() - 0.001).abs() < 1e-10);
     }
 
+    #[test]
+    fn test_modulated_resistor_stays_within_bounds() {
+        use crate::components::{Lfo, LfoShape};
+
+        // Unbounded, depth=1 range=100 would swing 10k up to 1.01M
+        let mut r = Resistor::new_modulated(
+            ComponentId(0),
+            "R1".to_string(),
+            [NodeId(1), NodeId(0)],
+            10e3,
+            "LFO1".to_string(),
+            1.0,
+            100.0,
+        )
+        .with_modulation_bounds(20e3, 200e3);
+        let mut lfo = Lfo::new("LFO1".to_string(), 100.0, LfoShape::Sine, 48000.0);
+
+        let (mut lowest, mut highest) = (f64::INFINITY, 0.0f64);
+        for _ in 0..480 {
+            r.update_modulation(lfo.tick());
+            lowest = lowest.min(r.effective_resistance);
+            highest = highest.max(r.effective_resistance);
+        }
+        assert_eq!(lowest, 20e3);
+        assert_eq!(highest, 200e3);
+    }
+
     #[test]
     fn test_capacitor_companion_model() {
         let mut c = Capacitor::new(
@@ -252,16 +467,22 @@ mod tests {
             1e-6, // 1µF
         );
         let dt = 1.0 / 48000.0;
-        let g = c.conductance(dt);
+        let trap = IntegrationMethod::Trapezoidal;
+        let g = c.conductance(dt, trap);
 
         // G_eq = 2C/dt = 2 * 1e-6 / (1/48000) = 96 mS
         assert!((g - 0.096).abs() < 1e-6);
 
         // Initial current source should be 0
-        assert!((c.current_source(dt)).abs() < 1e-10);
+        assert!((c.current_source(dt, trap)).abs() < 1e-10);
 
         // Update state with 1V across cap
-        c.update_state(1.0, dt);
+        c.update_state(1.0, dt, trap);
         assert!((c.v_prev - 1.0).abs() < 1e-10);
+
+        // Backward Euler halves the conductance and keeps no current history
+        let be = IntegrationMethod::BackwardEuler;
+        assert!((c.conductance(dt, be) - 0.048).abs() < 1e-6);
+        assert!((c.current_source(dt, be) + 0.048).abs() < 1e-6);
     }
 }
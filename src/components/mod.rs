@@ -1,34 +1,56 @@
 //! Component models for circuit simulation.
 //!
 //! This module provides models for all supported circuit components:
-//! - Linear: Resistor, Capacitor, Inductor
-//! - Sources: Voltage Source, Current Source
-//! - Nonlinear: Diode, BJT, Op-Amp
+//! - Linear: Resistor, Capacitor, Inductor, Transformer
+//! - Sources: Voltage Source, Current Source, Ammeter, Noise Source
+//! - Nonlinear: Diode, BJT, JFET, MOSFET, Triode, Op-Amp
 //! - Controls: Potentiometer, Switch
-//! - Digital: Delay Line, FDN Reverb
-//! - Modulation: LFO
+//! - Hysteretic: resistor with asymmetric lag behind its drive
+//! - Nonlinear resistor: resistance looked up from a control signal
+//! - Digital: Delay Line, FDN Reverb, Stereo Widener
+//! - Modulation: LFO, Envelope Follower
 //!
 //! Each component implements stamping into the MNA matrix.
 
-mod linear;
-mod sources;
-mod diode;
 mod bjt;
-mod opamp;
 mod controls;
 mod delay;
-mod reverb;
+mod diode;
+mod envelope;
+mod hysteretic;
+mod jfet;
 mod lfo;
+mod linear;
+mod mosfet;
+mod noise;
+mod nonlinear_resistor;
+mod opamp;
+mod reverb;
+mod sources;
+mod transformer;
+mod triode;
+mod widener;
 
-pub use linear::{Resistor, Capacitor, Inductor};
-pub use sources::{VoltageSource, CurrentSource};
-pub use diode::Diode;
 pub use bjt::{Bjt, BjtType};
-pub use opamp::OpAmp;
-pub use controls::{Potentiometer, Switch};
+pub use controls::{PotModulation, PotTaper, Potentiometer, Switch, SwitchPoles};
 pub use delay::DelayLine;
-pub use reverb::{FdnReverb, ReverbParams};
-pub use lfo::{Lfo, LfoShape};
+pub use diode::{Diode, DiodeParams};
+pub use envelope::EnvelopeFollower;
+pub use hysteretic::Hysteretic;
+pub use jfet::{Jfet, JfetType};
+pub use lfo::{Lfo, LfoShape, BAND_LIMITED_RATE};
+pub use linear::{
+    Capacitor, CapacitorModulation, Inductor, IntegrationMethod, Resistor, ResistorModulation,
+};
+pub use mosfet::{Mosfet, MosfetType};
+pub use noise::{NoiseSource, DEFAULT_NOISE_SEED};
+pub use nonlinear_resistor::NonlinearResistor;
+pub use opamp::{OpAmp, OpAmpModel, OpAmpParams};
+pub use reverb::{FdnReverb, ReverbParams, FDN_SIZES};
+pub use sources::{Ammeter, CurrentSource, VoltageSource};
+pub use transformer::Transformer;
+pub use triode::{Triode, TriodeParams};
+pub use widener::Widener;
 
 use crate::circuit::{BranchId, ComponentId, NodeId};
 use crate::dsl::{ComponentDef, ComponentType, ModelDef};
@@ -40,13 +62,21 @@ pub enum Component {
     Resistor(Resistor),
     Capacitor(Capacitor),
     Inductor(Inductor),
+    Transformer(Transformer),
     VoltageSource(VoltageSource),
     CurrentSource(CurrentSource),
+    Noise(NoiseSource),
+    Ammeter(Ammeter),
     Diode(Diode),
     Bjt(Bjt),
+    Jfet(Jfet),
+    Mosfet(Mosfet),
+    Triode(Triode),
     OpAmp(OpAmp),
     Potentiometer(Potentiometer),
     Switch(Switch),
+    Hysteretic(Hysteretic),
+    NonlinearResistor(NonlinearResistor),
 }
 
 impl Component {
@@ -60,31 +90,35 @@ impl Component {
     ) -> Result<Self> {
         match def.component_type {
             ComponentType::Resistor => {
-                let value = def.value.ok_or_else(|| {
-                    PedalerError::invalid_component(&def.name, def.line, "resistor requires a value")
-                })?;
+                let value = positive_value(def, "resistor")?;
 
                 // Check for modulation parameters
-                if let Some(lfo_name) = def.params.get("mod").or(def.params.get("lfo")) {
+                let mut resistor = if let Some(lfo_name) = def
+                    .string_params
+                    .get("mod")
+                    .or(def.string_params.get("lfo"))
+                {
                     // Modulated resistor: mod=lfo_name depth=0.8 range=4
-                    // We store lfo_name as a string reference through the value
-                    let lfo_name_str = format!("LFO{}", *lfo_name as u32); // Temp workaround
                     let depth = def.params.get("depth").copied().unwrap_or(0.8);
                     let range = def.params.get("range").copied().unwrap_or(4.0);
-                    Ok(Component::Resistor(Resistor::new_modulated(
+                    Resistor::new_modulated(
                         id,
                         def.name.clone(),
                         [nodes[0], nodes[1]],
                         value,
-                        lfo_name_str,
+                        lfo_name.clone(),
                         depth,
                         range,
-                    )))
-                } else if let Some(lfo_ref) = def.model_ref.as_ref().filter(|s| s.to_uppercase().starts_with("LFO")) {
+                    )
+                } else if let Some(lfo_ref) = def
+                    .model_ref
+                    .as_ref()
+                    .filter(|s| s.to_uppercase().starts_with("LFO"))
+                {
                     // Alternative syntax: R1 n1 n2 10k LFO1 depth=0.8
                     let depth = def.params.get("depth").copied().unwrap_or(0.8);
                     let range = def.params.get("range").copied().unwrap_or(4.0);
-                    Ok(Component::Resistor(Resistor::new_modulated(
+                    Resistor::new_modulated(
                         id,
                         def.name.clone(),
                         [nodes[0], nodes[1]],
@@ -92,41 +126,116 @@ impl Component {
                         lfo_ref.clone(),
                         depth,
                         range,
-                    )))
+                    )
                 } else {
-                    Ok(Component::Resistor(Resistor::new(
-                        id,
-                        def.name.clone(),
-                        [nodes[0], nodes[1]],
-                        value,
-                    )))
+                    Resistor::new(id, def.name.clone(), [nodes[0], nodes[1]], value)
+                };
+
+                // Bounds on the modulated value: rmin=1k rmax=100k
+                if resistor.is_modulated() {
+                    let r_min = positive_param(def, "rmin", linear::MIN_RESISTANCE)?;
+                    let r_max = positive_param(def, "rmax", f64::INFINITY)?;
+                    if r_min > r_max {
+                        return Err(PedalerError::invalid_parameter(
+                            &def.name,
+                            "rmin",
+                            format!("must not exceed rmax ({}), got {}", r_max, r_min),
+                        ));
+                    }
+                    resistor = resistor.with_modulation_bounds(r_min, r_max);
                 }
+                Ok(Component::Resistor(
+                    resistor.with_tolerance(tolerance(def)?),
+                ))
             }
 
             ComponentType::Capacitor => {
-                let value = def.value.ok_or_else(|| {
-                    PedalerError::invalid_component(&def.name, def.line, "capacitor requires a value")
-                })?;
-                Ok(Component::Capacitor(Capacitor::new(
-                    id,
-                    def.name.clone(),
-                    [nodes[0], nodes[1]],
-                    value,
-                )))
+                let value = positive_value(def, "capacitor")?;
+                let mut cap = Capacitor::new(id, def.name.clone(), [nodes[0], nodes[1]], value);
+                // Leaky capacitor: rleak=1M
+                if let Some(&rleak) = def.params.get("rleak") {
+                    if rleak.is_nan() || rleak <= 0.0 {
+                        return Err(PedalerError::invalid_parameter(
+                            &def.name,
+                            "rleak",
+                            format!("must be positive, got {}", rleak),
+                        ));
+                    }
+                    cap = cap.with_leakage(rleak);
+                }
+                // Modulated capacitor: mod=LFO1 depth=0.8 range=4
+                if let Some(source) = def
+                    .string_params
+                    .get("mod")
+                    .or(def.string_params.get("lfo"))
+                {
+                    let depth = def.params.get("depth").copied().unwrap_or(0.8);
+                    if !(0.0..=1.0).contains(&depth) {
+                        return Err(PedalerError::invalid_parameter(
+                            &def.name,
+                            "depth",
+                            format!("must be between 0 and 1, got {}", depth),
+                        ));
+                    }
+                    let range = def.params.get("range").copied().unwrap_or(4.0);
+                    if !(range.is_finite() && range >= 0.0) {
+                        return Err(PedalerError::invalid_parameter(
+                            &def.name,
+                            "range",
+                            format!("must not be negative, got {}", range),
+                        ));
+                    }
+                    cap = cap.with_modulation(source.clone(), depth, range);
+                }
+                Ok(Component::Capacitor(cap.with_tolerance(tolerance(def)?)))
             }
 
             ComponentType::Inductor => {
-                let value = def.value.ok_or_else(|| {
-                    PedalerError::invalid_component(&def.name, def.line, "inductor requires a value")
-                })?;
+                let value = positive_value(def, "inductor")?;
                 let branch = BranchId(*branch_counter);
                 *branch_counter += 1;
-                Ok(Component::Inductor(Inductor::new(
+                Ok(Component::Inductor(
+                    Inductor::new(id, def.name.clone(), [nodes[0], nodes[1]], value, branch)
+                        .with_tolerance(tolerance(def)?),
+                ))
+            }
+
+            ComponentType::Transformer => {
+                let (Some(l_pri), Some(&l_sec), Some(&k)) =
+                    (def.value, def.params.get("lsec"), def.params.get("k"))
+                else {
+                    return Err(PedalerError::invalid_component(
+                        &def.name,
+                        def.line,
+                        "transformer requires primary and secondary inductances and a coupling factor",
+                    ));
+                };
+                for (param, l) in [("lpri", l_pri), ("lsec", l_sec)] {
+                    if l.is_nan() || l <= 0.0 {
+                        return Err(PedalerError::invalid_parameter(
+                            &def.name,
+                            param,
+                            format!("must be positive, got {}", l),
+                        ));
+                    }
+                }
+                if k.is_nan() || k <= 0.0 || k > 1.0 {
+                    return Err(PedalerError::invalid_parameter(
+                        &def.name,
+                        "k",
+                        format!("coupling must be in (0, 1], got {}", k),
+                    ));
+                }
+                let branches = [BranchId(*branch_counter), BranchId(*branch_counter + 1)];
+                *branch_counter += 2;
+                Ok(Component::Transformer(Transformer::new(
                     id,
                     def.name.clone(),
-                    [nodes[0], nodes[1]],
-                    value,
-                    branch,
+                    [nodes[0], nodes[1], nodes[2], nodes[3]],
+                    l_pri,
+                    l_sec,
+                    k,
+                    branches,
                 )))
             }
 
@@ -155,9 +264,55 @@ impl Component {
                 )))
             }
 
+            ComponentType::Noise => {
+                let amplitude = def.value.ok_or_else(|| {
+                    PedalerError::invalid_component(
+                        &def.name,
+                        def.line,
+                        "noise source requires an amplitude",
+                    )
+                })?;
+                if amplitude.is_nan() || amplitude < 0.0 {
+                    return Err(PedalerError::invalid_parameter(
+                        &def.name,
+                        "amplitude",
+                        format!("must not be negative, got {}", amplitude),
+                    ));
+                }
+                let seed = match def.params.get("seed") {
+                    Some(&seed) if seed.is_nan() || seed < 0.0 || seed.fract() != 0.0 => {
+                        return Err(PedalerError::invalid_parameter(
+                            &def.name,
+                            "seed",
+                            format!("must be a non-negative integer, got {}", seed),
+                        ));
+                    }
+                    Some(&seed) => seed as u64,
+                    None => DEFAULT_NOISE_SEED,
+                };
+                Ok(Component::Noise(NoiseSource::new(
+                    id,
+                    def.name.clone(),
+                    [nodes[0], nodes[1]],
+                    amplitude,
+                    seed,
+                )))
+            }
+
+            ComponentType::Ammeter => {
+                let branch = BranchId(*branch_counter);
+                *branch_counter += 1;
+                Ok(Component::Ammeter(Ammeter::new(
+                    id,
+                    def.name.clone(),
+                    [nodes[0], nodes[1]],
+                    branch,
+                )))
+            }
+
             ComponentType::Diode => {
                 let params = if let Some(m) = model {
-                    diode::DiodeParams::from_model(m)
+                    diode::DiodeParams::from_model(m)?
                 } else {
                     diode::DiodeParams::default()
                 };
@@ -184,57 +339,241 @@ impl Component {
                 )))
             }
 
+            ComponentType::Jfet => {
+                let (jfet_type, params) = if let Some(m) = model {
+                    jfet::JfetParams::from_model(m)?
+                } else {
+                    (JfetType::N, jfet::JfetParams::default())
+                };
+                Ok(Component::Jfet(Jfet::new(
+                    id,
+                    def.name.clone(),
+                    [nodes[0], nodes[1], nodes[2]], // D, G, S
+                    jfet_type,
+                    params,
+                )))
+            }
+
+            ComponentType::Mosfet => {
+                let (mosfet_type, params) = if let Some(m) = model {
+                    mosfet::MosfetParams::from_model(m)?
+                } else {
+                    (MosfetType::N, mosfet::MosfetParams::default())
+                };
+                Ok(Component::Mosfet(Mosfet::new(
+                    id,
+                    def.name.clone(),
+                    [nodes[0], nodes[1], nodes[2]], // D, G, S
+                    mosfet_type,
+                    params,
+                )))
+            }
+
+            ComponentType::Triode => {
+                let params = if let Some(m) = model {
+                    triode::TriodeParams::from_model(m)?
+                } else {
+                    triode::TriodeParams::default()
+                };
+                Ok(Component::Triode(Triode::new(
+                    id,
+                    def.name.clone(),
+                    [nodes[0], nodes[1], nodes[2]], // P, G, K
+                    params,
+                )))
+            }
+
             ComponentType::OpAmp => {
                 let params = if let Some(m) = model {
                     opamp::OpAmpParams::from_model(m)
                 } else {
                     opamp::OpAmpParams::ideal()
                 };
-                // Op-amp now uses VCCS model, no branch needed
-                // We use a dummy branch ID
-                let branch = BranchId(0);
+                // The nullor and high-gain models carry their output current
+                // as a branch
+                let branch = if params.uses_branch() {
+                    let branch = BranchId(*branch_counter);
+                    *branch_counter += 1;
+                    Some(branch)
+                } else {
+                    None
+                };
+                // So does a dominant pole, for its state
+                let pole = if params.has_pole() {
+                    let pole = BranchId(*branch_counter);
+                    *branch_counter += 1;
+                    Some(pole)
+                } else {
+                    None
+                };
                 Ok(Component::OpAmp(OpAmp::new(
                     id,
                     def.name.clone(),
                     [nodes[0], nodes[1], nodes[2]], // out, in+, in-
                     params,
                     branch,
+                    pole,
                 )))
             }
 
             ComponentType::Potentiometer => {
                 let total_resistance = def.value.ok_or_else(|| {
-                    PedalerError::invalid_component(&def.name, def.line, "potentiometer requires a value")
+                    PedalerError::invalid_component(
+                        &def.name,
+                        def.line,
+                        "potentiometer requires a value",
+                    )
                 })?;
                 let position = def.params.get("position").copied().unwrap_or(0.5);
-                Ok(Component::Potentiometer(Potentiometer::new(
+                let mut pot = Potentiometer::new(
                     id,
                     def.name.clone(),
                     [nodes[0], nodes[1], nodes[2]], // n1, wiper, n2
                     total_resistance,
                     position,
-                )))
+                );
+                // Audio taper: taper=log
+                if let Some(name) = def.string_params.get("taper") {
+                    let taper = PotTaper::from_str(name).ok_or_else(|| {
+                        PedalerError::invalid_parameter(
+                            &def.name,
+                            "taper",
+                            format!("expected linear, log or reverselog, got '{}'", name),
+                        )
+                    })?;
+                    pot = pot.with_taper(taper);
+                }
+                // Modulated pot: mod=ENV1 depth=0.6
+                if let Some(source) = def.string_params.get("mod") {
+                    let depth = def.params.get("depth").copied().unwrap_or(0.5);
+                    pot.set_modulation(source.clone(), depth);
+                }
+                Ok(Component::Potentiometer(pot))
             }
 
             ComponentType::Switch => {
-                let closed = def.params.get("state").map(|v| *v > 0.5).unwrap_or(true);
+                let state = def.params.get("state").or(def.value.as_ref());
+                let closed = state.map(|v| *v > 0.5).unwrap_or(true);
                 Ok(Component::Switch(Switch::new(
                     id,
                     def.name.clone(),
-                    [nodes[0], nodes[1]],
+                    nodes.to_vec(), // [n1, n2] or [common, t1, t2]
                     closed,
                 )))
             }
 
-            // Digital effects and LFOs are handled separately in Circuit::from_ast
-            // and should never reach this function
-            ComponentType::Delay | ComponentType::Reverb | ComponentType::Lfo => {
-                Err(PedalerError::invalid_component(
-                    &def.name,
-                    def.line,
-                    "digital effects and LFOs should be handled separately",
-                ))
+            ComponentType::Hysteretic => {
+                let rmin = positive_param(def, "rmin", 1e3)?;
+                let rmax = positive_param(def, "rmax", 1e6)?;
+                if rmin > rmax {
+                    return Err(PedalerError::invalid_parameter(
+                        &def.name,
+                        "rmin",
+                        format!("must not exceed rmax ({}), got {}", rmax, rmin),
+                    ));
+                }
+                let mut hyst = Hysteretic::new(
+                    id,
+                    def.name.clone(),
+                    [nodes[0], nodes[1]],
+                    rmin,
+                    rmax,
+                    positive_param(def, "tau_rise", 0.01)?,
+                    positive_param(def, "tau_fall", 0.1)?,
+                )
+                .with_vref(positive_param(def, "vref", 1.0)?);
+                // Driven by a control signal: mod=ENV1
+                if let Some(source) = def.string_params.get("mod") {
+                    hyst = hyst.with_control(source.clone());
+                }
+                Ok(Component::Hysteretic(hyst))
+            }
+
+            ComponentType::NonlinearResistor => {
+                let control = def.string_params.get("mod").ok_or_else(|| {
+                    PedalerError::invalid_component(
+                        &def.name,
+                        def.line,
+                        "nonlinear resistor requires a control signal (mod=)",
+                    )
+                })?;
+                let list = |key: &str| -> Result<Vec<f64>> {
+                    let text = match (def.string_params.get(key), def.params.get(key)) {
+                        (Some(text), _) => text.clone(),
+                        (None, Some(v)) => v.to_string(),
+                        (None, None) => {
+                            return Err(PedalerError::invalid_component(
+                                &def.name,
+                                def.line,
+                                format!("nonlinear resistor requires a curve ({}=)", key),
+                            ))
+                        }
+                    };
+                    text.split(',')
+                        .map(|item| {
+                            crate::dsl::parse_value(item).ok_or_else(|| {
+                                PedalerError::invalid_parameter(
+                                    &def.name,
+                                    key,
+                                    format!("invalid value '{}'", item),
+                                )
+                            })
+                        })
+                        .collect()
+                };
+                let controls = list("ctl")?;
+                let resistances = list("r")?;
+                if controls.len() != resistances.len() || controls.len() < 2 {
+                    return Err(PedalerError::invalid_parameter(
+                        &def.name,
+                        "r",
+                        format!(
+                            "needs one resistance per control point and at least two points, got {} and {}",
+                            resistances.len(),
+                            controls.len()
+                        ),
+                    ));
+                }
+                if controls.iter().any(|x| !x.is_finite())
+                    || controls.windows(2).any(|w| w[1] <= w[0])
+                {
+                    return Err(PedalerError::invalid_parameter(
+                        &def.name,
+                        "ctl",
+                        "control points must be finite and strictly increasing",
+                    ));
+                }
+                if let Some(&r) = resistances.iter().find(|&&r| r.is_nan() || r <= 0.0) {
+                    return Err(PedalerError::invalid_parameter(
+                        &def.name,
+                        "r",
+                        format!("must be positive, got {}", r),
+                    ));
+                }
+                Ok(Component::NonlinearResistor(NonlinearResistor::new(
+                    id,
+                    def.name.clone(),
+                    [nodes[0], nodes[1]],
+                    control.clone(),
+                    controls.into_iter().zip(resistances).collect(),
+                )))
             }
+
+            // Digital effects, control sources, diode and tone stacks and
+            // subcircuit instances are handled separately in Circuit::from_ast
+            // and should never reach this function
+            ComponentType::Delay
+            | ComponentType::Reverb
+            | ComponentType::Widen
+            | ComponentType::Lfo
+            | ComponentType::Envelope
+            | ComponentType::DiodeStack
+            | ComponentType::ToneStack
+            | ComponentType::Subcircuit => Err(PedalerError::invalid_component(
+                &def.name,
+                def.line,
+                "digital effects, LFOs, diode stacks and subcircuits should be handled separately",
+            )),
         }
     }
 
@@ -244,13 +583,21 @@ impl Component {
             Component::Resistor(r) => r.id,
             Component::Capacitor(c) => c.id,
             Component::Inductor(l) => l.id,
+            Component::Transformer(t) => t.id,
             Component::VoltageSource(v) => v.id,
             Component::CurrentSource(i) => i.id,
+            Component::Noise(n) => n.id,
+            Component::Ammeter(a) => a.id,
             Component::Diode(d) => d.id,
             Component::Bjt(q) => q.id,
+            Component::Jfet(j) => j.id,
+            Component::Mosfet(m) => m.id,
+            Component::Triode(t) => t.id,
             Component::OpAmp(o) => o.id,
             Component::Potentiometer(p) => p.id,
             Component::Switch(s) => s.id,
+            Component::Hysteretic(h) => h.id,
+            Component::NonlinearResistor(r) => r.id,
         }
     }
 
@@ -260,18 +607,227 @@ impl Component {
             Component::Resistor(r) => &r.name,
             Component::Capacitor(c) => &c.name,
             Component::Inductor(l) => &l.name,
+            Component::Transformer(t) => &t.name,
             Component::VoltageSource(v) => &v.name,
             Component::CurrentSource(i) => &i.name,
+            Component::Noise(n) => &n.name,
+            Component::Ammeter(a) => &a.name,
             Component::Diode(d) => &d.name,
             Component::Bjt(q) => &q.name,
+            Component::Jfet(j) => &j.name,
+            Component::Mosfet(m) => &m.name,
+            Component::Triode(t) => &t.name,
             Component::OpAmp(o) => &o.name,
             Component::Potentiometer(p) => &p.name,
             Component::Switch(s) => &s.name,
+            Component::Hysteretic(h) => &h.name,
+            Component::NonlinearResistor(r) => &r.name,
+        }
+    }
+
+    /// Get the nodes this component connects to.
+    pub fn nodes(&self) -> &[NodeId] {
+        match self {
+            Component::Resistor(r) => &r.nodes,
+            Component::Capacitor(c) => &c.nodes,
+            Component::Inductor(l) => &l.nodes,
+            Component::Transformer(t) => &t.nodes,
+            Component::VoltageSource(v) => &v.nodes,
+            Component::CurrentSource(i) => &i.nodes,
+            Component::Noise(n) => &n.nodes,
+            Component::Ammeter(a) => &a.nodes,
+            Component::Diode(d) => &d.nodes,
+            Component::Bjt(q) => &q.nodes,
+            Component::Jfet(j) => &j.nodes,
+            Component::Mosfet(m) => &m.nodes,
+            Component::Triode(t) => &t.nodes,
+            Component::OpAmp(o) => &o.nodes,
+            Component::Potentiometer(p) => &p.nodes,
+            Component::Switch(s) => &s.nodes,
+            Component::Hysteretic(h) => &h.nodes,
+            Component::NonlinearResistor(r) => &r.nodes,
         }
     }
 
     /// Check if this component is nonlinear (requires Newton-Raphson iteration).
     pub fn is_nonlinear(&self) -> bool {
-        matches!(self, Component::Diode(_) | Component::Bjt(_))
+        match self {
+            Component::Diode(_)
+            | Component::Bjt(_)
+            | Component::Jfet(_)
+            | Component::Mosfet(_)
+            | Component::Triode(_) => true,
+            // Clipping at the rails; the nullor ignores them
+            Component::OpAmp(op) => op.params.model != OpAmpModel::Nullor,
+            _ => false,
+        }
+    }
+
+    /// Check if this component stores energy (has per-sample state).
+    pub fn is_reactive(&self) -> bool {
+        matches!(
+            self,
+            Component::Capacitor(_) | Component::Inductor(_) | Component::Transformer(_)
+        )
+    }
+
+    /// Check if this component's value is driven by an LFO or envelope.
+    pub fn is_modulated(&self) -> bool {
+        self.modulation_source().is_some()
+    }
+
+    /// Return the component's state to its initial conditions. Components
+    /// without initial conditions are left as they are.
+    pub fn reset_to_initial_conditions(&mut self) {
+        match self {
+            Component::Capacitor(c) => c.reset_to_initial_conditions(),
+            Component::Inductor(l) => l.reset_to_initial_conditions(),
+            Component::Transformer(t) => t.reset_to_initial_conditions(),
+            Component::Diode(d) => d.reset_to_initial_conditions(),
+            Component::Bjt(q) => q.reset_to_initial_conditions(),
+            Component::Jfet(j) => j.reset_to_initial_conditions(),
+            Component::Mosfet(m) => m.reset_to_initial_conditions(),
+            Component::Triode(t) => t.reset_to_initial_conditions(),
+            Component::OpAmp(op) => op.reset_to_initial_conditions(),
+            _ => {}
+        }
+    }
+
+    /// Name of the LFO or envelope follower driving this component, if any.
+    pub fn modulation_source(&self) -> Option<&str> {
+        match self {
+            Component::Resistor(r) => r.modulation.as_ref().map(|m| m.lfo_name.as_str()),
+            Component::Capacitor(c) => c.modulation.as_ref().map(|m| m.source.as_str()),
+            Component::Potentiometer(p) => p.modulation.as_ref().map(|m| m.source.as_str()),
+            Component::Hysteretic(h) => h.control.as_deref(),
+            Component::NonlinearResistor(r) => Some(r.control.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Present value of an independent source: volts for a voltage source,
+    /// amperes for a current source. `None` for other components.
+    pub fn source_value(&self) -> Option<f64> {
+        match self {
+            Component::VoltageSource(vs) => Some(vs.voltage()),
+            Component::CurrentSource(is) => Some(is.current()),
+            _ => None,
+        }
+    }
+
+    /// Set the value of an independent source, as for
+    /// [`source_value`](Self::source_value). Other components are left alone.
+    pub fn set_source_value(&mut self, value: f64) {
+        match self {
+            Component::VoltageSource(vs) => vs.set_value(value),
+            Component::CurrentSource(is) => is.set_value(value),
+            _ => {}
+        }
+    }
+}
+
+/// Magnitude below which recirculating effect state is flushed to zero.
+///
+/// This is about -400 dBFS, far below anything audible, but well above the
+/// point where f32 values turn subnormal and get slow on some CPUs.
+const DENORMAL_THRESHOLD: f32 = 1e-20;
+
+/// Flush a feedback value to zero once it is too small to matter, so decaying
+/// tails end in exact silence instead of lingering as subnormal floats.
+#[inline]
+fn flush_denormal(x: f32) -> f32 {
+    if x.abs() < DENORMAL_THRESHOLD {
+        0.0
+    } else {
+        x
+    }
+}
+
+/// Thermal voltage `kT/q` at `temp` kelvin, scaled from
+/// [`THERMAL_VOLTAGE`](crate::THERMAL_VOLTAGE) at the nominal temperature.
+fn thermal_voltage(temp: f64) -> f64 {
+    crate::THERMAL_VOLTAGE * temp / crate::NOMINAL_TEMPERATURE
+}
+
+/// Saturation current scale factor `Is(T) / Is(T_nom)` of a junction with
+/// bandgap `eg` (eV) and temperature exponent `xti`, by the SPICE law:
+///   Is(T) = Is * (T/Tnom)^XTI * exp(Eg/Vt(T) * (T/Tnom - 1))
+fn saturation_current_scale(temp: f64, eg: f64, xti: f64) -> f64 {
+    let ratio = temp / crate::NOMINAL_TEMPERATURE;
+    ratio.powf(xti) * (eg / thermal_voltage(temp) * (ratio - 1.0)).exp()
+}
+
+/// Get the value of a resistor, capacitor or inductor, which must be given
+/// and strictly positive: zero or negative values would short the circuit
+/// or make the companion conductance infinite.
+fn positive_value(def: &ComponentDef, kind: &str) -> Result<f64> {
+    match def.value {
+        None => Err(PedalerError::invalid_component(
+            &def.name,
+            def.line,
+            format!("{} requires a value", kind),
+        )),
+        Some(v) if v.is_nan() || v <= 0.0 => Err(PedalerError::invalid_parameter(
+            &def.name,
+            "value",
+            format!("must be positive, got {}", v),
+        )),
+        Some(v) => Ok(v),
+    }
+}
+
+/// Read an optional component parameter, which must be strictly positive
+/// when given.
+fn positive_param(def: &ComponentDef, key: &str, default: f64) -> Result<f64> {
+    match def.params.get(key) {
+        Some(&v) if v.is_nan() || v <= 0.0 => Err(PedalerError::invalid_parameter(
+            &def.name,
+            key,
+            format!("must be positive, got {}", v),
+        )),
+        Some(&v) => Ok(v),
+        None => Ok(default),
+    }
+}
+
+/// Read a component's relative tolerance, `tol=5%` (or `tolerance=`),
+/// which must lie in `[0, 1)`. Absent means exact.
+fn tolerance(def: &ComponentDef) -> Result<f64> {
+    match def.params.get("tol").or(def.params.get("tolerance")) {
+        Some(&tol) if !(0.0..1.0).contains(&tol) => Err(PedalerError::invalid_parameter(
+            &def.name,
+            "tol",
+            format!("must be at least 0 and below 100%, got {}", tol),
+        )),
+        Some(&tol) => Ok(tol),
+        None => Ok(0.0),
+    }
+}
+
+/// Look up a model parameter that must be strictly positive.
+///
+/// Returns `Ok(None)` if the parameter is absent.
+fn positive_model_param(model: &ModelDef, param: &str) -> Result<Option<f64>> {
+    match model.params.get(param) {
+        Some(&v) if v.is_nan() || v <= 0.0 => Err(PedalerError::invalid_parameter(
+            &model.name,
+            param,
+            format!("must be positive, got {}", v),
+        )),
+        other => Ok(other.copied()),
+    }
+}
+
+/// Look up a model parameter that must not be negative.
+///
+/// Returns `Ok(None)` if the parameter is absent.
+fn non_negative_model_param(model: &ModelDef, param: &str) -> Result<Option<f64>> {
+    match model.params.get(param) {
+        Some(&v) if v.is_nan() || v < 0.0 => Err(PedalerError::invalid_parameter(
+            &model.name,
+            param,
+            format!("must not be negative, got {}", v),
+        )),
+        other => Ok(other.copied()),
     }
 }
@@ -0,0 +1,332 @@
+//! MOSFET (Metal-Oxide-Semiconductor Field-Effect Transistor) model.
+//!
+//! Uses the Shichman-Hodges (SPICE level 1) square-law model for
+//! enhancement-mode N- and P-channel devices, with channel-length
+//! modulation. The gate is insulated and draws no current, and the body is
+//! tied to the source, so there is no body effect or body diode.
+//!
+//! For an N-channel device with `Vgst = Vgs - Vth`:
+//! - cutoff (`Vgst <= 0`): `Id = 0`
+//! - triode (`Vds < Vgst`): `Id = kp * (Vgst * Vds - Vds^2 / 2) * (1 + lambda * Vds)`
+//! - saturation: `Id = kp / 2 * Vgst^2 * (1 + lambda * Vds)`
+//!
+//! The channel is symmetric: with `Vds < 0`, drain and source swap roles.
+//! A P-channel device is the N-channel one with every voltage and current
+//! negated; as in SPICE, its threshold is given as a negative voltage.
+
+use crate::circuit::{ComponentId, NodeId};
+use crate::dsl::{ModelDef, ModelType};
+use crate::error::{PedalerError, Result};
+
+/// MOSFET channel type (N or P).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MosfetType {
+    N,
+    P,
+}
+
+/// Parameters for a MOSFET model.
+#[derive(Debug, Clone)]
+pub struct MosfetParams {
+    /// Threshold voltage, positive for NMOS and negative for PMOS
+    pub vth: f64,
+    /// Transconductance parameter (A/V²)
+    pub kp: f64,
+    /// Channel-length modulation (1/V)
+    pub lambda: f64,
+}
+
+impl Default for MosfetParams {
+    fn default() -> Self {
+        Self {
+            vth: 2.0,
+            kp: 0.1,
+            lambda: 0.0,
+        }
+    }
+}
+
+impl MosfetParams {
+    /// Create parameters from a model definition.
+    ///
+    /// Returns `InvalidParameter` for out-of-range values, e.g. a
+    /// non-positive `kp`, a negative `lambda`, or a threshold of the wrong
+    /// sign for an enhancement-mode device.
+    pub fn from_model(model: &ModelDef) -> Result<(MosfetType, Self)> {
+        let mosfet_type = match model.model_type {
+            ModelType::MosfetN => MosfetType::N,
+            ModelType::MosfetP => MosfetType::P,
+            _ => {
+                return Err(PedalerError::InvalidParameter {
+                    component: model.name.clone(),
+                    param: "type".to_string(),
+                    message: "expected NMOS or PMOS model type".to_string(),
+                });
+            }
+        };
+
+        let mut params = Self::default();
+        if mosfet_type == MosfetType::P {
+            params.vth = -params.vth;
+        }
+
+        if let Some(&vth) = model.params.get("vth").or(model.params.get("vto")) {
+            let enhancement = match mosfet_type {
+                MosfetType::N => vth > 0.0,
+                MosfetType::P => vth < 0.0,
+            };
+            if !enhancement {
+                return Err(PedalerError::invalid_parameter(
+                    &model.name,
+                    "vth",
+                    format!("enhancement-mode threshold must be positive for NMOS and negative for PMOS, got {}", vth),
+                ));
+            }
+            params.vth = vth;
+        }
+        if let Some(kp) = super::positive_model_param(model, "kp")? {
+            params.kp = kp;
+        }
+        if let Some(lambda) = super::non_negative_model_param(model, "lambda")? {
+            params.lambda = lambda;
+        }
+
+        Ok((mosfet_type, params))
+    }
+}
+
+/// A MOSFET component.
+#[derive(Debug, Clone)]
+pub struct Mosfet {
+    pub id: ComponentId,
+    pub name: String,
+    pub nodes: [NodeId; 3], // [drain, gate, source]
+    pub mosfet_type: MosfetType,
+    pub params: MosfetParams,
+    /// Current gate-source voltage operating point
+    pub v_gs_op: f64,
+    /// Current drain-source voltage operating point
+    pub v_ds_op: f64,
+    /// Gate-source voltage to start from (initial condition)
+    pub initial_v_gs_op: f64,
+    /// Drain-source voltage to start from (initial condition)
+    pub initial_v_ds_op: f64,
+}
+
+impl Mosfet {
+    /// Create a new MOSFET.
+    pub fn new(
+        id: ComponentId,
+        name: String,
+        nodes: [NodeId; 3],
+        mosfet_type: MosfetType,
+        params: MosfetParams,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            nodes,
+            mosfet_type,
+            params,
+            v_gs_op: 0.0,
+            v_ds_op: 0.0,
+            initial_v_gs_op: 0.0,
+            initial_v_ds_op: 0.0,
+        }
+    }
+
+    /// Get the drain node.
+    pub fn drain(&self) -> NodeId {
+        self.nodes[0]
+    }
+
+    /// Get the gate node.
+    pub fn gate(&self) -> NodeId {
+        self.nodes[1]
+    }
+
+    /// Get the source node.
+    pub fn source(&self) -> NodeId {
+        self.nodes[2]
+    }
+
+    /// +1 for N-channel, -1 for P-channel.
+    fn sign(&self) -> f64 {
+        match self.mosfet_type {
+            MosfetType::N => 1.0,
+            MosfetType::P => -1.0,
+        }
+    }
+
+    /// N-channel drain current and its partial derivatives
+    /// `(Id, dId/dVgs, dId/dVds)` for `v_ds >= 0`.
+    fn forward(&self, v_gs: f64, v_ds: f64) -> (f64, f64, f64) {
+        let MosfetParams { vth, kp, lambda } = self.params;
+        // The P-channel threshold is negative; mirrored, it is positive
+        let v_gst = v_gs - self.sign() * vth;
+        if v_gst <= 0.0 {
+            return (0.0, 0.0, 0.0);
+        }
+        let clm = 1.0 + lambda * v_ds;
+        if v_ds < v_gst {
+            // Triode
+            let shape = v_gst * v_ds - 0.5 * v_ds * v_ds;
+            let i_d = kp * shape * clm;
+            let gm = kp * v_ds * clm;
+            let gds = kp * (v_gst - v_ds) * clm + kp * shape * lambda;
+            (i_d, gm, gds)
+        } else {
+            // Saturation
+            let i_d = 0.5 * kp * v_gst * v_gst * clm;
+            let gm = kp * v_gst * clm;
+            let gds = 0.5 * kp * v_gst * v_gst * lambda;
+            (i_d, gm, gds)
+        }
+    }
+
+    /// N-channel drain current and `(dId/dVgs, dId/dVds)` at any `v_ds`,
+    /// swapping drain and source when `v_ds` is negative.
+    fn channel(&self, v_gs: f64, v_ds: f64) -> (f64, f64, f64) {
+        if v_ds >= 0.0 {
+            self.forward(v_gs, v_ds)
+        } else {
+            // Id(Vgs, Vds) = -F(Vgd, -Vds) with Vgd = Vgs - Vds
+            let (i, f_g, f_d) = self.forward(v_gs - v_ds, -v_ds);
+            (-i, -f_g, f_g + f_d)
+        }
+    }
+
+    /// Calculate the drain current (Id), flowing from drain to source.
+    pub fn i_d(&self, v_gs: f64, v_ds: f64) -> f64 {
+        let s = self.sign();
+        s * self.channel(s * v_gs, s * v_ds).0
+    }
+
+    /// Get partial derivatives for linearization.
+    /// Returns (gm, gds) - transconductance dId/dVgs and output conductance
+    /// dId/dVds.
+    pub fn linearize(&self, v_gs: f64, v_ds: f64) -> (f64, f64) {
+        // Negating both voltages and the current leaves the derivatives alone
+        let s = self.sign();
+        let (_, gm, gds) = self.channel(s * v_gs, s * v_ds);
+        (gm, gds.max(1e-12))
+    }
+
+    /// Update operating points.
+    pub fn update_operating_point(&mut self, v_gs: f64, v_ds: f64) {
+        self.v_gs_op = v_gs;
+        self.v_ds_op = v_ds;
+    }
+
+    /// Return to the initial operating point.
+    pub fn reset_to_initial_conditions(&mut self) {
+        self.v_gs_op = self.initial_v_gs_op;
+        self.v_ds_op = self.initial_v_ds_op;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mosfet(mosfet_type: MosfetType) -> Mosfet {
+        let vth = match mosfet_type {
+            MosfetType::N => 2.0,
+            MosfetType::P => -2.0,
+        };
+        let params = MosfetParams {
+            vth,
+            kp: 1e-3,
+            lambda: 0.02,
+        };
+        Mosfet::new(
+            ComponentId(0),
+            "M1".to_string(),
+            [NodeId(1), NodeId(2), NodeId(0)],
+            mosfet_type,
+            params,
+        )
+    }
+
+    /// Compare `linearize` against central differences of `i_d`.
+    fn assert_derivatives(m: &Mosfet, v_gs: f64, v_ds: f64) {
+        let h = 1e-6;
+        let gm = (m.i_d(v_gs + h, v_ds) - m.i_d(v_gs - h, v_ds)) / (2.0 * h);
+        let gds = (m.i_d(v_gs, v_ds + h) - m.i_d(v_gs, v_ds - h)) / (2.0 * h);
+        let (a_gm, a_gds) = m.linearize(v_gs, v_ds);
+        assert!(
+            (a_gm - gm).abs() < 1e-7,
+            "gm {} vs {} at ({}, {})",
+            a_gm,
+            gm,
+            v_gs,
+            v_ds
+        );
+        assert!(
+            (a_gds - gds).abs() < 1e-7,
+            "gds {} vs {} at ({}, {})",
+            a_gds,
+            gds,
+            v_gs,
+            v_ds
+        );
+    }
+
+    #[test]
+    fn test_cutoff() {
+        let m = mosfet(MosfetType::N);
+        assert_eq!(m.i_d(0.0, 5.0), 0.0);
+        assert_eq!(m.i_d(2.0, 5.0), 0.0);
+        let (gm, gds) = m.linearize(1.0, 5.0);
+        assert_eq!(gm, 0.0);
+        assert!(gds > 0.0 && gds < 1e-9);
+    }
+
+    #[test]
+    fn test_triode() {
+        // Vgst = 1.5 V, Vds = 0.5 V
+        let m = mosfet(MosfetType::N);
+        let expected = 1e-3 * (1.5 * 0.5 - 0.5 * 0.5 * 0.5) * (1.0 + 0.02 * 0.5);
+        assert!((m.i_d(3.5, 0.5) - expected).abs() < 1e-15);
+        assert_derivatives(&m, 3.5, 0.5);
+
+        // Near the origin the channel is a resistor of 1 / (kp Vgst)
+        let r = 1e-3 / m.i_d(4.0, 1e-3);
+        assert!(
+            (r - 1.0 / (1e-3 * 2.0)).abs() < 1.0,
+            "channel resistance {}",
+            r
+        );
+
+        // Reversed, drain and source swap roles
+        assert_derivatives(&m, 3.5, -0.5);
+        assert!((m.i_d(4.0, -1e-3) / m.i_d(4.0, 1e-3) + 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_saturation() {
+        // Vgst = 1 V: kp / 2 * Vgst^2, nearly flat in Vds
+        let m = mosfet(MosfetType::N);
+        let expected = 0.5e-3 * (1.0 + 0.02 * 5.0);
+        assert!((m.i_d(3.0, 5.0) - expected).abs() < 1e-15);
+        assert!(m.i_d(3.0, 9.0) > m.i_d(3.0, 5.0));
+        assert_derivatives(&m, 3.0, 5.0);
+        let (gm, gds) = m.linearize(3.0, 5.0);
+        assert!(gm > 50.0 * gds);
+
+        // The P-channel device mirrors the N-channel one
+        let p = mosfet(MosfetType::P);
+        assert_eq!(p.i_d(-3.0, -5.0), -m.i_d(3.0, 5.0));
+        assert_derivatives(&p, -3.0, -5.0);
+    }
+
+    #[test]
+    fn test_depletion_threshold_rejected() {
+        let ast = crate::dsl::parse(".model MBAD PMOS (vth=1)").unwrap();
+        let err = MosfetParams::from_model(&ast.models["MBAD"]).unwrap_err();
+        assert!(matches!(
+            err,
+            PedalerError::InvalidParameter { ref param, .. } if param == "vth"
+        ));
+    }
+}
@@ -0,0 +1,117 @@
+//! Nonlinear resistor following a user-specified resistance curve.
+//!
+//! The resistance is an arbitrary function of a named control signal (LFO
+//! or envelope follower), given as a table of `(control, resistance)`
+//! points, e.g. the lux-to-ohms curve of an LDR or the temperature curve of
+//! a thermistor. Between points, `ln R` is interpolated linearly, which
+//! follows the roughly exponential curves of these parts far better than
+//! linear interpolation. Outside the table the end values hold.
+//!
+//! Unlike a modulated resistor, which scales a nominal value, the curve is
+//! an absolute mapping, and unlike the hysteretic resistor it has no memory.
+
+use crate::circuit::{ComponentId, NodeId};
+
+/// A two-terminal resistor whose value is looked up from a control signal.
+#[derive(Debug, Clone)]
+pub struct NonlinearResistor {
+    pub id: ComponentId,
+    pub name: String,
+    pub nodes: [NodeId; 2],
+    /// LFO or envelope follower driving the resistance
+    pub control: String,
+    /// Curve points as `(control, resistance)`, sorted by control value
+    pub points: Vec<(f64, f64)>,
+    /// Resistance at the current control value
+    resistance: f64,
+}
+
+impl NonlinearResistor {
+    /// Create a new nonlinear resistor, starting at a control value of 0.
+    ///
+    /// `points` must hold at least two points, with strictly increasing
+    /// control values and positive resistances.
+    pub fn new(
+        id: ComponentId,
+        name: String,
+        nodes: [NodeId; 2],
+        control: String,
+        points: Vec<(f64, f64)>,
+    ) -> Self {
+        let mut resistor = Self {
+            id,
+            name,
+            nodes,
+            control,
+            points,
+            resistance: 0.0,
+        };
+        resistor.update(0.0);
+        resistor
+    }
+
+    /// Resistance the curve gives for a control value.
+    pub fn resistance_at(&self, control: f64) -> f64 {
+        let points = &self.points;
+        let (first, last) = (points[0], points[points.len() - 1]);
+        if control <= first.0 {
+            return first.1;
+        }
+        if control >= last.0 {
+            return last.1;
+        }
+
+        // First point above the control value; the one before is at or below it
+        let i = points.partition_point(|&(x, _)| x <= control);
+        let ((x0, r0), (x1, r1)) = (points[i - 1], points[i]);
+        let t = (control - x0) / (x1 - x0);
+        r0 * (r1 / r0).powf(t)
+    }
+
+    /// Look up the resistance for the current control value.
+    pub fn update(&mut self, control: f64) {
+        self.resistance = self.resistance_at(control);
+    }
+
+    /// Current resistance.
+    pub fn resistance(&self) -> f64 {
+        self.resistance
+    }
+
+    /// Current conductance.
+    pub fn conductance(&self) -> f64 {
+        1.0 / self.resistance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_curve_interpolates_log_resistance() {
+        // Dark, dim and bright points of an LDR
+        let points = vec![(0.0, 1e6), (0.5, 1e4), (1.0, 1e3)];
+        let mut ldr = NonlinearResistor::new(
+            ComponentId(0),
+            "R_LDR".to_string(),
+            [NodeId(1), NodeId(0)],
+            "ENV1".to_string(),
+            points,
+        );
+        assert_eq!(ldr.resistance(), 1e6);
+
+        assert!((ldr.resistance_at(0.5) - 1e4).abs() < 1e-6);
+        // Halfway between 1M and 10k in log terms is 100k
+        assert!((ldr.resistance_at(0.25) / 1e5 - 1.0).abs() < 1e-9);
+        assert!((ldr.resistance_at(0.75) / 1e7f64.sqrt() - 1.0).abs() < 1e-9);
+
+        // The ends hold outside the table
+        assert_eq!(ldr.resistance_at(-1.0), 1e6);
+        assert_eq!(ldr.resistance_at(2.0), 1e3);
+
+        ldr.update(1.0);
+        assert_eq!(ldr.resistance(), 1e3);
+        assert!((ldr.conductance() - 1e-3).abs() < 1e-15);
+    }
+}
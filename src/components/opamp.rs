@@ -3,13 +3,53 @@
 //! Supports both ideal op-amp (infinite gain) and finite-gain models.
 //! The op-amp enforces V+ = V- for ideal model, or Vout = A * (V+ - V-)
 //! for finite gain model.
+//!
+//! Two MNA formulations are available (see [`OpAmpModel`]): a VCCS with
+//! output resistance, and an ideal nullor that enforces V+ = V- exactly
+//! through its own branch current. Above a gain of 1e6 the VCCS is stamped
+//! in its equivalent Thevenin form, with the output current as a branch and
+//! the row scaled by 1/A, which keeps the matrix well conditioned.
+//!
+//! Except for the nullor, the open-loop output is clamped half a volt inside
+//! the rails. This makes the op-amp nonlinear: each Newton-Raphson iteration
+//! stamps it linearized around the last iterate, with its gain dropping to
+//! zero while the output sits on a rail.
+//!
+//! A finite slew rate narrows the same clamp to the range the output can
+//! reach from its previous sample, so a fast edge at the input comes out as
+//! a ramp.
+//!
+//! A finite gain-bandwidth product adds a dominant pole: the open-loop
+//! voltage becomes an internal state `Vc` with its own branch row,
+//! `dVc/dt = 2π·GBW·(V+ - V- - Vc/A)`, so the open-loop gain is `A` at DC,
+//! rolls off above `GBW/A` and reaches unity near `GBW`. The rails and slew
+//! limits then clamp `Vc` on its way to the output, and the state itself, so
+//! it never winds up past them.
+
+use std::f64::consts::PI;
 
 use crate::circuit::{BranchId, ComponentId, NodeId};
+use crate::components::IntegrationMethod;
 use crate::dsl::ModelDef;
 
+/// MNA formulation used for an op-amp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OpAmpModel {
+    /// Voltage-controlled current source with output resistance (Norton output).
+    /// Finite gain; numerically forgiving.
+    #[default]
+    Vccs,
+    /// Ideal nullor: V+ = V- is enforced exactly and the output current is a
+    /// free branch unknown. Gives textbook results in feedback circuits, but
+    /// ignores gain, rails and resistances and needs negative feedback.
+    Nullor,
+}
+
 /// Parameters for an op-amp model.
 #[derive(Debug, Clone)]
 pub struct OpAmpParams {
+    /// MNA formulation
+    pub model: OpAmpModel,
     /// Open-loop DC gain (A_OL), use f64::INFINITY for ideal
     pub gain: f64,
     /// Output resistance (R_out)
@@ -22,6 +62,8 @@ pub struct OpAmpParams {
     pub v_rail_neg: f64,
     /// Slew rate (V/µs), 0 = infinite
     pub slew_rate: f64,
+    /// Gain-bandwidth product (Hz), 0 = infinite
+    pub gbw: f64,
 }
 
 impl Default for OpAmpParams {
@@ -34,36 +76,42 @@ impl OpAmpParams {
     /// Create parameters for an ideal op-amp.
     pub fn ideal() -> Self {
         Self {
+            model: OpAmpModel::Vccs,
             gain: 1e9, // Very high but finite for numerical stability
             r_out: 0.1,
             r_in: 1e12,
             v_rail_pos: 15.0,
             v_rail_neg: -15.0,
             slew_rate: 0.0,
+            gbw: 0.0,
         }
     }
 
     /// Create parameters for a typical 741-style op-amp.
     pub fn ua741() -> Self {
         Self {
+            model: OpAmpModel::Vccs,
             gain: 2e5, // 200,000 open-loop gain
             r_out: 75.0,
             r_in: 2e6,
             v_rail_pos: 15.0,
             v_rail_neg: -15.0,
             slew_rate: 0.5,
+            gbw: 1e6,
         }
     }
 
     /// Create parameters for a TL072-style JFET op-amp.
     pub fn tl072() -> Self {
         Self {
+            model: OpAmpModel::Vccs,
             gain: 2e5,
             r_out: 100.0,
             r_in: 1e12,
             v_rail_pos: 15.0,
             v_rail_neg: -15.0,
             slew_rate: 13.0,
+            gbw: 3e6,
         }
     }
 
@@ -89,6 +137,17 @@ impl OpAmpParams {
         if let Some(&vn) = model.params.get("vneg") {
             params.v_rail_neg = vn;
         }
+        if let Some(&sr) = model.params.get("slew") {
+            params.slew_rate = sr;
+        }
+        if let Some(&gbw) = model.params.get("gbw") {
+            params.gbw = gbw;
+        }
+        if let Some(&nullor) = model.params.get("nullor") {
+            if nullor > 0.5 {
+                params.model = OpAmpModel::Nullor;
+            }
+        }
 
         params
     }
@@ -97,6 +156,21 @@ impl OpAmpParams {
     pub fn is_ideal(&self) -> bool {
         self.gain > 1e6
     }
+
+    /// Whether the output current is stamped as a branch unknown.
+    ///
+    /// Besides the nullor, this is the case for ideal VCCS op-amps, which
+    /// are stamped as a gain-scaled Thevenin output so that a huge
+    /// transconductance never enters the matrix.
+    pub fn uses_branch(&self) -> bool {
+        self.model == OpAmpModel::Nullor || self.is_ideal()
+    }
+
+    /// Whether the open-loop voltage is a dominant-pole state with its own
+    /// branch. The nullor has no open-loop voltage and ignores `gbw`.
+    pub fn has_pole(&self) -> bool {
+        self.model != OpAmpModel::Nullor && self.gbw > 0.0
+    }
 }
 
 /// An operational amplifier component.
@@ -106,9 +180,21 @@ pub struct OpAmp {
     pub name: String,
     pub nodes: [NodeId; 3], // [output, non-inverting (+), inverting (-)]
     pub params: OpAmpParams,
-    pub branch: BranchId,
-    /// Current output voltage (for slew rate limiting)
+    /// Output branch, allocated when [`OpAmpParams::uses_branch`] holds
+    pub branch: Option<BranchId>,
+    /// Dominant-pole branch, whose unknown is the open-loop voltage `Vc`,
+    /// allocated when [`OpAmpParams::has_pole`] holds
+    pub pole: Option<BranchId>,
+    /// Open-loop output voltage of the previous sample (for slew rate limiting)
     pub v_out: f64,
+    /// Output voltage to start from (initial condition)
+    pub initial_v_out: f64,
+    /// Dominant-pole state `Vc` of the previous sample
+    pub v_pole: f64,
+    /// `dVc/dt / (2π·GBW)` of the previous sample, for the trapezoidal rule
+    pole_rate: f64,
+    /// Dominant-pole state to start from (initial condition)
+    pub initial_v_pole: f64,
 }
 
 impl OpAmp {
@@ -118,7 +204,8 @@ impl OpAmp {
         name: String,
         nodes: [NodeId; 3],
         params: OpAmpParams,
-        branch: BranchId,
+        branch: Option<BranchId>,
+        pole: Option<BranchId>,
     ) -> Self {
         Self {
             id,
@@ -126,10 +213,22 @@ impl OpAmp {
             nodes,
             params,
             branch,
+            pole,
             v_out: 0.0,
+            initial_v_out: 0.0,
+            v_pole: 0.0,
+            pole_rate: 0.0,
+            initial_v_pole: 0.0,
         }
     }
 
+    /// Return to the initial output voltage and pole state, at rest.
+    pub fn reset_to_initial_conditions(&mut self) {
+        self.v_out = self.initial_v_out;
+        self.v_pole = self.initial_v_pole;
+        self.pole_rate = 0.0;
+    }
+
     /// Get the output node.
     pub fn output(&self) -> NodeId {
         self.nodes[0]
@@ -159,18 +258,110 @@ impl OpAmp {
             .min(self.params.v_rail_pos - 0.5)
     }
 
-    /// Calculate the actual output voltage with slew rate limiting.
-    pub fn v_out_slew_limited(&mut self, v_pos: f64, v_neg: f64, dt: f64) -> f64 {
-        let v_target = self.v_out_limited(v_pos, v_neg);
-
-        if self.params.slew_rate > 0.0 {
+    /// Range the open-loop output can take over a time step `dt`.
+    ///
+    /// This is the span between the rails, narrowed by a finite slew rate to
+    /// what the output can reach from the previous sample. An infinite `dt`
+    /// (the DC limit) leaves just the rails.
+    pub fn output_range(&self, dt: f64) -> (f64, f64) {
+        let lo = self.params.v_rail_neg + 0.5;
+        let hi = self.params.v_rail_pos - 0.5;
+        if self.params.slew_rate > 0.0 && dt.is_finite() {
             let max_change = self.params.slew_rate * 1e6 * dt; // Convert V/µs to V/s
-            let change = (v_target - self.v_out).clamp(-max_change, max_change);
-            self.v_out += change;
+            let v_prev = self.v_out.clamp(lo, hi);
+            (lo.max(v_prev - max_change), hi.min(v_prev + max_change))
         } else {
-            self.v_out = v_target;
+            (lo, hi)
         }
+    }
 
+    /// Linearize the limited open-loop output over a time step `dt` around
+    /// the input difference `v_diff`, given the output voltage `v_out` of
+    /// the same iterate.
+    ///
+    /// Returns `(gain, v_open)`: the small-signal gain, which collapses to
+    /// zero once the output is clamped at a rail or at its slew limit, and
+    /// the open-loop output voltage at `v_diff`. A limit only takes hold
+    /// once `v_out` is already on its side of the middle of the
+    /// [`output_range`](Self::output_range): clamped, the op-amp ignores its
+    /// feedback, so Newton-Raphson could otherwise bounce from one limit
+    /// straight to the other without ever trying the linear region.
+    pub fn linearize(&self, v_diff: f64, v_out: f64, dt: f64) -> (f64, f64) {
+        let v_ideal = self.v_out_ideal(v_diff, 0.0);
+        let (slope, v_open) = self.linearize_clamp(v_ideal, v_out, dt);
+        if slope == 0.0 {
+            (0.0, v_open)
+        } else {
+            (self.params.gain, v_open)
+        }
+    }
+
+    /// Linearize the clamp of an unlimited open-loop voltage `v_ideal`
+    /// into the [`output_range`](Self::output_range), as in
+    /// [`linearize`](Self::linearize).
+    ///
+    /// Returns `(slope, v_open)`: 1 between the limits and 0 once clamped,
+    /// and the clamped voltage.
+    pub fn linearize_clamp(&self, v_ideal: f64, v_out: f64, dt: f64) -> (f64, f64) {
+        let (lo, hi) = self.output_range(dt);
+        let v_open = v_ideal.clamp(lo, hi);
+        let mid = 0.5 * (lo + hi);
+        let clamped = (v_ideal > v_open && v_out > mid) || (v_ideal < v_open && v_out < mid);
+        if clamped {
+            (0.0, v_open)
+        } else {
+            (1.0, v_ideal)
+        }
+    }
+
+    /// Companion model of the dominant pole over a time step `dt`.
+    ///
+    /// The pole row, divided by `2π·GBW·A`, reads
+    /// `(g + 1/A)·Vc - (V+ - V-) = history`; returns `(g, history)`. At DC
+    /// (infinite `dt`) `g` is zero, leaving `Vc = A·(V+ - V-)`.
+    pub fn pole_companion(&self, dt: f64, method: IntegrationMethod) -> (f64, f64) {
+        if !dt.is_finite() {
+            return (0.0, 0.0);
+        }
+        let g = method.scale() * self.pole_capacitance() / dt;
+        match method {
+            IntegrationMethod::Trapezoidal => (g, g * self.v_pole + self.pole_rate),
+            IntegrationMethod::BackwardEuler => (g, g * self.v_pole),
+        }
+    }
+
+    /// `1 / (2π·GBW)`: the coefficient of `dVc/dt` in the pole row, and of
+    /// `jω` in small-signal analysis.
+    pub fn pole_capacitance(&self) -> f64 {
+        1.0 / (2.0 * PI * self.params.gbw)
+    }
+
+    /// Record the solved pole state `v_c` and input difference `v_diff` as
+    /// the starting point of the next time step, clamping the state into
+    /// the output range so it doesn't wind up past the rails.
+    ///
+    /// A clamped state is held, so its rate is zero: the trapezoidal rule
+    /// would otherwise carry the overdrive into the first sample back
+    /// between the limits.
+    pub fn update_pole(&mut self, v_c: f64, v_diff: f64, dt: f64) {
+        let (lo, hi) = self.output_range(dt);
+        self.v_pole = v_c.clamp(lo, hi);
+        self.pole_rate = if self.v_pole == v_c {
+            v_diff - self.v_pole / self.params.gain
+        } else {
+            0.0
+        };
+        self.v_out = self.v_pole;
+    }
+
+    /// Calculate the actual output voltage with slew rate limiting, and
+    /// record it as the starting point of the next time step.
+    ///
+    /// The simulator calls this with the solved input voltages after every
+    /// sample.
+    pub fn v_out_slew_limited(&mut self, v_pos: f64, v_neg: f64, dt: f64) -> f64 {
+        let (lo, hi) = self.output_range(dt);
+        self.v_out = self.v_out_ideal(v_pos, v_neg).clamp(lo, hi);
         self.v_out
     }
 
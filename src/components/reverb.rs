@@ -3,15 +3,39 @@
 //! Implements an algorithmic reverb using multiple delay lines with a
 //! Hadamard feedback matrix. This creates dense, natural-sounding
 //! reverberation without requiring impulse response files.
+//!
+//! An optional energy guard watches the total energy stored in the delay
+//! lines and pulls the feedback gain back if the network starts to
+//! self-oscillate.
 
+use super::flush_denormal;
 use crate::circuit::NodeId;
 
 /// Default number of delay lines in the FDN.
 const NUM_DELAY_LINES: usize = 4;
 
+/// Delay line counts the FDN supports; the Hadamard matrix needs a power of two.
+pub const FDN_SIZES: [usize; 3] = [4, 8, 16];
+
+/// Most delay lines in the FDN.
+const MAX_DELAY_LINES: usize = 16;
+
 /// Base delay times in seconds (mutually prime in samples at common rates).
 /// These create a natural-sounding reverb without metallic resonances.
-const BASE_DELAY_TIMES: [f64; NUM_DELAY_LINES] = [0.029, 0.037, 0.043, 0.053];
+/// An FDN of `n` lines uses the first `n`, so each size interleaves new
+/// lengths between those of the next smaller one.
+const BASE_DELAY_TIMES: [f64; MAX_DELAY_LINES] = [
+    0.029, 0.037, 0.043, 0.053, // 4 lines
+    0.031, 0.041, 0.047, 0.059, // 8 lines
+    0.023, 0.067, 0.071, 0.073, 0.079, 0.083, 0.089, 0.097, // 16 lines
+];
+
+/// Feedback gain the energy guard falls back to once runaway growth is seen.
+const SAFE_DECAY: f32 = 0.9;
+
+/// Tolerated ratio of feedback energy written to energy read per window.
+/// A stable network (loop gain at most one) never exceeds 1.0.
+const GROWTH_TOLERANCE: f64 = 1.05;
 
 /// FDN Reverb parameters.
 #[derive(Debug, Clone)]
@@ -26,6 +50,11 @@ pub struct ReverbParams {
     pub mix: f32,
     /// Pre-delay in seconds
     pub predelay: f64,
+    /// Monitor delay-line energy and contain runaway feedback
+    pub energy_guard: bool,
+    /// Number of delay lines, one of [`FDN_SIZES`]; more lines give a
+    /// denser tail
+    pub lines: usize,
 }
 
 impl Default for ReverbParams {
@@ -36,6 +65,8 @@ impl Default for ReverbParams {
             damping: 0.3,
             mix: 0.5,
             predelay: 0.0,
+            energy_guard: false,
+            lines: NUM_DELAY_LINES,
         }
     }
 }
@@ -59,6 +90,15 @@ impl ReverbParams {
         if let Some(&v) = params.get("predelay") {
             p.predelay = v.max(0.0);
         }
+        if let Some(&v) = params.get("guard") {
+            p.energy_guard = v > 0.5;
+        }
+        if let Some(&v) = params.get("lines") {
+            p.lines = FDN_SIZES
+                .into_iter()
+                .find(|&n| n as f64 == v)
+                .unwrap_or(NUM_DELAY_LINES);
+        }
         p
     }
 }
@@ -75,43 +115,73 @@ pub struct FdnReverb {
     /// Parameters
     pub params: ReverbParams,
     /// Delay line buffers
-    delay_buffers: [Vec<f32>; NUM_DELAY_LINES],
+    delay_buffers: Vec<Vec<f32>>,
     /// Write positions for each delay line
-    write_positions: [usize; NUM_DELAY_LINES],
+    write_positions: Vec<usize>,
     /// Delay lengths in samples
-    delay_lengths: [usize; NUM_DELAY_LINES],
+    delay_lengths: Vec<usize>,
     /// One-pole lowpass filter states for damping
-    lp_states: [f32; NUM_DELAY_LINES],
+    lp_states: Vec<f32>,
     /// Pre-delay buffer (optional)
     predelay_buffer: Vec<f32>,
     /// Pre-delay write position
     predelay_pos: usize,
     /// Pre-delay length in samples
     predelay_len: usize,
+    /// Energy guard state
+    monitor: EnergyMonitor,
+}
+
+/// Windowed energy tracking for the energy guard.
+///
+/// Once per window (the longest delay line, i.e. one full recirculation) the
+/// energy stored in the delay lines is compared with the previous window. If
+/// it grew and the feedback path wrote back more energy than it read out, the
+/// loop gain is above one and the network is self-oscillating.
+#[derive(Debug, Clone)]
+struct EnergyMonitor {
+    /// Window length in samples
+    window: usize,
+    /// Samples processed in the current window
+    count: usize,
+    /// Delay-line energy at the end of the previous window
+    prev_energy: f64,
+    /// Energy read out of the delay lines during the current window
+    read_energy: f64,
+    /// Energy fed back into the delay lines during the current window
+    feedback_energy: f64,
+    /// Feedback gain ceiling imposed after a trip
+    decay_limit: f32,
+    /// Number of times runaway growth has been contained
+    trips: usize,
 }
 
 impl FdnReverb {
     /// Create a new FDN reverb.
+    ///
+    /// A line count outside [`FDN_SIZES`] falls back to 4 lines, as in
+    /// [`ReverbParams::from_params`].
     pub fn new(
         name: String,
         input_node: NodeId,
         output_node: NodeId,
-        params: ReverbParams,
+        mut params: ReverbParams,
         sample_rate: f32,
     ) -> Self {
         // Calculate delay lengths based on size parameter
         // Size scales from 0.5x to 2x the base delay times
         let size_scale = 0.5 + params.size as f64 * 1.5;
 
-        let mut delay_lengths = [0usize; NUM_DELAY_LINES];
-        let mut delay_buffers: [Vec<f32>; NUM_DELAY_LINES] = Default::default();
-
-        for i in 0..NUM_DELAY_LINES {
-            let delay_time = BASE_DELAY_TIMES[i] * size_scale;
-            let len = ((delay_time * sample_rate as f64) as usize).max(1);
-            delay_lengths[i] = len;
-            delay_buffers[i] = vec![0.0; len];
+        if !FDN_SIZES.contains(&params.lines) {
+            params.lines = NUM_DELAY_LINES;
         }
+        let lines = params.lines;
+        let delay_lengths: Vec<usize> = BASE_DELAY_TIMES[..lines]
+            .iter()
+            .map(|&t| ((t * size_scale * sample_rate as f64) as usize).max(1))
+            .collect();
+        let delay_buffers = delay_lengths.iter().map(|&len| vec![0.0; len]).collect();
+        let window = delay_lengths.iter().copied().max().unwrap_or(1);
 
         // Pre-delay
         let predelay_len = ((params.predelay * sample_rate as f64) as usize).max(1);
@@ -123,12 +193,21 @@ impl FdnReverb {
             output_node,
             params,
             delay_buffers,
-            write_positions: [0; NUM_DELAY_LINES],
+            write_positions: vec![0; lines],
             delay_lengths,
-            lp_states: [0.0; NUM_DELAY_LINES],
+            lp_states: vec![0.0; lines],
             predelay_buffer,
             predelay_pos: 0,
             predelay_len,
+            monitor: EnergyMonitor {
+                window,
+                count: 0,
+                prev_energy: 0.0,
+                read_energy: 0.0,
+                feedback_energy: 0.0,
+                decay_limit: f32::INFINITY,
+                trips: 0,
+            },
         }
     }
 
@@ -145,64 +224,152 @@ impl FdnReverb {
         };
 
         // Read delayed outputs
-        let mut delayed = [0.0f32; NUM_DELAY_LINES];
-        for i in 0..NUM_DELAY_LINES {
-            delayed[i] = self.delay_buffers[i][self.write_positions[i]];
+        let lines = self.delay_buffers.len();
+        let mut delayed = [0.0f32; MAX_DELAY_LINES];
+        let delayed = &mut delayed[..lines];
+        for (i, d) in delayed.iter_mut().enumerate() {
+            *d = self.delay_buffers[i][self.write_positions[i]];
         }
 
         // Apply damping (one-pole lowpass filter)
         let damping = self.params.damping;
-        for i in 0..NUM_DELAY_LINES {
-            self.lp_states[i] = self.lp_states[i] * damping + delayed[i] * (1.0 - damping);
-            delayed[i] = self.lp_states[i];
+        for (lp, d) in self.lp_states.iter_mut().zip(delayed.iter_mut()) {
+            *lp = flush_denormal(*lp * damping + *d * (1.0 - damping));
+            *d = *lp;
         }
 
-        // Apply Hadamard feedback matrix (4x4)
+        // Apply the Hadamard feedback matrix, e.g. for 4 lines
         // H = 1/2 * [[ 1,  1,  1,  1],
         //           [ 1, -1,  1, -1],
         //           [ 1,  1, -1, -1],
         //           [ 1, -1, -1,  1]]
-        let feedback = hadamard_4x4(&delayed);
+        let mut feedback = [0.0f32; MAX_DELAY_LINES];
+        let feedback = &mut feedback[..lines];
+        feedback.copy_from_slice(delayed);
+        hadamard(feedback);
 
         // Scale by decay and write back to delay lines
-        let decay = self.params.decay;
-        for i in 0..NUM_DELAY_LINES {
-            let new_sample = predelayed + feedback[i] * decay;
+        let decay = self.params.decay.min(self.monitor.decay_limit);
+        for (i, fb) in feedback.iter().enumerate() {
+            let new_sample = flush_denormal(predelayed + fb * decay);
             self.delay_buffers[i][self.write_positions[i]] = new_sample;
             self.write_positions[i] = (self.write_positions[i] + 1) % self.delay_lengths[i];
         }
 
-        // Sum delayed outputs for wet signal
-        let wet = (delayed[0] + delayed[1] + delayed[2] + delayed[3]) * 0.25;
+        if self.params.energy_guard {
+            for (d, fb) in delayed.iter().zip(feedback.iter()) {
+                self.monitor.read_energy += (*d as f64).powi(2);
+                self.monitor.feedback_energy += (*fb as f64 * decay as f64).powi(2);
+            }
+            self.monitor.count += 1;
+            if self.monitor.count >= self.monitor.window {
+                self.check_energy();
+            }
+        }
+
+        // Average the delayed outputs for the wet signal
+        let wet = delayed.iter().sum::<f32>() / lines as f32;
 
         // Mix dry and wet
         let mix = self.params.mix;
         input * (1.0 - mix) + wet * mix
     }
 
-    /// Reset the reverb state.
+    /// Total energy (sum of squares) currently stored in the delay lines.
+    pub fn energy(&self) -> f64 {
+        self.delay_buffers
+            .iter()
+            .flat_map(|buf| buf.iter())
+            .map(|&s| (s as f64).powi(2))
+            .sum()
+    }
+
+    /// Number of times the energy guard has had to contain runaway feedback.
+    ///
+    /// A non-zero value means the configured parameters are unstable.
+    pub fn energy_trips(&self) -> usize {
+        self.monitor.trips
+    }
+
+    /// End-of-window energy check; attenuates the network if it is
+    /// self-oscillating.
+    fn check_energy(&mut self) {
+        let energy = self.energy();
+        let growing = energy > self.monitor.prev_energy;
+        let loop_gain_exceeded =
+            self.monitor.feedback_energy > GROWTH_TOLERANCE * self.monitor.read_energy + 1e-12;
+
+        if !energy.is_finite() {
+            // Already blown up: clear the network and start over safely
+            for buf in &mut self.delay_buffers {
+                buf.fill(0.0);
+            }
+            self.lp_states.fill(0.0);
+            self.trip();
+            self.monitor.prev_energy = 0.0;
+        } else if growing && loop_gain_exceeded {
+            // Pull the stored signal back to the previous window's level and
+            // cap the feedback gain so the loop is contractive from now on
+            let scale = (self.monitor.prev_energy / energy).sqrt() as f32;
+            for buf in &mut self.delay_buffers {
+                buf.iter_mut().for_each(|s| *s *= scale);
+            }
+            self.lp_states.iter_mut().for_each(|s| *s *= scale);
+            self.trip();
+        } else {
+            self.monitor.prev_energy = energy;
+        }
+
+        self.monitor.count = 0;
+        self.monitor.read_energy = 0.0;
+        self.monitor.feedback_energy = 0.0;
+    }
+
+    /// Record a containment and clamp the feedback gain.
+    fn trip(&mut self) {
+        self.monitor.decay_limit = self.monitor.decay_limit.min(SAFE_DECAY);
+        self.monitor.trips += 1;
+    }
+
+    /// Reset the reverb state, releasing any feedback limit the energy
+    /// guard imposed.
     pub fn reset(&mut self) {
         for buf in &mut self.delay_buffers {
             buf.fill(0.0);
         }
-        self.write_positions = [0; NUM_DELAY_LINES];
-        self.lp_states = [0.0; NUM_DELAY_LINES];
+        self.write_positions.fill(0);
+        self.lp_states.fill(0.0);
         self.predelay_buffer.fill(0.0);
         self.predelay_pos = 0;
+        self.monitor.count = 0;
+        self.monitor.prev_energy = 0.0;
+        self.monitor.read_energy = 0.0;
+        self.monitor.feedback_energy = 0.0;
+        self.monitor.decay_limit = f32::INFINITY;
+        self.monitor.trips = 0;
     }
 }
 
-/// Apply 4x4 Hadamard matrix to input vector.
-/// The Hadamard matrix is unitary (energy-preserving) which prevents
-/// the reverb from building up or dying out unnaturally.
+/// Apply the NxN Hadamard matrix to `x` in place, N being its power-of-two
+/// length.
+/// The Hadamard matrix, scaled by 1/sqrt(N), is unitary (energy-preserving)
+/// which prevents the reverb from building up or dying out unnaturally.
+/// The fast Walsh-Hadamard transform takes N log N additions.
 #[inline]
-fn hadamard_4x4(x: &[f32; 4]) -> [f32; 4] {
-    // H * x where H is the 4x4 Hadamard matrix divided by 2
-    let a = x[0] + x[1] + x[2] + x[3];
-    let b = x[0] - x[1] + x[2] - x[3];
-    let c = x[0] + x[1] - x[2] - x[3];
-    let d = x[0] - x[1] - x[2] + x[3];
-    [a * 0.5, b * 0.5, c * 0.5, d * 0.5]
+fn hadamard(x: &mut [f32]) {
+    let n = x.len();
+    let mut half = 1;
+    while half < n {
+        for block in x.chunks_exact_mut(2 * half) {
+            let (a, b) = block.split_at_mut(half);
+            for (a, b) in a.iter_mut().zip(b) {
+                (*a, *b) = (*a + *b, *a - *b);
+            }
+        }
+        half *= 2;
+    }
+    let scale = 1.0 / (n as f32).sqrt();
+    x.iter_mut().for_each(|v| *v *= scale);
 }
 
 #[cfg(test)]
@@ -211,8 +378,8 @@ mod tests {
 
     #[test]
     fn test_hadamard() {
-        let input = [1.0, 0.0, 0.0, 0.0];
-        let output = hadamard_4x4(&input);
+        let mut output = [1.0, 0.0, 0.0, 0.0];
+        hadamard(&mut output);
         // First column of Hadamard/2: [0.5, 0.5, 0.5, 0.5]
         assert!((output[0] - 0.5).abs() < 1e-6);
         assert!((output[1] - 0.5).abs() < 1e-6);
@@ -220,6 +387,52 @@ mod tests {
         assert!((output[3] - 0.5).abs() < 1e-6);
     }
 
+    #[test]
+    fn test_hadamard_8_preserves_energy() {
+        let input = [0.3f32, -1.2, 0.7, 0.05, 2.0, -0.4, 0.9, -1.6];
+        let mut output = input;
+        hadamard(&mut output);
+        let energy = |x: &[f32]| x.iter().map(|v| v * v).sum::<f32>();
+        assert!((energy(&output) - energy(&input)).abs() < 1e-5);
+
+        // Symmetric and unitary, so it is its own inverse
+        hadamard(&mut output);
+        for (a, b) in output.iter().zip(&input) {
+            assert!((a - b).abs() < 1e-6);
+        }
+    }
+
+    /// Output samples over the first 100ms after an impulse that carry
+    /// any of it.
+    fn echo_count(lines: usize) -> usize {
+        let params = ReverbParams {
+            damping: 0.0,
+            mix: 1.0,
+            lines,
+            ..Default::default()
+        };
+        let mut reverb = FdnReverb::new("RV1".to_string(), NodeId(1), NodeId(2), params, 48000.0);
+        reverb.process(1.0);
+        (0..4800)
+            .filter(|_| reverb.process(0.0).abs() > 1e-6)
+            .count()
+    }
+
+    #[test]
+    fn test_more_lines_give_denser_tail() {
+        let (four, eight, sixteen) = (echo_count(4), echo_count(8), echo_count(16));
+        assert!(
+            eight > 2 * four && sixteen > eight,
+            "{} {} {}",
+            four,
+            eight,
+            sixteen
+        );
+
+        // Other sizes fall back to 4 lines
+        assert_eq!(echo_count(5), four);
+    }
+
     #[test]
     fn test_reverb_decay() {
         let params = ReverbParams {
@@ -228,15 +441,11 @@ mod tests {
             damping: 0.0,
             mix: 1.0, // 100% wet
             predelay: 0.0,
+            energy_guard: false,
+            lines: 4,
         };
 
-        let mut reverb = FdnReverb::new(
-            "RV1".to_string(),
-            NodeId(1),
-            NodeId(2),
-            params,
-            48000.0,
-        );
+        let mut reverb = FdnReverb::new("RV1".to_string(), NodeId(1), NodeId(2), params, 48000.0);
 
         // Send an impulse
         let _ = reverb.process(1.0);
@@ -250,7 +459,45 @@ mod tests {
 
         // After 1 second with decay=0.5, should have decayed significantly
         // The FDN recirculates so it won't fully decay, but should be below 0.5
-        assert!(max_output < 0.5, "Reverb should decay, got max {}", max_output);
+        assert!(
+            max_output < 0.5,
+            "Reverb should decay, got max {}",
+            max_output
+        );
+    }
+
+    #[test]
+    fn test_reverb_tail_decays_to_exact_zero() {
+        let params = ReverbParams {
+            decay: 0.5,
+            damping: 0.7,
+            mix: 1.0,
+            ..Default::default()
+        };
+        let mut reverb = FdnReverb::new("RV1".to_string(), NodeId(1), NodeId(2), params, 48000.0);
+        reverb.process(1.0);
+
+        let state_is_normal = |r: &FdnReverb| {
+            r.delay_buffers
+                .iter()
+                .flatten()
+                .chain(&r.lp_states)
+                .all(|v| !v.is_subnormal())
+        };
+        for i in 0..10 * 48000 {
+            reverb.process(0.0);
+            if i % 1000 == 0 {
+                assert!(
+                    state_is_normal(&reverb),
+                    "subnormal state after {} samples",
+                    i
+                );
+            }
+        }
+
+        assert!(reverb.delay_buffers.iter().flatten().all(|&v| v == 0.0));
+        assert!(reverb.lp_states.iter().all(|&v| v == 0.0));
+        assert_eq!(reverb.process(0.0), 0.0);
     }
 
     #[test]
@@ -260,16 +507,67 @@ mod tests {
             ..Default::default()
         };
 
-        let mut reverb = FdnReverb::new(
-            "RV1".to_string(),
-            NodeId(1),
-            NodeId(2),
-            dry_params,
-            48000.0,
-        );
+        let mut reverb =
+            FdnReverb::new("RV1".to_string(), NodeId(1), NodeId(2), dry_params, 48000.0);
 
         // With 100% dry, output should equal input
         let out = reverb.process(0.5);
         assert!((out - 0.5).abs() < 1e-6);
     }
+
+    fn overdriven(energy_guard: bool) -> FdnReverb {
+        let params = ReverbParams {
+            decay: 1.3, // Loop gain > 1: self-oscillates
+            damping: 0.0,
+            mix: 1.0,
+            energy_guard,
+            ..Default::default()
+        };
+        FdnReverb::new("RV1".to_string(), NodeId(1), NodeId(2), params, 48000.0)
+    }
+
+    fn peak_after_impulse(reverb: &mut FdnReverb, samples: usize) -> f32 {
+        let _ = reverb.process(1.0);
+        (0..samples)
+            .map(|_| reverb.process(0.0).abs())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn test_energy_guard_contains_runaway_decay() {
+        // Unguarded, the over-driven network grows without bound
+        let mut runaway = overdriven(false);
+        assert!(peak_after_impulse(&mut runaway, 96000) > 100.0);
+
+        let mut guarded = overdriven(true);
+        let peak = peak_after_impulse(&mut guarded, 96000);
+        assert!(peak.is_finite() && peak < 1.0, "peak {}", peak);
+        assert!(guarded.energy_trips() > 0);
+        assert!(guarded.energy() < 1.0);
+
+        // A reset starts over at the configured feedback gain
+        guarded.reset();
+        assert_eq!(guarded.energy_trips(), 0);
+        assert_eq!(guarded.monitor.decay_limit, f32::INFINITY);
+    }
+
+    #[test]
+    fn test_energy_guard_ignores_stable_settings() {
+        for damping in [0.0, 0.7] {
+            let params = ReverbParams {
+                decay: 0.99,
+                damping,
+                energy_guard: true,
+                ..Default::default()
+            };
+            let mut reverb =
+                FdnReverb::new("RV1".to_string(), NodeId(1), NodeId(2), params, 48000.0);
+
+            // Sustained input builds energy up toward steady state without tripping
+            for n in 0..96000 {
+                let _ = reverb.process((n as f32 * 0.05).sin());
+            }
+            assert_eq!(reverb.energy_trips(), 0);
+        }
+    }
 }
@@ -1,4 +1,4 @@
-//! Voltage and current sources.
+//! Voltage and current sources, and the ammeter current probe.
 
 use crate::circuit::{BranchId, ComponentId, NodeId};
 
@@ -86,3 +86,28 @@ impl CurrentSource {
         self.current_value
     }
 }
+
+/// An ammeter (current probe).
+///
+/// Modeled as a zero-volt voltage source, the standard SPICE trick for
+/// measuring current: its branch current equals the current flowing
+/// through it from n+ to n-, without disturbing the circuit.
+#[derive(Debug, Clone)]
+pub struct Ammeter {
+    pub id: ComponentId,
+    pub name: String,
+    pub nodes: [NodeId; 2], // [positive, negative] - measures current from + to -
+    pub branch: BranchId,
+}
+
+impl Ammeter {
+    /// Create a new ammeter.
+    pub fn new(id: ComponentId, name: String, nodes: [NodeId; 2], branch: BranchId) -> Self {
+        Self {
+            id,
+            name,
+            nodes,
+            branch,
+        }
+    }
+}
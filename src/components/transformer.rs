@@ -0,0 +1,157 @@
+//! Transformer, modeled as a pair of magnetically coupled inductors.
+//!
+//! The windings have self-inductances `Lp` and `Ls` and a mutual inductance
+//! `M = k * sqrt(Lp * Ls)`, with the dotted ends at `p+` and `s+`:
+//!
+//! ```text
+//! vp = Lp dip/dt + M dis/dt
+//! vs = M dip/dt + Ls dis/dt
+//! ```
+//!
+//! The turns ratio is `sqrt(Ls / Lp)`. As with the inductor, each winding
+//! has a branch current in the MNA matrix; the companion model is the
+//! inductor's with the 2x2 inductance matrix in place of `L`, so the
+//! windings' equivalent resistances couple the two branch rows.
+
+use super::IntegrationMethod;
+use crate::circuit::{BranchId, ComponentId, NodeId};
+
+/// A transformer (coupled inductors).
+#[derive(Debug, Clone)]
+pub struct Transformer {
+    pub id: ComponentId,
+    pub name: String,
+    pub nodes: [NodeId; 4], // [p+, p-, s+, s-]
+    /// Primary self-inductance (H)
+    pub l_pri: f64,
+    /// Secondary self-inductance (H)
+    pub l_sec: f64,
+    /// Coupling coefficient, in (0, 1]
+    pub k: f64,
+    /// Branch currents of the [primary, secondary] windings
+    pub branches: [BranchId; 2],
+
+    // State for discrete-time model
+    /// Previous winding currents
+    pub i_prev: [f64; 2],
+    /// Previous winding voltages
+    pub v_prev: [f64; 2],
+    /// Winding currents to start from (initial condition)
+    pub initial_currents: [f64; 2],
+}
+
+impl Transformer {
+    /// Create a new transformer.
+    pub fn new(
+        id: ComponentId,
+        name: String,
+        nodes: [NodeId; 4],
+        l_pri: f64,
+        l_sec: f64,
+        k: f64,
+        branches: [BranchId; 2],
+    ) -> Self {
+        Self {
+            id,
+            name,
+            nodes,
+            l_pri,
+            l_sec,
+            k,
+            branches,
+            i_prev: [0.0; 2],
+            v_prev: [0.0; 2],
+            initial_currents: [0.0; 2],
+        }
+    }
+
+    /// Mutual inductance, `k * sqrt(Lp * Ls)`.
+    pub fn mutual(&self) -> f64 {
+        self.k * (self.l_pri * self.l_sec).sqrt()
+    }
+
+    /// Secondary to primary turns ratio, `sqrt(Ls / Lp)`.
+    pub fn turns_ratio(&self) -> f64 {
+        (self.l_sec / self.l_pri).sqrt()
+    }
+
+    /// Inductance matrix `[[Lp, M], [M, Ls]]`.
+    pub fn inductances(&self) -> [[f64; 2]; 2] {
+        let m = self.mutual();
+        [[self.l_pri, m], [m, self.l_sec]]
+    }
+
+    /// Return to the initial currents, at rest.
+    pub fn reset_to_initial_conditions(&mut self) {
+        self.i_prev = self.initial_currents;
+        self.v_prev = [0.0; 2];
+    }
+
+    /// Equivalent resistance matrix of the companion model: the inductance
+    /// matrix times 2/dt for trapezoidal integration, 1/dt for backward
+    /// Euler.
+    pub fn resistances(&self, dt: f64, method: IntegrationMethod) -> [[f64; 2]; 2] {
+        let scale = method.scale() / dt;
+        self.inductances().map(|row| row.map(|l| scale * l))
+    }
+
+    /// Equivalent voltage sources of the companion model, one per winding.
+    pub fn voltage_sources(&self, dt: f64, method: IntegrationMethod) -> [f64; 2] {
+        // V_eq = R * i_prev + v_prev, or R * i_prev
+        let r = self.resistances(dt, method);
+        std::array::from_fn(|j| {
+            let history = match method {
+                IntegrationMethod::Trapezoidal => self.v_prev[j],
+                IntegrationMethod::BackwardEuler => 0.0,
+            };
+            r[j][0] * self.i_prev[0] + r[j][1] * self.i_prev[1] + history
+        })
+    }
+
+    /// Update the state after solving.
+    pub fn update_state(&mut self, i_new: [f64; 2], dt: f64, method: IntegrationMethod) {
+        // v_new = R * (i_new - i_prev) - v_prev, or R * (i_new - i_prev)
+        let r = self.resistances(dt, method);
+        let di = [i_new[0] - self.i_prev[0], i_new[1] - self.i_prev[1]];
+        for (row, v_prev) in r.iter().zip(&mut self.v_prev) {
+            let v = row[0] * di[0] + row[1] * di[1];
+            *v_prev = match method {
+                IntegrationMethod::Trapezoidal => v - *v_prev,
+                IntegrationMethod::BackwardEuler => v,
+            };
+        }
+        self.i_prev = i_new;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coupled_companion_model() {
+        let mut t = Transformer::new(
+            ComponentId(0),
+            "XFMR1".to_string(),
+            [NodeId(1), NodeId(0), NodeId(2), NodeId(0)],
+            1.0,
+            4.0,
+            0.5,
+            [BranchId(0), BranchId(1)],
+        );
+        assert!((t.turns_ratio() - 2.0).abs() < 1e-12);
+        assert!((t.mutual() - 1.0).abs() < 1e-12);
+
+        // A current step in the primary alone induces M/Lp of its voltage
+        // in the secondary
+        let dt = 1e-3;
+        t.update_state([1e-3, 0.0], dt, IntegrationMethod::BackwardEuler);
+        assert!((t.v_prev[0] - 1.0).abs() < 1e-12);
+        assert!((t.v_prev[1] - 1.0).abs() < 1e-12);
+        let r = t.resistances(dt, IntegrationMethod::BackwardEuler);
+        assert_eq!(
+            t.voltage_sources(dt, IntegrationMethod::BackwardEuler),
+            [r[0][0] * 1e-3, r[1][0] * 1e-3]
+        );
+    }
+}
@@ -0,0 +1,286 @@
+//! Triode vacuum tube model.
+//!
+//! Uses Koren's triode equations, as in his SPICE tube models. With the
+//! plate-cathode voltage `Vpk` and grid-cathode voltage `Vgk`:
+//!
+//! - `E1 = Vpk / kp * ln(1 + exp(kp * (1/mu + Vgk / sqrt(kvb + Vpk^2))))`
+//! - `Ip = 2 * E1^ex / kg1` for `E1 > 0`, else `0`
+//!
+//! The factor of two is Koren's `1 + sgn(E1)`, so published `kg1` values
+//! can be used as they are. The plate only conducts with `Vpk > 0`.
+//!
+//! Once the grid goes positive of the cathode it draws current like a
+//! Child-law diode, `Ig = gcf * Vgk^1.5`, which is what makes an
+//! overdriven stage clip asymmetrically against its grid resistor.
+
+use crate::circuit::{ComponentId, NodeId};
+use crate::dsl::{ModelDef, ModelType};
+use crate::error::{PedalerError, Result};
+
+/// Parameters for a triode model. The defaults are Koren's 12AX7.
+#[derive(Debug, Clone)]
+pub struct TriodeParams {
+    /// Amplification factor
+    pub mu: f64,
+    /// Exponent of the plate current law
+    pub ex: f64,
+    /// Plate current scale (inverse perveance)
+    pub kg1: f64,
+    /// Shape of the transition into cutoff
+    pub kp: f64,
+    /// Knee voltage² of the plate curves (V²)
+    pub kvb: f64,
+    /// Grid current factor (A/V^1.5)
+    pub gcf: f64,
+}
+
+impl Default for TriodeParams {
+    fn default() -> Self {
+        Self {
+            mu: 100.0,
+            ex: 1.4,
+            kg1: 1060.0,
+            kp: 600.0,
+            kvb: 300.0,
+            gcf: 2e-4,
+        }
+    }
+}
+
+impl TriodeParams {
+    /// Create parameters from a model definition.
+    ///
+    /// Returns `InvalidParameter` for out-of-range values: every parameter
+    /// must be positive, except `gcf`, which must not be negative.
+    pub fn from_model(model: &ModelDef) -> Result<Self> {
+        if model.model_type != ModelType::Triode {
+            return Err(PedalerError::InvalidParameter {
+                component: model.name.clone(),
+                param: "type".to_string(),
+                message: "expected TRIODE model type".to_string(),
+            });
+        }
+
+        let mut params = Self::default();
+
+        if let Some(mu) = super::positive_model_param(model, "mu")? {
+            params.mu = mu;
+        }
+        if let Some(ex) = super::positive_model_param(model, "ex")? {
+            params.ex = ex;
+        }
+        if let Some(kg1) = super::positive_model_param(model, "kg1")? {
+            params.kg1 = kg1;
+        }
+        if let Some(kp) = super::positive_model_param(model, "kp")? {
+            params.kp = kp;
+        }
+        if let Some(kvb) = super::positive_model_param(model, "kvb")? {
+            params.kvb = kvb;
+        }
+        if let Some(gcf) = super::non_negative_model_param(model, "gcf")? {
+            params.gcf = gcf;
+        }
+
+        Ok(params)
+    }
+}
+
+/// A triode component.
+#[derive(Debug, Clone)]
+pub struct Triode {
+    pub id: ComponentId,
+    pub name: String,
+    pub nodes: [NodeId; 3], // [plate, grid, cathode]
+    pub params: TriodeParams,
+    /// Current grid-cathode voltage operating point
+    pub v_gk_op: f64,
+    /// Current plate-cathode voltage operating point
+    pub v_pk_op: f64,
+    /// Grid-cathode voltage to start from (initial condition)
+    pub initial_v_gk_op: f64,
+    /// Plate-cathode voltage to start from (initial condition)
+    pub initial_v_pk_op: f64,
+}
+
+impl Triode {
+    /// Create a new triode.
+    pub fn new(id: ComponentId, name: String, nodes: [NodeId; 3], params: TriodeParams) -> Self {
+        Self {
+            id,
+            name,
+            nodes,
+            params,
+            v_gk_op: 0.0,
+            v_pk_op: 0.0,
+            initial_v_gk_op: 0.0,
+            initial_v_pk_op: 0.0,
+        }
+    }
+
+    /// Get the plate node.
+    pub fn plate(&self) -> NodeId {
+        self.nodes[0]
+    }
+
+    /// Get the grid node.
+    pub fn grid(&self) -> NodeId {
+        self.nodes[1]
+    }
+
+    /// Get the cathode node.
+    pub fn cathode(&self) -> NodeId {
+        self.nodes[2]
+    }
+
+    /// Koren's `E1` and its partial derivatives `(E1, dE1/dVgk, dE1/dVpk)`.
+    fn e1(&self, v_gk: f64, v_pk: f64) -> (f64, f64, f64) {
+        let TriodeParams { mu, kp, kvb, .. } = self.params;
+        let s = (kvb + v_pk * v_pk).sqrt();
+        let u = kp * (1.0 / mu + v_gk / s);
+        // ln(1 + e^u) and its derivative, the logistic function, without
+        // overflowing for large u
+        let softplus = u.max(0.0) + (-u.abs()).exp().ln_1p();
+        let logistic = if u >= 0.0 {
+            1.0 / (1.0 + (-u).exp())
+        } else {
+            let e = u.exp();
+            e / (1.0 + e)
+        };
+        let e1 = v_pk / kp * softplus;
+        let d_gk = v_pk * logistic / s;
+        let d_pk = softplus / kp - logistic * v_gk * v_pk * v_pk / (s * s * s);
+        (e1, d_gk, d_pk)
+    }
+
+    /// Plate current and its partial derivatives `(Ip, dIp/dVgk, dIp/dVpk)`.
+    fn plate_current(&self, v_gk: f64, v_pk: f64) -> (f64, f64, f64) {
+        let (e1, d_gk, d_pk) = self.e1(v_gk, v_pk);
+        if e1 <= 0.0 {
+            return (0.0, 0.0, 0.0);
+        }
+        let TriodeParams { ex, kg1, .. } = self.params;
+        let i_p = 2.0 * e1.powf(ex) / kg1;
+        let d_e1 = 2.0 * ex * e1.powf(ex - 1.0) / kg1;
+        (i_p, d_e1 * d_gk, d_e1 * d_pk)
+    }
+
+    /// Calculate the plate current (Ip), flowing from plate to cathode.
+    pub fn i_p(&self, v_gk: f64, v_pk: f64) -> f64 {
+        self.plate_current(v_gk, v_pk).0
+    }
+
+    /// Calculate the grid current (Ig), flowing from grid to cathode.
+    pub fn i_g(&self, v_gk: f64) -> f64 {
+        if v_gk <= 0.0 {
+            0.0
+        } else {
+            self.params.gcf * v_gk * v_gk.sqrt()
+        }
+    }
+
+    /// Get partial derivatives for linearization.
+    /// Returns (gm, gp, gg) - transconductance dIp/dVgk, plate conductance
+    /// dIp/dVpk and grid conductance dIg/dVgk.
+    pub fn linearize(&self, v_gk: f64, v_pk: f64) -> (f64, f64, f64) {
+        let (_, gm, gp) = self.plate_current(v_gk, v_pk);
+        let gg = 1.5 * self.params.gcf * v_gk.max(0.0).sqrt();
+        (gm, gp.max(1e-12), gg.max(1e-12))
+    }
+
+    /// Update operating points.
+    pub fn update_operating_point(&mut self, v_gk: f64, v_pk: f64) {
+        self.v_gk_op = v_gk;
+        self.v_pk_op = v_pk;
+    }
+
+    /// Return to the initial operating point.
+    pub fn reset_to_initial_conditions(&mut self) {
+        self.v_gk_op = self.initial_v_gk_op;
+        self.v_pk_op = self.initial_v_pk_op;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triode() -> Triode {
+        Triode::new(
+            ComponentId(0),
+            "TRIODE1".to_string(),
+            [NodeId(1), NodeId(2), NodeId(0)],
+            TriodeParams::default(),
+        )
+    }
+
+    /// Koren's plate current written out directly, for a 12AX7.
+    fn koren(v_gk: f64, v_pk: f64) -> f64 {
+        let e1 = v_pk / 600.0
+            * (1.0 + (600.0 * (1.0 / 100.0 + v_gk / (300.0 + v_pk * v_pk).sqrt())).exp()).ln();
+        if e1 > 0.0 {
+            2.0 * e1.powf(1.4) / 1060.0
+        } else {
+            0.0
+        }
+    }
+
+    #[test]
+    fn test_plate_current_matches_koren() {
+        let t = triode();
+        for &(v_gk, v_pk) in &[
+            (0.0, 100.0),
+            (-1.0, 200.0),
+            (-2.0, 250.0),
+            (-0.5, 150.0),
+            (-4.0, 300.0),
+        ] {
+            let expected = koren(v_gk, v_pk);
+            let i_p = t.i_p(v_gk, v_pk);
+            assert!(
+                (i_p - expected).abs() <= 1e-9 * expected.max(1e-12),
+                "Ip {} vs {} at ({}, {})",
+                i_p,
+                expected,
+                v_gk,
+                v_pk
+            );
+        }
+
+        // About 1.9 mA at Vgk = -1 V, Vpk = 200 V, falling off as the
+        // grid goes negative and nothing with the plate reversed
+        let i_p = t.i_p(-1.0, 200.0);
+        assert!(i_p > 1.8e-3 && i_p < 2e-3, "Ip {}", i_p);
+        assert!(t.i_p(-2.0, 200.0) < i_p);
+        assert_eq!(t.i_p(0.0, -10.0), 0.0);
+    }
+
+    #[test]
+    fn test_derivatives() {
+        let t = triode();
+        let h = 1e-6;
+        for &(v_gk, v_pk) in &[(-1.0, 200.0), (-0.2, 50.0), (0.5, 100.0)] {
+            let gm = (t.i_p(v_gk + h, v_pk) - t.i_p(v_gk - h, v_pk)) / (2.0 * h);
+            let gp = (t.i_p(v_gk, v_pk + h) - t.i_p(v_gk, v_pk - h)) / (2.0 * h);
+            let gg = (t.i_g(v_gk + h) - t.i_g(v_gk - h)) / (2.0 * h);
+            let (a_gm, a_gp, a_gg) = t.linearize(v_gk, v_pk);
+            assert!((a_gm - gm).abs() < 1e-8, "gm {} vs {}", a_gm, gm);
+            assert!((a_gp - gp).abs() < 1e-8, "gp {} vs {}", a_gp, gp);
+            assert!((a_gg - gg).abs() < 1e-8, "gg {} vs {}", a_gg, gg);
+        }
+
+        // Amplification factor gm / gp close to mu in the linear region
+        let (gm, gp, _) = t.linearize(-1.0, 200.0);
+        assert!((gm / gp - 100.0).abs() < 10.0, "mu {}", gm / gp);
+    }
+
+    #[test]
+    fn test_invalid_model_params_rejected() {
+        let ast = crate::dsl::parse(".model TBAD TRIODE (mu=100 kg1=0)").unwrap();
+        let err = TriodeParams::from_model(&ast.models["TBAD"]).unwrap_err();
+        assert!(matches!(
+            err,
+            PedalerError::InvalidParameter { ref param, .. } if param == "kg1"
+        ));
+    }
+}
@@ -0,0 +1,141 @@
+//! Mono-to-stereo widener for creating a stereo image from a mono chain.
+//!
+//! Two modes are available:
+//! - Haas (default): the right channel is crossfaded towards a copy of the
+//!   input delayed by a few milliseconds. At full width the right channel
+//!   is simply the left channel delayed, which the ear hears as width.
+//! - Mid/side: a side signal `(x − x_delayed) / 2` is added to the left and
+//!   subtracted from the right, so `L + R` is always `2x` and the effect
+//!   collapses cleanly to mono.
+
+use crate::circuit::NodeId;
+
+/// A mono-in, stereo-out widener.
+#[derive(Debug, Clone)]
+pub struct Widener {
+    /// Component name
+    pub name: String,
+    /// Input node (reads voltage from here)
+    pub input_node: NodeId,
+    /// Left output node
+    pub left_node: NodeId,
+    /// Right output node
+    pub right_node: NodeId,
+    /// Ring buffer holding the delayed copy
+    buffer: Vec<f32>,
+    /// Current write position in the buffer
+    write_pos: usize,
+    /// Stereo width (0.0 = mono, 1.0 = full)
+    width: f32,
+    /// Use mid/side processing instead of a plain Haas delay
+    mid_side: bool,
+}
+
+impl Widener {
+    /// Create a new widener.
+    ///
+    /// # Arguments
+    /// * `name` - Component name
+    /// * `nodes` - Input, left output and right output nodes
+    /// * `width` - Stereo width (0.0-1.0)
+    /// * `delay_time` - Inter-channel delay in seconds
+    /// * `sample_rate` - Sample rate in Hz
+    /// * `mid_side` - Use mid/side processing
+    pub fn new(
+        name: String,
+        nodes: [NodeId; 3],
+        width: f32,
+        delay_time: f64,
+        sample_rate: f32,
+        mid_side: bool,
+    ) -> Self {
+        let delay_samples = ((delay_time * sample_rate as f64).round() as usize).max(1);
+
+        Self {
+            name,
+            input_node: nodes[0],
+            left_node: nodes[1],
+            right_node: nodes[2],
+            buffer: vec![0.0; delay_samples],
+            write_pos: 0,
+            width: width.clamp(0.0, 1.0),
+            mid_side,
+        }
+    }
+
+    /// Get the inter-channel delay in samples.
+    pub fn delay_samples(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Process one sample, returning the `(left, right)` outputs.
+    pub fn process(&mut self, input: f32) -> (f32, f32) {
+        let delayed = self.buffer[self.write_pos];
+        self.buffer[self.write_pos] = input;
+        self.write_pos = (self.write_pos + 1) % self.buffer.len();
+
+        if self.mid_side {
+            let side = self.width * 0.5 * (input - delayed);
+            (input + side, input - side)
+        } else {
+            (input, input * (1.0 - self.width) + delayed * self.width)
+        }
+    }
+
+    /// Reset the widener (clear buffer).
+    pub fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.write_pos = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn widener(width: f32, mid_side: bool) -> Widener {
+        // 5 samples of delay at 1kHz
+        Widener::new(
+            "W1".to_string(),
+            [NodeId(1), NodeId(2), NodeId(3)],
+            width,
+            0.005,
+            1000.0,
+            mid_side,
+        )
+    }
+
+    fn run(w: &mut Widener, input: &[f32]) -> (Vec<f32>, Vec<f32>) {
+        input.iter().map(|&x| w.process(x)).unzip()
+    }
+
+    #[test]
+    fn test_zero_width_is_mono() {
+        let input: Vec<f32> = (0..50).map(|i| (i as f32 * 0.3).sin()).collect();
+        for mid_side in [false, true] {
+            let (left, right) = run(&mut widener(0.0, mid_side), &input);
+            assert_eq!(left, right);
+            assert_eq!(left, input);
+        }
+    }
+
+    #[test]
+    fn test_full_width_delays_right_channel() {
+        let mut impulse = vec![0.0f32; 20];
+        impulse[2] = 1.0;
+        let (left, right) = run(&mut widener(1.0, false), &impulse);
+
+        assert_eq!(left, impulse);
+        assert_eq!(right.iter().position(|&v| v == 1.0), Some(7));
+    }
+
+    #[test]
+    fn test_mid_side_preserves_mono_sum() {
+        let input: Vec<f32> = (0..50).map(|i| (i as f32 * 0.7).sin()).collect();
+        let (left, right) = run(&mut widener(1.0, true), &input);
+        assert!(left != right);
+        for ((l, r), x) in left.iter().zip(&right).zip(&input) {
+            assert!((l + r - 2.0 * x).abs() < 1e-6);
+        }
+    }
+}
@@ -1,6 +1,14 @@
 //! Abstract Syntax Tree types for the circuit DSL.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+
+use crate::error::{PedalerError, Result};
+
+/// Name of the resistor inserted by [`CircuitAst::add_source_impedance`].
+pub const SOURCE_IMPEDANCE_NAME: &str = "R_SOURCE_Z";
+
+/// Name of the resistor inserted by [`CircuitAst::add_load_impedance`].
+pub const LOAD_IMPEDANCE_NAME: &str = "R_LOAD_Z";
 
 /// Complete AST representation of a parsed circuit.
 #[derive(Debug, Clone)]
@@ -9,12 +17,18 @@ pub struct CircuitAst {
     pub components: Vec<ComponentDef>,
     /// Model definitions
     pub models: HashMap<String, ModelDef>,
-    /// Input node name
-    pub input_node: Option<String>,
-    /// Output node name
-    pub output_node: Option<String>,
+    /// Input node names, one per channel in `.input` order
+    pub input_nodes: Vec<String>,
+    /// Output node names, one per channel in `.output` order
+    pub output_nodes: Vec<String>,
+    /// Auxiliary control input node names (e.g. a sidechain)
+    pub control_nodes: Vec<String>,
     /// All referenced node names (including implicit ones)
     pub nodes: Vec<String>,
+    /// Named values from `.param` directives
+    pub params: HashMap<String, f64>,
+    /// Subcircuit definitions from `.subckt` blocks
+    pub subcircuits: HashMap<String, SubcircuitDef>,
 }
 
 impl CircuitAst {
@@ -23,10 +37,371 @@ impl CircuitAst {
         Self {
             components: Vec::new(),
             models: HashMap::new(),
-            input_node: None,
-            output_node: None,
+            input_nodes: Vec::new(),
+            output_nodes: Vec::new(),
+            control_nodes: Vec::new(),
             nodes: Vec::new(),
+            params: HashMap::new(),
+            subcircuits: HashMap::new(),
+        }
+    }
+
+    /// Replace every subcircuit instance with a copy of the subcircuit body.
+    ///
+    /// The components of an instance `X1` are renamed `X1.<name>`. Ports
+    /// bind to the instance's nodes, and every other node of the body
+    /// becomes `X1.<node>`, so each instance has its own internal nodes.
+    /// Nested instances flatten recursively (`X1.X2.R1`); a subcircuit that
+    /// instantiates itself, directly or not, is a `ParseError`.
+    pub fn flatten_subcircuits(&mut self) -> Result<()> {
+        if !self
+            .components
+            .iter()
+            .any(|c| c.component_type == ComponentType::Subcircuit)
+        {
+            return Ok(());
+        }
+        let mut flat = Vec::with_capacity(self.components.len());
+        for def in std::mem::take(&mut self.components) {
+            expand_instance(&self.subcircuits, def, &mut Vec::new(), &mut flat)?;
+        }
+        self.components = flat;
+        Ok(())
+    }
+
+    /// Replace every `.param` reference in component values and model
+    /// parameters with its value.
+    ///
+    /// A reference is written `{NAME}` or, where a number is expected, as a
+    /// bare `NAME`. A bare identifier in a `key=value` parameter only counts
+    /// as a reference when it names a `.param`, since it may also name an
+    /// LFO or envelope follower. Returns a `ParseError` for an undefined
+    /// reference.
+    pub fn resolve_params(&mut self) -> Result<()> {
+        let params = &self.params;
+        for def in &mut self.components {
+            for (slot, reference) in std::mem::take(&mut def.param_refs) {
+                let value = resolve_param(params, &reference, def.line)?;
+                if slot == "value" {
+                    def.value = Some(value);
+                } else {
+                    def.params.insert(slot, value);
+                }
+            }
+            let named: Vec<String> = def
+                .string_params
+                .iter()
+                .filter(|(_, text)| text.starts_with('{') || params.contains_key(text.as_str()))
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in named {
+                let reference = def.string_params.remove(&key).expect("listed above");
+                def.params
+                    .insert(key, resolve_param(params, &reference, def.line)?);
+            }
+        }
+        for model in self.models.values_mut() {
+            for (key, reference) in std::mem::take(&mut model.param_refs) {
+                model
+                    .params
+                    .insert(key, resolve_param(params, &reference, model.line)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace every tone stack with its passive network.
+    ///
+    /// A tone stack `T1` from `in` to `out` is two legs summed into `out`
+    /// through 100k each, both controls being 100k linear pots to the
+    /// reference node:
+    ///
+    /// - treble: a 2.2n high-pass into the pot `T1.TREBLE`, whose wiper
+    ///   feeds the sum (corner about 720 Hz)
+    /// - bass: a 10k/100n low-pass into the pot `T1.BASS` (corner about
+    ///   160 Hz)
+    ///
+    /// The pots take the `treble` and `bass` positions (0.5 by default), so
+    /// hosts turn the knobs like any other pot. Internal nodes are named
+    /// `T1.hp`, `T1.tw`, `T1.lp` and `T1.bw`. Run after
+    /// [`resolve_params`](Self::resolve_params), as the positions may be
+    /// `.param` references.
+    pub fn expand_tone_stacks(&mut self) -> Result<()> {
+        if !self
+            .components
+            .iter()
+            .any(|c| c.component_type == ComponentType::ToneStack)
+        {
+            return Ok(());
+        }
+        let mut expanded = Vec::with_capacity(self.components.len());
+        for def in std::mem::take(&mut self.components) {
+            if def.component_type != ComponentType::ToneStack {
+                expanded.push(def);
+                continue;
+            }
+            let position = |key: &str, value: Option<f64>| -> Result<f64> {
+                let v = def.params.get(key).copied().or(value).unwrap_or(0.5);
+                if !(0.0..=1.0).contains(&v) {
+                    return Err(PedalerError::invalid_parameter(
+                        &def.name,
+                        key,
+                        format!("must be between 0 and 1, got {}", v),
+                    ));
+                }
+                Ok(v)
+            };
+            if def.params.contains_key("extra") {
+                return Err(PedalerError::invalid_component(
+                    &def.name,
+                    def.line,
+                    "expected at most two knob positions, treble and bass",
+                ));
+            }
+            let treble = position("treble", def.value)?;
+            let bass = position("bass", None)?;
+
+            let [input, output, reference] = [0, 1, 2].map(|i| def.nodes[i].clone());
+            let node = |suffix: &str| format!("{}.{}", def.name, suffix);
+            let part =
+                |component_type, suffix: &str, nodes: &[String], value, position: Option<f64>| {
+                    let mut part = part_def(component_type, &node(suffix), nodes.to_vec(), value);
+                    part.params
+                        .extend(position.map(|p| ("position".to_string(), p)));
+                    part.line = def.line;
+                    part
+                };
+            use ComponentType::{Capacitor, Potentiometer, Resistor};
+            expanded.extend([
+                part(Capacitor, "CT", &[input.clone(), node("hp")], 2.2e-9, None),
+                part(
+                    Potentiometer,
+                    "TREBLE",
+                    &[reference.clone(), node("tw"), node("hp")],
+                    100e3,
+                    Some(treble),
+                ),
+                part(Resistor, "RT", &[node("tw"), output.clone()], 100e3, None),
+                part(Resistor, "RB", &[input, node("lp")], 10e3, None),
+                part(
+                    Capacitor,
+                    "CB",
+                    &[node("lp"), reference.clone()],
+                    100e-9,
+                    None,
+                ),
+                part(
+                    Potentiometer,
+                    "BASS",
+                    &[reference, node("bw"), node("lp")],
+                    100e3,
+                    Some(bass),
+                ),
+                part(Resistor, "RBM", &[node("bw"), output], 100e3, None),
+            ]);
+        }
+        self.components = expanded;
+        Ok(())
+    }
+
+    /// Drive each input through a series resistance, modeling the output
+    /// impedance of whatever feeds the circuit (e.g. a guitar pickup).
+    ///
+    /// The input voltage source is moved to a new node, which becomes the
+    /// input node, and a resistor named [`SOURCE_IMPEDANCE_NAME`] connects it
+    /// to the original input node. With several input channels, each gets
+    /// its own resistor, named `R_SOURCE_Z_<node>`.
+    pub fn add_source_impedance(&mut self, resistance: f64) -> Result<()> {
+        if self.input_nodes.is_empty() {
+            return Err(PedalerError::MissingInput);
+        }
+        let channels = self.input_nodes.len();
+        for channel in 0..channels {
+            let input = self.input_nodes[channel].clone();
+            let name = termination_name(SOURCE_IMPEDANCE_NAME, &input, channels);
+            self.check_termination(&name, resistance)?;
+
+            // The circuit uses the last source touching the input node
+            let source = self
+                .components
+                .iter()
+                .rposition(|c| {
+                    c.component_type == ComponentType::VoltageSource && c.nodes.contains(&input)
+                })
+                .ok_or_else(|| PedalerError::UndrivenInput {
+                    node: input.clone(),
+                })?;
+
+            let mut source_node = format!("{}_source", input);
+            while self.nodes.contains(&source_node) {
+                source_node.push('_');
+            }
+            for node in &mut self.components[source].nodes {
+                if *node == input {
+                    *node = source_node.clone();
+                }
+            }
+
+            self.nodes.push(source_node.clone());
+            self.components.push(resistor_def(
+                &name,
+                [source_node.clone(), input],
+                resistance,
+            ));
+            self.input_nodes[channel] = source_node;
+        }
+        Ok(())
+    }
+
+    /// Load each output with a resistance to ground, modeling the input
+    /// impedance of whatever follows the circuit (e.g. an amplifier).
+    ///
+    /// The resistor is named [`LOAD_IMPEDANCE_NAME`], or `R_LOAD_Z_<node>`
+    /// with several output channels.
+    pub fn add_load_impedance(&mut self, resistance: f64) -> Result<()> {
+        if self.output_nodes.is_empty() {
+            return Err(PedalerError::MissingOutput);
+        }
+        let channels = self.output_nodes.len();
+        for output in self.output_nodes.clone() {
+            let name = termination_name(LOAD_IMPEDANCE_NAME, &output, channels);
+            self.check_termination(&name, resistance)?;
+            self.components
+                .push(resistor_def(&name, [output, "0".to_string()], resistance));
+        }
+        Ok(())
+    }
+
+    /// Check that a termination resistor can be added under `name`.
+    fn check_termination(&self, name: &str, resistance: f64) -> Result<()> {
+        if resistance.is_nan() || resistance <= 0.0 {
+            return Err(PedalerError::invalid_parameter(
+                name,
+                "resistance",
+                format!("must be positive, got {}", resistance),
+            ));
+        }
+        if self.components.iter().any(|c| c.name == name) {
+            return Err(PedalerError::DuplicateComponent {
+                name: name.to_string(),
+            });
         }
+        Ok(())
+    }
+}
+
+/// Push `def` onto `out`, expanded into its body if it is a subcircuit
+/// instance. `stack` holds the subcircuits being expanded, outermost first.
+fn expand_instance(
+    subcircuits: &HashMap<String, SubcircuitDef>,
+    def: ComponentDef,
+    stack: &mut Vec<String>,
+    out: &mut Vec<ComponentDef>,
+) -> Result<()> {
+    if def.component_type != ComponentType::Subcircuit {
+        out.push(def);
+        return Ok(());
+    }
+
+    let name = def.model_ref.clone().unwrap_or_default();
+    let subckt = subcircuits
+        .get(&name)
+        .ok_or_else(|| PedalerError::UndefinedSubcircuit {
+            subcircuit: name.clone(),
+            component: def.name.clone(),
+        })?;
+    if def.nodes.len() != subckt.ports.len() {
+        return Err(PedalerError::invalid_component(
+            &def.name,
+            def.line,
+            format!(
+                "subcircuit {} has {} ports, got {} nodes",
+                name,
+                subckt.ports.len(),
+                def.nodes.len()
+            ),
+        ));
+    }
+    if stack.contains(&name) {
+        stack.push(name);
+        return Err(PedalerError::parse(
+            def.line,
+            format!("subcircuit instantiates itself: {}", stack.join(" -> ")),
+        ));
+    }
+
+    // Names of the body's components, which references to them follow
+    let local: HashSet<&str> = subckt.components.iter().map(|c| c.name.as_str()).collect();
+    let scoped = |name: &str| format!("{}.{}", def.name, name);
+
+    stack.push(name);
+    for body in &subckt.components {
+        let mut c = body.clone();
+        c.name = scoped(&c.name);
+        for node in &mut c.nodes {
+            if let Some(i) = subckt.ports.iter().position(|p| p == node) {
+                *node = def.nodes[i].clone();
+            } else if node != "0" {
+                *node = scoped(node);
+            }
+        }
+        // LFOs and envelope followers of the body are renamed with it
+        for text in c.string_params.values_mut().chain(c.model_ref.as_mut()) {
+            if local.contains(text.as_str()) {
+                *text = scoped(text);
+            }
+        }
+        expand_instance(subcircuits, c, stack, out)?;
+    }
+    stack.pop();
+    Ok(())
+}
+
+/// Look up a `.param` reference, written `{NAME}` or `NAME`.
+fn resolve_param(params: &HashMap<String, f64>, reference: &str, line: usize) -> Result<f64> {
+    let name = reference
+        .strip_prefix('{')
+        .and_then(|r| r.strip_suffix('}'))
+        .unwrap_or(reference);
+    params
+        .get(name)
+        .copied()
+        .ok_or_else(|| PedalerError::parse(line, format!("undefined parameter '{}'", name)))
+}
+
+/// Name of a termination resistor at `node`, suffixed with the node when
+/// there are several channels to terminate.
+fn termination_name(base: &str, node: &str, channels: usize) -> String {
+    if channels > 1 {
+        format!("{}_{}", base, node)
+    } else {
+        base.to_string()
+    }
+}
+
+/// A plain resistor inserted by the circuit build rather than written in
+/// the source.
+fn resistor_def(name: &str, nodes: [String; 2], resistance: f64) -> ComponentDef {
+    part_def(ComponentType::Resistor, name, nodes.to_vec(), resistance)
+}
+
+/// A component with only a value, inserted by the circuit build.
+fn part_def(
+    component_type: ComponentType,
+    name: &str,
+    nodes: Vec<String>,
+    value: f64,
+) -> ComponentDef {
+    ComponentDef {
+        component_type,
+        name: name.to_string(),
+        nodes,
+        value: Some(value),
+        model_ref: None,
+        params: HashMap::new(),
+        string_params: HashMap::new(),
+        param_refs: HashMap::new(),
+        line: 0,
     }
 }
 
@@ -51,6 +426,11 @@ pub struct ComponentDef {
     pub model_ref: Option<String>,
     /// Additional parameters
     pub params: HashMap<String, f64>,
+    /// Additional parameters with identifier values (e.g. `mod=ENV1`)
+    pub string_params: HashMap<String, String>,
+    /// `.param` references still to be resolved, keyed by `"value"` or the
+    /// parameter they set
+    pub param_refs: HashMap<String, String>,
     /// Source line number for error reporting
     pub line: usize,
 }
@@ -64,26 +444,52 @@ pub enum ComponentType {
     Capacitor,
     /// Inductor
     Inductor,
+    /// Transformer (coupled inductors)
+    Transformer,
     /// Diode
     Diode,
     /// Bipolar Junction Transistor
     Bjt,
+    /// Junction Field-Effect Transistor
+    Jfet,
+    /// Enhancement-mode MOSFET
+    Mosfet,
+    /// Triode vacuum tube
+    Triode,
     /// Voltage Source
     VoltageSource,
     /// Current Source
     CurrentSource,
+    /// White noise current source
+    Noise,
+    /// Ammeter (zero-volt current probe)
+    Ammeter,
     /// Operational Amplifier
     OpAmp,
     /// Potentiometer
     Potentiometer,
     /// Switch
     Switch,
+    /// Hysteretic resistor
+    Hysteretic,
+    /// Resistor following a resistance curve of a control signal
+    NonlinearResistor,
     /// Digital Delay Line
     Delay,
     /// FDN Reverb
     Reverb,
+    /// Mono-to-stereo widener
+    Widen,
     /// Low Frequency Oscillator (control signal)
     Lfo,
+    /// Envelope follower (control signal)
+    Envelope,
+    /// Series diode chains in each direction, expanded into single diodes
+    DiodeStack,
+    /// Treble and bass controls, expanded into a passive RC network
+    ToneStack,
+    /// Subcircuit instance, flattened when the circuit is built
+    Subcircuit,
 }
 
 impl ComponentType {
@@ -95,8 +501,56 @@ impl ComponentType {
             'L' => Some(Self::Inductor),
             'D' => Some(Self::Diode),
             'Q' => Some(Self::Bjt),
+            'J' => Some(Self::Jfet),
+            'M' => Some(Self::Mosfet),
             'V' => Some(Self::VoltageSource),
             'I' => Some(Self::CurrentSource),
+            'N' => Some(Self::Noise),
+            'X' => Some(Self::Subcircuit),
+            _ => None,
+        }
+    }
+
+    /// Component type named by the prefix of a component name, as in `R1`,
+    /// `POT_GAIN` or `XFMR1`. Multi-letter prefixes take precedence over the
+    /// single-letter ones they start with.
+    pub fn from_name(name: &str) -> Option<Self> {
+        const PREFIXES: [(&str, ComponentType); 8] = [
+            ("OP", ComponentType::OpAmp),
+            ("POT", ComponentType::Potentiometer),
+            ("SW", ComponentType::Switch),
+            ("AM", ComponentType::Ammeter),
+            ("XFMR", ComponentType::Transformer),
+            ("DSTACK", ComponentType::DiodeStack),
+            ("TONE", ComponentType::ToneStack),
+            ("TRIODE", ComponentType::Triode),
+        ];
+        let upper = name.to_ascii_uppercase();
+        PREFIXES
+            .iter()
+            .find(|(prefix, _)| upper.starts_with(prefix))
+            .map(|&(_, ct)| ct)
+            .or_else(|| name.chars().next().and_then(Self::from_prefix))
+    }
+
+    /// Keyword that introduces a component of this type before its name,
+    /// as in `LFO LFO1 2 sine`; `None` for types only written by prefix.
+    pub fn keyword(&self) -> Option<&'static str> {
+        match self {
+            Self::OpAmp => Some("OPAMP"),
+            Self::Potentiometer => Some("POT"),
+            Self::Switch => Some("SW"),
+            Self::Hysteretic => Some("HYST"),
+            Self::NonlinearResistor => Some("NLR"),
+            Self::Delay => Some("DELAY"),
+            Self::Reverb => Some("REVERB"),
+            Self::Widen => Some("WIDEN"),
+            Self::Lfo => Some("LFO"),
+            Self::Envelope => Some("ENV"),
+            Self::Ammeter => Some("AM"),
+            Self::DiodeStack => Some("DSTACK"),
+            Self::ToneStack => Some("TONE"),
+            Self::Triode => Some("TRIODE"),
             _ => None,
         }
     }
@@ -107,9 +561,17 @@ impl ComponentType {
             "OP" | "OPAMP" => Some(Self::OpAmp),
             "POT" => Some(Self::Potentiometer),
             "SW" | "SWITCH" => Some(Self::Switch),
+            "HYST" => Some(Self::Hysteretic),
+            "NLR" => Some(Self::NonlinearResistor),
             "DELAY" => Some(Self::Delay),
             "REVERB" | "REV" => Some(Self::Reverb),
+            "WIDEN" => Some(Self::Widen),
             "LFO" => Some(Self::Lfo),
+            "ENV" | "ENVELOPE" => Some(Self::Envelope),
+            "AM" | "AMMETER" => Some(Self::Ammeter),
+            "DSTACK" => Some(Self::DiodeStack),
+            "TONE" => Some(Self::ToneStack),
+            "TRIODE" => Some(Self::Triode),
             _ => None,
         }
     }
@@ -118,15 +580,88 @@ impl ComponentType {
     pub fn expected_node_count(&self) -> usize {
         match self {
             Self::Resistor | Self::Capacitor | Self::Inductor => 2,
+            Self::Transformer => 4, // p+, p-, s+, s-
             Self::Diode => 2,
-            Self::Bjt => 3,        // collector, base, emitter
+            Self::Bjt => 3,    // collector, base, emitter
+            Self::Jfet => 3,   // drain, gate, source
+            Self::Mosfet => 3, // drain, gate, source
+            Self::Triode => 3, // plate, grid, cathode
             Self::VoltageSource | Self::CurrentSource => 2,
-            Self::OpAmp => 3,      // out, in+, in-
+            Self::Noise => 2,
+            Self::Ammeter => 2,
+            Self::OpAmp => 3,         // out, in+, in-
             Self::Potentiometer => 3, // n1, wiper, n2
             Self::Switch => 2,
+            Self::Hysteretic => 2,
+            Self::NonlinearResistor => 2,
             Self::Delay => 2,      // in, out
             Self::Reverb => 2,     // in, out
+            Self::Widen => 3,      // in, left, right
             Self::Lfo => 0,        // No electrical nodes - purely a control signal
+            Self::Envelope => 1,   // followed node
+            Self::DiodeStack => 2, // anode, cathode
+            Self::ToneStack => 3,  // in, out, reference
+            Self::Subcircuit => 0, // as many as the subcircuit has ports
+        }
+    }
+
+    /// Number of nodes that may follow the expected ones, as the second
+    /// throw of a switch.
+    pub fn optional_node_count(&self) -> usize {
+        match self {
+            Self::Switch => 1, // common, t1, t2
+            _ => 0,
+        }
+    }
+
+    /// Name of the parameter set by the `index`th number after the value.
+    pub fn extra_value_name(&self, index: usize) -> &'static str {
+        match (self, index) {
+            // XFMR1 p+ p- s+ s- L_pri L_sec k
+            (Self::Transformer, 0) => "lsec",
+            (Self::Transformer, _) => "k",
+            // N1 n+ n- amplitude seed
+            (Self::Noise, _) => "seed",
+            // TONE1 in out ref treble bass; a third number lands in a slot
+            // of its own so the tone stack can reject it
+            (Self::ToneStack, 0) => "bass",
+            (Self::ToneStack, _) => "extra",
+            _ => "position",
+        }
+    }
+
+    /// Whether a bare identifier after the nodes can be the component's
+    /// value, rather than a model, LFO or shape name.
+    pub fn takes_value(&self) -> bool {
+        matches!(
+            self,
+            Self::Resistor
+                | Self::Capacitor
+                | Self::Inductor
+                | Self::Transformer
+                | Self::VoltageSource
+                | Self::CurrentSource
+                | Self::Noise
+                | Self::Potentiometer
+                | Self::Delay
+                | Self::ToneStack
+        )
+    }
+
+    /// Whether a bare identifier after the nodes and value can name the
+    /// component's model, LFO or shape, as in `D1 a k 1N4148`,
+    /// `R1 a b 10k LFO1` or `LFO LFO1 2 sine`.
+    pub fn takes_model_ref(&self, text: &str) -> bool {
+        match self {
+            Self::Diode
+            | Self::Bjt
+            | Self::Jfet
+            | Self::Mosfet
+            | Self::Triode
+            | Self::OpAmp
+            | Self::Lfo => true,
+            Self::Resistor => text.to_ascii_uppercase().starts_with("LFO"),
+            _ => false,
         }
     }
 }
@@ -136,14 +671,30 @@ impl ComponentType {
 pub struct ModelDef {
     /// Model name
     pub name: String,
-    /// Model type (D for diode, NPN/PNP for BJT, etc.)
+    /// Model type (D for diode, NPN/PNP for BJT, NJF/PJF for JFET, NMOS/PMOS for MOSFET, TRIODE, etc.)
     pub model_type: ModelType,
     /// Model parameters
     pub params: HashMap<String, f64>,
+    /// `.param` references still to be resolved, keyed by model parameter
+    pub param_refs: HashMap<String, String>,
     /// Source line number
     pub line: usize,
 }
 
+/// A subcircuit: a netlist fragment between `.subckt` and `.ends`, placed
+/// into the circuit by `X` instances.
+#[derive(Debug, Clone)]
+pub struct SubcircuitDef {
+    /// Subcircuit name
+    pub name: String,
+    /// Port node names, bound in order to the nodes of each instance
+    pub ports: Vec<String>,
+    /// Components of the body, with node names local to the subcircuit
+    pub components: Vec<ComponentDef>,
+    /// Source line of the `.subckt` directive
+    pub line: usize,
+}
+
 /// Model types for parameterized components.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ModelType {
@@ -153,21 +704,52 @@ pub enum ModelType {
     BjtNpn,
     /// PNP BJT model
     BjtPnp,
+    /// N-channel JFET model
+    JfetN,
+    /// P-channel JFET model
+    JfetP,
+    /// N-channel MOSFET model
+    MosfetN,
+    /// P-channel MOSFET model
+    MosfetP,
+    /// Triode vacuum tube model
+    Triode,
     /// Op-amp model
     OpAmp,
 }
 
 impl ModelType {
     /// Parse a model type from string.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
         match s.to_ascii_uppercase().as_str() {
             "D" | "DIODE" => Some(Self::Diode),
             "NPN" => Some(Self::BjtNpn),
             "PNP" => Some(Self::BjtPnp),
+            "NJF" => Some(Self::JfetN),
+            "PJF" => Some(Self::JfetP),
+            "NMOS" => Some(Self::MosfetN),
+            "PMOS" => Some(Self::MosfetP),
+            "TRIODE" => Some(Self::Triode),
             "OP" | "OPAMP" => Some(Self::OpAmp),
             _ => None,
         }
     }
+
+    /// The type's name in a `.model` directive.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Diode => "D",
+            Self::BjtNpn => "NPN",
+            Self::BjtPnp => "PNP",
+            Self::JfetN => "NJF",
+            Self::JfetP => "PJF",
+            Self::MosfetN => "NMOS",
+            Self::MosfetP => "PMOS",
+            Self::Triode => "TRIODE",
+            Self::OpAmp => "OPAMP",
+        }
+    }
 }
 
 /// Voltage source type.
@@ -0,0 +1,653 @@
+//! Comment-preserving pretty-printer for circuit files.
+//!
+//! The formatter works on the token stream rather than the AST, so comments
+//! and blank-line structure survive the round trip. It applies these rules:
+//!
+//! - Consecutive component lines are aligned into columns, with trailing
+//!   comments aligned after the longest line.
+//! - Consecutive directive lines are sorted (`.input`, `.output`, `.node`,
+//!   `.param`, `.model`) and aligned the same way. `.subckt` and `.ends`
+//!   lines keep their places.
+//! - Values with a unit suffix, or of magnitude 1000 and above, are
+//!   rewritten in engineering notation (`4.7K` → `4.7k`, `0.1u` → `100n`,
+//!   `10000` → `10k`). Plain numbers and exponent forms are kept as written.
+//! - Directive names are lowercased, indentation and trailing whitespace are
+//!   removed, and runs of blank lines collapse to one.
+//!
+//! [`CircuitAst::to_dsl`] goes the other way, writing a parsed or built
+//! circuit out as canonical DSL text.
+
+use std::collections::HashMap;
+
+use super::ast::{CircuitAst, ComponentDef, ComponentType};
+use super::lexer::{parse_value, Lexer, Token, TokenKind};
+use crate::error::Result;
+
+/// A classified source line.
+enum Line {
+    /// Empty line
+    Blank,
+    /// Line holding only a comment
+    Comment(String),
+    /// Directive with its cells and optional trailing comment
+    Directive(Vec<String>, Option<String>),
+    /// Component with its cells and optional trailing comment
+    Component(Vec<String>, Option<String>),
+}
+
+/// Format a circuit description.
+///
+/// The input must parse as a valid circuit; parse errors are returned
+/// unchanged. Formatting is idempotent.
+pub fn format(input: &str) -> Result<String> {
+    super::parse(input)?;
+
+    let lines = split_lines(input)?;
+    let mut out = String::with_capacity(input.len());
+    let mut i = 0;
+
+    while i < lines.len() {
+        match &lines[i] {
+            Line::Blank => {
+                // Collapse runs of blank lines and drop leading ones
+                if !out.is_empty() && !out.ends_with("\n\n") {
+                    out.push('\n');
+                }
+                i += 1;
+            }
+            Line::Comment(text) => {
+                out.push_str(text);
+                out.push('\n');
+                i += 1;
+            }
+            Line::Directive(..) => {
+                // Subcircuit bounds stay put, so nothing sorts across them
+                let mut run = Vec::new();
+                while let Some(Line::Directive(cells, comment)) = lines.get(i) {
+                    let bound = matches!(cells[0].as_str(), ".subckt" | ".ends");
+                    if bound && !run.is_empty() {
+                        break;
+                    }
+                    run.push((cells.clone(), comment.clone()));
+                    i += 1;
+                    if bound {
+                        break;
+                    }
+                }
+                run.sort_by_key(|(cells, _)| directive_rank(&cells[0]));
+                write_aligned(&mut out, &run);
+            }
+            Line::Component(..) => {
+                let mut run = Vec::new();
+                while let Some(Line::Component(cells, comment)) = lines.get(i) {
+                    run.push((cells.clone(), comment.clone()));
+                    i += 1;
+                }
+                write_aligned(&mut out, &run);
+            }
+        }
+    }
+
+    // Exactly one trailing newline
+    let trimmed = out.trim_end().len();
+    out.truncate(trimmed);
+    out.push('\n');
+    Ok(out)
+}
+
+impl CircuitAst {
+    /// Write the circuit as canonical DSL text: directives first (`.input`,
+    /// `.output`, `.control`, `.node`, `.param`, `.model`, `.subckt`), then
+    /// the components, each run aligned into columns.
+    ///
+    /// Parsing the text gives back an equivalent circuit. Values are written
+    /// in engineering notation where that reads back exactly, and extra
+    /// positional values (a pot's position, a transformer's `lsec` and `k`)
+    /// as `key=value`. `.node` lines appear only when the node order differs
+    /// from the order of first use. A component whose name does not start
+    /// with its type's prefix is written after its type keyword where it has
+    /// one (`POT`, `SW`, `OPAMP`, ...); other types need the prefix, e.g. a
+    /// resistor's name must start with `R`.
+    pub fn to_dsl(&self) -> String {
+        let mut out = String::new();
+
+        let mut run: Vec<Vec<String>> = Vec::new();
+        for (directive, nodes) in [
+            (".input", &self.input_nodes),
+            (".output", &self.output_nodes),
+            (".control", &self.control_nodes),
+        ] {
+            run.extend(
+                nodes
+                    .iter()
+                    .map(|node| vec![directive.to_string(), node.clone()]),
+            );
+        }
+        // The parser lists declared nodes first, then the rest in order of
+        // first use, so declare the shortest prefix that restores the order
+        let declared = (0..=self.nodes.len())
+            .find(|&k| {
+                let mut order = self.nodes[..k].to_vec();
+                for node in self.components.iter().flat_map(|def| &def.nodes) {
+                    if node != "0" && !order.contains(node) {
+                        order.push(node.clone());
+                    }
+                }
+                order == self.nodes
+            })
+            .unwrap_or(self.nodes.len());
+        run.extend(
+            self.nodes[..declared]
+                .iter()
+                .map(|node| vec![".node".to_string(), node.clone()]),
+        );
+        for (name, value) in sorted(&self.params) {
+            run.push(vec![".param".to_string(), name.clone(), value_text(*value)]);
+        }
+        for (name, model) in sorted(&self.models) {
+            let mut cells = vec![
+                ".model".to_string(),
+                name.clone(),
+                model.model_type.name().to_string(),
+            ];
+            let mut params: Vec<String> = sorted(&model.params)
+                .into_iter()
+                .map(|(key, value)| format!("{}={}", key, value_text(*value)))
+                .chain(
+                    sorted(&model.param_refs)
+                        .into_iter()
+                        .map(|(key, r)| format!("{}={}", key, r)),
+                )
+                .collect();
+            if let Some(first) = params.first_mut() {
+                first.insert(0, '(');
+            }
+            if let Some(last) = params.last_mut() {
+                last.push(')');
+            }
+            cells.extend(params);
+            run.push(cells);
+        }
+        write_run(&mut out, &run);
+
+        for (name, subcircuit) in sorted(&self.subcircuits) {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            let mut header = vec![".subckt".to_string(), name.clone()];
+            header.extend(subcircuit.ports.iter().cloned());
+            out.push_str(&header.join(" "));
+            out.push('\n');
+            write_run(
+                &mut out,
+                &subcircuit
+                    .components
+                    .iter()
+                    .map(component_cells)
+                    .collect::<Vec<_>>(),
+            );
+            out.push_str(".ends\n");
+        }
+
+        if !self.components.is_empty() {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            write_run(
+                &mut out,
+                &self
+                    .components
+                    .iter()
+                    .map(component_cells)
+                    .collect::<Vec<_>>(),
+            );
+        }
+        out
+    }
+}
+
+/// Entries of a map in key order, for reproducible output.
+fn sorted<V>(map: &HashMap<String, V>) -> Vec<(&String, &V)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+/// Write lines of cells aligned into columns, without comments.
+fn write_run(out: &mut String, run: &[Vec<String>]) {
+    let run: Vec<(Vec<String>, Option<String>)> =
+        run.iter().map(|cells| (cells.clone(), None)).collect();
+    write_aligned(out, &run);
+}
+
+/// The cells of one component line.
+fn component_cells(def: &ComponentDef) -> Vec<String> {
+    let ct = def.component_type;
+    let upper = def.name.to_ascii_uppercase();
+    // Written as the parser would read the name alone, or after the keyword
+    let names_itself = ComponentType::from_keyword(&def.name).is_none()
+        && !upper.starts_with("DELAY")
+        && !upper.starts_with("REV")
+        && ComponentType::from_name(&def.name) == Some(ct);
+    let mut cells = match ct.keyword() {
+        Some(keyword) if !names_itself => vec![keyword.to_string(), def.name.clone()],
+        _ => vec![def.name.clone()],
+    };
+    cells.extend(def.nodes.iter().cloned());
+
+    // Instances end with the subcircuit name and have nothing else
+    if ct == ComponentType::Subcircuit {
+        cells.extend(def.model_ref.iter().cloned());
+        return cells;
+    }
+
+    // Sources write their DC/AC flag as a keyword before the value
+    let is_source = matches!(
+        ct,
+        ComponentType::VoltageSource | ComponentType::CurrentSource
+    );
+    let flag = |key: &str| is_source && def.params.get(key) == Some(&1.0);
+    for key in ["dc", "ac"] {
+        if flag(key) {
+            cells.push(key.to_uppercase());
+        }
+    }
+
+    if let Some(value) = def.value {
+        cells.push(value_text(value));
+    } else if let Some(reference) = def.param_refs.get("value") {
+        cells.push(param_ref_text(reference, ct.takes_value()));
+    }
+    cells.extend(def.model_ref.iter().cloned());
+
+    for (key, value) in sorted(&def.params) {
+        if !flag(key) {
+            cells.push(format!("{}={}", key, value_text(*value)));
+        }
+    }
+    for (key, text) in sorted(&def.string_params) {
+        cells.push(format!("{}={}", key, text));
+    }
+    for (key, reference) in sorted(&def.param_refs) {
+        if key != "value" {
+            cells.push(format!("{}={}", key, param_ref_text(reference, false)));
+        }
+    }
+    cells
+}
+
+/// A `.param` reference, braced unless it was written bare where a bare
+/// name reads as a reference.
+fn param_ref_text(reference: &str, bare_ok: bool) -> String {
+    if bare_ok || reference.starts_with('{') {
+        reference.to_string()
+    } else {
+        format!("{{{}}}", reference)
+    }
+}
+
+/// A number in engineering notation when that reads back as exactly the
+/// same value, and otherwise in full.
+fn value_text(value: f64) -> String {
+    let plain = format!("{}", value);
+    if value == 0.0 || (1e-3..1e3).contains(&value.abs()) {
+        return plain;
+    }
+    let suffixed = engineering(value);
+    if (1e-12..1e12).contains(&value.abs()) && parse_value(&suffixed) == Some(value) {
+        return suffixed;
+    }
+    let exponent = format!("{:e}", value);
+    if exponent.len() < plain.len() {
+        exponent
+    } else {
+        plain
+    }
+}
+
+/// Tokenize the input with comments retained and group tokens into lines.
+fn split_lines(input: &str) -> Result<Vec<Line>> {
+    let mut lexer = Lexer::with_comments(input);
+    let mut lines = Vec::new();
+    let mut tokens: Vec<Token> = Vec::new();
+
+    loop {
+        let token = lexer.next_token()?;
+        match token.kind {
+            TokenKind::Newline | TokenKind::Eof => {
+                let at_eof = token.kind == TokenKind::Eof;
+                if !(at_eof && tokens.is_empty()) {
+                    lines.push(classify(std::mem::take(&mut tokens)));
+                }
+                if at_eof {
+                    break;
+                }
+            }
+            _ => tokens.push(token),
+        }
+    }
+
+    Ok(lines)
+}
+
+/// Turn the tokens of one line into a [`Line`].
+fn classify(mut tokens: Vec<Token>) -> Line {
+    let comment = match tokens.last() {
+        Some(tok) if tok.kind == TokenKind::Comment => tokens.pop().map(|t| t.text),
+        _ => None,
+    };
+
+    let Some(first) = tokens.first() else {
+        return match comment {
+            Some(text) => Line::Comment(text),
+            None => Line::Blank,
+        };
+    };
+    let is_directive = first.kind == TokenKind::Directive;
+
+    let mut cells: Vec<String> = Vec::with_capacity(tokens.len());
+    let mut open_paren = false;
+    let mut iter = tokens.into_iter().peekable();
+
+    while let Some(tok) = iter.next() {
+        let mut cell = match tok.kind {
+            TokenKind::OpenParen => {
+                open_paren = true;
+                continue;
+            }
+            TokenKind::CloseParen => {
+                match cells.last_mut() {
+                    Some(last) => last.push(')'),
+                    None => cells.push(")".to_string()),
+                }
+                continue;
+            }
+            TokenKind::Directive => tok.text.to_lowercase(),
+            TokenKind::Number => normalize_value(&tok.text),
+            _ => tok.text,
+        };
+
+        // Join `key = value` into a single `key=value` cell
+        if iter.peek().is_some_and(|t| t.kind == TokenKind::Equals) {
+            iter.next();
+            cell.push('=');
+            if let Some(value) =
+                iter.next_if(|t| t.kind == TokenKind::Number || t.kind == TokenKind::Identifier)
+            {
+                if value.kind == TokenKind::Number {
+                    cell.push_str(&normalize_value(&value.text));
+                } else {
+                    cell.push_str(&value.text);
+                }
+            }
+            // List items are kept verbatim: fwd=Si,Si,Ge
+            while let Some(comma) = iter.next_if(|t| t.kind == TokenKind::Comma) {
+                cell.push_str(&comma.text);
+                if let Some(item) =
+                    iter.next_if(|t| t.kind == TokenKind::Number || t.kind == TokenKind::Identifier)
+                {
+                    cell.push_str(&item.text);
+                }
+            }
+        }
+
+        if open_paren {
+            cell.insert(0, '(');
+            open_paren = false;
+        }
+        cells.push(cell);
+    }
+
+    if is_directive {
+        Line::Directive(cells, comment)
+    } else {
+        Line::Component(cells, comment)
+    }
+}
+
+/// Sort key for directives within a run.
+fn directive_rank(name: &str) -> usize {
+    match name {
+        ".input" => 0,
+        ".output" => 1,
+        ".node" => 2,
+        ".param" => 3,
+        ".model" => 4,
+        _ => 5,
+    }
+}
+
+/// Write a run of lines with their cells padded into aligned columns.
+fn write_aligned(out: &mut String, run: &[(Vec<String>, Option<String>)]) {
+    let columns = run.iter().map(|(cells, _)| cells.len()).max().unwrap_or(0);
+    let mut widths = vec![0usize; columns];
+    for (cells, _) in run {
+        for (width, cell) in widths.iter_mut().zip(cells) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let rendered: Vec<String> = run
+        .iter()
+        .map(|(cells, _)| {
+            let mut line = String::new();
+            for (j, cell) in cells.iter().enumerate() {
+                if j + 1 == cells.len() {
+                    line.push_str(cell);
+                } else {
+                    line.push_str(&format!("{:<width$} ", cell, width = widths[j]));
+                }
+            }
+            line
+        })
+        .collect();
+
+    let comment_column = rendered
+        .iter()
+        .zip(run)
+        .filter(|(_, (_, comment))| comment.is_some())
+        .map(|(line, _)| line.chars().count())
+        .max()
+        .unwrap_or(0)
+        + 2;
+
+    for (line, (_, comment)) in rendered.iter().zip(run) {
+        match comment {
+            Some(text) => {
+                out.push_str(&format!("{:<width$}{}", line, text, width = comment_column));
+            }
+            None => out.push_str(line),
+        }
+        out.push('\n');
+    }
+}
+
+/// Rewrite a numeric token in engineering notation where that is unambiguous.
+fn normalize_value(text: &str) -> String {
+    // Ratios are kept as written; "50ppm" would otherwise become "50u"
+    if text.ends_with('%') || text.ends_with("ppm") {
+        return text.to_string();
+    }
+
+    let has_exponent = text.contains(['e', 'E']);
+    let has_suffix = text
+        .chars()
+        .last()
+        .is_some_and(|c| matches!(c, 'p' | 'n' | 'u' | 'µ' | 'm' | 'k' | 'K' | 'M' | 'G'));
+    let Some(value) = parse_value(text) else {
+        return text.to_string();
+    };
+    if has_exponent || !(has_suffix || value.abs() >= 1000.0) {
+        return text.to_string();
+    }
+    engineering(value)
+}
+
+/// A value as a mantissa below 1000 and a unit suffix, e.g. `4.7k`.
+fn engineering(value: f64) -> String {
+    const SUFFIXES: [(i32, &str); 8] = [
+        (-12, "p"),
+        (-9, "n"),
+        (-6, "u"),
+        (-3, "m"),
+        (0, ""),
+        (3, "k"),
+        (6, "M"),
+        (9, "G"),
+    ];
+
+    if value == 0.0 {
+        return "0".to_string();
+    }
+
+    let mut exp = ((value.abs().log10() / 3.0).floor() as i32 * 3).clamp(-12, 9);
+    let mut mantissa = round_mantissa(value / 10f64.powi(exp));
+    if mantissa.abs() >= 1000.0 && exp < 9 {
+        exp += 3;
+        mantissa = round_mantissa(value / 10f64.powi(exp));
+    }
+
+    let suffix = SUFFIXES
+        .iter()
+        .find(|(e, _)| *e == exp)
+        .map(|(_, s)| *s)
+        .unwrap_or("");
+    format!("{}{}", mantissa, suffix)
+}
+
+/// Round away floating-point noise from a scaled mantissa.
+fn round_mantissa(m: f64) -> f64 {
+    (m * 1e6).round() / 1e6
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_value() {
+        assert_eq!(normalize_value("4.7K"), "4.7k");
+        assert_eq!(normalize_value("0.1u"), "100n");
+        assert_eq!(normalize_value("10000"), "10k");
+        assert_eq!(normalize_value("4.7n"), "4.7n");
+        assert_eq!(normalize_value("0.5"), "0.5");
+        assert_eq!(normalize_value("1e-14"), "1e-14");
+        assert_eq!(normalize_value("50ppm"), "50ppm");
+        assert_eq!(normalize_value("50%"), "50%");
+    }
+
+    #[test]
+    fn test_format_aligns_and_sorts() {
+        let input = "# Filter\n.output out\n.input in\n\n\n  R1 in out 10000   ; series\nC_LONG out 0 0.1u\n";
+        let formatted = format(input).unwrap();
+        assert_eq!(
+            formatted,
+            "# Filter\n.input  in\n.output out\n\nR1     in  out 10k  ; series\nC_LONG out 0   100n\n"
+        );
+    }
+
+    /// Assert that two ASTs describe the same circuit, ignoring line numbers.
+    fn assert_same_circuit(a: &CircuitAst, b: &CircuitAst) {
+        assert_eq!(a.input_nodes, b.input_nodes);
+        assert_eq!(a.output_nodes, b.output_nodes);
+        assert_eq!(a.control_nodes, b.control_nodes);
+        assert_eq!(a.nodes, b.nodes);
+        assert_eq!(a.params, b.params);
+        assert_eq!(a.models.len(), b.models.len());
+        for (name, model) in &a.models {
+            assert_eq!(model.model_type, b.models[name].model_type);
+            assert_eq!(model.params, b.models[name].params, "{}", name);
+            assert_eq!(model.param_refs, b.models[name].param_refs, "{}", name);
+        }
+        let bodies = |ast: &CircuitAst| {
+            let mut bodies: Vec<(String, Vec<String>, Vec<ComponentDef>)> = ast
+                .subcircuits
+                .values()
+                .map(|s| (s.name.clone(), s.ports.clone(), s.components.clone()))
+                .collect();
+            bodies.sort_by(|x, y| x.0.cmp(&y.0));
+            bodies
+        };
+        let mut components = vec![(a.components.clone(), b.components.clone())];
+        for ((name, ports, x), (_, other_ports, y)) in bodies(a).into_iter().zip(bodies(b)) {
+            assert_eq!(ports, other_ports, "{}", name);
+            components.push((x, y));
+        }
+        for (x, y) in components {
+            assert_eq!(x.len(), y.len());
+            for (c, d) in x.iter().zip(&y) {
+                assert_eq!(c.component_type, d.component_type, "{}", c.name);
+                assert_eq!(
+                    (
+                        &c.name,
+                        &c.nodes,
+                        c.value,
+                        &c.model_ref,
+                        &c.params,
+                        &c.string_params,
+                        &c.param_refs
+                    ),
+                    (
+                        &d.name,
+                        &d.nodes,
+                        d.value,
+                        &d.model_ref,
+                        &d.params,
+                        &d.string_params,
+                        &d.param_refs
+                    )
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_to_dsl_round_trips() {
+        let mut sources: Vec<String> =
+            std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/circuits"))
+                .unwrap()
+                .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+                .collect();
+        sources.push(
+            ".input in\n.output out\n.node spare\n.param RG 22k\n\
+             .model QX NPN (bf=200 is=1e-14)\n\
+             .subckt stage a b\nR1 a mid {RG}\nC1 mid b 47n\n.ends\n\
+             V_IN in 0 AC 1\nVCC vcc 0 DC 9\nX1 in mid stage\nQ1 vcc mid out QX\n\
+             XFMR1 out 0 sec 0 1 4 0.98\nSW_BYP out sec in OPEN\nPOT_TONE out w 0 100k 0.3 taper=log\n\
+             LFO LFO1 0.5 triangle\nR2 w 0 10k LFO1 depth=0.4\nDSTACK1 w 0 fwd=Si,Ge\nC2 sec 0 0.000123456789\n"
+                .to_string(),
+        );
+
+        for source in &sources {
+            let ast = super::super::parse(source).unwrap();
+            let text = ast.to_dsl();
+            let reparsed = super::super::parse(&text).unwrap_or_else(|e| panic!("{}\n{}", e, text));
+            assert_same_circuit(&ast, &reparsed);
+            assert_eq!(reparsed.to_dsl(), text);
+        }
+    }
+
+    #[test]
+    fn test_format_is_idempotent() {
+        let sources = [
+            include_str!("../../examples/circuits/fuzz.ped"),
+            include_str!("../../examples/circuits/phaser.ped"),
+            include_str!("../../examples/circuits/delay_hall_phaser.ped"),
+        ];
+        for source in sources {
+            let once = format(source).unwrap();
+            let twice = format(&once).unwrap();
+            assert_eq!(once, twice);
+
+            // The formatted file describes the same circuit
+            let before = super::super::parse(source).unwrap();
+            let after = super::super::parse(&once).unwrap();
+            assert_eq!(before.components.len(), after.components.len());
+            for (a, b) in before.components.iter().zip(&after.components) {
+                assert_eq!(a.name, b.name);
+                assert_eq!(a.nodes, b.nodes);
+                assert_eq!(a.value, b.value);
+            }
+        }
+    }
+}
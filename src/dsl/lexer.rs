@@ -30,6 +30,11 @@ pub enum TokenKind {
     CloseParen,
     /// Equals sign '='
     Equals,
+    /// Comma ',' separating list values
+    Comma,
+    /// Comment (including its leading `#` or `;`), only produced when
+    /// comments are retained
+    Comment,
     /// Newline
     Newline,
     /// End of file
@@ -43,6 +48,9 @@ pub struct Lexer<'a> {
     line: usize,
     column: usize,
     line_start: usize,
+    keep_comments: bool,
+    /// Token read ahead by [`peek_token`](Self::peek_token)
+    peeked: Option<Token>,
 }
 
 impl<'a> Lexer<'a> {
@@ -54,11 +62,39 @@ impl<'a> Lexer<'a> {
             line: 1,
             column: 1,
             line_start: 0,
+            keep_comments: false,
+            peeked: None,
+        }
+    }
+
+    /// Create a lexer that emits [`TokenKind::Comment`] tokens instead of
+    /// skipping comments. Used by the formatter.
+    pub fn with_comments(input: &'a str) -> Self {
+        Self {
+            keep_comments: true,
+            ..Self::new(input)
         }
     }
 
     /// Get the next token.
     pub fn next_token(&mut self) -> Result<Token> {
+        match self.peeked.take() {
+            Some(token) => Ok(token),
+            None => self.lex_token(),
+        }
+    }
+
+    /// Peek at the next token without consuming it. The following
+    /// [`next_token`](Self::next_token) returns the same token.
+    pub fn peek_token(&mut self) -> Result<&Token> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.lex_token()?);
+        }
+        Ok(self.peeked.as_ref().expect("token was just peeked"))
+    }
+
+    /// Read the next token from the input.
+    fn lex_token(&mut self) -> Result<Token> {
         self.skip_whitespace_and_comments();
 
         let (_start_pos, ch) = match self.chars.peek().copied() {
@@ -77,6 +113,22 @@ impl<'a> Lexer<'a> {
         let start_line = self.line;
 
         let token = match ch {
+            '#' | ';' => {
+                let mut text = String::new();
+                while let Some(&(_, c)) = self.chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    text.push(c);
+                    self.advance();
+                }
+                Token {
+                    kind: TokenKind::Comment,
+                    text: text.trim_end().to_string(),
+                    line: start_line,
+                    column: start_column,
+                }
+            }
             '\n' => {
                 self.advance();
                 Token {
@@ -123,6 +175,34 @@ impl<'a> Lexer<'a> {
                     column: start_column,
                 }
             }
+            ',' => {
+                self.advance();
+                Token {
+                    kind: TokenKind::Comma,
+                    text: ",".to_string(),
+                    line: start_line,
+                    column: start_column,
+                }
+            }
+            '{' => {
+                // Braced .param reference, kept whole: {NAME}
+                self.advance();
+                let name = self.read_identifier();
+                if name.is_empty() || self.chars.peek().map(|&(_, c)| c) != Some('}') {
+                    return Err(PedalerError::lexer(
+                        start_line,
+                        start_column,
+                        "expected a parameter name and '}' after '{'",
+                    ));
+                }
+                self.advance();
+                Token {
+                    kind: TokenKind::Identifier,
+                    text: format!("{{{}}}", name),
+                    line: start_line,
+                    column: start_column,
+                }
+            }
             '-' | '+' | '0'..='9' => {
                 let text = self.read_number();
                 Token {
@@ -163,30 +243,17 @@ impl<'a> Lexer<'a> {
         Ok(token)
     }
 
-    /// Peek at the next token without consuming it.
-    #[allow(dead_code)]
-    pub fn peek_token(&mut self) -> Result<Token> {
-        // Save state - this is a simplified implementation
-        let _saved_line = self.line;
-        let _saved_column = self.column;
-        let _saved_line_start = self.line_start;
-
-        let token = self.next_token()?;
-
-        // This is a simplified peek - in practice we'd need proper state restoration
-        // For now, we'll use a different approach in the parser
-        Ok(token)
-    }
-
     fn current_pos(&self) -> usize {
         // Note: peek() on a Peekable doesn't require &mut self when just reading
         // We clone the iterator position info we need
         self.input.len() - self.input[self.line_start..].len() + self.column - 1
     }
 
-    #[allow(dead_code)]
     fn current_pos_from_peek(&mut self) -> usize {
-        self.chars.peek().map(|(pos, _)| *pos).unwrap_or(self.input.len())
+        self.chars
+            .peek()
+            .map(|(pos, _)| *pos)
+            .unwrap_or(self.input.len())
     }
 
     fn advance(&mut self) -> Option<char> {
@@ -208,7 +275,7 @@ impl<'a> Lexer<'a> {
         while let Some(&(_, ch)) = self.chars.peek() {
             if ch == ' ' || ch == '\t' || ch == '\r' {
                 self.advance();
-            } else if ch == '#' || ch == ';' {
+            } else if (ch == '#' || ch == ';') && !self.keep_comments {
                 // Skip comment until end of line
                 while let Some(&(_, c)) = self.chars.peek() {
                     if c == '\n' {
@@ -292,9 +359,18 @@ impl<'a> Lexer<'a> {
             }
         }
 
-        // Unit suffix (p, n, u, m, k, M, G)
-        if let Some(&(_, ch)) = self.chars.peek() {
-            if matches!(ch, 'p' | 'n' | 'u' | 'µ' | 'm' | 'k' | 'K' | 'M' | 'G') {
+        // Ratio suffix (%, ppm) or unit suffix (p, n, u, m, k, M, G)
+        let pos = self.current_pos_from_peek();
+        if self.input[pos..].starts_with("ppm") {
+            text.push_str("ppm");
+            for _ in 0..3 {
+                self.advance();
+            }
+        } else if let Some(&(_, ch)) = self.chars.peek() {
+            if matches!(
+                ch,
+                '%' | 'p' | 'n' | 'u' | 'µ' | 'm' | 'k' | 'K' | 'M' | 'G'
+            ) {
                 text.push(ch);
                 self.advance();
             }
@@ -356,13 +432,21 @@ impl<'a> Lexer<'a> {
     }
 }
 
-/// Parse a number string with optional unit suffix.
+/// Parse a number string with optional unit suffix, or a `%` / `ppm` ratio.
 pub fn parse_value(text: &str) -> Option<f64> {
     let text = text.trim();
     if text.is_empty() {
         return None;
     }
 
+    // Ratios: 50% = 0.5, 100ppm = 1e-4
+    if let Some(num_str) = text.strip_suffix('%') {
+        return num_str.parse::<f64>().ok().map(|v| v / 100.0);
+    }
+    if let Some(num_str) = text.strip_suffix("ppm") {
+        return num_str.parse::<f64>().ok().map(|v| v * 1e-6);
+    }
+
     let (num_str, multiplier) = if let Some(last) = text.chars().last() {
         let mult = match last {
             'p' => 1e-12,
@@ -406,6 +490,10 @@ mod tests {
         assert!(approx_eq(parse_value("1M"), Some(1_000_000.0)));
         assert!(approx_eq(parse_value("2.2"), Some(2.2)));
         assert!(approx_eq(parse_value("1e-9"), Some(1e-9)));
+        assert!(approx_eq(parse_value("50%"), Some(0.5)));
+        assert!(approx_eq(parse_value("0.5"), Some(0.5)));
+        assert!(approx_eq(parse_value("100ppm"), Some(1e-4)));
+        assert_eq!(parse_value("k%"), None);
     }
 
     #[test]
@@ -422,6 +510,19 @@ mod tests {
         assert_eq!(tok.text, "in");
     }
 
+    #[test]
+    fn test_peek_then_read_gives_the_same_token() {
+        let mut lexer = Lexer::new("V1 in 0 DC 9\n");
+        lexer.next_token().unwrap();
+
+        let peeked = lexer.peek_token().unwrap().clone();
+        assert_eq!(lexer.peek_token().unwrap().text, "in");
+        assert_eq!(lexer.next_token().unwrap(), peeked);
+
+        // Reading carries on after the peeked token
+        assert_eq!(lexer.next_token().unwrap().text, "0");
+    }
+
     #[test]
     fn test_lexer_directive() {
         let input = ".model D1 D (is=1e-14)";
@@ -431,4 +532,18 @@ mod tests {
         assert_eq!(tok.kind, TokenKind::Directive);
         assert_eq!(tok.text, ".model");
     }
+
+    #[test]
+    fn test_lexer_keeps_comments() {
+        let input = "R1 in out 1k ; bias\n";
+        let mut lexer = Lexer::with_comments(input);
+
+        let kinds: Vec<TokenKind> = std::iter::from_fn(|| {
+            let tok = lexer.next_token().unwrap();
+            (tok.kind != TokenKind::Eof).then_some(tok.kind)
+        })
+        .collect();
+        assert_eq!(kinds[4], TokenKind::Comment);
+        assert_eq!(kinds[5], TokenKind::Newline);
+    }
 }
@@ -10,14 +10,16 @@
 //! line        = comment | directive | component | empty
 //! comment     = ('#' | ';') { any_char }
 //! directive   = '.' directive_name { argument }
-//! component   = type name node+ [value] [model_ref]
+//! component   = type name node+ [value] [model_ref] { key '=' value }
 //!
-//! directive_name = "node" | "model" | "input" | "output" | "param"
-//! type        = "R" | "C" | "L" | "D" | "Q" | "V" | "I" | "OP" | "POT" | "SW"
+//! directive_name = "node" | "model" | "input" | "output" | "param" | "subckt" | "ends"
+//! type        = "R" | "C" | "L" | "D" | "Q" | "J" | "M" | "V" | "I" | "OP" | "POT" | "SW" | "TONE" | "X"
 //! name        = identifier
 //! node        = identifier | "0" | "GND"
-//! value       = number [unit_suffix]
-//! model_ref   = identifier
+//! value       = number [unit_suffix] | param_ref
+//! param_ref   = identifier | '{' identifier '}'
+//! model_ref   = identifier          (diodes, transistors, op-amps, LFOs,
+//!                                    and LFO names for resistors)
 //!
 //! number      = ['-'] digit+ ['.' digit+] [('e'|'E') ['-'|'+'] digit+]
 //! unit_suffix = 'p' | 'n' | 'u' | 'm' | 'k' | 'M' | 'G'
@@ -28,16 +30,27 @@
 //!
 //! | Type | Description | Syntax |
 //! |------|-------------|--------|
-//! | R | Resistor | `R<name> <n+> <n-> <value>` |
-//! | C | Capacitor | `C<name> <n+> <n-> <value>` |
-//! | L | Inductor | `L<name> <n+> <n-> <value>` |
+//! | R | Resistor | `R<name> <n+> <n-> <value> [tol=<ratio>]` |
+//! | C | Capacitor | `C<name> <n+> <n-> <value> [tol=<ratio>]` |
+//! | L | Inductor | `L<name> <n+> <n-> <value> [tol=<ratio>]` |
+//! | XFMR | Transformer | `XFMR<name> <p+> <p-> <s+> <s-> <L_pri> <L_sec> <k>` |
 //! | D | Diode | `D<name> <anode> <cathode> [model]` |
 //! | Q | BJT | `Q<name> <collector> <base> <emitter> [model]` |
+//! | J | JFET | `J<name> <drain> <gate> <source> [model]` |
+//! | M | MOSFET | `M<name> <drain> <gate> <source> [model]` |
+//! | TRIODE | Triode | `TRIODE<name> <plate> <grid> <cathode> [model]` |
 //! | V | Voltage Source | `V<name> <n+> <n-> <DC value> [AC amplitude]` |
 //! | I | Current Source | `I<name> <n+> <n-> <value>` |
+//! | N | Noise Source | `N<name> <n+> <n-> <amplitude> [seed]` |
+//! | AM | Ammeter | `AM<name> <n+> <n->` |
 //! | OP | Op-Amp | `OP<name> <out> <in+> <in-> [model]` |
 //! | POT | Potentiometer | `POT<name> <n1> <wiper> <n2> <value> <position>` |
-//! | SW | Switch | `SW<name> <n1> <n2> <state>` |
+//! | SW | Switch | `SW<name> <n1> <n2> [<t2>] <state>` |
+//! | TONE | Tone Stack | `TONE<name> <in> <out> <ref> [treble] [bass]` |
+//! | HYST | Hysteretic Resistor | `HYST <name> <n1> <n2> [rmin=] [rmax=] [tau_rise=] [tau_fall=]` |
+//! | NLR | Nonlinear Resistor | `NLR <name> <n1> <n2> mod=<source> ctl=<x1,x2,...> r=<r1,r2,...>` |
+//! | ENV | Envelope Follower | `ENV <name> <node> [attack=] [release=] [gain=]` |
+//! | X | Subcircuit Instance | `X<name> <node>... <subckt>` |
 //!
 //! # Directives
 //!
@@ -45,8 +58,10 @@
 //! |-----------|-------------|--------|
 //! | .node | Declare a node | `.node <name>` |
 //! | .model | Define a component model | `.model <name> <type> (<params>)` |
-//! | .input | Mark audio input node | `.input <node>` |
-//! | .output | Mark audio output node | `.output <node>` |
+//! | .param | Define a named value | `.param <name> <value>` |
+//! | .subckt | Define a subcircuit, up to `.ends` | `.subckt <name> <port>...` |
+//! | .input | Mark audio input node; repeat for more channels | `.input <node>` |
+//! | .output | Mark audio output node; repeat for more channels | `.output <node>` |
 //!
 //! # Example
 //!
@@ -61,12 +76,20 @@
 //! ```
 
 mod ast;
+mod format;
 mod lexer;
 mod parser;
+mod spice;
+mod values;
 
 pub use ast::*;
-pub use lexer::{Lexer, Token, TokenKind};
+pub use format::format;
+pub use lexer::{parse_value, Lexer, Token, TokenKind};
 pub use parser::Parser;
+pub use spice::import_spice;
+#[cfg(feature = "cli")]
+pub use values::parse_values_file;
+pub use values::{parse_values, ValueOverride};
 
 use crate::error::Result;
 
@@ -78,11 +101,22 @@ pub fn parse(input: &str) -> Result<CircuitAst> {
 }
 
 /// Parse a circuit DSL file.
+///
+/// Files with a `.cir`, `.sp` or `.spice` extension are read as SPICE
+/// netlists with [`import_spice`].
 #[cfg(feature = "cli")]
 pub fn parse_file(path: &std::path::Path) -> Result<CircuitAst> {
-    let content = std::fs::read_to_string(path).map_err(|e| crate::error::PedalerError::FileReadError {
-        path: path.display().to_string(),
-        source: e,
-    })?;
-    parse(&content)
+    let content =
+        std::fs::read_to_string(path).map_err(|e| crate::error::PedalerError::FileReadError {
+            path: path.display().to_string(),
+            source: e,
+        })?;
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("cir" | "sp" | "spice") => import_spice(&content),
+        _ => parse(&content),
+    }
 }
@@ -10,7 +10,6 @@ use crate::error::{PedalerError, Result};
 pub struct Parser<'a> {
     lexer: Lexer<'a>,
     current: Token,
-    peeked: Option<Token>,
 }
 
 impl<'a> Parser<'a> {
@@ -22,19 +21,18 @@ impl<'a> Parser<'a> {
             line: 1,
             column: 1,
         });
-        Self {
-            lexer,
-            current,
-            peeked: None,
-        }
+        Self { lexer, current }
     }
 
     /// Parse the entire circuit description.
     pub fn parse(&mut self) -> Result<CircuitAst> {
         let mut ast = CircuitAst::new();
+        // Component nodes in order of first appearance, so that node
+        // numbering (and with it the solver's arithmetic) is reproducible
+        let mut component_nodes = Vec::new();
         let mut node_set = std::collections::HashSet::new();
 
-        // Always include ground
+        // Ground is not listed
         node_set.insert("0".to_string());
         node_set.insert("GND".to_string());
 
@@ -53,16 +51,15 @@ impl<'a> Parser<'a> {
                     let component = self.parse_component()?;
                     // Collect node names
                     for node in &component.nodes {
-                        node_set.insert(node.clone());
+                        if node_set.insert(node.clone()) {
+                            component_nodes.push(node.clone());
+                        }
                     }
                     ast.components.push(component);
                 }
                 TokenKind::Eof => break,
                 _ => {
-                    return Err(PedalerError::parse(
-                        self.current.line,
-                        format!("unexpected token: {:?}", self.current.text),
-                    ));
+                    return Err(self.error(format!("unexpected token: {:?}", self.current.text)));
                 }
             }
 
@@ -72,21 +69,18 @@ impl<'a> Parser<'a> {
             }
         }
 
-        // Build node list (excluding ground aliases)
-        ast.nodes = node_set
-            .into_iter()
-            .filter(|n| n != "0" && n != "GND")
-            .collect();
+        // Append component nodes after any declared with .node
+        for node in component_nodes {
+            if !ast.nodes.contains(&node) {
+                ast.nodes.push(node);
+            }
+        }
 
         Ok(ast)
     }
 
     fn advance(&mut self) -> Result<()> {
-        self.current = if let Some(tok) = self.peeked.take() {
-            tok
-        } else {
-            self.lexer.next_token()?
-        };
+        self.current = self.lexer.next_token()?;
         Ok(())
     }
 
@@ -96,26 +90,54 @@ impl<'a> Parser<'a> {
             self.advance()?;
             Ok(tok)
         } else {
-            Err(PedalerError::parse(
-                self.current.line,
-                format!("expected {:?}, got {:?}", kind, self.current.kind),
-            ))
+            Err(self.error(format!("expected {:?}, got {:?}", kind, self.current.kind)))
         }
     }
 
+    /// A parse error pointing at the current token.
+    fn error(&self, message: impl Into<String>) -> PedalerError {
+        error_at(&self.current, message)
+    }
+
+    /// Read the node of an `.input`/`.output`/`.control` directive, rejecting ground.
+    fn expect_signal_node(&mut self, directive: &str, line: usize) -> Result<String> {
+        let is_ground = match self.current.kind {
+            TokenKind::Number => self.current.text == "0",
+            TokenKind::Identifier => self.current.text.eq_ignore_ascii_case("GND"),
+            _ => false,
+        };
+        if is_ground {
+            return Err(PedalerError::GroundSignalNode {
+                directive: directive.to_string(),
+                line,
+            });
+        }
+        Ok(self.expect(TokenKind::Identifier)?.text)
+    }
+
     fn parse_directive(&mut self, ast: &mut CircuitAst) -> Result<()> {
-        let directive = self.current.text.clone();
-        let line = self.current.line;
+        let directive = self.current.clone();
+        let line = directive.line;
         self.advance()?;
 
-        match directive.to_lowercase().as_str() {
+        match directive.text.to_lowercase().as_str() {
             ".input" => {
-                let node = self.expect(TokenKind::Identifier)?;
-                ast.input_node = Some(node.text);
+                let node = self.expect_signal_node(".input", line)?;
+                if !ast.input_nodes.contains(&node) {
+                    ast.input_nodes.push(node);
+                }
             }
             ".output" => {
-                let node = self.expect(TokenKind::Identifier)?;
-                ast.output_node = Some(node.text);
+                let node = self.expect_signal_node(".output", line)?;
+                if !ast.output_nodes.contains(&node) {
+                    ast.output_nodes.push(node);
+                }
+            }
+            ".control" => {
+                let node = self.expect_signal_node(".control", line)?;
+                if !ast.control_nodes.contains(&node) {
+                    ast.control_nodes.push(node);
+                }
             }
             ".node" => {
                 let node = self.expect(TokenKind::Identifier)?;
@@ -123,19 +145,48 @@ impl<'a> Parser<'a> {
                     ast.nodes.push(node.text);
                 }
             }
+            ".param" => {
+                let name = self.expect(TokenKind::Identifier)?;
+                let text = self.current.text.clone();
+                let value = match self.current.kind {
+                    TokenKind::Number | TokenKind::Identifier => parse_value(&text),
+                    _ => None,
+                }
+                .ok_or_else(|| {
+                    self.error(format!(
+                        "invalid value for parameter {}: {:?}",
+                        name.text, text
+                    ))
+                })?;
+                self.advance()?;
+                if ast.params.insert(name.text.clone(), value).is_some() {
+                    return Err(error_at(
+                        &name,
+                        format!("parameter {} is already defined", name.text),
+                    ));
+                }
+            }
+            ".subckt" => {
+                let subckt = self.parse_subcircuit(ast, line)?;
+                if ast.subcircuits.contains_key(&subckt.name) {
+                    return Err(PedalerError::parse(
+                        line,
+                        format!("subcircuit {} is already defined", subckt.name),
+                    ));
+                }
+                ast.subcircuits.insert(subckt.name.clone(), subckt);
+            }
             ".model" => {
                 let model = self.parse_model_def(line)?;
                 if ast.models.contains_key(&model.name) {
-                    return Err(PedalerError::DuplicateModel {
-                        name: model.name,
-                    });
+                    return Err(PedalerError::DuplicateModel { name: model.name });
                 }
                 ast.models.insert(model.name.clone(), model);
             }
             _ => {
-                return Err(PedalerError::parse(
-                    line,
-                    format!("unknown directive: {}", directive),
+                return Err(error_at(
+                    &directive,
+                    format!("unknown directive: {}", directive.text),
                 ));
             }
         }
@@ -145,13 +196,13 @@ impl<'a> Parser<'a> {
 
     fn parse_model_def(&mut self, line: usize) -> Result<ModelDef> {
         let name = self.expect(TokenKind::Identifier)?.text;
-        let type_str = self.expect(TokenKind::Identifier)?.text;
+        let type_tok = self.expect(TokenKind::Identifier)?;
 
-        let model_type = ModelType::from_str(&type_str).ok_or_else(|| {
-            PedalerError::parse(line, format!("unknown model type: {}", type_str))
-        })?;
+        let model_type = ModelType::from_str(&type_tok.text)
+            .ok_or_else(|| error_at(&type_tok, format!("unknown model type: {}", type_tok.text)))?;
 
         let mut params = HashMap::new();
+        let mut param_refs = HashMap::new();
 
         // Parse parameters in parentheses: (param=value param2=value2)
         if self.current.kind == TokenKind::OpenParen {
@@ -165,20 +216,31 @@ impl<'a> Parser<'a> {
                 self.expect(TokenKind::Equals)?;
 
                 let value = if self.current.kind == TokenKind::Number {
-                    let text = self.current.text.clone();
+                    let tok = self.current.clone();
                     self.advance()?;
+                    // The lexer ends a number at the first character that
+                    // can't continue it, so `5V` arrives as `5` and `V`;
+                    // take the whole word so it fails as one number
+                    let mut text = tok.text.clone();
+                    while self.follows_without_space(&text, &tok)
+                        && matches!(
+                            self.current.kind,
+                            TokenKind::Number | TokenKind::Identifier | TokenKind::Directive
+                        )
+                    {
+                        text.push_str(&self.current.text);
+                        self.advance()?;
+                    }
                     parse_value(&text).ok_or_else(|| {
-                        PedalerError::parse(line, format!("invalid number: {}", text))
+                        error_at(&tok, format!("invalid number for {}: {}", param_name, text))
                     })?
                 } else if self.current.kind == TokenKind::Identifier {
-                    // Could be a number with unit like "1e-14"
-                    let text = self.current.text.clone();
+                    // A .param reference, resolved when the circuit is built
+                    param_refs.insert(param_name.to_lowercase(), self.current.text.clone());
                     self.advance()?;
-                    parse_value(&text).ok_or_else(|| {
-                        PedalerError::parse(line, format!("invalid number: {}", text))
-                    })?
+                    continue;
                 } else {
-                    return Err(PedalerError::parse(line, "expected parameter value"));
+                    return Err(self.error("expected parameter value"));
                 };
 
                 params.insert(param_name.to_lowercase(), value);
@@ -193,6 +255,120 @@ impl<'a> Parser<'a> {
             name,
             model_type,
             params,
+            param_refs,
+            line,
+        })
+    }
+
+    /// Whether the current token starts right where `text`, read from
+    /// `start`, ends, with no whitespace between them.
+    fn follows_without_space(&self, text: &str, start: &Token) -> bool {
+        self.current.line == start.line
+            && self.current.column == start.column + text.chars().count()
+    }
+
+    /// Parse a `.subckt` block after its directive, up to and including
+    /// `.ends`. Models and parameters defined inside are global.
+    fn parse_subcircuit(&mut self, ast: &mut CircuitAst, line: usize) -> Result<SubcircuitDef> {
+        let name = self.expect(TokenKind::Identifier)?.text;
+        let mut ports: Vec<String> = Vec::new();
+        while self.current.kind == TokenKind::Identifier || self.current.kind == TokenKind::Number {
+            let port = self.current.text.clone();
+            if port == "0" || port.eq_ignore_ascii_case("GND") {
+                return Err(self.error(format!("subcircuit {} cannot have ground as a port", name)));
+            }
+            if ports.contains(&port) {
+                return Err(self.error(format!("subcircuit {} lists port {} twice", name, port)));
+            }
+            ports.push(port);
+            self.advance()?;
+        }
+
+        let mut components = Vec::new();
+        loop {
+            match self.current.kind {
+                TokenKind::Newline => {
+                    self.advance()?;
+                    continue;
+                }
+                TokenKind::Eof => {
+                    return Err(PedalerError::parse(
+                        line,
+                        format!("subcircuit {} is missing its .ends", name),
+                    ));
+                }
+                TokenKind::Directive => match self.current.text.to_lowercase().as_str() {
+                    ".ends" => {
+                        self.advance()?;
+                        // The name after .ends is optional, but must match
+                        if self.current.kind == TokenKind::Identifier {
+                            if self.current.text != name {
+                                return Err(self.error(format!(
+                                    ".ends {} does not close {}",
+                                    self.current.text, name
+                                )));
+                            }
+                            self.advance()?;
+                        }
+                        break;
+                    }
+                    ".model" | ".param" => self.parse_directive(ast)?,
+                    directive => {
+                        return Err(self.error(format!(
+                            "{} is not allowed inside subcircuit {}",
+                            directive, name
+                        )));
+                    }
+                },
+                TokenKind::Identifier => components.push(self.parse_component()?),
+                _ => {
+                    return Err(self.error(format!("unexpected token: {:?}", self.current.text)));
+                }
+            }
+
+            if self.current.kind == TokenKind::Newline {
+                self.advance()?;
+            }
+        }
+
+        Ok(SubcircuitDef {
+            name,
+            ports,
+            components,
+            line,
+        })
+    }
+
+    /// Parse the rest of a subcircuit instance line: its nodes, then the
+    /// name of the subcircuit.
+    fn parse_instance(&mut self, name: String, line: usize) -> Result<ComponentDef> {
+        let mut nodes = Vec::new();
+        while self.current.kind == TokenKind::Identifier || self.current.kind == TokenKind::Number {
+            let text = self.current.text.clone();
+            self.advance()?;
+            if text.eq_ignore_ascii_case("GND") {
+                nodes.push("0".to_string());
+            } else {
+                nodes.push(text);
+            }
+        }
+        let Some(subcircuit) = nodes.pop() else {
+            return Err(PedalerError::invalid_component(
+                &name,
+                line,
+                "expected nodes and a subcircuit name",
+            ));
+        };
+
+        Ok(ComponentDef {
+            component_type: ComponentType::Subcircuit,
+            name,
+            nodes,
+            value: None,
+            model_ref: Some(subcircuit),
+            params: HashMap::new(),
+            string_params: HashMap::new(),
+            param_refs: HashMap::new(),
             line,
         })
     }
@@ -209,15 +385,8 @@ impl<'a> Parser<'a> {
             let actual_name = self.expect(TokenKind::Identifier)?.text;
             (ct, actual_name)
         } else {
-            // Check for multi-char prefixes
             let upper = first_token.to_uppercase();
-            if upper.starts_with("OP") {
-                (ComponentType::OpAmp, first_token)
-            } else if upper.starts_with("POT") {
-                (ComponentType::Potentiometer, first_token)
-            } else if upper.starts_with("SW") {
-                (ComponentType::Switch, first_token)
-            } else if upper.starts_with("DELAY") {
+            if upper.starts_with("DELAY") {
                 // DELAY keyword used as prefix - next token is name
                 let actual_name = self.expect(TokenKind::Identifier)?.text;
                 (ComponentType::Delay, actual_name)
@@ -226,9 +395,8 @@ impl<'a> Parser<'a> {
                 let actual_name = self.expect(TokenKind::Identifier)?.text;
                 (ComponentType::Reverb, actual_name)
             } else {
-                // Finally, check single-character prefix
-                let first_char = first_token.chars().next().unwrap_or('?');
-                let ct = ComponentType::from_prefix(first_char).ok_or_else(|| {
+                // Multi-char prefixes, then the single-char ones
+                let ct = ComponentType::from_name(&first_token).ok_or_else(|| {
                     PedalerError::UnknownComponentType {
                         component_type: first_token.clone(),
                         line,
@@ -238,11 +406,19 @@ impl<'a> Parser<'a> {
             }
         };
 
+        if component_type == ComponentType::Subcircuit {
+            return self.parse_instance(name, line);
+        }
+
         let expected_nodes = component_type.expected_node_count();
         let mut nodes = Vec::with_capacity(expected_nodes);
         let mut value = None;
         let mut model_ref = None;
         let mut params = HashMap::new();
+        let mut string_params = HashMap::new();
+        let mut param_refs = HashMap::new();
+        // Numbers after the value, e.g. pot position
+        let mut extra_values = 0;
 
         // Parse nodes and optional parameters until end of line
         while self.current.kind != TokenKind::Newline && self.current.kind != TokenKind::Eof {
@@ -254,14 +430,38 @@ impl<'a> Parser<'a> {
                     // Check for param=value syntax
                     if self.current.kind == TokenKind::Equals {
                         self.advance()?; // consume '='
-                        // Parse the value
+                                         // Parse the value
                         if self.current.kind == TokenKind::Number
                             || self.current.kind == TokenKind::Identifier
                         {
-                            let val_text = self.current.text.clone();
+                            let mut val_text = self.current.text.clone();
                             self.advance()?;
-                            if let Some(v) = parse_value(&val_text) {
-                                params.insert(text.to_lowercase(), v);
+
+                            // Comma-separated lists are kept as text: fwd=Si,Si,Ge
+                            let mut is_list = false;
+                            while self.current.kind == TokenKind::Comma {
+                                self.advance()?;
+                                if self.current.kind != TokenKind::Number
+                                    && self.current.kind != TokenKind::Identifier
+                                {
+                                    return Err(self.error(format!(
+                                        "expected a list item after ',' in '{}'",
+                                        text
+                                    )));
+                                }
+                                val_text.push(',');
+                                val_text.push_str(&self.current.text);
+                                self.advance()?;
+                                is_list = true;
+                            }
+
+                            match parse_value(&val_text) {
+                                Some(v) if !is_list => {
+                                    params.insert(text.to_lowercase(), v);
+                                }
+                                _ => {
+                                    string_params.insert(text.to_lowercase(), val_text);
+                                }
                             }
                         }
                         continue;
@@ -269,11 +469,44 @@ impl<'a> Parser<'a> {
 
                     // Check if this looks like a value with unit suffix
                     if nodes.len() >= expected_nodes {
+                        let value_free = value.is_none() && !param_refs.contains_key("value");
                         if let Some(v) = parse_value(&text) {
                             value = Some(v);
-                        } else {
-                            // Could be a model reference
+                        } else if text == "DC" || text == "AC" {
+                            // Source type keyword before the value
+                            params.insert(text.to_lowercase(), 1.0);
+                        } else if let Some(state) = switch_state(component_type, &text) {
+                            params.insert("state".to_string(), state);
+                        } else if nodes.len()
+                            < expected_nodes + component_type.optional_node_count()
+                            && value.is_none()
+                            && params.is_empty()
+                        {
+                            // An optional node, e.g. a switch's second throw
+                            nodes.push(if text.to_uppercase() == "GND" {
+                                "0".to_string()
+                            } else {
+                                text
+                            });
+                        } else if text.starts_with('{')
+                            || (value_free && component_type.takes_value())
+                        {
+                            // A .param reference, resolved when the circuit is built
+                            let slot = if value_free {
+                                "value"
+                            } else {
+                                extra_values += 1;
+                                component_type.extra_value_name(extra_values - 1)
+                            };
+                            param_refs.insert(slot.to_string(), text);
+                        } else if model_ref.is_none() && component_type.takes_model_ref(&text) {
                             model_ref = Some(text);
+                        } else {
+                            return Err(PedalerError::invalid_component(
+                                &name,
+                                line,
+                                format!("unexpected '{}' after the component; notes go in key=value form", text),
+                            ));
                         }
                     } else {
                         // Check for special keywords first
@@ -304,11 +537,15 @@ impl<'a> Parser<'a> {
                         // Ground node
                         nodes.push("0".to_string());
                     } else if let Some(v) = parse_value(&text) {
-                        if value.is_none() {
+                        if value.is_none() && !param_refs.contains_key("value") {
                             value = Some(v);
                         } else {
                             // Additional numeric parameter (e.g., pot position)
-                            params.insert("position".to_string(), v);
+                            params.insert(
+                                component_type.extra_value_name(extra_values).to_string(),
+                                v,
+                            );
+                            extra_values += 1;
                         }
                     }
                 }
@@ -321,11 +558,7 @@ impl<'a> Parser<'a> {
             return Err(PedalerError::invalid_component(
                 &name,
                 line,
-                format!(
-                    "expected {} nodes, got {}",
-                    expected_nodes,
-                    nodes.len()
-                ),
+                format!("expected {} nodes, got {}", expected_nodes, nodes.len()),
             ));
         }
 
@@ -336,11 +569,30 @@ impl<'a> Parser<'a> {
             value,
             model_ref,
             params,
+            string_params,
+            param_refs,
             line,
         })
     }
 }
 
+/// The `state` of a switch written as `OPEN` or `CLOSED`.
+fn switch_state(component_type: ComponentType, text: &str) -> Option<f64> {
+    if component_type != ComponentType::Switch {
+        return None;
+    }
+    match text.to_ascii_uppercase().as_str() {
+        "OPEN" => Some(0.0),
+        "CLOSED" => Some(1.0),
+        _ => None,
+    }
+}
+
+/// A parse error pointing at `token`.
+fn error_at(token: &Token, message: impl Into<String>) -> PedalerError {
+    PedalerError::parse_at(token.line, token.column, message)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -360,8 +612,8 @@ mod tests {
     fn test_parse_input_output() {
         let input = ".input in\n.output out\nR1 in out 1k";
         let ast = super::super::parse(input).unwrap();
-        assert_eq!(ast.input_node, Some("in".to_string()));
-        assert_eq!(ast.output_node, Some("out".to_string()));
+        assert_eq!(ast.input_nodes, vec!["in".to_string()]);
+        assert_eq!(ast.output_nodes, vec!["out".to_string()]);
     }
 
     #[test]
@@ -374,6 +626,98 @@ mod tests {
         assert!((model.params["is"] - 1e-14).abs() < 1e-20);
     }
 
+    #[test]
+    fn test_model_param_numbers() {
+        let ast = super::super::parse(".model QX NPN (is=1e-14 bf=2.5e2 vaf=-3)").unwrap();
+        assert_eq!(ast.models["QX"].params["is"], 1e-14);
+        assert_eq!(ast.models["QX"].params["bf"], 250.0);
+
+        // Malformed numbers fail whole, pointing at where they start
+        for (param, text) in [("is", "1e"), ("vz", "5V"), ("n", "1.8.2")] {
+            let err =
+                super::super::parse(&format!(".model DX D ({}={})", param, text)).unwrap_err();
+            let column = Some(".model DX D (=".len() + param.len() + 1);
+            assert!(
+                matches!(err, PedalerError::ParseError { column: c, .. } if c == column),
+                "{:?}",
+                err
+            );
+            assert!(err
+                .to_string()
+                .contains(&format!("invalid number for {}: {}", param, text)));
+        }
+    }
+
+    #[test]
+    fn test_parse_list_param() {
+        let ast = super::super::parse("DSTACK1 a k fwd=Si,Si,Ge rev=LED").unwrap();
+        let def = &ast.components[0];
+        assert_eq!(def.component_type, ComponentType::DiodeStack);
+        assert_eq!(def.nodes, vec!["a", "k"]);
+        assert_eq!(def.string_params["fwd"], "Si,Si,Ge");
+        assert_eq!(def.string_params["rev"], "LED");
+    }
+
+    #[test]
+    fn test_parse_percent_param() {
+        let ast = super::super::parse("DELAY d1 in out 300m mix=50% feedback=0.3").unwrap();
+        let def = &ast.components[0];
+        assert_eq!(def.params["mix"], 0.5);
+        assert_eq!(def.params["feedback"], 0.3);
+    }
+
+    #[test]
+    fn test_ground_output_is_rejected_early() {
+        for src in [".input in\n.output GND\n", ".input 0\n.output out\n"] {
+            let err = super::super::parse(src).unwrap_err();
+            assert!(
+                matches!(err, PedalerError::GroundSignalNode { .. }),
+                "{:?}",
+                err
+            );
+            assert!(err.to_string().contains("pick a signal node"));
+        }
+    }
+
+    #[test]
+    fn test_parse_error_points_at_the_token() {
+        let err = super::super::parse("R1 in out 1k\n.model DX QQ (is=1)\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Parse error at line 2, column 11: unknown model type: QQ"
+        );
+
+        let err = super::super::parse(".model DX D (is=1 n=)\n").unwrap_err();
+        assert!(
+            matches!(
+                err,
+                PedalerError::ParseError {
+                    line: 1,
+                    column: Some(21),
+                    ..
+                }
+            ),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_trailing_tokens_are_params_or_errors() {
+        let ast = super::super::parse("R1 in out 10k tol=0.05\nSW1 a b OPEN\n").unwrap();
+        assert_eq!(ast.components[0].params["tol"], 0.05);
+        assert_eq!(ast.components[0].model_ref, None);
+        assert_eq!(ast.components[1].params["state"], 0.0);
+
+        let err = super::super::parse("R1 in out 10k oops\n").unwrap_err();
+        assert!(
+            matches!(err, PedalerError::InvalidComponent { ref name, line: 1, .. } if name == "R1"),
+            "{:?}",
+            err
+        );
+        assert!(err.to_string().contains("'oops'"));
+    }
+
     #[test]
     fn test_parse_with_comments() {
         let input = "# This is a comment\nR1 in out 1k ; inline comment style\n";
@@ -0,0 +1,476 @@
+//! SPICE netlist import.
+//!
+//! Reads the subset of SPICE that describes a pedal's audio path into a
+//! [`CircuitAst`], so existing `.cir` decks can be simulated as they are:
+//!
+//! ```text
+//! RC low-pass
+//! V1 in 0 DC 0 AC 1
+//! R1 in out 10k
+//! C1 out 0 100n
+//! .tran 10u 10m
+//! .print tran v(out)
+//! .end
+//! ```
+//!
+//! - `R`, `C`, `L`, `D`, `Q` and `V` elements. Values take SPICE's suffixes,
+//!   which differ from the DSL's: `M` is milli, `MEG` is mega and `F` is
+//!   femto; trailing unit letters (`10uF`, `1kohm`) are ignored.
+//! - `.model` cards. Parameters the DSL names differently are renamed
+//!   (`BV` to `vz`, `VAF` to `va`, `NF` to `n`); the rest are kept and
+//!   unused ones have no effect.
+//! - `.print`, `.plot`, `.probe` and `.save` voltages become the outputs.
+//!   Without one, a node named `out` is the output.
+//! - `.tran`, `.op`, `.ac`, `.dc`, `.options` and `.control` blocks are
+//!   skipped: the sample rate is the simulator's. `.end` ends the deck.
+//!
+//! As in SPICE, the first line is the title, `*` starts a comment line, `;`
+//! or `$` an inline comment, `+` continues the previous line, names are
+//! case-insensitive and node `0` is ground. Model names are read in upper
+//! case and node names in lower case.
+//!
+//! The first `V` source with an `AC`, `SIN` or `PULSE` specification is the
+//! audio input; every other `V` source is a DC supply at its DC value.
+
+use std::collections::HashMap;
+
+use super::ast::{CircuitAst, ComponentDef, ComponentType, ModelDef, ModelType};
+use crate::error::{PedalerError, Result};
+
+/// Parse a SPICE netlist into a circuit AST.
+///
+/// Returns a `ParseError` for elements and cards outside the supported
+/// subset, such as subcircuits, or for a value that is not a number.
+pub fn import_spice(input: &str) -> Result<CircuitAst> {
+    let mut ast = CircuitAst::new();
+    let mut outputs = Vec::new();
+    let mut in_control = false;
+
+    for (line, card) in cards(input) {
+        let fields = fields(&card);
+        let keyword = fields[0].to_lowercase();
+
+        // ngspice scripts between .control and .endc are not netlist cards
+        if in_control {
+            in_control = keyword != ".endc";
+            continue;
+        }
+
+        match keyword.as_str() {
+            ".end" => break,
+            ".control" => in_control = true,
+            ".model" => {
+                let model = model_card(&fields, line)?;
+                if ast.models.contains_key(&model.name) {
+                    return Err(PedalerError::DuplicateModel { name: model.name });
+                }
+                ast.models.insert(model.name.clone(), model);
+            }
+            ".print" | ".plot" | ".probe" | ".save" => {
+                // v(out) reads as `v out` once the parentheses are gone
+                for pair in fields[1..].windows(2) {
+                    if pair[0].eq_ignore_ascii_case("v") {
+                        let node = node_name(&pair[1]);
+                        if node != "0" && !outputs.contains(&node) {
+                            outputs.push(node);
+                        }
+                    }
+                }
+            }
+            ".tran" | ".op" | ".ac" | ".dc" | ".options" | ".option" | ".temp" | ".meas"
+            | ".measure" => {}
+            _ if keyword.starts_with('.') => {
+                return Err(PedalerError::parse(
+                    line,
+                    format!("unsupported SPICE card '{}'", fields[0]),
+                ));
+            }
+            _ => {
+                let def = element(&fields, line, &mut ast)?;
+                ast.components.push(def);
+            }
+        }
+    }
+
+    // Nodes in order of first appearance, as the DSL parser lists them
+    for def in &ast.components {
+        for node in &def.nodes {
+            if node != "0" && !ast.nodes.contains(node) {
+                ast.nodes.push(node.clone());
+            }
+        }
+    }
+    if outputs.is_empty() && ast.nodes.iter().any(|n| n == "out") {
+        outputs.push("out".to_string());
+    }
+    ast.output_nodes = outputs;
+
+    Ok(ast)
+}
+
+/// Cards of the deck with the line each starts on: the title, comments and
+/// blank lines dropped, and continuation lines joined.
+fn cards(input: &str) -> Vec<(usize, String)> {
+    let mut cards: Vec<(usize, String)> = Vec::new();
+    for (i, raw) in input.lines().enumerate().skip(1) {
+        let text = raw.split(';').next().unwrap_or("");
+        let text = text.split(" $").next().unwrap_or("").trim();
+        if text.is_empty() || text.starts_with('*') {
+            continue;
+        }
+        match (text.strip_prefix('+'), cards.last_mut()) {
+            (Some(rest), Some((_, card))) => {
+                card.push(' ');
+                card.push_str(rest);
+            }
+            _ => cards.push((i + 1, text.to_string())),
+        }
+    }
+    cards
+}
+
+/// Split a card into fields. Parentheses and commas separate fields, and
+/// spaces around `=` are dropped, so `D(IS = 1n)` reads as `D IS=1n`.
+fn fields(card: &str) -> Vec<String> {
+    let spaced: String = card
+        .chars()
+        .map(|c| if matches!(c, '(' | ')' | ',') { ' ' } else { c })
+        .collect();
+    let joined = spaced.split_whitespace().collect::<Vec<_>>().join(" ");
+    joined
+        .replace(" =", "=")
+        .replace("= ", "=")
+        .split(' ')
+        .map(str::to_string)
+        .collect()
+}
+
+/// A node name: `0` or `GND` for ground, otherwise lower case.
+fn node_name(text: &str) -> String {
+    if text == "0" || text.eq_ignore_ascii_case("gnd") {
+        "0".to_string()
+    } else {
+        text.to_lowercase()
+    }
+}
+
+/// Parse a number with a SPICE scale suffix, ignoring trailing unit
+/// letters: `4.7k`, `1MEG`, `100nF`, `2.52e-9`.
+fn spice_value(text: &str) -> Option<f64> {
+    let bytes = text.as_bytes();
+    let mut end = 0;
+    if matches!(bytes.first(), Some(b'+' | b'-')) {
+        end += 1;
+    }
+    while end < bytes.len() && (bytes[end].is_ascii_digit() || bytes[end] == b'.') {
+        end += 1;
+    }
+    // An exponent needs digits after it, so `1meg` is not `1e...`
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let digits = end + 1 + usize::from(matches!(bytes.get(end + 1), Some(b'+' | b'-')));
+        if bytes.get(digits).is_some_and(u8::is_ascii_digit) {
+            end = digits;
+            while end < bytes.len() && bytes[end].is_ascii_digit() {
+                end += 1;
+            }
+        }
+    }
+
+    let number: f64 = text[..end].parse().ok()?;
+    let suffix = text[end..].to_lowercase();
+    let scale = if suffix.starts_with("meg") {
+        1e6
+    } else if suffix.starts_with("mil") {
+        25.4e-6
+    } else {
+        match suffix.chars().next() {
+            None => 1.0,
+            Some('t') => 1e12,
+            Some('g') => 1e9,
+            Some('k') => 1e3,
+            Some('m') => 1e-3,
+            Some('u' | 'µ') => 1e-6,
+            Some('n') => 1e-9,
+            Some('p') => 1e-12,
+            Some('f') => 1e-15,
+            Some(c) if c.is_alphabetic() => 1.0,
+            Some(_) => return None,
+        }
+    };
+    Some(number * scale)
+}
+
+/// A `.model` card: `.model <name> <type> <param>=<value>...`.
+fn model_card(fields: &[String], line: usize) -> Result<ModelDef> {
+    let [_, name, kind, rest @ ..] = fields else {
+        return Err(PedalerError::parse(line, "expected '.model <name> <type>'"));
+    };
+    let model_type = ModelType::from_str(kind)
+        .ok_or_else(|| PedalerError::parse(line, format!("unknown model type: {}", kind)))?;
+
+    let mut params = HashMap::new();
+    for field in rest {
+        let Some((key, value)) = field.split_once('=') else {
+            return Err(PedalerError::parse(
+                line,
+                format!("expected <param>=<value>, got '{}'", field),
+            ));
+        };
+        let value = spice_value(value).ok_or_else(|| {
+            PedalerError::parse(line, format!("invalid value '{}' for {}", value, key))
+        })?;
+        let key = match key.to_lowercase().as_str() {
+            "bv" => "vz".to_string(),
+            "vaf" => "va".to_string(),
+            "nf" => "n".to_string(),
+            other => other.to_string(),
+        };
+        params.insert(key, value);
+    }
+
+    Ok(ModelDef {
+        name: name.to_uppercase(),
+        model_type,
+        params,
+        param_refs: HashMap::new(),
+        line,
+    })
+}
+
+/// An element card. A `V` source that becomes the audio input is recorded
+/// in `ast`.
+fn element(fields: &[String], line: usize, ast: &mut CircuitAst) -> Result<ComponentDef> {
+    let name = fields[0].clone();
+    let (positional, pairs): (Vec<&String>, Vec<&String>) =
+        fields[1..].iter().partition(|f| !f.contains('='));
+    let value_at = |index: usize| -> Result<f64> {
+        let text = positional
+            .get(index)
+            .ok_or_else(|| PedalerError::invalid_component(&name, line, "missing value"))?;
+        spice_value(text).ok_or_else(|| {
+            PedalerError::invalid_component(&name, line, format!("invalid value '{}'", text))
+        })
+    };
+    let nodes_upto = |count: usize| -> Result<Vec<String>> {
+        if positional.len() < count {
+            return Err(PedalerError::invalid_component(
+                &name,
+                line,
+                format!("expected {} nodes, got {}", count, positional.len()),
+            ));
+        }
+        Ok(positional[..count].iter().map(|n| node_name(n)).collect())
+    };
+
+    let mut def = ComponentDef {
+        component_type: ComponentType::Resistor,
+        name: name.clone(),
+        nodes: Vec::new(),
+        value: None,
+        model_ref: None,
+        params: HashMap::new(),
+        string_params: HashMap::new(),
+        param_refs: HashMap::new(),
+        line,
+    };
+    for pair in pairs {
+        let (key, value) = pair.split_once('=').unwrap_or_default();
+        if let Some(v) = spice_value(value) {
+            def.params.insert(key.to_lowercase(), v);
+        }
+    }
+
+    match name.chars().next().map(|c| c.to_ascii_uppercase()) {
+        Some(kind @ ('R' | 'C' | 'L')) => {
+            def.component_type = match kind {
+                'R' => ComponentType::Resistor,
+                'C' => ComponentType::Capacitor,
+                _ => ComponentType::Inductor,
+            };
+            def.nodes = nodes_upto(2)?;
+            def.value = Some(value_at(2)?);
+        }
+        Some('D') => {
+            // D<name> <anode> <cathode> [model] [area]
+            def.component_type = ComponentType::Diode;
+            def.nodes = nodes_upto(2)?;
+            def.model_ref = positional.get(2).map(|m| m.to_uppercase());
+        }
+        Some('Q') => {
+            // Q<name> <c> <b> <e> [substrate] [model] [area]: a plain number
+            // after the fourth field is an area, so the fourth is the model.
+            // Model names often start with digits (`2N5088`), so suffixed
+            // values do not count as numbers here.
+            def.component_type = ComponentType::Bjt;
+            def.nodes = nodes_upto(3)?;
+            let model = match positional.get(4) {
+                Some(field) if field.parse::<f64>().is_err() => Some(field),
+                _ => positional.get(3),
+            };
+            def.model_ref = model.map(|m| m.to_uppercase());
+        }
+        Some('V') => {
+            def.component_type = ComponentType::VoltageSource;
+            def.nodes = nodes_upto(2)?;
+            let (dc, ac) = source_spec(&positional[2..]);
+            if ast.input_nodes.is_empty() {
+                if let Some(amplitude) = ac {
+                    let input = if def.nodes[0] == "0" {
+                        &def.nodes[1]
+                    } else {
+                        &def.nodes[0]
+                    };
+                    ast.input_nodes.push(input.clone());
+                    def.params.insert("ac".to_string(), 1.0);
+                    def.value = Some(amplitude);
+                    return Ok(def);
+                }
+            }
+            def.params.insert("dc".to_string(), 1.0);
+            def.value = Some(dc);
+        }
+        _ => {
+            return Err(PedalerError::parse(
+                line,
+                format!("unsupported SPICE element '{}'", name),
+            ));
+        }
+    }
+    Ok(def)
+}
+
+/// DC value and, for a signal source, amplitude of a `V` specification:
+/// `[DC] <v> [AC <mag>] [SIN(<vo> <va> <freq> ...)]`.
+fn source_spec(fields: &[&String]) -> (f64, Option<f64>) {
+    let mut dc = 0.0;
+    let mut ac = None;
+    let mut i = 0;
+    while i < fields.len() {
+        let next = fields.get(i + 1).and_then(|f| spice_value(f));
+        match fields[i].to_lowercase().as_str() {
+            "dc" => {
+                dc = next.unwrap_or(0.0);
+                i += 1;
+            }
+            "ac" => {
+                ac = Some(next.unwrap_or(1.0));
+                if next.is_some() {
+                    i += 1;
+                }
+            }
+            "sin" => {
+                // SIN(vo va freq ...): the amplitude follows the offset
+                let amplitude = fields
+                    .get(i + 2)
+                    .and_then(|f| spice_value(f))
+                    .unwrap_or(1.0);
+                ac = ac.or(Some(amplitude));
+                break;
+            }
+            "pulse" | "pwl" | "exp" | "sffm" => {
+                ac = ac.or(Some(1.0));
+                break;
+            }
+            text => {
+                if let Some(v) = spice_value(text) {
+                    dc = v;
+                }
+            }
+        }
+        i += 1;
+    }
+    (dc, ac)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Circuit, Simulator};
+
+    #[test]
+    fn test_import_rc_deck() {
+        let deck = "RC low-pass\n\
+                    * input and filter\n\
+                    V1 in 0 DC 0 AC 1\n\
+                    R1 in out 10k ; series\n\
+                    C1 out 0 100NF\n\
+                    .tran 10u 10m\n\
+                    .end\n\
+                    R2 out 0 1k\n";
+        let ast = import_spice(deck).unwrap();
+        assert_eq!(ast.input_nodes, vec!["in"]);
+        assert_eq!(ast.output_nodes, vec!["out"]);
+        assert_eq!(ast.components.len(), 3);
+        assert_eq!(ast.components[1].component_type, ComponentType::Resistor);
+        assert_eq!(ast.components[1].value, Some(10e3));
+        assert!((ast.components[2].value.unwrap() - 100e-9).abs() < 1e-18);
+        assert_eq!(ast.components[0].params.get("ac"), Some(&1.0));
+
+        // SPICE's M is milli and MEG mega
+        assert_eq!(spice_value("1MEG"), Some(1e6));
+        assert_eq!(spice_value("2.2m"), Some(2.2e-3));
+        assert_eq!(spice_value("1e-14"), Some(1e-14));
+        assert_eq!(spice_value("4.7kohm"), Some(4.7e3));
+        assert_eq!(spice_value("k"), None);
+    }
+
+    #[test]
+    fn test_import_diode_clipper_deck() {
+        let deck = "Diode clipper\n\
+                    VIN in 0 SIN(0 0.5 1k)\n\
+                    VCC vcc 0 9\n\
+                    R1 in OUT 4.7k\n\
+                    C1 OUT 0 10n\n\
+                    D1 OUT 0 D1N4148\n\
+                    D2 0 out d1n4148 1.0\n\
+                    R2 vcc out 1MEG\n\
+                    .model D1N4148 D(IS=2.52n N=1.752\n\
+                    + RS=0.568 BV = 100)\n\
+                    .print tran V(out)\n";
+        let ast = import_spice(deck).unwrap();
+        assert_eq!(ast.input_nodes, vec!["in"]);
+        assert_eq!(ast.output_nodes, vec!["out"]);
+        assert_eq!(ast.components[0].value, Some(0.5));
+        assert_eq!(ast.components[1].value, Some(9.0));
+        assert_eq!(ast.components[1].params.get("dc"), Some(&1.0));
+        assert_eq!(ast.components[4].component_type, ComponentType::Diode);
+        assert_eq!(ast.components[4].nodes, vec!["out", "0"]);
+        assert_eq!(ast.components[5].model_ref.as_deref(), Some("D1N4148"));
+
+        let model = &ast.models["D1N4148"];
+        assert_eq!(model.model_type, ModelType::Diode);
+        assert!((model.params["is"] - 2.52e-9).abs() < 1e-20);
+        assert_eq!(model.params["vz"], 100.0);
+
+        // The imported deck simulates like a DSL netlist
+        let mut sim = Simulator::new(Circuit::from_ast(ast).unwrap(), 48000.0);
+        let input: Vec<f32> = (0..480).map(|n| (n as f32 * 0.13).sin()).collect();
+        let mut output = vec![0.0; input.len()];
+        sim.process_block(&input, &mut output).unwrap();
+        assert!(output.iter().all(|v| v.is_finite()));
+
+        let err = import_spice("title\nX1 a b opamp\n").unwrap_err();
+        assert!(
+            matches!(err, PedalerError::ParseError { line: 2, .. }),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_import_bjt_model_and_substrate() {
+        let deck = "BJT fields\n\
+                    V1 in 0 AC 1\n\
+                    R1 in b 10k\n\
+                    Q1 c b e 2N5088\n\
+                    Q2 c b e 2N5088 1.5\n\
+                    Q3 c b e 0 2N5088\n\
+                    Q4 c b e 0 2n5088 2\n";
+        let ast = import_spice(deck).unwrap();
+        for bjt in &ast.components[2..] {
+            assert_eq!(bjt.component_type, ComponentType::Bjt);
+            assert_eq!(bjt.nodes, vec!["c", "b", "e"]);
+            assert_eq!(bjt.model_ref.as_deref(), Some("2N5088"), "{}", bjt.name);
+        }
+    }
+}
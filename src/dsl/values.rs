@@ -0,0 +1,116 @@
+//! Component value files.
+//!
+//! A value file overrides component values of a netlist after it is loaded,
+//! for example with the measured values of a built unit:
+//!
+//! ```text
+//! # name, value
+//! R1, 9.83k
+//! C2, 97n
+//! ```
+//!
+//! Values take the same unit suffixes as the DSL. Blank lines and lines
+//! starting with `#` or `;` are ignored, as is a leading `name,value` header.
+
+use super::lexer::parse_value;
+use crate::error::{PedalerError, Result};
+
+/// One `name,value` entry of a value file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueOverride {
+    /// Component name
+    pub name: String,
+    /// Value in base units
+    pub value: f64,
+    /// Line number in the file (1-based)
+    pub line: usize,
+}
+
+/// Parse a value file.
+pub fn parse_values(input: &str) -> Result<Vec<ValueOverride>> {
+    let mut values = Vec::new();
+
+    for (i, raw) in input.lines().enumerate() {
+        let line = i + 1;
+        let text = raw.trim();
+        if text.is_empty() || text.starts_with('#') || text.starts_with(';') {
+            continue;
+        }
+
+        let fields: Vec<&str> = text.split(',').map(str::trim).collect();
+        let [name, value] = fields[..] else {
+            return Err(PedalerError::parse(
+                line,
+                format!("expected 'name,value', got '{}'", text),
+            ));
+        };
+        if values.is_empty()
+            && name.eq_ignore_ascii_case("name")
+            && value.eq_ignore_ascii_case("value")
+        {
+            continue;
+        }
+
+        let value = parse_value(value).ok_or_else(|| {
+            PedalerError::parse(line, format!("invalid value '{}' for '{}'", value, name))
+        })?;
+        values.push(ValueOverride {
+            name: name.to_string(),
+            value,
+            line,
+        });
+    }
+
+    Ok(values)
+}
+
+/// Parse a value file from disk.
+#[cfg(feature = "cli")]
+pub fn parse_values_file(path: &std::path::Path) -> Result<Vec<ValueOverride>> {
+    let content = std::fs::read_to_string(path).map_err(|e| PedalerError::FileReadError {
+        path: path.display().to_string(),
+        source: e,
+    })?;
+    parse_values(&content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Circuit, Simulator};
+
+    #[test]
+    fn test_parse_values() {
+        let values = parse_values("name,value\n# measured\nR1, 9.83k\n\nC2,97n\n").unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[0].name, "R1");
+        assert!((values[0].value - 9830.0).abs() < 1e-9);
+        assert_eq!(values[1].line, 5);
+
+        assert!(parse_values("R1 10k\n").is_err());
+        assert!(parse_values("R1,ten\n").is_err());
+    }
+
+    #[test]
+    fn test_override_matches_netlist_value() {
+        let render = |r1: &str, overrides: &str| {
+            let src = format!(
+                ".input in\n.output out\nVIN in 0 AC\nR1 in out {}\nC1 out 0 10n\n",
+                r1
+            );
+            let circuit = Circuit::from_ast(crate::dsl::parse(&src).unwrap()).unwrap();
+            let mut sim = Simulator::new(circuit, 48000.0);
+            for v in parse_values(overrides).unwrap() {
+                sim.set_parameter(&v.name, v.value).unwrap();
+            }
+            let input: Vec<f32> = (0..256).map(|i| (i as f32 * 0.2).sin()).collect();
+            let mut output = vec![0.0f32; input.len()];
+            sim.process_block(&input, &mut output).unwrap();
+            output
+        };
+
+        let edited = render("22k", "");
+        assert_eq!(render("10k", "R1,22k\n"), edited);
+        assert_ne!(render("10k", ""), edited);
+    }
+}
@@ -21,9 +21,13 @@ pub enum PedalerError {
         message: String,
     },
 
-    /// Error during parsing
-    #[error("Parse error at line {line}: {message}")]
-    ParseError { line: usize, message: String },
+    /// Error during parsing, at the offending token's column when known
+    #[error("Parse error at line {line}{}: {message}", column.map(|c| format!(", column {}", c)).unwrap_or_default())]
+    ParseError {
+        line: usize,
+        column: Option<usize>,
+        message: String,
+    },
 
     /// Invalid component definition
     #[error("Invalid component '{name}' at line {line}: {message}")]
@@ -45,10 +49,21 @@ pub enum PedalerError {
         message: String,
     },
 
+    /// `.input` or `.output` pointing at ground
+    #[error("{directive} cannot be ground (line {line}); pick a signal node")]
+    GroundSignalNode { directive: String, line: usize },
+
     /// Undefined model reference
     #[error("Undefined model '{model}' referenced by component '{component}'")]
     UndefinedModel { model: String, component: String },
 
+    /// Undefined subcircuit reference
+    #[error("Undefined subcircuit '{subcircuit}' referenced by component '{component}'")]
+    UndefinedSubcircuit {
+        subcircuit: String,
+        component: String,
+    },
+
     // ============ Circuit Validation Errors ============
     /// Node not found in circuit
     #[error("Node '{node}' not found in circuit")]
@@ -70,6 +85,16 @@ pub enum PedalerError {
     #[error("No output node specified (use '.output <node>')")]
     MissingOutput,
 
+    /// Input node has no voltage or current source to inject the audio signal
+    #[error("Input node '{node}' is not driven by a voltage or current source (add e.g. 'V_IN {node} 0 AC')")]
+    UndrivenInput { node: String },
+
+    /// Control input node has no voltage source to inject the control signal
+    #[error(
+        "Control node '{node}' is not driven by a voltage source (add e.g. 'V_{node} {node} 0 AC')"
+    )]
+    UndrivenControl { node: String },
+
     /// Duplicate component name
     #[error("Duplicate component name '{name}'")]
     DuplicateComponent { name: String },
@@ -88,7 +113,9 @@ pub enum PedalerError {
     SingularMatrix,
 
     /// Newton-Raphson iteration did not converge
-    #[error("Newton-Raphson did not converge after {iterations} iterations (residual: {residual:.2e})")]
+    #[error(
+        "Newton-Raphson did not converge after {iterations} iterations (residual: {residual:.2e})"
+    )]
     ConvergenceFailure { iterations: usize, residual: f64 },
 
     /// Numerical overflow detected
@@ -99,6 +126,10 @@ pub enum PedalerError {
     #[error("Invalid simulation parameter: {message}")]
     InvalidSimulationParam { message: String },
 
+    /// No controllable parameter with this name
+    #[error("Unknown parameter '{name}'")]
+    UnknownParameter { name: String },
+
     // ============ I/O Errors ============
     /// Error reading circuit file
     #[error("Failed to read circuit file '{path}': {source}")]
@@ -108,6 +139,14 @@ pub enum PedalerError {
         source: std::io::Error,
     },
 
+    /// Error writing circuit file
+    #[error("Failed to write circuit file '{path}': {source}")]
+    FileWriteError {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+
     /// Error reading audio input
     #[error("Audio input error: {message}")]
     AudioInputError { message: String },
@@ -133,16 +172,30 @@ impl PedalerError {
         }
     }
 
-    /// Create a parse error
+    /// Create a parse error about a whole line
     pub fn parse(line: usize, message: impl Into<String>) -> Self {
         Self::ParseError {
             line,
+            column: None,
+            message: message.into(),
+        }
+    }
+
+    /// Create a parse error pointing at the token at `line` and `column`
+    pub fn parse_at(line: usize, column: usize, message: impl Into<String>) -> Self {
+        Self::ParseError {
+            line,
+            column: Some(column),
             message: message.into(),
         }
     }
 
     /// Create an invalid component error
-    pub fn invalid_component(name: impl Into<String>, line: usize, message: impl Into<String>) -> Self {
+    pub fn invalid_component(
+        name: impl Into<String>,
+        line: usize,
+        message: impl Into<String>,
+    ) -> Self {
         Self::InvalidComponent {
             name: name.into(),
             line,
@@ -150,6 +203,19 @@ impl PedalerError {
         }
     }
 
+    /// Create an invalid parameter error
+    pub fn invalid_parameter(
+        component: impl Into<String>,
+        param: impl Into<String>,
+        message: impl Into<String>,
+    ) -> Self {
+        Self::InvalidParameter {
+            component: component.into(),
+            param: param.into(),
+            message: message.into(),
+        }
+    }
+
     /// Create a convergence failure error
     pub fn convergence_failure(iterations: usize, residual: f64) -> Self {
         Self::ConvergenceFailure {
@@ -16,7 +16,9 @@
 //! - [`circuit`] - Circuit graph representation and validation
 //! - [`components`] - Component models (resistors, capacitors, diodes, etc.)
 //! - [`solver`] - MNA matrix assembly and numerical solving
+//! - [`analysis`] - FFT and sweep-based frequency-response measurement
 //! - [`audio`] - Audio I/O and processing (CLI only)
+//! - `testing` - Golden-output test helpers (`testing` feature)
 //!
 //! ## Usage
 //!
@@ -31,7 +33,7 @@
 //! ```javascript
 //! import { WasmPedalSim } from 'pedaler_core';
 //!
-//! const sim = new WasmPedalSim(circuitDsl, 48000);
+//! const sim = new WasmPedalSim(circuitDsl, 48000, 128);
 //! sim.process_block(inputBuffer, outputBuffer);
 //! ```
 //!
@@ -45,8 +47,10 @@
 //! 3. For nonlinear elements, iterate using Newton-Raphson until convergence
 //!
 //! Reactive elements (C, L) are discretized using the trapezoidal rule for
-//! accuracy and stability.
+//! accuracy and stability, or optionally backward Euler, which damps the
+//! ringing the trapezoidal rule can show on stiff circuits.
 
+pub mod analysis;
 pub mod circuit;
 pub mod components;
 pub mod dsl;
@@ -56,10 +60,16 @@ pub mod solver;
 #[cfg(feature = "cli")]
 pub mod audio;
 
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
 // Re-export main types for convenience
-pub use circuit::Circuit;
+pub use circuit::{Circuit, CircuitBuilder};
+pub use components::{Component, IntegrationMethod, LfoShape};
 pub use error::{PedalerError, Result};
-pub use solver::{Simulator, SimulatorConfig};
+pub use solver::{
+    ac_analysis, InitialGuess, ParamInfo, ParamKind, Simulator, SimulatorConfig, SolverStats,
+};
 
 // WASM bindings
 #[cfg(feature = "wasm")]
@@ -73,3 +83,9 @@ pub const DEFAULT_SAMPLE_RATE: f32 = 48000.0;
 
 /// Thermal voltage at room temperature (approximately 26mV)
 pub const THERMAL_VOLTAGE: f64 = 0.0258;
+
+/// Nominal device temperature in kelvin, at which model parameters are specified
+pub const NOMINAL_TEMPERATURE: f64 = 300.15;
+
+/// 0 °C in kelvin
+pub const ZERO_CELSIUS: f64 = 273.15;
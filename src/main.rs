@@ -5,18 +5,28 @@
 //! # Usage
 //!
 //! ```bash
+//! pedaler circuit.ped --input input.wav --output output.wav
 //! ffmpeg -i input.wav -f f32le -ac 1 -ar 48000 - | pedaler circuit.ped | ffmpeg -f f32le -ac 1 -ar 48000 -i - output.wav
+//! pedaler --values measured.csv circuit.ped < input.f32 > output.f32
+//! pedaler fmt circuit.ped
+//! pedaler diff a.ped b.ped --input test.wav
+//! pedaler ac circuit.ped > bode.csv
 //! ```
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use pedaler_core::{
-    audio::process_audio,
+    ac_analysis,
+    analysis::compare_responses,
+    audio::{
+        process_audio_with_output, process_samples_with_output, read_wav, AudioOutput,
+        OutputFormat, WavAudio,
+    },
     circuit::Circuit,
     dsl,
-    error::Result,
-    Simulator, SimulatorConfig, DEFAULT_SAMPLE_RATE,
+    error::{PedalerError, Result},
+    Simulator, SimulatorConfig, SolverStats, DEFAULT_SAMPLE_RATE,
 };
 
 /// Default maximum Newton-Raphson iterations
@@ -28,12 +38,16 @@ const DEFAULT_TOLERANCE: f64 = 1e-6;
 /// Guitar pedal circuit simulator
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path to the circuit description file (.ped)
-    #[arg(value_name = "CIRCUIT_FILE")]
-    circuit_file: PathBuf,
+    #[arg(value_name = "CIRCUIT_FILE", required = true)]
+    circuit_file: Option<PathBuf>,
 
-    /// Sample rate in Hz
+    /// Sample rate in Hz (taken from the file with --input)
     #[arg(short, long, default_value_t = DEFAULT_SAMPLE_RATE)]
     sample_rate: f32,
 
@@ -45,28 +59,428 @@ struct Args {
     /// Higher = faster but less accurate. Default is 1e-4.
     #[arg(short = 't', long, default_value_t = DEFAULT_TOLERANCE)]
     tolerance: f64,
+
+    /// Run the circuit at N times the sample rate (1, 2, 4 or 8) to reduce aliasing
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = parse_oversample)]
+    oversample: usize,
+
+    /// Highpass the output at 20 Hz to remove DC offset
+    #[arg(long)]
+    block_dc: bool,
+
+    /// Output sample encoding [default: wav for a .wav --output, f32le otherwise]
+    #[arg(short = 'f', long, value_enum)]
+    format: Option<OutputFormat>,
+
+    /// Round output samples to N decimal places for reproducible comparisons
+    #[arg(long, value_name = "N")]
+    round: Option<usize>,
+
+    /// Read input from a WAV file instead of raw f32le on stdin (mixed down to mono
+    /// unless the circuit has one input per channel)
+    #[arg(long, value_name = "WAV")]
+    input: Option<PathBuf>,
+
+    /// Write output to a file instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Override component values from a `name,value` CSV file
+    #[arg(long, value_name = "CSV")]
+    values: Option<PathBuf>,
+
+    /// Drive the input through this series resistance (e.g. 10k)
+    #[arg(long, value_name = "OHMS", value_parser = parse_ohms)]
+    source_impedance: Option<f64>,
+
+    /// Load the output with this resistance to ground (e.g. 500k)
+    #[arg(long, value_name = "OHMS", value_parser = parse_ohms)]
+    load_impedance: Option<f64>,
+
+    /// Bias every op-amp input that has no DC path with this resistance to ground (e.g. 1M)
+    #[arg(long, value_name = "OHMS", value_parser = parse_ohms)]
+    opamp_bias: Option<f64>,
+
+    /// Print Newton-Raphson iteration statistics to stderr when done
+    #[arg(long)]
+    stats: bool,
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+/// Resistances inserted into the circuit: source and load terminations, and
+/// bias returns for floating op-amp inputs.
+#[derive(Debug, Clone, Copy, Default)]
+struct Termination {
+    source: Option<f64>,
+    load: Option<f64>,
+    opamp_bias: Option<f64>,
+}
 
-    // Parse the circuit file
-    let ast = dsl::parse_file(&args.circuit_file)?;
+/// Parse a resistance with the DSL's unit suffixes.
+fn parse_ohms(s: &str) -> std::result::Result<f64, String> {
+    dsl::parse_value(s).ok_or_else(|| format!("invalid resistance '{}'", s))
+}
 
-    // Build the circuit
-    let circuit = Circuit::from_ast(ast)?;
+/// Parse a frequency with the DSL's unit suffixes.
+fn parse_hertz(s: &str) -> std::result::Result<f64, String> {
+    dsl::parse_value(s).ok_or_else(|| format!("invalid frequency '{}'", s))
+}
 
-    // Validate
-    pedaler_core::circuit::validate_circuit(&circuit)?;
+/// Parse an oversampling factor.
+fn parse_oversample(s: &str) -> std::result::Result<usize, String> {
+    match s.parse() {
+        Ok(factor @ (1 | 2 | 4 | 8)) => Ok(factor),
+        _ => Err(format!(
+            "oversampling factor must be 1, 2, 4 or 8, got '{}'",
+            s
+        )),
+    }
+}
+
+/// Auxiliary subcommands
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Reformat a circuit file (aligned columns, normalized values, sorted directives)
+    Fmt {
+        /// Path to the circuit description file (.ped)
+        #[arg(value_name = "CIRCUIT_FILE")]
+        circuit_file: PathBuf,
+
+        /// Rewrite the file in place instead of printing to stdout
+        #[arg(short, long)]
+        write: bool,
+    },
+
+    /// Compare how two circuits respond to the same input
+    Diff {
+        /// Reference circuit (.ped)
+        #[arg(value_name = "CIRCUIT_A")]
+        circuit_a: PathBuf,
+
+        /// Circuit to compare against the reference (.ped)
+        #[arg(value_name = "CIRCUIT_B")]
+        circuit_b: PathBuf,
+
+        /// Test signal (WAV, mixed down to mono; sets the sample rate)
+        #[arg(long, value_name = "WAV")]
+        input: PathBuf,
+    },
+
+    /// Print the small-signal frequency response as CSV (Bode plot data)
+    Ac {
+        /// Path to the circuit description file (.ped)
+        #[arg(value_name = "CIRCUIT_FILE")]
+        circuit_file: PathBuf,
+
+        /// Lowest frequency in Hz
+        #[arg(long, default_value_t = 20.0, value_parser = parse_hertz)]
+        start: f64,
+
+        /// Highest frequency in Hz (e.g. 20k)
+        #[arg(long, default_value_t = 20000.0, value_parser = parse_hertz)]
+        stop: f64,
+
+        /// Log-spaced frequencies per decade
+        #[arg(long, default_value_t = 20)]
+        points_per_decade: usize,
+    },
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
 
-    // Create simulator with configuration
     let config = SimulatorConfig::new()
         .with_max_iterations(args.max_iterations)
-        .with_tolerance(args.tolerance);
-    let mut simulator = Simulator::with_config(circuit, args.sample_rate, config);
+        .with_tolerance(args.tolerance)
+        .with_oversample(args.oversample)
+        .with_dc_block(args.block_dc);
+
+    match &args.command {
+        Some(Command::Fmt {
+            circuit_file,
+            write,
+        }) => return format_file(circuit_file, *write),
+        Some(Command::Diff {
+            circuit_a,
+            circuit_b,
+            input,
+        }) => return diff_circuits(circuit_a, circuit_b, input, config),
+        Some(Command::Ac {
+            circuit_file,
+            start,
+            stop,
+            points_per_decade,
+        }) => return print_ac_response(circuit_file, *start, *stop, *points_per_decade),
+        None => {}
+    }
+    let circuit_file = args.circuit_file.expect("clap enforces CIRCUIT_FILE");
+
+    let termination = Termination {
+        source: args.source_impedance,
+        load: args.load_impedance,
+        opamp_bias: args.opamp_bias,
+    };
+    let input = args.input.as_deref().map(load_wav).transpose()?;
+    let sample_rate = input
+        .as_ref()
+        .map_or(args.sample_rate, |audio| audio.sample_rate as f32);
+    let mut simulator = load_simulator(&circuit_file, sample_rate, config, termination)?;
+    if let Some(path) = &args.values {
+        apply_values(path, &mut simulator)?;
+    }
 
     // Process audio
-    process_audio(&mut simulator)?;
+    let format = args.format.unwrap_or_else(|| match &args.output {
+        Some(path)
+            if path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("wav")) =>
+        {
+            OutputFormat::Wav
+        }
+        _ => OutputFormat::F32le,
+    });
+    let mut output =
+        AudioOutput::with_format(format, args.round).with_sample_rate(sample_rate as u32);
+    if let Some(path) = &args.output {
+        output = output.with_output_file(path)?;
+    }
+    match &input {
+        Some(audio) => {
+            let inputs = simulator.input_channels();
+            let samples =
+                audio
+                    .interleaved_for(inputs)
+                    .ok_or_else(|| PedalerError::AudioInputError {
+                        message: format!(
+                            "a {}-channel WAV file cannot feed a circuit with {} inputs",
+                            audio.channels, inputs
+                        ),
+                    })?;
+            process_samples_with_output(&mut simulator, &samples, output)?
+        }
+        None => process_audio_with_output(&mut simulator, output)?,
+    }
+    if args.stats {
+        print_stats(simulator.stats());
+    }
 
     Ok(())
 }
+
+/// Print solver statistics to stderr.
+fn print_stats(stats: &SolverStats) {
+    eprintln!("samples:          {}", stats.samples);
+    eprintln!(
+        "iterations:       {:.2} avg, {} max",
+        stats.avg_iterations(),
+        stats.max_iterations
+    );
+    eprintln!("worst residual:   {:.3e}", stats.worst_residual);
+    eprintln!("gmin fallbacks:   {}", stats.gmin_fallbacks);
+    eprintln!("source fallbacks: {}", stats.source_fallbacks);
+    eprintln!("budget overruns:  {}", stats.overruns);
+}
+
+/// Parse, build and validate a circuit file, and create its simulator.
+fn load_simulator(
+    path: &Path,
+    sample_rate: f32,
+    config: SimulatorConfig,
+    termination: Termination,
+) -> Result<Simulator> {
+    let mut ast = dsl::parse_file(path)?;
+    if let Some(resistance) = termination.source {
+        ast.add_source_impedance(resistance)?;
+    }
+    if let Some(resistance) = termination.load {
+        ast.add_load_impedance(resistance)?;
+    }
+    let mut circuit = Circuit::from_ast(ast)?;
+    if let Some(resistance) = termination.opamp_bias {
+        circuit.add_opamp_bias_resistors(resistance)?;
+    }
+    pedaler_core::circuit::validate_circuit(&circuit)?;
+    warn_disconnected(path, &circuit);
+    warn_floating_opamp_inputs(path, &circuit);
+    warn_sample_rate(path, &circuit, sample_rate, config.oversample);
+    Simulator::with_config(circuit, sample_rate, config)
+}
+
+/// Warn about op-amp inputs with no DC path to ground, which leave the
+/// op-amp's bias undefined.
+fn warn_floating_opamp_inputs(path: &Path, circuit: &Circuit) {
+    for warning in circuit.floating_opamp_warnings() {
+        eprintln!(
+            "warning: {}: {}, or use --opamp-bias",
+            path.display(),
+            warning
+        );
+    }
+}
+
+/// Warn when the internal sample rate is too low for the circuit's fastest
+/// pole.
+fn warn_sample_rate(path: &Path, circuit: &Circuit, sample_rate: f32, oversample: usize) {
+    let internal_rate = sample_rate * oversample as f32;
+    let factor = circuit.oversampling_factor(internal_rate);
+    if factor > 1 {
+        let total = oversample * factor as usize;
+        let suggestion = if total <= 8 {
+            format!("--oversample {}", total)
+        } else {
+            format!("--sample-rate {}", internal_rate * factor as f32)
+        };
+        eprintln!(
+            "warning: {}: fastest time constant is {:.3e} s, too fast for {} Hz; \
+             consider {}x oversampling ({})",
+            path.display(),
+            circuit.fastest_time_constant().unwrap_or_default(),
+            internal_rate,
+            factor,
+            suggestion
+        );
+    }
+}
+
+/// Warn about sub-circuits that are not wired to the input. They still
+/// simulate, but are almost always a netlist mistake.
+fn warn_disconnected(path: &Path, circuit: &Circuit) {
+    for group in circuit.subcircuits() {
+        if circuit.input_nodes.iter().any(|node| group.contains(node)) {
+            continue;
+        }
+        let names: Vec<&str> = group.iter().map(|&node| circuit.node_name(node)).collect();
+        let inputs: Vec<&str> = circuit
+            .input_nodes
+            .iter()
+            .map(|&node| circuit.node_name(node))
+            .collect();
+        eprintln!(
+            "warning: {}: node(s) {} are not connected to the input node '{}'",
+            path.display(),
+            names.join(", "),
+            inputs.join("', '")
+        );
+    }
+}
+
+/// Override component values from a value file. Names that do not match a
+/// settable component are reported and skipped.
+fn apply_values(path: &Path, simulator: &mut Simulator) -> Result<()> {
+    for v in dsl::parse_values_file(path)? {
+        match simulator.set_parameter(&v.name, v.value) {
+            Ok(()) => {}
+            Err(PedalerError::UnknownParameter { name }) => {
+                eprintln!(
+                    "warning: {}:{}: no component '{}' with a settable value",
+                    path.display(),
+                    v.line,
+                    name
+                );
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Render a test signal through two circuits and print how they differ.
+fn diff_circuits(a: &Path, b: &Path, input: &Path, config: SimulatorConfig) -> Result<()> {
+    let audio = load_wav(input)?;
+    let sample_rate = audio.sample_rate as f32;
+
+    let mut sim_a = load_simulator(a, sample_rate, config.clone(), Termination::default())?;
+    let mut sim_b = load_simulator(b, sample_rate, config, Termination::default())?;
+    let cmp = compare_responses(&mut sim_a, &mut sim_b, &audio.samples)?;
+
+    let db = |v: f64| 20.0 * v.max(1e-12).log10();
+    println!("A: {}", a.display());
+    println!("B: {}", b.display());
+    println!();
+    println!(
+        "RMS difference:   {:.6} ({:.1} dBFS)",
+        cmp.rms_difference,
+        db(cmp.rms_difference)
+    );
+    println!(
+        "Peak divergence:  {:.6} at {:.3} s",
+        cmp.peak_divergence,
+        cmp.peak_index as f64 / audio.sample_rate as f64
+    );
+    println!("Level (B vs A):   {:+.2} dB", cmp.gain_difference_db);
+    if let Some((freq, diff)) = cmp.max_spectral_difference() {
+        println!("Max spectral:     {:+.2} dB at {:.0} Hz", diff, freq);
+    }
+
+    let bands = cmp.octave_bands();
+    if !bands.is_empty() {
+        println!();
+        println!("Octave band     B vs A");
+        for (center, diff) in bands {
+            println!("{:>8.0} Hz  {:+8.2} dB", center, diff);
+        }
+    }
+    Ok(())
+}
+
+/// Read a WAV file, mixed down to mono.
+fn load_wav(path: &Path) -> Result<WavAudio> {
+    let file = std::fs::File::open(path).map_err(|e| PedalerError::FileReadError {
+        path: path.display().to_string(),
+        source: e,
+    })?;
+    read_wav(std::io::BufReader::new(file)).map_err(|e| PedalerError::AudioInputError {
+        message: format!("{}: {}", path.display(), e),
+    })
+}
+
+/// Print the AC response at log-spaced frequencies from `start` to `stop`
+/// as `frequency_hz,magnitude_db,phase_deg` rows.
+fn print_ac_response(path: &Path, start: f64, stop: f64, points_per_decade: usize) -> Result<()> {
+    if !(start > 0.0 && start < stop) || points_per_decade == 0 {
+        return Err(PedalerError::InvalidSimulationParam {
+            message: format!(
+                "frequency range {}..{} Hz must be increasing and above 0, with at least one point per decade",
+                start, stop
+            ),
+        });
+    }
+    let circuit = Circuit::from_ast(dsl::parse_file(path)?)?;
+    pedaler_core::circuit::validate_circuit(&circuit)?;
+
+    let decades = (stop / start).log10();
+    let points = (decades * points_per_decade as f64).ceil() as usize;
+    let freqs: Vec<f64> = (0..=points)
+        .map(|i| start * 10f64.powf(decades * i as f64 / points.max(1) as f64))
+        .collect();
+
+    println!("frequency_hz,magnitude_db,phase_deg");
+    for (freq, h) in ac_analysis(&circuit, &freqs)? {
+        println!(
+            "{:.3},{:.4},{:.3}",
+            freq,
+            20.0 * h.abs().max(1e-12).log10(),
+            h.arg().to_degrees()
+        );
+    }
+    Ok(())
+}
+
+/// Format a circuit file, printing the result or writing it back in place.
+fn format_file(path: &Path, write: bool) -> Result<()> {
+    let source = std::fs::read_to_string(path).map_err(|e| PedalerError::FileReadError {
+        path: path.display().to_string(),
+        source: e,
+    })?;
+    let formatted = dsl::format(&source)?;
+
+    if write {
+        std::fs::write(path, formatted).map_err(|e| PedalerError::FileWriteError {
+            path: path.display().to_string(),
+            source: e,
+        })
+    } else {
+        print!("{}", formatted);
+        Ok(())
+    }
+}
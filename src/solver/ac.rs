@@ -0,0 +1,337 @@
+//! Small-signal AC analysis.
+//!
+//! The circuit is linearized around its DC operating point and solved in
+//! the frequency domain: for each angular frequency `ω` the complex MNA
+//! system `(G + jωB) x = z` is assembled, with `G` holding conductances and
+//! the linearized devices, and `B` holding capacitances and inductances.
+//! The input source is driven at 1 V and every other source is zeroed, so
+//! the output node voltage is the transfer function at that frequency.
+//!
+//! The complex system is solved as the real system of twice the size
+//!
+//! ```text
+//! [ G  -ωB ] [ Re x ]   [ Re z ]
+//! [ ωB   G ] [ Im x ] = [ Im z ]
+//! ```
+//!
+//! so it reuses the real LU factorization.
+
+use std::f64::consts::PI;
+
+use super::mna::{stamp_linear_components, MnaMatrix};
+use super::Simulator;
+use crate::analysis::Complex;
+use crate::circuit::{Circuit, NodeId};
+use crate::components::{Component, IntegrationMethod};
+use crate::error::{PedalerError, Result};
+
+/// Complex response of the output to a 1 V input at each of `freqs` (Hz),
+/// or to 1 A when the input is driven by a current source.
+///
+/// Diodes, transistors and op-amps are linearized at the DC operating point
+/// for the present input and control voltages, so a clipping stage reports
+/// its small-signal gain at rest. Digital effects hold their output, and
+/// modulated components keep their present values.
+///
+/// Returns `(frequency, response)` pairs in the order of `freqs`; the
+/// magnitude of each response is the gain and its argument the phase.
+pub fn ac_analysis(circuit: &Circuit, freqs: &[f64]) -> Result<Vec<(f64, Complex)>> {
+    let source_idx = circuit
+        .input_source_idx
+        .ok_or_else(|| PedalerError::UndrivenInput {
+            node: circuit.node_name(circuit.input_node).to_string(),
+        })?;
+    let input = &circuit.components[source_idx];
+
+    // Solve the operating point, and read the biased devices back from it
+    let mut simulator = Simulator::new(circuit.clone(), crate::DEFAULT_SAMPLE_RATE);
+    simulator.freeze_operating_point()?;
+    let circuit = simulator.circuit();
+    let op = circuit.operating_point.as_deref().expect("frozen above");
+
+    let n = circuit.matrix_size();
+    let mut g = MnaMatrix::new(n);
+    g.x.copy_from_slice(op);
+    stamp_linear_components(circuit, &mut g, f64::INFINITY, IntegrationMethod::default());
+    stamp_small_signal(circuit, op, &mut g);
+    let mut b = MnaMatrix::new(n);
+    stamp_reactances(circuit, &mut b);
+
+    let output = circuit.node_index(circuit.output_node);
+    let mut system = MnaMatrix::new(2 * n);
+    freqs
+        .iter()
+        .map(|&freq| {
+            let w = 2.0 * PI * freq;
+            system.clear();
+            for i in 0..n {
+                for j in 0..n {
+                    let (g_ij, b_ij) = (g.get(i, j), b.get(i, j));
+                    if g_ij != 0.0 {
+                        system.add(i, j, g_ij);
+                        system.add(i + n, j + n, g_ij);
+                    }
+                    if b_ij != 0.0 {
+                        system.add(i, j + n, -w * b_ij);
+                        system.add(i + n, j, w * b_ij);
+                    }
+                }
+            }
+            match input {
+                Component::VoltageSource(vs) => system.z[circuit.branch_index(vs.branch)] = 1.0,
+                Component::CurrentSource(is) => system.stamp_current_source(
+                    circuit.node_index(is.nodes[0]),
+                    circuit.node_index(is.nodes[1]),
+                    1.0,
+                ),
+                _ => unreachable!("the input source is a voltage or current source"),
+            }
+            system.factor()?;
+            system.solve()?;
+            let response = match output {
+                Some(i) => Complex::new(system.x[i], system.x[i + n]),
+                None => Complex::default(),
+            };
+            Ok((freq, response))
+        })
+        .collect()
+}
+
+/// Stamp the small-signal conductances of nonlinear devices at the
+/// operating point `op`, and zero-volt sources for digital effect outputs.
+fn stamp_small_signal(circuit: &Circuit, op: &[f64], matrix: &mut MnaMatrix) {
+    let voltage = |node: NodeId| {
+        if node.is_ground() {
+            0.0
+        } else {
+            op[node.0 - 1]
+        }
+    };
+    for component in &circuit.components {
+        match component {
+            Component::Diode(d) => {
+                let (g, _) = d.linearize(voltage(d.nodes[0]) - voltage(d.nodes[1]));
+                let (n_anode, n_cathode) = (
+                    circuit.node_index(d.nodes[0]),
+                    circuit.node_index(d.nodes[1]),
+                );
+                matrix.stamp_conductance(n_anode, n_cathode, g);
+            }
+            Component::Bjt(q) => {
+                let v_b = voltage(q.base());
+                let (gm, go, gpi, gmu) =
+                    q.linearize(v_b - voltage(q.emitter()), v_b - voltage(q.collector()));
+                let n_c = circuit.node_index(q.collector());
+                let n_b = circuit.node_index(q.base());
+                let n_e = circuit.node_index(q.emitter());
+                matrix.stamp_conductance(n_b, n_e, gpi);
+                matrix.stamp_conductance(n_b, n_c, gmu);
+                matrix.stamp_conductance(n_c, n_e, go);
+                matrix.stamp_vccs(n_c, n_e, n_b, n_e, gm);
+            }
+            Component::Jfet(j) => {
+                let v_s = voltage(j.source());
+                let (gm, gds) = j.linearize(voltage(j.gate()) - v_s, voltage(j.drain()) - v_s);
+                let n_d = circuit.node_index(j.drain());
+                let n_g = circuit.node_index(j.gate());
+                let n_s = circuit.node_index(j.source());
+                matrix.stamp_conductance(n_d, n_s, gds);
+                matrix.stamp_vccs(n_d, n_s, n_g, n_s, gm);
+            }
+            Component::Mosfet(m) => {
+                let v_s = voltage(m.source());
+                let (gm, gds) = m.linearize(voltage(m.gate()) - v_s, voltage(m.drain()) - v_s);
+                let n_d = circuit.node_index(m.drain());
+                let n_g = circuit.node_index(m.gate());
+                let n_s = circuit.node_index(m.source());
+                matrix.stamp_conductance(n_d, n_s, gds);
+                matrix.stamp_vccs(n_d, n_s, n_g, n_s, gm);
+            }
+            Component::Triode(t) => {
+                let v_k = voltage(t.cathode());
+                let (gm, gp, gg) = t.linearize(voltage(t.grid()) - v_k, voltage(t.plate()) - v_k);
+                let n_p = circuit.node_index(t.plate());
+                let n_g = circuit.node_index(t.grid());
+                let n_k = circuit.node_index(t.cathode());
+                matrix.stamp_conductance(n_p, n_k, gp);
+                matrix.stamp_vccs(n_p, n_k, n_g, n_k, gm);
+                matrix.stamp_conductance(n_g, n_k, gg);
+            }
+            _ => {}
+        }
+    }
+
+    let effect_outputs = circuit
+        .delay_defs
+        .iter()
+        .map(|d| (d.output_node, d.branch))
+        .chain(
+            circuit
+                .reverb_defs
+                .iter()
+                .map(|r| (r.output_node, r.branch)),
+        )
+        .chain(
+            circuit
+                .widen_defs
+                .iter()
+                .flat_map(|w| [(w.left_node, w.left_branch), (w.right_node, w.right_branch)]),
+        );
+    for (node, branch) in effect_outputs {
+        matrix.stamp_voltage_source(
+            circuit.node_index(node),
+            None,
+            circuit.branch_index(branch),
+            0.0,
+        );
+    }
+}
+
+/// Stamp the coefficients of `jω`: capacitances between their nodes,
+/// inductances, mutual ones included, on their branch rows, and op-amp
+/// dominant poles on theirs.
+fn stamp_reactances(circuit: &Circuit, matrix: &mut MnaMatrix) {
+    for component in &circuit.components {
+        match component {
+            Component::Capacitor(c) => {
+                let (n1, n2) = (
+                    circuit.node_index(c.nodes[0]),
+                    circuit.node_index(c.nodes[1]),
+                );
+                matrix.stamp_conductance(n1, n2, c.capacitance);
+            }
+            Component::Inductor(l) => {
+                // The DC stamp shorts the inductor; V - jωL I = 0 adds the
+                // impedance
+                let br = circuit.branch_index(l.branch);
+                matrix.add(br, br, -l.inductance);
+            }
+            Component::Transformer(t) => {
+                let br = t.branches.map(|b| circuit.branch_index(b));
+                let l = t.inductances();
+                for j in 0..2 {
+                    matrix.add(br[j], br[0], -l[j][0]);
+                    matrix.add(br[j], br[1], -l[j][1]);
+                }
+            }
+            Component::OpAmp(op) => {
+                // The pole row at DC is Vc / A = V+ - V-; jω / (2π GBW) * Vc
+                // rolls it off
+                if let Some(pole) = op.pole {
+                    let pl = circuit.branch_index(pole);
+                    matrix.add(pl, pl, op.pole_capacitance());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl;
+
+    fn circuit(src: &str) -> Circuit {
+        Circuit::from_ast(dsl::parse(src).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_rc_lowpass_corner_is_3db_down() {
+        // 1k into 100n: corner at 1591.5 Hz
+        let c = circuit(".input in\n.output out\nV_IN in 0 AC\nR1 in out 1k\nC1 out 0 100n\n");
+        let corner = 1.0 / (2.0 * PI * 1e3 * 100e-9);
+        let response = ac_analysis(&c, &[10.0, corner, 100.0 * corner]).unwrap();
+
+        let db = |h: Complex| 20.0 * h.abs().log10();
+        assert!(db(response[0].1).abs() < 1e-3);
+        assert!(
+            (db(response[1].1) + 10.0 * 2.0f64.log10()).abs() < 1e-9,
+            "{} dB",
+            db(response[1].1)
+        );
+        assert!((response[1].1.arg() + PI / 4.0).abs() < 1e-9);
+        assert!((db(response[2].1) + 40.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_clipper_is_linearized_at_rest() {
+        // At rest the diodes barely conduct and the stage passes the signal;
+        // biased on, they shunt it
+        let src = ".input in\n.output out\nV_IN in 0 AC\nR1 in out 1k\nD1 out 0\n";
+        let at_rest = ac_analysis(&circuit(src), &[1000.0]).unwrap()[0].1;
+        assert!((at_rest.abs() - 1.0).abs() < 1e-6);
+
+        let biased = format!("{}V_B b 0 DC 5\nR2 b out 1k\n", src);
+        let shunted = ac_analysis(&circuit(&biased), &[1000.0]).unwrap()[0].1;
+        assert!(shunted.abs() < 0.05, "gain {}", shunted.abs());
+    }
+
+    #[test]
+    fn test_opamp_gbw_sets_open_loop_unity_gain() {
+        // Open loop, the gain is A up to the pole at GBW / A and falls at
+        // 20 dB per decade to unity at GBW
+        for model in ["gain=2e5 rout=1 gbw=1e6", "gbw=1e6"] {
+            let src = format!(
+                ".input in\n.output out\n.model OPX OP ({})\nV_IN in 0 AC\nOP1 out in 0 OPX\nRL out 0 10k\n",
+                model
+            );
+            let c = circuit(&src);
+            let a = if model.starts_with("gain") { 2e5 } else { 1e9 };
+            let response = ac_analysis(&c, &[1e6 / a / 100.0, 1e4, 1e6]).unwrap();
+            assert!(
+                (response[0].1.abs() / a - 1.0).abs() < 1e-3,
+                "{}: DC gain {}",
+                model,
+                response[0].1.abs()
+            );
+            assert!(
+                (response[1].1.abs() / 100.0 - 1.0).abs() < 1e-3,
+                "{}: gain {} at 10 kHz",
+                model,
+                response[1].1.abs()
+            );
+            assert!(
+                (response[2].1.abs() - 1.0).abs() < 1e-3,
+                "{}: gain {} at GBW",
+                model,
+                response[2].1.abs()
+            );
+            assert!((response[2].1.arg() + PI / 2.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_tone_stack_treble_raises_high_frequencies() {
+        let gain = |treble: f64| {
+            let src = format!(
+                ".input in\n.output out\nV_IN in 0 AC\nTONE1 in out 0 {} bass=0.5\n",
+                treble
+            );
+            ac_analysis(&circuit(&src), &[8000.0]).unwrap()[0].1.abs()
+        };
+
+        let sweep: Vec<f64> = [0.0, 0.25, 0.5, 0.75, 1.0].into_iter().map(gain).collect();
+        assert!(
+            sweep.windows(2).all(|pair| pair[1] > pair[0]),
+            "{:?}",
+            sweep
+        );
+        assert!(sweep[0] < 0.01 && sweep[4] > 0.5, "{:?}", sweep);
+
+        // The treble position may be a bare .param reference, and a third
+        // number is an error
+        let src = ".input in\n.output out\n.param T 1\nV_IN in 0 AC\nTONE1 in out 0 T 0.5\n";
+        assert_eq!(
+            ac_analysis(&circuit(src), &[8000.0]).unwrap()[0].1.abs(),
+            sweep[4]
+        );
+        let src = ".input in\n.output out\nV_IN in 0 AC\nTONE1 in out 0 0.5 0.5 0.5\n";
+        let err = Circuit::from_ast(dsl::parse(src).unwrap()).unwrap_err();
+        assert!(
+            matches!(err, PedalerError::InvalidComponent { .. }),
+            "{:?}",
+            err
+        );
+    }
+}
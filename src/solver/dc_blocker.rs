@@ -0,0 +1,52 @@
+//! DC blocker for the output, standing in for a pedal's output coupling
+//! capacitor.
+//!
+//! A one-pole, one-zero highpass:
+//!
+//! ```text
+//! y[n] = x[n] - x[n-1] + R * y[n-1],   R = exp(-2π fc / fs)
+//! ```
+//!
+//! The zero at DC removes any offset completely, and the pole at `R` sets
+//! the cutoff at `fc`, well below the guitar's lowest note.
+
+use std::f64::consts::PI;
+
+/// Cutoff of the DC blocker (Hz).
+pub const DC_BLOCK_CUTOFF: f64 = 20.0;
+
+/// First-order highpass on one output channel.
+#[derive(Debug, Clone)]
+pub(crate) struct DcBlocker {
+    /// Pole radius
+    r: f64,
+    /// Previous input
+    x_prev: f64,
+    /// Previous output
+    y_prev: f64,
+}
+
+impl DcBlocker {
+    /// Create a blocker for the host `sample_rate`.
+    pub fn new(sample_rate: f64) -> Self {
+        Self {
+            r: (-2.0 * PI * DC_BLOCK_CUTOFF / sample_rate).exp(),
+            x_prev: 0.0,
+            y_prev: 0.0,
+        }
+    }
+
+    /// Filter one sample.
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = x - self.x_prev + self.r * self.y_prev;
+        self.x_prev = x;
+        self.y_prev = y;
+        y
+    }
+
+    /// Clear the filter state.
+    pub fn reset(&mut self) {
+        self.x_prev = 0.0;
+        self.y_prev = 0.0;
+    }
+}
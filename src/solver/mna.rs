@@ -1,13 +1,21 @@
 //! MNA matrix assembly and solving.
 
+use super::{CURRENT_ABSTOL, CURRENT_RELTOL};
 use crate::circuit::{Circuit, NodeId};
-use crate::components::Component;
+use crate::components::{Component, IntegrationMethod, OpAmpModel, SwitchPoles};
 use crate::error::Result;
 
+/// Matrix size from which [`MnaMatrix::new`] stores the system sparsely.
+///
+/// Each node of a circuit only touches a handful of components, so past a
+/// few dozen unknowns most of a dense matrix is zeros, and sparse
+/// elimination that skips them beats the dense `O(n³)` factorization.
+pub const SPARSE_THRESHOLD: usize = 32;
+
 /// MNA matrix system Ax = z.
 #[derive(Debug)]
 pub struct MnaMatrix {
-    /// System matrix A (row-major)
+    /// System matrix A (row-major; empty when stored sparsely)
     pub a: Vec<f64>,
     /// Source vector z
     pub z: Vec<f64>,
@@ -15,15 +23,30 @@ pub struct MnaMatrix {
     pub x: Vec<f64>,
     /// Matrix dimension
     pub size: usize,
-    /// LU decomposition of A (for efficient solving)
+    /// LU decomposition of A (for efficient solving; empty when stored
+    /// sparsely)
     pub lu: Vec<f64>,
     /// Pivot indices for LU decomposition
     pub pivots: Vec<usize>,
+    /// Sparse storage of A and its factors, used instead of `a` and `lu`
+    sparse: Option<SparseMatrix>,
+    /// Number of LU decompositions performed so far
+    factorizations: u64,
 }
 
 impl MnaMatrix {
-    /// Create a new MNA matrix for the given circuit.
+    /// Create a new MNA matrix for the given circuit, sparse from
+    /// [`SPARSE_THRESHOLD`] unknowns on.
     pub fn new(size: usize) -> Self {
+        if size >= SPARSE_THRESHOLD {
+            Self::sparse(size)
+        } else {
+            Self::dense(size)
+        }
+    }
+
+    /// Create a new MNA matrix with dense storage.
+    pub fn dense(size: usize) -> Self {
         Self {
             a: vec![0.0; size * size],
             z: vec![0.0; size],
@@ -31,28 +54,61 @@ impl MnaMatrix {
             size,
             lu: vec![0.0; size * size],
             pivots: vec![0; size],
+            sparse: None,
+            factorizations: 0,
+        }
+    }
+
+    /// Create a new MNA matrix with sparse storage.
+    pub fn sparse(size: usize) -> Self {
+        Self {
+            a: Vec::new(),
+            z: vec![0.0; size],
+            x: vec![0.0; size],
+            size,
+            lu: Vec::new(),
+            pivots: vec![0; size],
+            sparse: Some(SparseMatrix::new(size)),
+            factorizations: 0,
         }
     }
 
+    /// Whether the matrix is stored sparsely.
+    pub fn is_sparse(&self) -> bool {
+        self.sparse.is_some()
+    }
+
     /// Clear the matrix and vectors to zero.
     pub fn clear(&mut self) {
-        self.a.fill(0.0);
+        match &mut self.sparse {
+            Some(sparse) => sparse.clear(),
+            None => self.a.fill(0.0),
+        }
         self.z.fill(0.0);
     }
 
     /// Get matrix element at (row, col).
     pub fn get(&self, row: usize, col: usize) -> f64 {
-        self.a[row * self.size + col]
+        match &self.sparse {
+            Some(sparse) => sparse.get(row, col),
+            None => self.a[row * self.size + col],
+        }
     }
 
     /// Set matrix element at (row, col).
     pub fn set(&mut self, row: usize, col: usize, value: f64) {
-        self.a[row * self.size + col] = value;
+        match &mut self.sparse {
+            Some(sparse) => *sparse.entry(row, col) = value,
+            None => self.a[row * self.size + col] = value,
+        }
     }
 
     /// Add to matrix element at (row, col).
     pub fn add(&mut self, row: usize, col: usize, value: f64) {
-        self.a[row * self.size + col] += value;
+        match &mut self.sparse {
+            Some(sparse) => *sparse.entry(row, col) += value,
+            None => self.a[row * self.size + col] += value,
+        }
     }
 
     /// Add to source vector element.
@@ -102,7 +158,12 @@ impl MnaMatrix {
 
     /// Stamp a current source between two nodes.
     /// Current flows from n+ to n-.
-    pub fn stamp_current_source(&mut self, n_pos: Option<usize>, n_neg: Option<usize>, current: f64) {
+    pub fn stamp_current_source(
+        &mut self,
+        n_pos: Option<usize>,
+        n_neg: Option<usize>,
+        current: f64,
+    ) {
         // Current enters n- and leaves n+
         if let Some(i) = n_pos {
             self.add_source(i, -current);
@@ -169,6 +230,11 @@ impl MnaMatrix {
 
     /// Perform LU decomposition with partial pivoting.
     pub fn factor(&mut self) -> Result<()> {
+        self.factorizations += 1;
+        if let Some(sparse) = &mut self.sparse {
+            return sparse.factor(&mut self.pivots);
+        }
+
         let n = self.size;
         self.lu.copy_from_slice(&self.a);
 
@@ -197,9 +263,7 @@ impl MnaMatrix {
             if max_row != k {
                 self.pivots.swap(k, max_row);
                 for j in 0..n {
-                    let tmp = self.lu[k * n + j];
-                    self.lu[k * n + j] = self.lu[max_row * n + j];
-                    self.lu[max_row * n + j] = tmp;
+                    self.lu.swap(k * n + j, max_row * n + j);
                 }
             }
 
@@ -217,14 +281,85 @@ impl MnaMatrix {
         Ok(())
     }
 
+    /// Number of LU decompositions performed so far.
+    pub fn factorization_count(&self) -> u64 {
+        self.factorizations
+    }
+
+    /// Smallest pivot magnitude of the last factorization.
+    ///
+    /// A pivot many orders of magnitude below the matrix entries means the
+    /// system is close to singular and the solution has lost precision.
+    pub fn min_pivot(&self) -> f64 {
+        if let Some(sparse) = &self.sparse {
+            return sparse
+                .factors
+                .iter()
+                .map(|row| row.upper.first().map_or(0.0, |&(_, v)| v.abs()))
+                .fold(f64::INFINITY, f64::min);
+        }
+        let n = self.size;
+        (0..n)
+            .map(|i| self.lu[i * n + i].abs())
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    /// Largest entry of `A·x - z`: how far `x` is from satisfying the
+    /// stamped equations, in amperes for node rows (the KCL imbalance) and
+    /// volts for branch rows.
+    pub fn residual_norm(&self) -> f64 {
+        let n = self.size;
+        (0..n)
+            .map(|i| {
+                let ax: f64 = match &self.sparse {
+                    Some(sparse) => sparse.rows[i].iter().map(|&(j, v)| v * self.x[j]).sum(),
+                    None => (0..n).map(|j| self.a[i * n + j] * self.x[j]).sum(),
+                };
+                (ax - self.z[i]).abs()
+            })
+            .fold(0.0, f64::max)
+    }
+
+    /// Whether `x` satisfies the stamped equations. Each of the first
+    /// `node_rows` rows, the KCL at a node, must balance to within
+    /// [`CURRENT_RELTOL`] of the largest current into the node plus
+    /// [`CURRENT_ABSTOL`]; the branch rows after them to within
+    /// `voltage_tol` volts.
+    pub fn balances(&self, node_rows: usize, voltage_tol: f64) -> bool {
+        let n = self.size;
+        (0..n).all(|i| {
+            let (mut ax, mut largest) = (0.0, self.z[i].abs());
+            let mut add = |term: f64| {
+                ax += term;
+                largest = largest.max(term.abs());
+            };
+            match &self.sparse {
+                Some(sparse) => sparse.rows[i].iter().for_each(|&(j, v)| add(v * self.x[j])),
+                None => (0..n).for_each(|j| add(self.a[i * n + j] * self.x[j])),
+            }
+            let tol = if i < node_rows {
+                CURRENT_RELTOL * largest + CURRENT_ABSTOL
+            } else {
+                voltage_tol
+            };
+            (ax - self.z[i]).abs() < tol
+        })
+    }
+
     /// Solve the system using the pre-computed LU decomposition.
+    ///
+    /// The permuted `z` is written straight into `x` and substituted in
+    /// place, so solving never allocates.
     pub fn solve(&mut self) -> Result<()> {
         let n = self.size;
 
         // Apply pivot permutation to z
-        let b = self.z.clone();
         for i in 0..n {
-            self.x[i] = b[self.pivots[i]];
+            self.x[i] = self.z[self.pivots[i]];
+        }
+
+        if let Some(sparse) = &self.sparse {
+            return sparse.solve(&mut self.x);
         }
 
         // Forward substitution (L * y = Pb)
@@ -263,8 +398,181 @@ impl MnaMatrix {
     }
 }
 
-/// Stamp all linear components into the MNA matrix.
-pub fn stamp_linear_components(circuit: &Circuit, matrix: &mut MnaMatrix, dt: f64) {
+/// Sparse storage of an MNA matrix and its LU factors.
+///
+/// Stamped entries are kept per row as `(column, value)` pairs. Clearing
+/// zeroes them in place, so once every entry has been stamped the pattern
+/// is fixed and restamping does not allocate.
+#[derive(Debug)]
+struct SparseMatrix {
+    /// Stamped entries of each row of A, in stamping order
+    rows: Vec<Vec<(usize, f64)>>,
+    /// Factored rows, in pivoted order
+    factors: Vec<SparseRow>,
+    /// Buffer for merging rows during elimination
+    scratch: Vec<(usize, f64)>,
+}
+
+/// One row of a sparse LU factorization.
+#[derive(Debug, Default)]
+struct SparseRow {
+    /// Multipliers of L left of the diagonal, by column
+    lower: Vec<(usize, f64)>,
+    /// Entries of U from the diagonal on, by column
+    upper: Vec<(usize, f64)>,
+}
+
+impl SparseMatrix {
+    fn new(size: usize) -> Self {
+        Self {
+            rows: vec![Vec::new(); size],
+            factors: (0..size).map(|_| SparseRow::default()).collect(),
+            scratch: Vec::new(),
+        }
+    }
+
+    fn clear(&mut self) {
+        for row in &mut self.rows {
+            for entry in row.iter_mut() {
+                entry.1 = 0.0;
+            }
+        }
+    }
+
+    fn get(&self, row: usize, col: usize) -> f64 {
+        self.rows[row]
+            .iter()
+            .find(|e| e.0 == col)
+            .map_or(0.0, |e| e.1)
+    }
+
+    /// The entry at (row, col), added to the pattern if it is new.
+    fn entry(&mut self, row: usize, col: usize) -> &mut f64 {
+        let row = &mut self.rows[row];
+        let i = match row.iter().position(|e| e.0 == col) {
+            Some(i) => i,
+            None => {
+                row.push((col, 0.0));
+                row.len() - 1
+            }
+        };
+        &mut row[i].1
+    }
+
+    /// Gaussian elimination with partial pivoting, visiting only the
+    /// nonzero entries of each row and the fill-in they create.
+    fn factor(&mut self, pivots: &mut [usize]) -> Result<()> {
+        let n = self.rows.len();
+        for (i, (row, factors)) in self.rows.iter().zip(&mut self.factors).enumerate() {
+            factors.lower.clear();
+            factors.upper.clear();
+            factors.upper.extend(row.iter().filter(|e| e.1 != 0.0));
+            factors.upper.sort_unstable_by_key(|e| e.0);
+            pivots[i] = i;
+        }
+
+        for k in 0..n {
+            // Earlier columns are eliminated, so a row with an entry in
+            // column k has it first
+            let mut max_val = 0.0;
+            let mut max_row = k;
+            for i in k..n {
+                if let Some(&(col, val)) = self.factors[i].upper.first() {
+                    if col == k && val.abs() > max_val {
+                        max_val = val.abs();
+                        max_row = i;
+                    }
+                }
+            }
+
+            if max_val < 1e-15 {
+                return Err(crate::error::PedalerError::SingularMatrix);
+            }
+
+            if max_row != k {
+                pivots.swap(k, max_row);
+                self.factors.swap(k, max_row);
+            }
+
+            // Eliminate
+            let (done, rest) = self.factors.split_at_mut(k + 1);
+            let pivot_row = &done[k].upper;
+            let pivot = pivot_row[0].1;
+            for row in rest {
+                let Some(&(col, val)) = row.upper.first() else {
+                    continue;
+                };
+                if col != k {
+                    continue;
+                }
+                let factor = val / pivot;
+                row.lower.push((k, factor));
+                merge_scaled(&row.upper[1..], &pivot_row[1..], factor, &mut self.scratch);
+                std::mem::swap(&mut row.upper, &mut self.scratch);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Forward and back substitution on the permuted source vector in `x`.
+    fn solve(&self, x: &mut [f64]) -> Result<()> {
+        // Forward substitution (L * y = Pb)
+        for (i, row) in self.factors.iter().enumerate() {
+            for &(j, l) in &row.lower {
+                x[i] -= l * x[j];
+            }
+        }
+
+        // Back substitution (U * x = y)
+        for (i, row) in self.factors.iter().enumerate().rev() {
+            for &(j, u) in &row.upper[1..] {
+                x[i] -= u * x[j];
+            }
+            let diag = row.upper[0].1;
+            if diag.abs() < 1e-15 {
+                return Err(crate::error::PedalerError::SingularMatrix);
+            }
+            x[i] /= diag;
+        }
+
+        Ok(())
+    }
+}
+
+/// Write `a - factor * b` into `out`, where `a` and `b` are sparse rows
+/// sorted by column.
+fn merge_scaled(a: &[(usize, f64)], b: &[(usize, f64)], factor: f64, out: &mut Vec<(usize, f64)>) {
+    out.clear();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        let col_a = a.get(i).map_or(usize::MAX, |e| e.0);
+        let col_b = b.get(j).map_or(usize::MAX, |e| e.0);
+        if col_a < col_b {
+            out.push(a[i]);
+            i += 1;
+        } else if col_b < col_a {
+            out.push((col_b, -factor * b[j].1));
+            j += 1;
+        } else {
+            out.push((col_a, a[i].1 - factor * b[j].1));
+            i += 1;
+            j += 1;
+        }
+    }
+}
+
+/// Stamp all linear components into the MNA matrix, with capacitor and
+/// inductor companion models for `method`.
+///
+/// An infinite `dt` stamps the DC limit: capacitors are open apart from
+/// their leakage, inductors are shorts, and neither carries its history.
+pub fn stamp_linear_components(
+    circuit: &Circuit,
+    matrix: &mut MnaMatrix,
+    dt: f64,
+    method: IntegrationMethod,
+) {
     for component in &circuit.components {
         match component {
             Component::Resistor(r) => {
@@ -276,25 +584,52 @@ pub fn stamp_linear_components(circuit: &Circuit, matrix: &mut MnaMatrix, dt: f6
             Component::Capacitor(c) => {
                 let n1 = circuit.node_index(c.nodes[0]);
                 let n2 = circuit.node_index(c.nodes[1]);
-                let g = c.conductance(dt);
+                let g = c.conductance(dt, method) + c.leakage_conductance();
                 matrix.stamp_conductance(n1, n2, g);
                 // Companion current source
-                let i_eq = c.current_source(dt);
-                matrix.stamp_current_source(n1, n2, i_eq);
+                if dt.is_finite() {
+                    let i_eq = c.current_source(dt, method);
+                    matrix.stamp_current_source(n1, n2, i_eq);
+                }
             }
 
             Component::Inductor(l) => {
                 let n1 = circuit.node_index(l.nodes[0]);
                 let n2 = circuit.node_index(l.nodes[1]);
                 let br = circuit.branch_index(l.branch);
-                let r_eq = l.resistance(dt);
-                let v_eq = l.voltage_source(dt);
-
-                // Stamp as voltage source with series resistance
-                matrix.stamp_voltage_source(n1, n2, br, v_eq);
+                let r_eq = l.resistance(dt, method);
+                let v_eq = if dt.is_finite() {
+                    l.voltage_source(dt, method)
+                } else {
+                    0.0
+                };
+
+                // Stamp as voltage source with series resistance; either
+                // rule gives v - r_eq * i = -v_eq
+                matrix.stamp_voltage_source(n1, n2, br, -v_eq);
                 matrix.add(br, br, -r_eq);
             }
 
+            Component::Transformer(t) => {
+                let br = t.branches.map(|b| circuit.branch_index(b));
+                let r_eq = t.resistances(dt, method);
+                let v_eq = if dt.is_finite() {
+                    t.voltage_sources(dt, method)
+                } else {
+                    [0.0; 2]
+                };
+
+                // Each winding as the inductor above, with the other
+                // winding's current coupled into its branch row
+                for j in 0..2 {
+                    let n1 = circuit.node_index(t.nodes[2 * j]);
+                    let n2 = circuit.node_index(t.nodes[2 * j + 1]);
+                    matrix.stamp_voltage_source(n1, n2, br[j], -v_eq[j]);
+                    matrix.add(br[j], br[0], -r_eq[j][0]);
+                    matrix.add(br[j], br[1], -r_eq[j][1]);
+                }
+            }
+
             Component::VoltageSource(v) => {
                 let n1 = circuit.node_index(v.nodes[0]);
                 let n2 = circuit.node_index(v.nodes[1]);
@@ -308,11 +643,112 @@ pub fn stamp_linear_components(circuit: &Circuit, matrix: &mut MnaMatrix, dt: f6
                 matrix.stamp_current_source(n1, n2, i.current());
             }
 
+            Component::Noise(n) => {
+                let n1 = circuit.node_index(n.nodes[0]);
+                let n2 = circuit.node_index(n.nodes[1]);
+                matrix.stamp_current_source(n1, n2, n.current());
+            }
+
+            Component::Ammeter(a) => {
+                // Zero-volt source: its branch current is the probed current
+                let n1 = circuit.node_index(a.nodes[0]);
+                let n2 = circuit.node_index(a.nodes[1]);
+                let br = circuit.branch_index(a.branch);
+                matrix.stamp_voltage_source(n1, n2, br, 0.0);
+            }
+
             Component::OpAmp(op) => {
                 let n_out = circuit.node_index(op.output());
                 let n_pos = circuit.node_index(op.input_pos());
                 let n_neg = circuit.node_index(op.input_neg());
 
+                // The open-loop output E = clamp(A * (V+ - V-)) is linearized
+                // around the last iterate as E = a * (V+ - V-) + e0, where a
+                // is A between the rails and slew limits and zero on them
+                let v_diff = matrix.voltage(n_pos) - matrix.voltage(n_neg);
+
+                if let Some(pole) = op.pole {
+                    // Dominant pole: the state Vc follows A * (V+ - V-)
+                    // through its row, (g + 1/A) * Vc - (V+ - V-) = history,
+                    // and E = clamp(Vc) is linearized as E = s * Vc + e0
+                    let pl = circuit.branch_index(pole);
+                    let (g, history) = op.pole_companion(dt, method);
+                    matrix.add(pl, pl, g + 1.0 / op.params.gain);
+                    if let Some(p) = n_pos {
+                        matrix.add(pl, p, -1.0);
+                    }
+                    if let Some(n) = n_neg {
+                        matrix.add(pl, n, 1.0);
+                    }
+                    matrix.add_source(pl, history);
+
+                    let v_c = matrix.x[pl];
+                    let (s, v_open) = op.linearize_clamp(v_c, matrix.voltage(n_out), dt);
+                    let e0 = v_open - s * v_c;
+                    if let Some(branch) = op.branch {
+                        // Thevenin output Vout = E + Rout * I; every entry
+                        // is near 1 already
+                        let br = circuit.branch_index(branch);
+                        if let Some(out) = n_out {
+                            matrix.add(out, br, 1.0);
+                            matrix.add(br, out, -1.0);
+                        }
+                        matrix.add(br, pl, s);
+                        matrix.add(br, br, op.params.r_out);
+                        matrix.add_source(br, -e0);
+                    } else if let Some(out) = n_out {
+                        // Norton output: E / Rout into the output, across Rout
+                        let g_out = op.output_conductance();
+                        matrix.add(out, pl, -s * g_out);
+                        matrix.add(out, out, g_out);
+                        matrix.stamp_current_source(None, n_out, e0 * g_out);
+                    }
+                    matrix.stamp_conductance(n_pos, n_neg, op.input_conductance());
+                    continue;
+                }
+
+                let (a, v_open) = op.linearize(v_diff, matrix.voltage(n_out), dt);
+                let e0 = v_open - a * v_diff;
+
+                if let Some(branch) = op.branch {
+                    // The output current is a free unknown that enters the
+                    // output node
+                    let br = circuit.branch_index(branch);
+                    if let Some(out) = n_out {
+                        matrix.add(out, br, 1.0);
+                    }
+                    if op.params.model == OpAmpModel::Nullor {
+                        // Its row enforces V+ - V- = 0
+                        if let Some(p) = n_pos {
+                            matrix.add(br, p, 1.0);
+                        }
+                        if let Some(n) = n_neg {
+                            matrix.add(br, n, -1.0);
+                        }
+                        continue;
+                    }
+
+                    // High gain: Thevenin output Vout = E + Rout * I. Between
+                    // the rails the row is divided by A. This is the same
+                    // circuit as the VCCS below, but every entry stays near 1
+                    // instead of gm = A / Rout, which wrecks the pivots for
+                    // A = 1e9.
+                    let scale = if a > 0.0 { 1.0 / a } else { 1.0 };
+                    if let Some(p) = n_pos {
+                        matrix.add(br, p, a * scale);
+                    }
+                    if let Some(n) = n_neg {
+                        matrix.add(br, n, -a * scale);
+                    }
+                    if let Some(out) = n_out {
+                        matrix.add(br, out, -scale);
+                    }
+                    matrix.add(br, br, op.params.r_out * scale);
+                    matrix.add_source(br, -e0 * scale);
+                    matrix.stamp_conductance(n_pos, n_neg, op.input_conductance());
+                    continue;
+                }
+
                 // Model op-amp as VCCS + output resistance
                 // This is more numerically stable than VCVS for high gains
                 //
@@ -323,11 +759,13 @@ pub fn stamp_linear_components(circuit: &Circuit, matrix: &mut MnaMatrix, dt: f6
                 // At DC: Vout = I * Rout = gm * Rout * (V+ - V-) = A * (V+ - V-)
                 // where A = gm * Rout, so gm = A / Rout
 
-                let gm = op.transconductance(); // = gain / r_out
                 let g_out = op.output_conductance(); // = 1 / r_out
+                let gm = a * g_out; // = gain / r_out between the rails
 
-                // Stamp VCCS: current flows from output to ground
-                matrix.stamp_vccs(n_out, None, n_pos, n_neg, gm);
+                // Stamp VCCS: current is driven from ground into the output,
+                // so that Vout = E across Rout
+                matrix.stamp_vccs(None, n_out, n_pos, n_neg, gm);
+                matrix.stamp_current_source(None, n_out, e0 * g_out);
 
                 // Stamp output resistance to ground
                 if let Some(out) = n_out {
@@ -353,11 +791,142 @@ pub fn stamp_linear_components(circuit: &Circuit, matrix: &mut MnaMatrix, dt: f6
             Component::Switch(s) => {
                 let n1 = circuit.node_index(s.nodes[0]);
                 let n2 = circuit.node_index(s.nodes[1]);
-                matrix.stamp_conductance(n1, n2, s.conductance());
+                match s.poles() {
+                    SwitchPoles::Spst => matrix.stamp_conductance(n1, n2, s.conductance()),
+                    SwitchPoles::Spdt => {
+                        // Common to each throw, one contact closed
+                        let n3 = circuit.node_index(s.nodes[2]);
+                        let [g1, g2] = s.throw_conductances();
+                        matrix.stamp_conductance(n1, n2, g1);
+                        matrix.stamp_conductance(n1, n3, g2);
+                    }
+                }
+            }
+
+            Component::Hysteretic(h) => {
+                let n1 = circuit.node_index(h.nodes[0]);
+                let n2 = circuit.node_index(h.nodes[1]);
+                matrix.stamp_conductance(n1, n2, h.conductance());
+            }
+
+            Component::NonlinearResistor(r) => {
+                let n1 = circuit.node_index(r.nodes[0]);
+                let n2 = circuit.node_index(r.nodes[1]);
+                matrix.stamp_conductance(n1, n2, r.conductance());
             }
 
             // Nonlinear components handled separately
-            Component::Diode(_) | Component::Bjt(_) => {}
+            Component::Diode(_)
+            | Component::Bjt(_)
+            | Component::Jfet(_)
+            | Component::Mosfet(_)
+            | Component::Triode(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl;
+
+    #[test]
+    fn test_sparse_matches_dense_on_ladder() {
+        // 40 RC sections with an inductor in every fifth, driven by a source
+        // whose branch row has a zero diagonal and needs pivoting
+        let mut src = String::from(".input in\n.output n40\nV_IN in 0 AC 1\nR0 in n0 100\n");
+        for i in 1..=40 {
+            if i % 5 == 0 {
+                src += &format!("L{i} n{} n{i} 10m\n", i - 1);
+            } else {
+                src += &format!("R{i} n{} n{i} 1k\n", i - 1);
+            }
+            src += &format!("C{i} n{i} 0 10n\nRG{i} n{i} 0 100k\n");
+        }
+        let circuit = Circuit::from_ast(dsl::parse(&src).unwrap()).unwrap();
+        let size = circuit.matrix_size();
+        assert!(MnaMatrix::new(size).is_sparse());
+
+        let mut dense = MnaMatrix::dense(size);
+        let mut sparse = MnaMatrix::sparse(size);
+        for matrix in [&mut dense, &mut sparse] {
+            // Twice, so the sparse pattern is reused after clearing
+            for _ in 0..2 {
+                matrix.clear();
+                stamp_linear_components(
+                    &circuit,
+                    matrix,
+                    1.0 / 48000.0,
+                    IntegrationMethod::default(),
+                );
+                matrix.factor().unwrap();
+                matrix.solve().unwrap();
+            }
+        }
+
+        for (i, (d, s)) in dense.x.iter().zip(&sparse.x).enumerate() {
+            assert!((d - s).abs() < 1e-9, "x[{}]: {} vs {}", i, d, s);
+        }
+        assert!(dense.x.iter().any(|v| v.abs() > 1e-3));
+        assert_eq!(sparse.get(0, 0), dense.get(0, 0));
+        assert!((sparse.min_pivot() / dense.min_pivot() - 1.0).abs() < 1e-6);
+    }
+
+    /// Solve `a·x = z` by Gaussian elimination on augmented rows.
+    fn reference_solve(a: &[Vec<f64>], z: &[f64]) -> Vec<f64> {
+        let n = z.len();
+        let mut rows: Vec<Vec<f64>> = a
+            .iter()
+            .zip(z)
+            .map(|(row, &b)| row.iter().copied().chain([b]).collect())
+            .collect();
+        for k in 0..n {
+            let pivot = (k..n)
+                .max_by(|&i, &j| rows[i][k].abs().total_cmp(&rows[j][k].abs()))
+                .unwrap();
+            rows.swap(k, pivot);
+            let pivot_row = rows[k].clone();
+            for (i, row) in rows.iter_mut().enumerate() {
+                if i != k {
+                    let factor = row[k] / pivot_row[k];
+                    for (x, p) in row[k..].iter_mut().zip(&pivot_row[k..]) {
+                        *x -= factor * p;
+                    }
+                }
+            }
+        }
+        (0..n).map(|i| rows[i][n] / rows[i][i]).collect()
+    }
+
+    #[test]
+    fn test_solve_matches_reference() {
+        // Two nodes and a voltage source branch: the zero diagonal of the
+        // branch row forces a row swap
+        let a = vec![
+            vec![1.5e-3, -1e-3, 1.0],
+            vec![-1e-3, 1.1e-3, 0.0],
+            vec![1.0, 0.0, 0.0],
+        ];
+        for mut matrix in [MnaMatrix::dense(3), MnaMatrix::sparse(3)] {
+            for (i, row) in a.iter().enumerate() {
+                for (j, &value) in row.iter().enumerate() {
+                    if value != 0.0 {
+                        matrix.set(i, j, value);
+                    }
+                }
+            }
+            matrix.factor().unwrap();
+
+            // Solving again with a new source vector reuses the factors and
+            // must not see the previous solution
+            for z in [[0.0, 2e-3, 9.0], [1e-3, 0.0, -4.5]] {
+                matrix.z.copy_from_slice(&z);
+                matrix.solve().unwrap();
+                let expected = reference_solve(&a, &z);
+                for (x, e) in matrix.x.iter().zip(&expected) {
+                    assert!((x - e).abs() < 1e-9 * e.abs().max(1.0), "{} vs {}", x, e);
+                }
+            }
         }
     }
 }
@@ -24,17 +24,31 @@
 //! - i is the sum of current sources into each node
 //! - e is the vector of voltage source values
 
+mod ac;
+mod dc_blocker;
 mod mna;
 mod newton;
+mod oversample;
 mod simulator;
 
-pub use mna::MnaMatrix;
-pub use newton::NewtonRaphson;
-pub use simulator::{Simulator, SimulatorConfig};
+pub use ac::ac_analysis;
+pub use dc_blocker::DC_BLOCK_CUTOFF;
+pub(crate) use mna::stamp_linear_components;
+pub use mna::{MnaMatrix, SPARSE_THRESHOLD};
+pub use newton::{Fallback, InitialGuess, NewtonRaphson};
+pub use simulator::{ParamInfo, ParamKind, Simulator, SimulatorConfig, SolverStats};
 
 /// Default convergence tolerance for Newton-Raphson iteration.
 pub const DEFAULT_TOLERANCE: f64 = 1e-4;
 
+/// Absolute tolerance on the KCL error at a node when checking that a
+/// Newton-Raphson solution balances, in amperes (SPICE's `abstol`).
+pub const CURRENT_ABSTOL: f64 = 1e-12;
+
+/// Tolerance on the KCL error at a node relative to the largest current
+/// into it (SPICE's `reltol`).
+pub const CURRENT_RELTOL: f64 = 1e-3;
+
 /// Default maximum Newton-Raphson iterations per time step.
 pub const DEFAULT_MAX_ITERATIONS: usize = 50;
 
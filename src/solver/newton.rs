@@ -1,10 +1,47 @@
 //! Newton-Raphson iteration for nonlinear components.
 
-use crate::circuit::Circuit;
-use crate::components::Component;
-use crate::error::{PedalerError, Result};
 use super::mna::MnaMatrix;
-use super::{DEFAULT_TOLERANCE, DEFAULT_MAX_ITERATIONS};
+use super::{DEFAULT_MAX_ITERATIONS, DEFAULT_TOLERANCE};
+use crate::circuit::{Circuit, NodeId};
+use crate::components::{Component, IntegrationMethod};
+use crate::error::{PedalerError, Result};
+
+/// Conductance to ground on every node at the first rung of the Gmin ladder.
+const GMIN_START: f64 = 1e-2;
+
+/// Factor the Gmin conductance shrinks by at each rung.
+const GMIN_STEP: f64 = 10.0;
+
+/// Conductance below which the ladder ends with a solve without Gmin.
+const GMIN_END: f64 = 1e-12;
+
+/// Steps source stepping ramps the independent sources up in.
+const SOURCE_STEPS: usize = 10;
+
+/// Starting point for the Newton-Raphson iteration of each sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InitialGuess {
+    /// Start from the previous sample's solution
+    #[default]
+    PreviousSample,
+    /// Start from all node voltages and branch currents at zero
+    Zero,
+    /// Solve the DC equations for the present inputs once, with the
+    /// nonlinear devices linearized at the previous sample's solution, and
+    /// start from that, falling back to the previous sample if the solve
+    /// fails. The solve costs an iteration of its own, so this pays off
+    /// after abrupt changes rather than on every sample.
+    DcOperatingPoint,
+}
+
+/// Convergence aid a solve fell back to after plain iteration failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fallback {
+    /// Gmin stepping converged
+    Gmin,
+    /// Gmin stepping failed and source stepping converged
+    Source,
+}
 
 /// Newton-Raphson solver for nonlinear circuits.
 pub struct NewtonRaphson {
@@ -12,8 +49,34 @@ pub struct NewtonRaphson {
     pub max_iterations: usize,
     /// Convergence tolerance
     pub tolerance: f64,
+    /// Initial guess strategy
+    pub initial_guess: InitialGuess,
+    /// Most linear solves per call to [`NewtonRaphson::solve`], if limited
+    pub time_budget: Option<usize>,
+    /// Retry with Gmin stepping when plain iteration fails to converge
+    pub gmin_stepping: bool,
+    /// Retry with source stepping when Gmin stepping fails too
+    pub source_stepping: bool,
+    /// Companion model rule for capacitors and inductors
+    pub integration: IntegrationMethod,
+    /// Use a DC operating point guess for the next solve only
+    dc_guess_pending: bool,
+    /// Linear solves left in the present call
+    work_left: usize,
+    /// Whether the last solve ran out of budget
+    budget_exceeded: bool,
+    /// Largest update of the last solve's final iteration
+    residual: f64,
+    /// Largest imbalance of the equations at the last solve's solution
+    kcl_residual: f64,
+    /// Convergence aid the last solve needed, if any
+    fallback: Option<Fallback>,
     /// Previous solution for convergence check
     x_prev: Vec<f64>,
+    /// Solution to fall back to if a DC operating point guess fails
+    x_saved: Vec<f64>,
+    /// Iterate with the smallest update so far, returned if the budget runs out
+    x_best: Vec<f64>,
 }
 
 impl Default for NewtonRaphson {
@@ -28,7 +91,20 @@ impl NewtonRaphson {
         Self {
             max_iterations: DEFAULT_MAX_ITERATIONS,
             tolerance: DEFAULT_TOLERANCE,
+            initial_guess: InitialGuess::default(),
+            time_budget: None,
+            gmin_stepping: true,
+            source_stepping: true,
+            integration: IntegrationMethod::default(),
+            dc_guess_pending: false,
+            work_left: usize::MAX,
+            budget_exceeded: false,
+            residual: 0.0,
+            kcl_residual: 0.0,
+            fallback: None,
             x_prev: Vec::new(),
+            x_saved: Vec::new(),
+            x_best: Vec::new(),
         }
     }
 
@@ -36,8 +112,7 @@ impl NewtonRaphson {
     pub fn with_max_iterations(max_iterations: usize) -> Self {
         Self {
             max_iterations,
-            tolerance: DEFAULT_TOLERANCE,
-            x_prev: Vec::new(),
+            ..Self::new()
         }
     }
 
@@ -46,41 +121,275 @@ impl NewtonRaphson {
         Self {
             max_iterations,
             tolerance,
-            x_prev: Vec::new(),
+            ..Self::new()
         }
     }
 
+    /// Set the initial guess strategy.
+    pub fn with_initial_guess(mut self, initial_guess: InitialGuess) -> Self {
+        self.initial_guess = initial_guess;
+        self
+    }
+
+    /// Limit the linear solves of each call to [`NewtonRaphson::solve`].
+    pub fn with_time_budget(mut self, time_budget: Option<usize>) -> Self {
+        self.time_budget = time_budget;
+        self
+    }
+
+    /// Enable or disable the Gmin stepping fallback.
+    pub fn with_gmin_stepping(mut self, gmin_stepping: bool) -> Self {
+        self.gmin_stepping = gmin_stepping;
+        self
+    }
+
+    /// Enable or disable the source stepping fallback.
+    pub fn with_source_stepping(mut self, source_stepping: bool) -> Self {
+        self.source_stepping = source_stepping;
+        self
+    }
+
+    /// Set the rule the capacitor and inductor companion models integrate
+    /// with.
+    pub fn with_integration_method(mut self, integration: IntegrationMethod) -> Self {
+        self.integration = integration;
+        self
+    }
+
+    /// Whether the last solve ran out of budget and returned its best
+    /// iterate instead of a converged solution.
+    pub fn budget_exceeded(&self) -> bool {
+        self.budget_exceeded
+    }
+
+    /// Largest change of any unknown in the last solve's final iteration,
+    /// in volts or amperes: how close to the tolerance it stopped. Zero for
+    /// a linear circuit.
+    pub fn residual(&self) -> f64 {
+        self.residual
+    }
+
+    /// Largest imbalance of the circuit equations at the last solve's
+    /// solution, with the nonlinear devices restamped there: the KCL error
+    /// in amperes at a node, or the error in volts across a source. Zero
+    /// for a linear circuit.
+    pub fn kcl_residual(&self) -> f64 {
+        self.kcl_residual
+    }
+
+    /// Convergence aid the last solve fell back to, if plain iteration
+    /// failed.
+    pub fn fallback(&self) -> Option<Fallback> {
+        self.fallback
+    }
+
+    /// Use a DC operating point guess for the next solve, whatever the
+    /// configured strategy. Useful after an abrupt change to the circuit,
+    /// when the previous sample is no longer a good starting point.
+    pub fn request_dc_guess(&mut self) {
+        self.dc_guess_pending = true;
+    }
+
+    /// Preallocate working storage for a system of the given size, so that
+    /// the first call to [`NewtonRaphson::solve`] does not allocate.
+    pub fn prepare(&mut self, size: usize) {
+        self.x_prev.resize(size, 0.0);
+        self.x_saved.resize(size, 0.0);
+        self.x_best.resize(size, 0.0);
+    }
+
     /// Solve the nonlinear circuit using Newton-Raphson iteration.
     ///
-    /// Returns the number of iterations used.
-    pub fn solve(&mut self, circuit: &Circuit, matrix: &mut MnaMatrix, dt: f64) -> Result<usize> {
-        // Check if there are any nonlinear components
-        let has_nonlinear = circuit.components.iter().any(|c| c.is_nonlinear());
+    /// Returns the number of iterations used, counting the solve of a DC
+    /// operating point guess and the iterations of any attempts that failed
+    /// before a fallback converged. With a time budget they all count against it; once
+    /// it is spent, the iterate with the smallest update so far is taken as
+    /// the solution and [`budget_exceeded`](Self::budget_exceeded) is set.
+    ///
+    /// If the iteration fails to converge, it is retried with Gmin stepping:
+    /// a conductance to ground on every node, starting large enough to make
+    /// the circuit nearly linear and shrinking a decade at a time, each
+    /// solution seeding the next. If that fails too, source stepping ramps
+    /// every independent source up from zero in [`SOURCE_STEPS`] steps, again
+    /// seeding each step with the last. Only when both fail is the original
+    /// error returned.
+    pub fn solve(
+        &mut self,
+        circuit: &mut Circuit,
+        matrix: &mut MnaMatrix,
+        dt: f64,
+    ) -> Result<usize> {
+        self.solve_with(circuit, matrix, dt, &|_| {})
+    }
+
+    /// Like [`solve`](Self::solve), with `stamp_extra` stamping elements
+    /// outside the circuit's components (such as digital effects) into each
+    /// iteration's matrix.
+    ///
+    /// For a linear circuit the matrix is solved as the caller stamped it.
+    pub fn solve_with(
+        &mut self,
+        circuit: &mut Circuit,
+        matrix: &mut MnaMatrix,
+        dt: f64,
+        stamp_extra: &dyn Fn(&mut MnaMatrix),
+    ) -> Result<usize> {
+        self.work_left = self.time_budget.unwrap_or(usize::MAX);
+        self.budget_exceeded = false;
+        self.residual = 0.0;
+        self.kcl_residual = 0.0;
+        self.fallback = None;
 
-        if !has_nonlinear {
+        // Check if there are any nonlinear components
+        if !circuit.has_nonlinear() {
             // Purely linear circuit - solve directly
             matrix.factor()?;
             matrix.solve()?;
             return Ok(1);
         }
 
-        // Initialize x_prev
-        if self.x_prev.len() != matrix.size {
-            self.x_prev = vec![0.0; matrix.size];
+        self.prepare(matrix.size);
+        self.load_initial_guess(circuit, matrix, stamp_extra);
+        let error = match self.iterate(circuit, matrix, dt, stamp_extra, 0.0) {
+            Err(e) => e,
+            Ok(_) => return Ok(self.iterations_spent()),
+        };
+        if self.gmin_stepping && self.step_gmin(circuit, matrix, dt, stamp_extra).is_ok() {
+            self.fallback = Some(Fallback::Gmin);
+            return Ok(self.iterations_spent());
+        }
+        if self.source_stepping && self.step_sources(circuit, matrix, dt, stamp_extra).is_ok() {
+            self.fallback = Some(Fallback::Source);
+            return Ok(self.iterations_spent());
+        }
+        Err(error)
+    }
+
+    /// Iterations the present solve has made so far, in every attempt.
+    fn iterations_spent(&self) -> usize {
+        self.time_budget.unwrap_or(usize::MAX) - self.work_left
+    }
+
+    /// Walk the Gmin ladder down from [`GMIN_START`], finishing with a
+    /// solve without Gmin.
+    fn step_gmin(
+        &mut self,
+        circuit: &mut Circuit,
+        matrix: &mut MnaMatrix,
+        dt: f64,
+        stamp_extra: &dyn Fn(&mut MnaMatrix),
+    ) -> Result<()> {
+        matrix.x.fill(0.0);
+        let mut gmin = GMIN_START;
+        while gmin >= GMIN_END {
+            self.iterate(circuit, matrix, dt, stamp_extra, gmin)?;
+            if self.budget_exceeded {
+                return Ok(());
+            }
+            gmin /= GMIN_STEP;
         }
+        self.iterate(circuit, matrix, dt, stamp_extra, 0.0)
+            .map(|_| ())
+    }
 
-        // Use previous solution as initial guess
+    /// Ramp every independent source from zero to its full value in
+    /// [`SOURCE_STEPS`] steps.
+    fn step_sources(
+        &mut self,
+        circuit: &mut Circuit,
+        matrix: &mut MnaMatrix,
+        dt: f64,
+        stamp_extra: &dyn Fn(&mut MnaMatrix),
+    ) -> Result<()> {
+        let full: Vec<f64> = circuit.components.iter().filter_map(source_value).collect();
+        matrix.x.fill(0.0);
+        let mut result = Ok(());
+        for step in 1..=SOURCE_STEPS {
+            scale_sources(circuit, &full, step as f64 / SOURCE_STEPS as f64);
+            if let Err(e) = self.iterate(circuit, matrix, dt, stamp_extra, 0.0) {
+                result = Err(e);
+                break;
+            }
+            if self.budget_exceeded {
+                break;
+            }
+        }
+        scale_sources(circuit, &full, 1.0);
+        result
+    }
+
+    /// Load the initial guess for the next solve into `matrix.x`.
+    fn load_initial_guess(
+        &mut self,
+        circuit: &mut Circuit,
+        matrix: &mut MnaMatrix,
+        stamp_extra: &dyn Fn(&mut MnaMatrix),
+    ) {
+        let guess = if std::mem::take(&mut self.dc_guess_pending) {
+            InitialGuess::DcOperatingPoint
+        } else {
+            self.initial_guess
+        };
+
+        match guess {
+            // `matrix.x` is never reset between samples or blocks, so the
+            // first sample of a block warm-starts from the last sample of the
+            // previous one.
+            InitialGuess::PreviousSample => {}
+            InitialGuess::Zero => matrix.x.fill(0.0),
+            // One solve of the DC equations with the devices linearized at
+            // the previous solution, counted as an iteration
+            InitialGuess::DcOperatingPoint => {
+                if self.work_left == 0 {
+                    return;
+                }
+                self.work_left -= 1;
+                self.x_saved.copy_from_slice(&matrix.x);
+                let solved = self
+                    .stamp_iteration(circuit, matrix, f64::INFINITY, stamp_extra, 0.0)
+                    .and_then(|()| matrix.factor())
+                    .and_then(|()| matrix.solve());
+                if solved.is_err() || matrix.x.iter().any(|v| !v.is_finite()) {
+                    matrix.x.copy_from_slice(&self.x_saved);
+                }
+            }
+        }
+    }
+
+    /// Iterate from the guess in `matrix.x` until the solution converges,
+    /// with a conductance of `gmin` from every node to ground.
+    ///
+    /// A solution has converged once its update is below the tolerance and
+    /// the equations restamped at it balance too (see
+    /// [`MnaMatrix::balances`]): the KCL at each node to within a current
+    /// tolerance, and each source to within the tolerance in volts. A small
+    /// update alone can come from an iteration that has stalled short of
+    /// the solution, where a steep device's linearization moves the
+    /// voltages very little for a large error in its current.
+    fn iterate(
+        &mut self,
+        circuit: &mut Circuit,
+        matrix: &mut MnaMatrix,
+        dt: f64,
+        stamp_extra: &dyn Fn(&mut MnaMatrix),
+        gmin: f64,
+    ) -> Result<usize> {
         self.x_prev.copy_from_slice(&matrix.x);
+        self.x_best.copy_from_slice(&matrix.x);
+        let mut best_diff = f64::INFINITY;
+        // Whether the matrix is already stamped at the present iterate, by
+        // the residual check of an update that was small enough
+        let mut stamped = false;
 
         for iter in 0..self.max_iterations {
-            // Clear and rebuild matrix
-            matrix.clear();
-
-            // Stamp linear components
-            super::mna::stamp_linear_components(circuit, matrix, dt);
+            if self.work_left == 0 {
+                return Ok(self.stop_at_budget(circuit, matrix, iter, best_diff));
+            }
+            self.work_left -= 1;
 
-            // Stamp linearized nonlinear components
-            self.stamp_nonlinear_components(circuit, matrix)?;
+            if !std::mem::take(&mut stamped) {
+                self.stamp_iteration(circuit, matrix, dt, stamp_extra, gmin)?;
+            }
 
             // Solve the linear system
             matrix.factor()?;
@@ -95,28 +404,50 @@ impl NewtonRaphson {
 
             // Save current solution for next iteration (before convergence return)
             self.x_prev.copy_from_slice(&matrix.x);
+            if self.time_budget.is_some() && max_diff < best_diff {
+                best_diff = max_diff;
+                self.x_best.copy_from_slice(&matrix.x);
+            }
 
             if max_diff < self.tolerance {
-                // Update operating points for next time step
-                self.update_operating_points(circuit, matrix);
-                return Ok(iter + 1);
+                // Restamp at the new solution; if it doesn't balance, the
+                // next iteration starts from this stamp
+                self.stamp_iteration(circuit, matrix, dt, stamp_extra, gmin)?;
+                stamped = true;
+                if matrix.balances(circuit.num_nodes - 1, self.tolerance) {
+                    self.residual = max_diff;
+                    self.kcl_residual = matrix.residual_norm();
+                    // Update operating points for next time step
+                    self.update_operating_points(circuit, matrix);
+                    return Ok(iter + 1);
+                }
             }
         }
 
+        if self.work_left == 0 {
+            return Ok(self.stop_at_budget(circuit, matrix, self.max_iterations, best_diff));
+        }
+        self.work_left -= 1;
+
         // Calculate final residual for error reporting
         // Do one more solve to get the actual residual
-        matrix.clear();
-        super::mna::stamp_linear_components(circuit, matrix, dt);
-        self.stamp_nonlinear_components(circuit, matrix)?;
+        if !stamped {
+            self.stamp_iteration(circuit, matrix, dt, stamp_extra, gmin)?;
+        }
         matrix.factor()?;
         matrix.solve()?;
 
-        let final_residual = self.residual(matrix);
+        let final_residual = self.update_norm(matrix);
+        self.residual = final_residual;
 
         // Check if this last iteration converged
         if final_residual < self.tolerance {
-            self.update_operating_points(circuit, matrix);
-            return Ok(self.max_iterations);
+            self.stamp_iteration(circuit, matrix, dt, stamp_extra, gmin)?;
+            if matrix.balances(circuit.num_nodes - 1, self.tolerance) {
+                self.kcl_residual = matrix.residual_norm();
+                self.update_operating_points(circuit, matrix);
+                return Ok(self.max_iterations);
+            }
         }
 
         Err(PedalerError::convergence_failure(
@@ -125,13 +456,45 @@ impl NewtonRaphson {
         ))
     }
 
+    /// Rebuild the matrix with every component linearized at the iterate
+    /// in `matrix.x`, and a conductance of `gmin` from every node to ground.
+    fn stamp_iteration(
+        &self,
+        circuit: &mut Circuit,
+        matrix: &mut MnaMatrix,
+        dt: f64,
+        stamp_extra: &dyn Fn(&mut MnaMatrix),
+        gmin: f64,
+    ) -> Result<()> {
+        matrix.clear();
+        super::mna::stamp_linear_components(circuit, matrix, dt, self.integration);
+        stamp_extra(matrix);
+        self.stamp_nonlinear_components(circuit, matrix)?;
+        stamp_gmin(circuit, matrix, gmin);
+        Ok(())
+    }
+
     /// Stamp linearized nonlinear components into the matrix.
-    fn stamp_nonlinear_components(&self, circuit: &Circuit, matrix: &mut MnaMatrix) -> Result<()> {
-        for component in &circuit.components {
+    ///
+    /// Diodes are linearized at the previous iterate's voltage, limited to a
+    /// step from their last operating point, which then moves there.
+    fn stamp_nonlinear_components(
+        &self,
+        circuit: &mut Circuit,
+        matrix: &mut MnaMatrix,
+    ) -> Result<()> {
+        let node_index = |node: NodeId| {
+            if node.is_ground() {
+                None
+            } else {
+                Some(node.0 - 1)
+            }
+        };
+        for component in &mut circuit.components {
             match component {
                 Component::Diode(d) => {
-                    let n_anode = circuit.node_index(d.nodes[0]);
-                    let n_cathode = circuit.node_index(d.nodes[1]);
+                    let n_anode = node_index(d.nodes[0]);
+                    let n_cathode = node_index(d.nodes[1]);
 
                     // Get voltage across diode from previous iteration
                     let v_a = matrix.voltage(n_anode);
@@ -140,6 +503,7 @@ impl NewtonRaphson {
 
                     // Limit voltage step
                     let v_op = d.limit_voltage_step(d.v_op, v_d);
+                    d.update_operating_point(v_op);
 
                     // Get linearized model
                     let (g, i_eq) = d.linearize(v_op);
@@ -150,9 +514,9 @@ impl NewtonRaphson {
                 }
 
                 Component::Bjt(q) => {
-                    let n_c = circuit.node_index(q.collector());
-                    let n_b = circuit.node_index(q.base());
-                    let n_e = circuit.node_index(q.emitter());
+                    let n_c = node_index(q.collector());
+                    let n_b = node_index(q.base());
+                    let n_e = node_index(q.emitter());
 
                     // Get voltages
                     let v_c = matrix.voltage(n_c);
@@ -176,16 +540,68 @@ impl NewtonRaphson {
                     // Stamp transconductance (VCCS)
                     matrix.stamp_vccs(n_c, n_e, n_b, n_e, gm);
 
-                    // DC operating point currents
-                    let i_c = q.i_c(v_be, v_bc);
-                    let i_b = q.i_b(v_be, v_bc);
+                    // Companion current sources to match the operating point
+                    let (i_c_eq, i_b_eq) = q.companion_currents(v_be, v_bc);
+                    matrix.stamp_current_source(n_c, n_e, i_c_eq);
+                    matrix.stamp_current_source(n_b, n_e, i_b_eq);
+                }
+
+                Component::Jfet(j) => {
+                    let n_d = node_index(j.drain());
+                    let n_g = node_index(j.gate());
+                    let n_s = node_index(j.source());
 
-                    // Companion current sources to match DC point
-                    let i_c_eq = i_c - gm * v_be - go * (v_c - v_e);
-                    let i_b_eq = i_b - gpi * v_be - gmu * v_bc;
+                    let v_s = matrix.voltage(n_s);
+                    let v_gs = matrix.voltage(n_g) - v_s;
+                    let v_ds = matrix.voltage(n_d) - v_s;
 
-                    matrix.stamp_current_source(n_c, n_e, i_c_eq);
-                    matrix.stamp_current_source(n_b, None, -i_b_eq);
+                    // Id = gm * Vgs + gds * Vds + I_eq, flowing drain to source
+                    let (gm, gds) = j.linearize(v_gs, v_ds);
+                    let i_eq = j.i_d(v_gs, v_ds) - gm * v_gs - gds * v_ds;
+
+                    matrix.stamp_conductance(n_d, n_s, gds);
+                    matrix.stamp_vccs(n_d, n_s, n_g, n_s, gm);
+                    matrix.stamp_current_source(n_d, n_s, i_eq);
+                }
+
+                Component::Mosfet(m) => {
+                    let n_d = node_index(m.drain());
+                    let n_g = node_index(m.gate());
+                    let n_s = node_index(m.source());
+
+                    let v_s = matrix.voltage(n_s);
+                    let v_gs = matrix.voltage(n_g) - v_s;
+                    let v_ds = matrix.voltage(n_d) - v_s;
+
+                    // Same companion model as the JFET
+                    let (gm, gds) = m.linearize(v_gs, v_ds);
+                    let i_eq = m.i_d(v_gs, v_ds) - gm * v_gs - gds * v_ds;
+
+                    matrix.stamp_conductance(n_d, n_s, gds);
+                    matrix.stamp_vccs(n_d, n_s, n_g, n_s, gm);
+                    matrix.stamp_current_source(n_d, n_s, i_eq);
+                }
+
+                Component::Triode(t) => {
+                    let n_p = node_index(t.plate());
+                    let n_g = node_index(t.grid());
+                    let n_k = node_index(t.cathode());
+
+                    let v_k = matrix.voltage(n_k);
+                    let v_gk = matrix.voltage(n_g) - v_k;
+                    let v_pk = matrix.voltage(n_p) - v_k;
+
+                    // Plate current as for a JFET, Ip = gm * Vgk + gp * Vpk + I_eq,
+                    // and the grid current as a diode from grid to cathode
+                    let (gm, gp, gg) = t.linearize(v_gk, v_pk);
+                    let i_p_eq = t.i_p(v_gk, v_pk) - gm * v_gk - gp * v_pk;
+                    let i_g_eq = t.i_g(v_gk) - gg * v_gk;
+
+                    matrix.stamp_conductance(n_p, n_k, gp);
+                    matrix.stamp_vccs(n_p, n_k, n_g, n_k, gm);
+                    matrix.stamp_current_source(n_p, n_k, i_p_eq);
+                    matrix.stamp_conductance(n_g, n_k, gg);
+                    matrix.stamp_current_source(n_g, n_k, i_g_eq);
                 }
 
                 _ => {} // Linear components already handled
@@ -195,17 +611,64 @@ impl NewtonRaphson {
         Ok(())
     }
 
-    /// Update operating points after successful convergence.
-    fn update_operating_points(&self, circuit: &Circuit, matrix: &MnaMatrix) {
-        // Note: We can't mutate circuit components here since we only have &Circuit
-        // In practice, the operating points would be stored in a separate state struct
-        // or the circuit would need to use interior mutability (RefCell)
-        let _ = circuit;
-        let _ = matrix;
+    /// Give up on converging once the time budget is spent, leaving the best
+    /// iterate, whose update was `update`, in `matrix.x`. Returns
+    /// `iterations`.
+    fn stop_at_budget(
+        &mut self,
+        circuit: &mut Circuit,
+        matrix: &mut MnaMatrix,
+        iterations: usize,
+        update: f64,
+    ) -> usize {
+        self.budget_exceeded = true;
+        self.residual = update;
+        matrix.x.copy_from_slice(&self.x_best);
+        self.update_operating_points(circuit, matrix);
+        iterations
+    }
+
+    /// Store the solution's junction voltages in the diodes and transistors,
+    /// so the next sample limits its voltage steps from there.
+    fn update_operating_points(&self, circuit: &mut Circuit, matrix: &MnaMatrix) {
+        let voltage = |node: NodeId| {
+            if node.is_ground() {
+                0.0
+            } else {
+                matrix.x[node.0 - 1]
+            }
+        };
+        for component in &mut circuit.components {
+            match component {
+                Component::Diode(d) => {
+                    d.update_operating_point(voltage(d.nodes[0]) - voltage(d.nodes[1]));
+                }
+                Component::Bjt(q) => {
+                    let v_b = voltage(q.base());
+                    q.update_operating_point(
+                        v_b - voltage(q.emitter()),
+                        v_b - voltage(q.collector()),
+                    );
+                }
+                Component::Jfet(j) => {
+                    let v_s = voltage(j.source());
+                    j.update_operating_point(voltage(j.gate()) - v_s, voltage(j.drain()) - v_s);
+                }
+                Component::Mosfet(m) => {
+                    let v_s = voltage(m.source());
+                    m.update_operating_point(voltage(m.gate()) - v_s, voltage(m.drain()) - v_s);
+                }
+                Component::Triode(t) => {
+                    let v_k = voltage(t.cathode());
+                    t.update_operating_point(voltage(t.grid()) - v_k, voltage(t.plate()) - v_k);
+                }
+                _ => {}
+            }
+        }
     }
 
-    /// Calculate the residual for error reporting.
-    fn residual(&self, matrix: &MnaMatrix) -> f64 {
+    /// Largest change of any unknown since the previous iterate.
+    fn update_norm(&self, matrix: &MnaMatrix) -> f64 {
         let mut max_diff = 0.0f64;
         for i in 0..matrix.size {
             let diff = (matrix.x[i] - self.x_prev[i]).abs();
@@ -214,3 +677,385 @@ impl NewtonRaphson {
         max_diff
     }
 }
+
+/// Value of an independent voltage or current source.
+fn source_value(component: &Component) -> Option<f64> {
+    match component {
+        Component::VoltageSource(v) => Some(v.voltage()),
+        Component::CurrentSource(i) => Some(i.current()),
+        _ => None,
+    }
+}
+
+/// Set every independent source to `scale` times its value in `full`, which
+/// lists them in circuit order.
+fn scale_sources(circuit: &mut Circuit, full: &[f64], scale: f64) {
+    let sources = circuit
+        .components
+        .iter_mut()
+        .filter(|c| source_value(c).is_some());
+    for (component, &value) in sources.zip(full) {
+        match component {
+            Component::VoltageSource(v) => v.set_value(scale * value),
+            Component::CurrentSource(i) => i.set_value(scale * value),
+            _ => {}
+        }
+    }
+}
+
+/// Stamp a conductance of `gmin` from every node to ground.
+fn stamp_gmin(circuit: &Circuit, matrix: &mut MnaMatrix, gmin: f64) {
+    if gmin > 0.0 {
+        for node in 0..circuit.num_nodes - 1 {
+            matrix.stamp_conductance(Some(node), None, gmin);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl;
+
+    const CLIPPER: &str = ".input in\n.output out\nVIN in 0 AC\nR1 in out 1k\nD1 out 0\nD2 0 out\n";
+
+    /// A common-emitter stage biased from a 9V supply, driven through a
+    /// large resistor so that a jump of the input moves the bias only a
+    /// little.
+    const BJT_STAGE: &str = ".input in\n.output out\nVIN in 0 AC\nVCC vcc 0 DC 9\n\
+                             R1 vcc b 100k\nR2 b 0 22k\nRIN in b 100k\n\
+                             Q1 out b e\nRC vcc out 4.7k\nRE e 0 1k\n";
+
+    /// Iterations needed for the sample after the input of `src` jumps from
+    /// `-volts` to `+volts`.
+    fn iterations_after_jump(src: &str, volts: f64, initial_guess: InitialGuess) -> usize {
+        let mut circuit = Circuit::from_ast(dsl::parse(src).unwrap()).unwrap();
+        let source = circuit.input_source_idx.unwrap();
+        let mut matrix = MnaMatrix::new(circuit.matrix_size());
+        let mut newton = NewtonRaphson::new().with_initial_guess(initial_guess);
+        let dt = 1.0 / 48000.0;
+
+        let mut step = |circuit: &mut Circuit, voltage: f64| {
+            if let Component::VoltageSource(vs) = &mut circuit.components[source] {
+                vs.set_value(voltage);
+            }
+            newton.solve(circuit, &mut matrix, dt).unwrap()
+        };
+        step(&mut circuit, -volts);
+        step(&mut circuit, volts)
+    }
+
+    #[test]
+    fn test_with_config_sets_fields() {
+        let newton = NewtonRaphson::with_config(12, 1e-7);
+        assert_eq!(newton.max_iterations, 12);
+        assert_eq!(newton.tolerance, 1e-7);
+        assert_eq!(newton.initial_guess, InitialGuess::PreviousSample);
+        assert!(newton.x_prev.is_empty());
+
+        let defaults = NewtonRaphson::new();
+        assert_eq!(defaults.max_iterations, DEFAULT_MAX_ITERATIONS);
+        assert_eq!(defaults.tolerance, DEFAULT_TOLERANCE);
+    }
+
+    #[test]
+    fn test_time_budget_bounds_work_of_expensive_sample() {
+        let mut circuit = Circuit::from_ast(dsl::parse(CLIPPER).unwrap()).unwrap();
+        let source = circuit.input_source_idx.unwrap();
+        let mut matrix = MnaMatrix::new(circuit.matrix_size());
+        let mut newton = NewtonRaphson::new()
+            .with_initial_guess(InitialGuess::DcOperatingPoint)
+            .with_time_budget(Some(3));
+        assert!(iterations_after_jump(CLIPPER, 5.0, InitialGuess::Zero) > 3);
+
+        for voltage in [-5.0, 5.0] {
+            if let Component::VoltageSource(vs) = &mut circuit.components[source] {
+                vs.set_value(voltage);
+            }
+            let iterations = newton
+                .solve(&mut circuit, &mut matrix, 1.0 / 48000.0)
+                .unwrap();
+            assert!(iterations <= 3);
+            assert!(newton.budget_exceeded());
+            assert!(matrix.x.iter().all(|v| v.is_finite()));
+            assert!(matrix.node_voltage(&circuit, circuit.output_node).abs() < 5.0);
+        }
+
+        // A cheap sample fits in the budget
+        newton.initial_guess = InitialGuess::PreviousSample;
+        newton
+            .solve(&mut circuit, &mut matrix, 1.0 / 48000.0)
+            .unwrap();
+        newton
+            .solve(&mut circuit, &mut matrix, 1.0 / 48000.0)
+            .unwrap();
+        assert!(!newton.budget_exceeded());
+    }
+
+    #[test]
+    fn test_dc_guess_converges_faster_after_jump() {
+        // From zero the transistor starts cut off, while the DC solve
+        // linearized at the last sample lands next to the new bias point
+        let zero = iterations_after_jump(BJT_STAGE, 0.5, InitialGuess::Zero);
+        let dc = iterations_after_jump(BJT_STAGE, 0.5, InitialGuess::DcOperatingPoint);
+        assert!(
+            dc < zero,
+            "{} iterations from the DC guess, {} from zero",
+            dc,
+            zero
+        );
+    }
+
+    #[test]
+    fn test_dc_guess_is_one_linear_solve() {
+        let dt = 1.0 / 48000.0;
+        let mut circuit = Circuit::from_ast(dsl::parse(CLIPPER).unwrap()).unwrap();
+        let source = circuit.input_source_idx.unwrap();
+        let set_input = |circuit: &mut Circuit, voltage: f64| {
+            if let Component::VoltageSource(vs) = &mut circuit.components[source] {
+                vs.set_value(voltage);
+            }
+        };
+        let mut matrix = MnaMatrix::new(circuit.matrix_size());
+        let mut newton = NewtonRaphson::new();
+        set_input(&mut circuit, -5.0);
+        newton.solve(&mut circuit, &mut matrix, dt).unwrap();
+        set_input(&mut circuit, 5.0);
+
+        // The same solve by hand: the DC equations linearized at the
+        // previous sample, then the sample starting from their solution
+        let mut by_hand = circuit.clone();
+        let mut by_hand_matrix = MnaMatrix::new(circuit.matrix_size());
+        by_hand_matrix.x.copy_from_slice(&matrix.x);
+        let mut plain = NewtonRaphson::new();
+        plain
+            .stamp_iteration(
+                &mut by_hand,
+                &mut by_hand_matrix,
+                f64::INFINITY,
+                &|_| {},
+                0.0,
+            )
+            .unwrap();
+        by_hand_matrix.factor().unwrap();
+        by_hand_matrix.solve().unwrap();
+        let sample = plain.solve(&mut by_hand, &mut by_hand_matrix, dt).unwrap();
+
+        newton.request_dc_guess();
+        let iterations = newton.solve(&mut circuit, &mut matrix, dt).unwrap();
+        assert_eq!(iterations, 1 + sample);
+        assert_eq!(matrix.x, by_hand_matrix.x);
+    }
+
+    #[test]
+    fn test_converged_operating_points_are_stored() {
+        let src = ".input in\n.output out\nVIN in 0 AC\nR1 in out 1k\nD1 out 0\nD2 0 out\n";
+        let mut circuit = Circuit::from_ast(dsl::parse(src).unwrap()).unwrap();
+        let source = circuit.input_source_idx.unwrap();
+        let mut matrix = MnaMatrix::new(circuit.matrix_size());
+        let mut newton = NewtonRaphson::new();
+
+        for i in 0..8 {
+            if let Component::VoltageSource(vs) = &mut circuit.components[source] {
+                vs.set_value(2.0 * (i as f64 * 0.8).sin());
+            }
+            newton
+                .solve(&mut circuit, &mut matrix, 1.0 / 48000.0)
+                .unwrap();
+            let v_out = matrix.node_voltage(&circuit, circuit.output_node);
+            let v_op: Vec<f64> = circuit
+                .components
+                .iter()
+                .filter_map(|c| match c {
+                    Component::Diode(d) => Some(d.v_op),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(v_op, vec![v_out, -v_out]);
+        }
+    }
+
+    #[test]
+    fn test_small_update_with_unbalanced_kcl_is_not_converged() {
+        // A large-area diode carrying amperes: so steep that a Newton step
+        // from just above the solution moves it less than the tolerance
+        // while leaving amperes of KCL error
+        let src = ".input in\n.output out\n.model DBIG D (is=1m n=1 vf=1)\n\
+                   VIN in 0 DC 5\nR1 in out 1\nD1 out 0 DBIG\n";
+        let mut circuit = Circuit::from_ast(dsl::parse(src).unwrap()).unwrap();
+        let mut matrix = MnaMatrix::new(circuit.matrix_size());
+        let out = circuit.node_index(circuit.output_node).unwrap();
+        NewtonRaphson::with_config(50, 1e-12)
+            .solve(&mut circuit, &mut matrix, f64::INFINITY)
+            .unwrap();
+        let v_solution = matrix.x[out];
+
+        let tolerance = 0.05;
+        let diode = match &circuit.components[2] {
+            Component::Diode(d) => d.clone(),
+            other => panic!("expected the diode, got {:?}", other),
+        };
+        let imbalance = |v: f64| diode.current(v) - (5.0 - v);
+        let v0 = v_solution + 0.04;
+        let step = imbalance(v0) / (diode.conductance(v0) + 1.0);
+        assert!(step < tolerance, "step {}", step);
+        assert!(
+            imbalance(v0 - step) > 1.0,
+            "imbalance {}",
+            imbalance(v0 - step)
+        );
+
+        // Started there, the first update passes the delta test alone, but
+        // the iteration carries on until the currents balance
+        let mut newton = NewtonRaphson::with_config(50, tolerance)
+            .with_gmin_stepping(false)
+            .with_source_stepping(false);
+        matrix.x[out] += 0.04;
+        if let Component::Diode(d) = &mut circuit.components[2] {
+            d.update_operating_point(v0);
+        }
+        let iterations = newton
+            .solve(&mut circuit, &mut matrix, f64::INFINITY)
+            .unwrap();
+        assert!(iterations > 1);
+        assert!(
+            imbalance(matrix.x[out]).abs() < tolerance,
+            "imbalance {}",
+            imbalance(matrix.x[out])
+        );
+        assert!(newton.kcl_residual() < tolerance);
+    }
+
+    #[test]
+    fn test_micro_amp_kcl_error_is_not_converged() {
+        // The same stall at microamps, behind a 1M resistor: the KCL error
+        // left is tiny in amperes but as large as the current itself, so
+        // only a current tolerance relative to the node's currents sees it
+        let src = ".input in\n.output out\nVIN in 0 DC 5\nR1 in out 1M\nD1 out 0\n";
+        let mut circuit = Circuit::from_ast(dsl::parse(src).unwrap()).unwrap();
+        let mut matrix = MnaMatrix::new(circuit.matrix_size());
+        let out = circuit.node_index(circuit.output_node).unwrap();
+        NewtonRaphson::with_config(50, 1e-12)
+            .solve(&mut circuit, &mut matrix, f64::INFINITY)
+            .unwrap();
+        let v_solution = matrix.x[out];
+
+        let tolerance = 0.05;
+        let diode = match &circuit.components[2] {
+            Component::Diode(d) => d.clone(),
+            other => panic!("expected the diode, got {:?}", other),
+        };
+        let current = diode.current(v_solution);
+        let imbalance = |v: f64| diode.current(v) - (5.0 - v) / 1e6;
+        let v0 = v_solution + 0.04;
+        let step = imbalance(v0) / (diode.conductance(v0) + 1e-6);
+        assert!(step < tolerance, "step {}", step);
+        let stalled = imbalance(v0 - step);
+        assert!(
+            stalled > 0.5 * current && stalled < tolerance,
+            "imbalance {}",
+            stalled
+        );
+
+        let mut newton = NewtonRaphson::with_config(50, tolerance)
+            .with_gmin_stepping(false)
+            .with_source_stepping(false);
+        matrix.x[out] += 0.04;
+        if let Component::Diode(d) = &mut circuit.components[2] {
+            d.update_operating_point(v0);
+        }
+        let iterations = newton
+            .solve(&mut circuit, &mut matrix, f64::INFINITY)
+            .unwrap();
+        assert!(iterations > 1);
+        let error = imbalance(matrix.x[out]).abs();
+        assert!(
+            error < 1e-3 * current,
+            "imbalance {} at {} A",
+            error,
+            current
+        );
+    }
+
+    #[test]
+    fn test_gmin_stepping_recovers_stiff_diode_bridge() {
+        // Full-wave bridge fed by a floating source: nodes a and b are only
+        // held by the diodes, and plain iteration walks them into place in
+        // limited steps
+        let src = ".input in\n.output p\nVIN in 0 AC\nVS a x DC 5\nR1 x b 100\n\
+                   D1 a p\nD2 b p\nD3 0 a\nD4 0 b\nRL p 0 1M\n";
+        let circuit = Circuit::from_ast(dsl::parse(src).unwrap()).unwrap();
+        let solve = |newton: &mut NewtonRaphson| {
+            let mut circuit = circuit.clone();
+            let mut matrix = MnaMatrix::new(circuit.matrix_size());
+            newton
+                .solve(&mut circuit, &mut matrix, f64::INFINITY)
+                .map(|_| matrix.node_voltage(&circuit, circuit.output_node))
+        };
+
+        let mut plain = NewtonRaphson::with_max_iterations(10)
+            .with_gmin_stepping(false)
+            .with_source_stepping(false);
+        assert!(matches!(
+            solve(&mut plain),
+            Err(PedalerError::ConvergenceFailure { .. })
+        ));
+
+        let stepped =
+            solve(&mut NewtonRaphson::with_max_iterations(10).with_source_stepping(false)).unwrap();
+        let reference =
+            solve(&mut NewtonRaphson::with_max_iterations(500).with_gmin_stepping(false)).unwrap();
+        assert!(
+            (stepped - reference).abs() < 1e-3,
+            "{} vs {}",
+            stepped,
+            reference
+        );
+        assert!(stepped > 3.0 && stepped < 5.0, "bridge output {}", stepped);
+    }
+
+    #[test]
+    fn test_source_stepping_biases_cold_bjt_stage() {
+        // Stiff common-emitter stage: from all-zero node voltages plain
+        // iteration never settles, and gmin stepping does not recover it
+        let src =
+            ".input in\n.output out\nVIN in 0 AC\nVCC vcc 0 DC 24\nR1 vcc b 10k\nR2 b 0 10k\n\
+                   Q1 out b e\nRC vcc out 470\nRE e 0 220\n";
+        let circuit = Circuit::from_ast(dsl::parse(src).unwrap()).unwrap();
+        let solve = |newton: &mut NewtonRaphson| {
+            let mut circuit = circuit.clone();
+            let mut matrix = MnaMatrix::new(circuit.matrix_size());
+            newton
+                .solve(&mut circuit, &mut matrix, f64::INFINITY)
+                .map(|_| {
+                    let v = |name| matrix.node_voltage(&circuit, circuit.find_node(name).unwrap());
+                    (v("b"), v("out"), v("e"))
+                })
+        };
+
+        let plain = solve(
+            &mut NewtonRaphson::new()
+                .with_gmin_stepping(false)
+                .with_source_stepping(false),
+        );
+        assert!(matches!(
+            plain,
+            Err(PedalerError::ConvergenceFailure { .. })
+        ));
+        let gmin = solve(&mut NewtonRaphson::new().with_source_stepping(false));
+        assert!(matches!(gmin, Err(PedalerError::ConvergenceFailure { .. })));
+
+        let (v_b, v_out, v_e) = solve(&mut NewtonRaphson::new()).unwrap();
+        assert!(v_b - v_e > 0.6 && v_b - v_e < 0.8, "Vbe {}", v_b - v_e);
+        // The resistor currents balance at the transistor's terminals
+        let i_b = (24.0 - v_b) / 10e3 - v_b / 10e3;
+        let i_c = (24.0 - v_out) / 470.0;
+        assert!(
+            (i_b + i_c - v_e / 220.0).abs() < 1e-6,
+            "Ib {} + Ic {} vs Ie {}",
+            i_b,
+            i_c,
+            v_e / 220.0
+        );
+    }
+}
@@ -2,12 +2,22 @@
 
 use std::collections::HashMap;
 
+use crate::analysis::{deconvolve_sweep, log_sweep};
 use crate::circuit::{BranchId, Circuit, NodeId};
-use crate::components::{Component, DelayLine, FdnReverb, Lfo, LfoShape, ReverbParams};
-use crate::error::Result;
+use crate::components::{
+    Component, DelayLine, EnvelopeFollower, FdnReverb, IntegrationMethod, Lfo, LfoShape,
+    ReverbParams, Widener,
+};
+use crate::error::{PedalerError, Result};
+use crate::{NOMINAL_TEMPERATURE, ZERO_CELSIUS};
 
+use super::dc_blocker::DcBlocker;
 use super::mna::{stamp_linear_components, MnaMatrix};
-use super::{NewtonRaphson, DEFAULT_MAX_ITERATIONS, DEFAULT_TOLERANCE};
+use super::oversample::{Oversampler, MAX_OVERSAMPLE};
+use super::{
+    Fallback, InitialGuess, NewtonRaphson, DEFAULT_MAX_ITERATIONS, DEFAULT_TOLERANCE,
+    MIN_CONDUCTANCE,
+};
 
 /// Configuration for the simulator.
 #[derive(Debug, Clone)]
@@ -16,6 +26,20 @@ pub struct SimulatorConfig {
     pub max_iterations: usize,
     /// Convergence tolerance for Newton-Raphson (volts).
     pub tolerance: f64,
+    /// Starting point for each sample's Newton-Raphson iteration.
+    pub initial_guess: InitialGuess,
+    /// Most Newton-Raphson iterations per sample, if limited.
+    pub time_budget: Option<usize>,
+    /// Internal oversampling factor: 1 (off), 2, 4 or 8.
+    pub oversample: usize,
+    /// Retry failed solves by ramping the sources up from zero.
+    pub enable_source_stepping: bool,
+    /// Integration rule of the capacitor and inductor companion models.
+    pub integration: IntegrationMethod,
+    /// Ambient temperature of the diodes and BJTs (°C).
+    pub temperature_c: f64,
+    /// Highpass the outputs at 20 Hz to remove DC offset.
+    pub block_dc: bool,
 }
 
 impl Default for SimulatorConfig {
@@ -23,6 +47,13 @@ impl Default for SimulatorConfig {
         Self {
             max_iterations: DEFAULT_MAX_ITERATIONS,
             tolerance: DEFAULT_TOLERANCE,
+            initial_guess: InitialGuess::default(),
+            time_budget: None,
+            oversample: 1,
+            enable_source_stepping: true,
+            integration: IntegrationMethod::default(),
+            temperature_c: NOMINAL_TEMPERATURE - ZERO_CELSIUS,
+            block_dc: false,
         }
     }
 }
@@ -49,6 +80,222 @@ impl SimulatorConfig {
         self.tolerance = tolerance;
         self
     }
+
+    /// Set the Newton-Raphson initial guess strategy.
+    ///
+    /// The default warm start from the previous sample is best for audio-rate
+    /// signals; see [`InitialGuess`] for the alternatives.
+    pub fn with_initial_guess(mut self, initial_guess: InitialGuess) -> Self {
+        self.initial_guess = initial_guess;
+        self
+    }
+
+    /// Cap the Newton-Raphson iterations of each sample, counting the solve
+    /// of a DC operating point guess, for bounded latency in real-time hosts.
+    ///
+    /// A sample that runs out takes the best iterate so far as its solution
+    /// instead of converging, and is counted by
+    /// [`Simulator::budget_overrun_count`] and [`SolverStats::overruns`].
+    /// `None`, the default, leaves samples unlimited. Operating point solves
+    /// such as [`Simulator::dc_sweep`] are not limited either way.
+    pub fn with_time_budget(mut self, iterations: Option<usize>) -> Self {
+        self.time_budget = iterations;
+        self
+    }
+
+    /// Run the circuit at `factor` times the host sample rate.
+    ///
+    /// Clipping generates harmonics above the host Nyquist frequency, which
+    /// otherwise fold back into the audio band. Oversampled, the input is
+    /// interpolated up to the internal rate and the output lowpassed and
+    /// decimated back down, at the cost of `factor` times the work per
+    /// sample and 31 samples of latency.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `factor` is not 1, 2, 4 or 8.
+    pub fn with_oversample(mut self, factor: usize) -> Self {
+        assert!(
+            matches!(factor, 1 | 2 | 4 | 8),
+            "oversampling factor must be 1, 2, 4 or 8, got {}",
+            factor
+        );
+        self.oversample = factor;
+        self
+    }
+
+    /// Enable or disable source stepping (on by default).
+    ///
+    /// When Newton-Raphson fails to converge even with gmin stepping, the
+    /// independent sources are ramped from zero to their full values, each
+    /// partial solution seeding the next. This finds the bias point of
+    /// stiff transistor stages from a cold start.
+    pub fn with_source_stepping(mut self, enabled: bool) -> Self {
+        self.enable_source_stepping = enabled;
+        self
+    }
+
+    /// Set the integration rule of the capacitor and inductor companion
+    /// models (trapezoidal by default).
+    ///
+    /// Trapezoidal integration is more accurate, but a fast edge into a
+    /// stiff node, such as a hard switch, can leave it ringing at the
+    /// Nyquist frequency. Backward Euler damps that ringing at the cost of
+    /// some high-frequency loss.
+    pub fn with_integration_method(mut self, method: IntegrationMethod) -> Self {
+        self.integration = method;
+        self
+    }
+
+    /// Set the ambient temperature in °C (27 by default, where the device
+    /// models are specified).
+    ///
+    /// Diodes and BJTs take their thermal voltage `kT/q` and saturation
+    /// currents from it, so a hot pedal's junctions drop less voltage and a
+    /// cold one's more. JFETs, MOSFETs and op-amps don't depend on it.
+    ///
+    /// [`Simulator::with_config`] rejects a temperature that is not above
+    /// absolute zero.
+    pub fn with_temperature(mut self, celsius: f64) -> Self {
+        self.temperature_c = celsius;
+        self
+    }
+
+    /// Enable or disable the DC blocker on the outputs.
+    ///
+    /// Like a pedal's output coupling capacitor, a first-order highpass at
+    /// [`DC_BLOCK_CUTOFF`](super::DC_BLOCK_CUTOFF) removes the offset left by
+    /// asymmetric clipping or bias leakage. It filters the samples the
+    /// simulator returns; node voltages inside the circuit keep their DC.
+    pub fn with_dc_block(mut self, block_dc: bool) -> Self {
+        self.block_dc = block_dc;
+        self
+    }
+}
+
+/// Kind of a controllable parameter, which also fixes its unit and range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamKind {
+    /// Potentiometer knob position (0.0-1.0)
+    Potentiometer,
+    /// Switch state (0.0 = open, 1.0 = closed)
+    Switch,
+    /// LFO rate in Hz
+    LfoRate,
+    /// Resistor value in ohms
+    Resistance,
+    /// Capacitor value in farads
+    Capacitance,
+    /// Inductor value in henries
+    Inductance,
+}
+
+impl ParamKind {
+    /// Lowest LFO rate accepted by [`Simulator::set_parameter`], in Hz.
+    pub const LFO_RATE_MIN: f64 = 0.01;
+    /// Highest LFO rate accepted by [`Simulator::set_parameter`], in Hz.
+    pub const LFO_RATE_MAX: f64 = 50.0;
+
+    /// Get the inclusive `(min, max)` range of values for this kind.
+    ///
+    /// Component values only have to be positive, so their range is open-ended.
+    pub fn range(&self) -> (f64, f64) {
+        match self {
+            ParamKind::Potentiometer | ParamKind::Switch => (0.0, 1.0),
+            ParamKind::LfoRate => (Self::LFO_RATE_MIN, Self::LFO_RATE_MAX),
+            ParamKind::Resistance | ParamKind::Capacitance | ParamKind::Inductance => {
+                (f64::MIN_POSITIVE, f64::MAX)
+            }
+        }
+    }
+}
+
+/// A controllable parameter of a simulated circuit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamInfo {
+    /// Name of the component or LFO
+    pub name: String,
+    /// What the value controls
+    pub kind: ParamKind,
+    /// Lowest accepted value
+    pub min: f64,
+    /// Highest accepted value
+    pub max: f64,
+    /// Current value
+    pub value: f64,
+}
+
+impl ParamInfo {
+    fn new(name: &str, kind: ParamKind, value: f64) -> Self {
+        let (min, max) = kind.range();
+        Self {
+            name: name.to_string(),
+            kind,
+            min,
+            max,
+            value,
+        }
+    }
+}
+
+/// Newton-Raphson diagnostics accumulated over the samples a simulator
+/// has stepped, for tuning the tolerance and iteration limit.
+///
+/// Samples are counted at the internal rate, so with oversampling each host
+/// sample contributes `factor` of them. A linear solve counts as one
+/// iteration, and a sample's iterations include those of a DC guess and of
+/// any attempts that failed before a fallback converged.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SolverStats {
+    /// Samples solved
+    pub samples: u64,
+    /// Iterations over all samples
+    pub total_iterations: u64,
+    /// Most iterations any one sample took
+    pub max_iterations: usize,
+    /// Samples that only converged with Gmin stepping
+    pub gmin_fallbacks: u64,
+    /// Samples that only converged with source stepping
+    pub source_fallbacks: u64,
+    /// Samples that ran out of their time budget before converging
+    pub overruns: u64,
+    /// Largest final update of any sample, in volts or amperes
+    pub worst_residual: f64,
+}
+
+impl SolverStats {
+    /// Mean iterations per sample, or zero before the first sample.
+    pub fn avg_iterations(&self) -> f64 {
+        if self.samples == 0 {
+            0.0
+        } else {
+            self.total_iterations as f64 / self.samples as f64
+        }
+    }
+
+    /// Add a sample solved in `iterations`, with the solver's diagnostics.
+    fn record(&mut self, iterations: usize, newton: &NewtonRaphson) {
+        self.samples += 1;
+        self.total_iterations += iterations as u64;
+        self.max_iterations = self.max_iterations.max(iterations);
+        match newton.fallback() {
+            Some(Fallback::Gmin) => self.gmin_fallbacks += 1,
+            Some(Fallback::Source) => self.source_fallbacks += 1,
+            None => {}
+        }
+        if newton.budget_exceeded() {
+            self.overruns += 1;
+        }
+        self.worst_residual = self.worst_residual.max(newton.residual());
+    }
+
+    /// Add a sample solved directly with the last factors of a
+    /// time-invariant system.
+    fn record_cached(&mut self) {
+        self.samples += 1;
+        self.total_iterations += 1;
+        self.max_iterations = self.max_iterations.max(1);
+    }
 }
 
 /// An in-circuit digital delay effect.
@@ -71,6 +318,15 @@ struct InCircuitReverb {
     output_voltage: f64,
 }
 
+/// An in-circuit mono-to-stereo widener.
+struct InCircuitWidener {
+    effect: Widener,
+    left_branch: BranchId,
+    right_branch: BranchId,
+    /// Current `(left, right)` output voltages (set before MNA solve)
+    output_voltages: (f64, f64),
+}
+
 /// The main circuit simulator.
 pub struct Simulator {
     /// The circuit being simulated
@@ -79,53 +335,145 @@ pub struct Simulator {
     matrix: MnaMatrix,
     /// Newton-Raphson solver
     newton: NewtonRaphson,
-    /// Sample rate in Hz
+    /// Host sample rate in Hz
     sample_rate: f32,
-    /// Time step (1/sample_rate)
+    /// Time step at the internal rate (1 / (sample_rate * oversample))
     dt: f64,
+    /// Resamplers between the host and internal rates, one per channel,
+    /// when oversampling
+    oversamplers: Vec<Oversampler>,
+    /// Internal-rate input samples of each input channel, `MAX_OVERSAMPLE`
+    /// apiece
+    in_phases: Vec<f64>,
+    /// Internal-rate output samples of each output channel, `MAX_OVERSAMPLE`
+    /// apiece
+    out_phases: Vec<f64>,
+    /// Latest host-rate sample of each output channel
+    outputs: Vec<f32>,
+    /// DC blockers, one per output channel, when enabled
+    dc_blockers: Vec<DcBlocker>,
     /// In-circuit digital delay effects
     delays: Vec<InCircuitDelay>,
     /// In-circuit digital reverb effects
     reverbs: Vec<InCircuitReverb>,
+    /// In-circuit stereo wideners
+    wideners: Vec<InCircuitWidener>,
     /// LFOs for modulation (keyed by name)
     lfos: HashMap<String, Lfo>,
+    /// Envelope followers for modulation
+    envelopes: Vec<EnvelopeFollower>,
+    /// Latest value of every LFO and envelope, updated in place each sample
+    control_values: HashMap<String, f64>,
     /// Whether the circuit has any modulated components
     has_modulation: bool,
+    /// Whether the circuit has any noise sources
+    has_noise: bool,
+    /// Whether any transistor has self-heating enabled
+    has_self_heating: bool,
+    /// Whether the circuit has any hysteretic resistors
+    has_hysteretic: bool,
+    /// Whether the system matrix is the same every sample: a linear
+    /// circuit without modulation, hysteresis or digital effects
+    time_invariant: bool,
+    /// Whether `matrix.lu` holds the factors of the present system matrix
+    factors_valid: bool,
+    /// Last finite sample of each input channel, held in place of NaN or
+    /// infinite ones
+    last_inputs: Vec<f32>,
+    /// Number of NaN or infinite input samples replaced so far
+    invalid_inputs: u64,
+    /// Number of samples that ran out of their time budget
+    budget_overruns: u64,
+    /// Solver diagnostics since creation or the last `reset_stats`
+    stats: SolverStats,
 }
 
 impl Simulator {
     /// Create a new simulator for the given circuit with default configuration.
     pub fn new(circuit: Circuit, sample_rate: f32) -> Self {
-        Self::with_config(circuit, sample_rate, SimulatorConfig::default())
+        Self::build(circuit, sample_rate, SimulatorConfig::default())
     }
 
     /// Create a new simulator for the given circuit with custom configuration.
-    pub fn with_config(circuit: Circuit, sample_rate: f32, config: SimulatorConfig) -> Self {
+    ///
+    /// Fails with `InvalidParameter` if the configured temperature is not
+    /// above absolute zero.
+    pub fn with_config(
+        circuit: Circuit,
+        sample_rate: f32,
+        config: SimulatorConfig,
+    ) -> Result<Self> {
+        if config.temperature_c.is_nan() || config.temperature_c <= -ZERO_CELSIUS {
+            return Err(PedalerError::invalid_parameter(
+                "simulator",
+                "temperature",
+                format!(
+                    "must be above absolute zero, got {} °C",
+                    config.temperature_c
+                ),
+            ));
+        }
+        Ok(Self::build(circuit, sample_rate, config))
+    }
+
+    /// Create the simulator from a checked configuration.
+    fn build(mut circuit: Circuit, sample_rate: f32, config: SimulatorConfig) -> Self {
+        let temperature = config.temperature_c + ZERO_CELSIUS;
+        // Devices start at the nominal temperature, up to rounding in °C
+        if (temperature - NOMINAL_TEMPERATURE).abs() > 1e-9 {
+            for component in &mut circuit.components {
+                match component {
+                    Component::Diode(d) => d.set_temperature(temperature),
+                    Component::Bjt(q) => q.set_ambient_temperature(temperature),
+                    _ => {}
+                }
+            }
+        }
+
         let size = circuit.matrix_size();
-        let matrix = MnaMatrix::new(size);
-        let newton = NewtonRaphson::with_config(config.max_iterations, config.tolerance);
+        let mut matrix = MnaMatrix::new(size);
+        // A frozen operating point is where the solution starts
+        if let Some(op) = &circuit.operating_point {
+            matrix.x.copy_from_slice(op);
+        }
+        let mut newton = NewtonRaphson::with_config(config.max_iterations, config.tolerance)
+            .with_initial_guess(config.initial_guess)
+            .with_time_budget(config.time_budget)
+            .with_source_stepping(config.enable_source_stepping)
+            .with_integration_method(config.integration);
+        newton.prepare(size);
+
+        // Everything inside the simulation runs at the internal rate
+        let oversample = config.oversample.clamp(1, MAX_OVERSAMPLE);
+        let inputs = circuit.input_nodes.len();
+        let outputs = circuit.output_nodes.len();
+        let oversamplers = if oversample > 1 {
+            vec![Oversampler::new(oversample); inputs.max(outputs)]
+        } else {
+            Vec::new()
+        };
+        let host_rate = sample_rate;
+        let sample_rate = host_rate * oversample as f32;
         let dt = 1.0 / sample_rate as f64;
 
         // Instantiate digital delay effects with their circuit connections
         let delays: Vec<InCircuitDelay> = circuit
             .delay_defs
             .iter()
-            .map(|def| {
-                InCircuitDelay {
-                    effect: DelayLine::new(
-                        def.name.clone(),
-                        def.input_node,
-                        def.output_node,
-                        def.delay_time,
-                        sample_rate,
-                        def.mix,
-                        def.feedback,
-                    ),
-                    input_node: def.input_node,
-                    output_node: def.output_node,
-                    branch: def.branch,
-                    output_voltage: 0.0,
-                }
+            .map(|def| InCircuitDelay {
+                effect: DelayLine::new(
+                    def.name.clone(),
+                    def.input_node,
+                    def.output_node,
+                    def.delay_time,
+                    sample_rate,
+                    def.mix,
+                    def.feedback,
+                ),
+                input_node: def.input_node,
+                output_node: def.output_node,
+                branch: def.branch,
+                output_voltage: 0.0,
             })
             .collect();
 
@@ -151,153 +499,420 @@ impl Simulator {
             })
             .collect();
 
+        // Instantiate stereo wideners with their circuit connections
+        let wideners: Vec<InCircuitWidener> = circuit
+            .widen_defs
+            .iter()
+            .map(|def| InCircuitWidener {
+                effect: Widener::new(
+                    def.name.clone(),
+                    [def.input_node, def.left_node, def.right_node],
+                    def.width,
+                    def.delay_time,
+                    sample_rate,
+                    def.mid_side,
+                ),
+                left_branch: def.left_branch,
+                right_branch: def.right_branch,
+                output_voltages: (0.0, 0.0),
+            })
+            .collect();
+
         // Instantiate LFOs
         let lfos: HashMap<String, Lfo> = circuit
             .lfo_defs
             .iter()
             .map(|def| {
                 let shape = LfoShape::from_str(&def.shape).unwrap_or_default();
-                let lfo = Lfo::new(def.name.clone(), def.rate, shape, sample_rate as f64);
+                let lfo = Lfo::new(def.name.clone(), def.rate, shape, sample_rate as f64)
+                    .with_phase(def.phase)
+                    .with_range(def.min, def.max);
                 (def.name.clone(), lfo)
             })
             .collect();
 
-        // Check if any resistors are modulated
-        let has_modulation = circuit.components.iter().any(|c| {
-            matches!(c, Component::Resistor(r) if r.is_modulated())
-        });
+        // Instantiate envelope followers
+        let envelopes: Vec<EnvelopeFollower> = circuit
+            .envelope_defs
+            .iter()
+            .map(|def| {
+                EnvelopeFollower::new(
+                    def.name.clone(),
+                    def.input_node,
+                    def.attack,
+                    def.release,
+                    def.gain,
+                    sample_rate as f64,
+                )
+            })
+            .collect();
+
+        // All control sources are known up front, so stepping never inserts
+        let control_values: HashMap<String, f64> = lfos
+            .keys()
+            .chain(envelopes.iter().map(|env| &env.name))
+            .map(|name| (name.clone(), 0.0))
+            .collect();
+
+        // Check if any resistors or pots are modulated
+        let has_modulation = circuit.has_modulation();
+
+        // Check if any transistors track their junction temperature
+        let has_self_heating = circuit
+            .components
+            .iter()
+            .any(|c| matches!(c, Component::Bjt(q) if q.params.self_heating));
+        let has_hysteretic = circuit
+            .components
+            .iter()
+            .any(|c| matches!(c, Component::Hysteretic(_)));
+        // Noise only changes the sources, so it keeps the system time-invariant
+        let has_noise = circuit
+            .components
+            .iter()
+            .any(|c| matches!(c, Component::Noise(_)));
+        let time_invariant = !circuit.has_nonlinear()
+            && !has_modulation
+            && !has_hysteretic
+            && delays.is_empty()
+            && reverbs.is_empty()
+            && wideners.is_empty();
 
         Self {
             circuit,
             matrix,
             newton,
-            sample_rate,
+            sample_rate: host_rate,
             dt,
+            oversamplers,
+            in_phases: vec![0.0; inputs * MAX_OVERSAMPLE],
+            out_phases: vec![0.0; outputs * MAX_OVERSAMPLE],
+            outputs: vec![0.0; outputs],
+            dc_blockers: if config.block_dc {
+                vec![DcBlocker::new(host_rate as f64); outputs]
+            } else {
+                Vec::new()
+            },
             delays,
             reverbs,
+            wideners,
             lfos,
+            envelopes,
+            control_values,
             has_modulation,
+            has_noise,
+            has_self_heating,
+            has_hysteretic,
+            time_invariant,
+            factors_valid: false,
+            last_inputs: vec![0.0; inputs],
+            invalid_inputs: 0,
+            budget_overruns: 0,
+            stats: SolverStats::default(),
         }
     }
 
-    /// Get the sample rate.
+    /// Create a simulator for one unit of the circuit, with every toleranced
+    /// component drawn from its tolerance by `seed` (see
+    /// [`Circuit::randomize_tolerances`]). For a custom configuration,
+    /// randomize the circuit first and use [`with_config`](Self::with_config).
+    pub fn with_randomized(mut circuit: Circuit, sample_rate: f32, seed: u64) -> Self {
+        circuit.randomize_tolerances(seed);
+        Self::new(circuit, sample_rate)
+    }
+
+    /// Create a Monte-Carlo batch of `count` units of the circuit, drawn
+    /// with the seeds `seed`, `seed + 1`, and so on.
+    pub fn monte_carlo(circuit: &Circuit, sample_rate: f32, count: usize, seed: u64) -> Vec<Self> {
+        (0..count as u64)
+            .map(|i| Self::with_randomized(circuit.clone(), sample_rate, seed.wrapping_add(i)))
+            .collect()
+    }
+
+    /// Get the host sample rate.
     pub fn sample_rate(&self) -> f32 {
         self.sample_rate
     }
 
-    /// Set the input voltage (audio sample).
+    /// Get the rate the circuit is stepped at: the host rate times the
+    /// oversampling factor.
+    pub fn internal_sample_rate(&self) -> f64 {
+        self.sample_rate as f64 * self.oversample_factor() as f64
+    }
+
+    /// The oversampling factor, 1 when not oversampling.
+    fn oversample_factor(&self) -> usize {
+        self.oversamplers.first().map_or(1, |os| os.factor())
+    }
+
+    /// Number of audio input channels, one per `.input`.
+    pub fn input_channels(&self) -> usize {
+        self.circuit.input_nodes.len()
+    }
+
+    /// Number of audio output channels, one per `.output`.
+    pub fn output_channels(&self) -> usize {
+        self.circuit.output_nodes.len()
+    }
+
+    /// Set the input voltage (audio sample) of the first input channel.
+    /// When the input is driven by a current source, the sample is its
+    /// current in amperes instead.
+    ///
+    /// A NaN or infinite sample would poison the solution and every reactive
+    /// state for the rest of the stream, so it is replaced by the last finite
+    /// sample and counted in [`invalid_input_count`](Self::invalid_input_count).
     pub fn set_input(&mut self, voltage: f32) {
-        // Find the audio input voltage source and set its value
-        if let Some(idx) = self.circuit.input_source_idx {
-            if let Component::VoltageSource(ref mut vs) = self.circuit.components[idx] {
-                vs.set_value(voltage as f64);
+        self.set_input_channel(0, voltage);
+    }
+
+    /// Set the input voltage of input channel `channel`, holding the last
+    /// finite sample in place of a NaN or infinite one.
+    fn set_input_channel(&mut self, channel: usize, voltage: f32) {
+        let voltage = if voltage.is_finite() {
+            self.last_inputs[channel] = voltage;
+            voltage
+        } else {
+            self.invalid_inputs += 1;
+            self.last_inputs[channel]
+        };
+        self.set_input_source(channel, voltage as f64);
+    }
+
+    /// Set the value of the source of input channel `channel`: its voltage,
+    /// or for a current source its current in amperes.
+    fn set_input_source(&mut self, channel: usize, voltage: f64) {
+        if let Some(idx) = self.circuit.input_sources[channel] {
+            self.circuit.components[idx].set_source_value(voltage);
+        }
+    }
+
+    /// Number of NaN or infinite input samples replaced so far.
+    pub fn invalid_input_count(&self) -> u64 {
+        self.invalid_inputs
+    }
+
+    /// Number of samples so far that ran out of their time budget (see
+    /// [`SimulatorConfig::with_time_budget`]) before converging.
+    pub fn budget_overrun_count(&self) -> u64 {
+        self.budget_overruns
+    }
+
+    /// Solver diagnostics accumulated since the simulator was created or
+    /// [`reset_stats`](Self::reset_stats) was last called.
+    pub fn stats(&self) -> &SolverStats {
+        &self.stats
+    }
+
+    /// Start accumulating [`stats`](Self::stats) afresh.
+    pub fn reset_stats(&mut self) {
+        self.stats = SolverStats::default();
+    }
+
+    /// Change the time budget of each sample; `None` removes the limit.
+    pub fn set_time_budget(&mut self, iterations: Option<usize>) {
+        self.newton.time_budget = iterations;
+    }
+
+    /// Set the voltage on a control input declared with `.control`.
+    ///
+    /// Control inputs are auxiliary signals, such as a sidechain, that are
+    /// separate from the audio path. Like [`Simulator::set_input`], the value
+    /// holds until it is set again, so it can be fed once per sample or only
+    /// when it changes.
+    pub fn set_control_input(&mut self, name: &str, value: f32) -> Result<()> {
+        let idx = *self.circuit.control_sources.get(name).ok_or_else(|| {
+            PedalerError::InvalidSimulationParam {
+                message: format!(
+                    "'{}' is not a control input (declare it with .control)",
+                    name
+                ),
             }
+        })?;
+        if let Component::VoltageSource(ref mut vs) = self.circuit.components[idx] {
+            vs.set_value(value as f64);
         }
+        Ok(())
     }
 
-    /// Update LFOs and modulated components.
+    /// Update LFOs, envelope followers and modulated components.
     fn update_modulation(&mut self) {
         if !self.has_modulation {
             return;
         }
 
-        // Tick all LFOs and collect their current values
-        let lfo_values: HashMap<String, f64> = self.lfos
-            .iter_mut()
-            .map(|(name, lfo)| (name.clone(), lfo.tick()))
-            .collect();
+        // Tick all LFOs and record their current values
+        for (name, lfo) in &mut self.lfos {
+            if let Some(value) = self.control_values.get_mut(name) {
+                *value = lfo.tick();
+            }
+        }
 
-        // Update modulated resistors
+        // Envelopes follow the previous sample's node voltages
+        for env in &mut self.envelopes {
+            let v = if env.input_node.is_ground() {
+                0.0
+            } else {
+                self.matrix.x[env.input_node.0 - 1]
+            };
+            if let Some(value) = self.control_values.get_mut(&env.name) {
+                *value = env.process(v);
+            }
+        }
+
+        // Update modulated components
         for component in &mut self.circuit.components {
-            if let Component::Resistor(r) = component {
-                if let Some(ref modulation) = r.modulation {
-                    if let Some(&value) = lfo_values.get(&modulation.lfo_name) {
-                        r.update_modulation(value);
+            match component {
+                Component::Resistor(r) => {
+                    if let Some(ref modulation) = r.modulation {
+                        if let Some(&value) = self.control_values.get(&modulation.lfo_name) {
+                            r.update_modulation(value);
+                        }
+                    }
+                }
+                Component::Capacitor(c) => {
+                    if let Some(ref modulation) = c.modulation {
+                        if let Some(&value) = self.control_values.get(&modulation.source) {
+                            c.update_modulation(value);
+                        }
+                    }
+                }
+                Component::Potentiometer(p) => {
+                    if let Some(ref modulation) = p.modulation {
+                        if let Some(&value) = self.control_values.get(&modulation.source) {
+                            p.update_modulation(value);
+                        }
                     }
                 }
+                Component::NonlinearResistor(r) => {
+                    if let Some(&value) = self.control_values.get(&r.control) {
+                        r.update(value);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Draw the next sample of every noise source.
+    fn update_noise(&mut self) {
+        if !self.has_noise {
+            return;
+        }
+        for component in &mut self.circuit.components {
+            if let Component::Noise(n) = component {
+                n.tick();
             }
         }
     }
 
-    /// Step the simulation by one sample.
+    /// Step the simulation by one sample at the internal rate.
+    ///
+    /// Without oversampling this is one host sample. With it, the input is
+    /// not resampled; feed audio through [`Simulator::process_sample`] or
+    /// [`Simulator::process_block`] instead.
     pub fn step(&mut self) -> Result<f32> {
         // Update LFOs and modulated components before stamping
         self.update_modulation();
+        self.update_noise();
 
         // Clear the matrix
         self.matrix.clear();
 
         // Stamp linear components
-        stamp_linear_components(&self.circuit, &mut self.matrix, self.dt);
+        stamp_linear_components(
+            &self.circuit,
+            &mut self.matrix,
+            self.dt,
+            self.newton.integration,
+        );
 
         // Stamp digital effects as voltage sources
         // They use their output_voltage (computed from previous sample's input)
-        self.stamp_digital_effects();
+        let num_nodes = self.circuit.num_nodes;
+        let (delays, reverbs, wideners) = (&self.delays, &self.reverbs, &self.wideners);
+        let stamp_effects =
+            |m: &mut MnaMatrix| stamp_digital_effects(num_nodes, delays, reverbs, wideners, m);
+        stamp_effects(&mut self.matrix);
 
-        // Solve (with Newton-Raphson if there are nonlinear components)
-        self.newton.solve(&self.circuit, &mut self.matrix, self.dt)?;
+        if self.factors_valid {
+            // Only the sources have changed, so the last factors still hold
+            self.matrix.solve()?;
+            self.stats.record_cached();
+        } else {
+            // Solve (with Newton-Raphson if there are nonlinear components),
+            // restamping the effects on every iteration
+            let iterations = self.newton.solve_with(
+                &mut self.circuit,
+                &mut self.matrix,
+                self.dt,
+                &stamp_effects,
+            )?;
+            if self.newton.budget_exceeded() {
+                self.budget_overruns += 1;
+            }
+            self.stats.record(iterations, &self.newton);
+            self.factors_valid = self.time_invariant;
+        }
+
+        // Stop before a blown-up solution reaches the states and the output
+        self.check_solution()?;
 
         // Update reactive component states
         self.update_reactive_states();
 
+        // Update junction temperatures of self-heating transistors
+        self.update_thermal_states();
+
+        // Move hysteretic resistors towards their drive for the next sample
+        self.update_hysteretic_states();
+
         // Read input voltages for digital effects and process them
         // The processed values will be used as output in the next sample
         self.process_digital_effects();
 
         // Read output voltage from circuit
-        let v_out = self.matrix.node_voltage(&self.circuit, self.circuit.output_node) as f32;
+        let v_out = self
+            .matrix
+            .node_voltage(&self.circuit, self.circuit.output_node) as f32;
 
         Ok(v_out)
     }
 
-    /// Stamp digital effects as voltage sources into the MNA matrix.
-    fn stamp_digital_effects(&mut self) {
-        let num_nodes = self.circuit.num_nodes;
-
-        // Stamp delays as voltage sources: V(out) - V(in) = output_voltage
-        for delay in &self.delays {
-            let row = num_nodes - 1 + delay.branch.0;
-
-            // Digital effect modeled as ideal voltage source between input and output
-            // KCL: branch current enters output node, leaves input node
-            // KVL: V(out) - V(in) = output_voltage
-
-            // Add branch current to output node KCL
-            if !delay.output_node.is_ground() {
-                let out_idx = delay.output_node.0 - 1;
-                self.matrix.add(out_idx, row, 1.0);
-                self.matrix.add(row, out_idx, 1.0);
-            }
-
-            // Subtract branch current from input node KCL
-            if !delay.input_node.is_ground() {
-                let in_idx = delay.input_node.0 - 1;
-                self.matrix.add(in_idx, row, -1.0);
-                self.matrix.add(row, in_idx, -1.0);
-            }
-
-            // RHS: the processed voltage value
-            self.matrix.add_source(row, delay.output_voltage);
-        }
-
-        // Stamp reverbs as voltage sources
-        for reverb in &self.reverbs {
-            let row = num_nodes - 1 + reverb.branch.0;
-
-            if !reverb.output_node.is_ground() {
-                let out_idx = reverb.output_node.0 - 1;
-                self.matrix.add(out_idx, row, 1.0);
-                self.matrix.add(row, out_idx, 1.0);
-            }
-
-            if !reverb.input_node.is_ground() {
-                let in_idx = reverb.input_node.0 - 1;
-                self.matrix.add(in_idx, row, -1.0);
-                self.matrix.add(row, in_idx, -1.0);
+    /// Return `NumericalOverflow` if the solution holds a NaN or infinite
+    /// entry, naming its node, or `I(name)` for a component's branch current.
+    ///
+    /// The LU solve turns an overflow into NaNs at unrelated nodes
+    /// (`inf * 0`), so the first infinite entry is named before any NaN.
+    fn check_solution(&self) -> Result<()> {
+        let x = &self.matrix.x;
+        let Some(index) = x
+            .iter()
+            .position(|v| v.is_infinite())
+            .or_else(|| x.iter().position(|v| v.is_nan()))
+        else {
+            return Ok(());
+        };
+        let value = x[index];
+        let num_nodes = self.circuit.num_nodes - 1;
+        let node = if index < num_nodes {
+            self.circuit.node_name(NodeId(index + 1)).to_string()
+        } else {
+            let branch = BranchId(index - num_nodes);
+            let owner = self.circuit.components.iter().find(|c| match c {
+                Component::VoltageSource(v) => v.branch == branch,
+                Component::Inductor(l) => l.branch == branch,
+                Component::Ammeter(a) => a.branch == branch,
+                Component::Transformer(t) => t.branches.contains(&branch),
+                Component::OpAmp(op) => op.branch == Some(branch) || op.pole == Some(branch),
+                _ => false,
+            });
+            match owner {
+                Some(c) => format!("I({})", c.name()),
+                None => format!("branch {}", branch.0),
             }
-
-            self.matrix.add_source(row, reverb.output_voltage);
-        }
+        };
+        Err(PedalerError::NumericalOverflow { node, value })
     }
 
     /// Process digital effects: read input voltages and compute output for next sample.
@@ -325,40 +940,238 @@ impl Simulator {
 
             reverb.output_voltage = reverb.effect.process(v_in as f32) as f64;
         }
+
+        // Process wideners
+        for widener in &mut self.wideners {
+            let input_node = widener.effect.input_node;
+            let v_in = if input_node.is_ground() {
+                0.0
+            } else {
+                self.matrix.x[input_node.0 - 1]
+            };
+
+            let (left, right) = widener.effect.process(v_in as f32);
+            widener.output_voltages = (left as f64, right as f64);
+        }
     }
 
     /// Process a block of samples.
+    ///
+    /// Blocks are contiguous: all state, including the previous solution
+    /// that seeds the Newton-Raphson iteration, carries over from the last
+    /// sample of the previous call. Splitting a signal into blocks of any
+    /// size therefore gives bit-identical output.
     pub fn process_block(&mut self, input: &[f32], output: &mut [f32]) -> Result<()> {
         for (i, &sample) in input.iter().enumerate() {
-            self.set_input(sample);
-            output[i] = self.step()?;
+            output[i] = self.process_sample(sample)?;
+        }
+        Ok(())
+    }
+
+    /// Process one host sample: set the input and step, through the
+    /// oversampler when one is configured.
+    ///
+    /// The sample goes to the first input channel and the first output
+    /// channel is returned; other input channels hold their last value.
+    pub fn process_sample(&mut self, input: f32) -> Result<f32> {
+        self.set_input_channel(0, input);
+        self.advance()?;
+        Ok(self.outputs[0])
+    }
+
+    /// Process one host sample on every channel: `inputs` holds one sample
+    /// per input channel and `outputs` receives one per output channel.
+    ///
+    /// Returns `InvalidSimulationParam` if either slice does not match the
+    /// circuit's channel count.
+    pub fn process_frame(&mut self, inputs: &[f32], outputs: &mut [f32]) -> Result<()> {
+        self.check_channels(inputs.len(), outputs.len())?;
+        for (channel, &sample) in inputs.iter().enumerate() {
+            self.set_input_channel(channel, sample);
+        }
+        self.advance()?;
+        outputs.copy_from_slice(&self.outputs);
+        Ok(())
+    }
+
+    /// Process a block of samples on every channel, one slice per channel in
+    /// `.input` and `.output` order.
+    ///
+    /// Every channel is processed for the length of the shortest input
+    /// slice; output slices must be at least that long. Like
+    /// [`process_block`](Self::process_block), blocks are contiguous.
+    /// Returns `InvalidSimulationParam` if the number of slices does not
+    /// match the circuit's channel count.
+    pub fn process_block_multi(
+        &mut self,
+        inputs: &[&[f32]],
+        outputs: &mut [&mut [f32]],
+    ) -> Result<()> {
+        self.check_channels(inputs.len(), outputs.len())?;
+        let len = inputs.iter().map(|input| input.len()).min().unwrap_or(0);
+        if outputs.iter().any(|output| output.len() < len) {
+            return Err(PedalerError::InvalidSimulationParam {
+                message: format!("output blocks must hold {} samples", len),
+            });
+        }
+        for i in 0..len {
+            for (channel, input) in inputs.iter().enumerate() {
+                self.set_input_channel(channel, input[i]);
+            }
+            self.advance()?;
+            for (output, &sample) in outputs.iter_mut().zip(&self.outputs) {
+                output[i] = sample;
+            }
+        }
+        Ok(())
+    }
+
+    /// Process interleaved frames: `input` holds one sample per input
+    /// channel for each frame, and `output` receives one sample per output
+    /// channel for each frame, in the same order.
+    ///
+    /// Any trailing partial input frame is ignored. Returns
+    /// `InvalidSimulationParam` if `output` is too short for the frames in
+    /// `input`.
+    pub fn process_interleaved(&mut self, input: &[f32], output: &mut [f32]) -> Result<()> {
+        let (inputs, outputs) = (self.input_channels(), self.output_channels());
+        let frames = input.len() / inputs;
+        if output.len() < frames * outputs {
+            return Err(PedalerError::InvalidSimulationParam {
+                message: format!(
+                    "output must hold {} samples for {} frames",
+                    frames * outputs,
+                    frames
+                ),
+            });
+        }
+        for (frame_in, frame_out) in input
+            .chunks_exact(inputs)
+            .zip(output.chunks_exact_mut(outputs))
+        {
+            self.process_frame(frame_in, frame_out)?;
+        }
+        Ok(())
+    }
+
+    /// Check that `inputs` and `outputs` match the circuit's channel counts.
+    fn check_channels(&self, inputs: usize, outputs: usize) -> Result<()> {
+        if inputs != self.input_channels() || outputs != self.output_channels() {
+            return Err(PedalerError::InvalidSimulationParam {
+                message: format!(
+                    "circuit has {} input and {} output channels, got {} and {}",
+                    self.input_channels(),
+                    self.output_channels(),
+                    inputs,
+                    outputs
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Advance by one host sample from the inputs already set, leaving each
+    /// output channel's sample in `outputs`.
+    fn advance(&mut self) -> Result<()> {
+        let factor = self.oversample_factor();
+        if factor == 1 {
+            self.step()?;
+            for (output, &node) in self.outputs.iter_mut().zip(&self.circuit.output_nodes) {
+                *output = self.matrix.node_voltage(&self.circuit, node) as f32;
+            }
+            self.block_dc();
+            return Ok(());
+        }
+
+        let inputs = self.last_inputs.len();
+        for (channel, os) in self.oversamplers.iter_mut().take(inputs).enumerate() {
+            let phases = &mut self.in_phases[channel * MAX_OVERSAMPLE..];
+            os.upsample(self.last_inputs[channel] as f64, phases);
+        }
+        for phase in 0..factor {
+            for channel in 0..inputs {
+                self.set_input_source(channel, self.in_phases[channel * MAX_OVERSAMPLE + phase]);
+            }
+            self.step()?;
+            for (channel, &node) in self.circuit.output_nodes.iter().enumerate() {
+                let v = self.matrix.node_voltage(&self.circuit, node) as f32;
+                self.out_phases[channel * MAX_OVERSAMPLE + phase] = v as f64;
+            }
+        }
+        for (channel, output) in self.outputs.iter_mut().enumerate() {
+            let phases = &self.out_phases[channel * MAX_OVERSAMPLE..][..factor];
+            *output = self.oversamplers[channel].decimate(phases) as f32;
         }
+        self.block_dc();
         Ok(())
     }
 
-    /// Update the state of reactive components (capacitors, inductors).
+    /// Pass the host-rate outputs through the DC blockers, if enabled.
+    fn block_dc(&mut self) {
+        for (output, blocker) in self.outputs.iter_mut().zip(&mut self.dc_blockers) {
+            *output = blocker.process(*output as f64) as f32;
+        }
+    }
+
+    /// Update the state of reactive components (capacitors, inductors), and
+    /// the output op-amps slew from.
     fn update_reactive_states(&mut self) {
         let num_nodes = self.circuit.num_nodes;
         let dt = self.dt;
+        let method = self.newton.integration;
 
         for component in &mut self.circuit.components {
             match component {
                 Component::Capacitor(c) => {
                     // Get node indices directly without borrowing circuit
-                    let n1_idx = if c.nodes[0].is_ground() { None } else { Some(c.nodes[0].0 - 1) };
-                    let n2_idx = if c.nodes[1].is_ground() { None } else { Some(c.nodes[1].0 - 1) };
+                    let n1_idx = if c.nodes[0].is_ground() {
+                        None
+                    } else {
+                        Some(c.nodes[0].0 - 1)
+                    };
+                    let n2_idx = if c.nodes[1].is_ground() {
+                        None
+                    } else {
+                        Some(c.nodes[1].0 - 1)
+                    };
 
                     let v1 = n1_idx.map(|i| self.matrix.x[i]).unwrap_or(0.0);
                     let v2 = n2_idx.map(|i| self.matrix.x[i]).unwrap_or(0.0);
                     let v = v1 - v2;
-                    c.update_state(v, dt);
+                    c.update_state(v, dt, method);
                 }
 
                 Component::Inductor(l) => {
                     // Branch index calculation without borrowing circuit
                     let br = (num_nodes - 1) + l.branch.0;
                     let i = self.matrix.x[br];
-                    l.update_state(i, dt);
+                    l.update_state(i, dt, method);
+                }
+
+                Component::Transformer(t) => {
+                    let i = t.branches.map(|b| self.matrix.x[(num_nodes - 1) + b.0]);
+                    t.update_state(i, dt, method);
+                }
+
+                Component::OpAmp(op) => {
+                    let voltage = |node: NodeId| {
+                        if node.is_ground() {
+                            0.0
+                        } else {
+                            self.matrix.x[node.0 - 1]
+                        }
+                    };
+                    let (v_pos, v_neg) = (voltage(op.input_pos()), voltage(op.input_neg()));
+                    match op.pole {
+                        Some(pole) => op.update_pole(
+                            self.matrix.x[(num_nodes - 1) + pole.0],
+                            v_pos - v_neg,
+                            dt,
+                        ),
+                        None => {
+                            op.v_out_slew_limited(v_pos, v_neg, dt);
+                        }
+                    }
                 }
 
                 _ => {}
@@ -366,14 +1179,2247 @@ impl Simulator {
         }
     }
 
+    /// Update the junction temperature of self-heating transistors.
+    fn update_thermal_states(&mut self) {
+        if !self.has_self_heating {
+            return;
+        }
+
+        let dt = self.dt;
+        for component in &mut self.circuit.components {
+            if let Component::Bjt(q) = component {
+                let voltage = |node: NodeId| {
+                    if node.is_ground() {
+                        0.0
+                    } else {
+                        self.matrix.x[node.0 - 1]
+                    }
+                };
+                let v_c = voltage(q.collector());
+                let v_b = voltage(q.base());
+                let v_e = voltage(q.emitter());
+                q.update_thermal(v_b - v_e, v_b - v_c, dt);
+            }
+        }
+    }
+
+    /// Advance hysteretic resistors, driven by their control signal or by
+    /// the voltage across them.
+    fn update_hysteretic_states(&mut self) {
+        if !self.has_hysteretic {
+            return;
+        }
+
+        let dt = self.dt;
+        for component in &mut self.circuit.components {
+            if let Component::Hysteretic(h) = component {
+                let drive = match &h.control {
+                    Some(source) => self.control_values.get(source).copied().unwrap_or(0.0),
+                    None => {
+                        let voltage = |node: NodeId| {
+                            if node.is_ground() {
+                                0.0
+                            } else {
+                                self.matrix.x[node.0 - 1]
+                            }
+                        };
+                        h.voltage_drive(voltage(h.nodes[0]) - voltage(h.nodes[1]))
+                    }
+                };
+                h.update(drive, dt);
+            }
+        }
+    }
+
     /// Get the current voltage at a node by name.
     pub fn node_voltage(&self, name: &str) -> Option<f64> {
         let node = self.circuit.find_node(name)?;
         Some(self.matrix.node_voltage(&self.circuit, node))
     }
 
-    /// Get a reference to the circuit.
-    pub fn circuit(&self) -> &Circuit {
+    /// Get the current value (0.0 to 1.0) of a named LFO, e.g. to animate a
+    /// modulation indicator.
+    ///
+    /// Returns `None` if no LFO with that name exists. LFOs only advance in
+    /// circuits with modulated components.
+    pub fn lfo_value(&self, name: &str) -> Option<f64> {
+        self.lfos.get(name).map(Lfo::current_value)
+    }
+
+    /// Get the current value (0.0 to 1.0) of a named envelope follower.
+    ///
+    /// Returns `None` if no envelope follower with that name exists.
+    pub fn envelope_value(&self, name: &str) -> Option<f64> {
+        self.envelopes
+            .iter()
+            .find(|env| env.name == name)
+            .map(EnvelopeFollower::current_value)
+    }
+
+    /// Get the current through a named ammeter (amps, flowing from n+ to n-).
+    ///
+    /// Returns `None` if no ammeter with that name exists.
+    pub fn probe_current(&self, name: &str) -> Option<f64> {
+        self.circuit.components.iter().find_map(|c| match c {
+            Component::Ammeter(a) if a.name == name => {
+                Some(self.matrix.x[self.circuit.branch_index(a.branch)])
+            }
+            _ => None,
+        })
+    }
+
+    /// Get the branch current of a named voltage source, inductor, ammeter
+    /// or transformer (its primary winding), in amps.
+    ///
+    /// The current flows from n+ through the component to n-, so a source
+    /// delivering power reads negative. Returns `None` for any other name.
+    pub fn branch_current(&self, component_name: &str) -> Option<f64> {
+        let branch = self.circuit.components.iter().find_map(|c| match c {
+            Component::VoltageSource(v) if v.name == component_name => Some(v.branch),
+            Component::Inductor(l) if l.name == component_name => Some(l.branch),
+            Component::Ammeter(a) if a.name == component_name => Some(a.branch),
+            Component::Transformer(t) if t.name == component_name => Some(t.branches[0]),
+            _ => None,
+        })?;
+        Some(self.matrix.x[self.circuit.branch_index(branch)])
+    }
+
+    /// The raw MNA solution of the last sample: node voltages (ground
+    /// excluded) followed by branch currents.
+    pub fn solution(&self) -> &[f64] {
+        &self.matrix.x
+    }
+
+    /// Compute the static transfer curve: the output voltage for each DC
+    /// input voltage in `input_voltages`.
+    ///
+    /// Each point is an operating-point solve with capacitors open and
+    /// inductors shorted, warm-started from the previous point, so sweeping
+    /// in order converges quickly. Digital effects hold their current output.
+    /// For an input driven by a current source, the sweep values are input
+    /// currents in amperes. No reactive state is advanced, and the simulator
+    /// is restored to where it was once the sweep finishes.
+    pub fn dc_sweep(&mut self, input_voltages: &[f64]) -> Result<Vec<f64>> {
+        let source_idx =
+            self.circuit
+                .input_source_idx
+                .ok_or_else(|| PedalerError::UndrivenInput {
+                    node: self.circuit.node_name(self.circuit.input_node).to_string(),
+                })?;
+
+        let saved_voltage = self.circuit.components[source_idx]
+            .source_value()
+            .unwrap_or(0.0);
+        let saved_x = self.matrix.x.clone();
+
+        let result = input_voltages
+            .iter()
+            .map(|&v| self.solve_dc_point(source_idx, v))
+            .collect();
+
+        self.circuit.components[source_idx].set_source_value(saved_voltage);
+        self.matrix.x.copy_from_slice(&saved_x);
+        result
+    }
+
+    /// Solve the operating point with the input source at `voltage` and
+    /// return the output voltage.
+    fn solve_dc_point(&mut self, source_idx: usize, voltage: f64) -> Result<f64> {
+        self.circuit.components[source_idx].set_source_value(voltage);
+        self.solve_operating_point()?;
+        Ok(self
+            .matrix
+            .node_voltage(&self.circuit, self.circuit.output_node))
+    }
+
+    /// Solve the DC operating point for the present inputs into `matrix.x`.
+    fn solve_operating_point(&mut self) -> Result<()> {
+        // An infinite time step stamps capacitors open and inductors short
+        let dt = f64::INFINITY;
+        self.matrix.clear();
+        stamp_linear_components(&self.circuit, &mut self.matrix, dt, self.newton.integration);
+        // Nodes reached only through (now open) capacitors would float
+        let shunts: Vec<_> = self
+            .circuit
+            .cap_coupled_nodes()
+            .into_iter()
+            .map(|n| self.circuit.node_index(n))
+            .collect();
+        let num_nodes = self.circuit.num_nodes;
+        let (delays, reverbs, wideners) = (&self.delays, &self.reverbs, &self.wideners);
+        let stamp_effects = |m: &mut MnaMatrix| {
+            stamp_digital_effects(num_nodes, delays, reverbs, wideners, m);
+            for &node in &shunts {
+                m.stamp_conductance(node, None, MIN_CONDUCTANCE);
+            }
+        };
+        stamp_effects(&mut self.matrix);
+        // Only audio-rate samples are limited by the time budget
+        let budget = self.newton.time_budget.take();
+        let result =
+            self.newton
+                .solve_with(&mut self.circuit, &mut self.matrix, dt, &stamp_effects);
+        self.newton.time_budget = budget;
+        // The factors are now those of the DC system
+        self.factors_valid = false;
+        result?;
+        Ok(())
+    }
+
+    /// Solve the DC operating point for the present inputs and bake it into
+    /// the circuit as initial conditions.
+    ///
+    /// Capacitors take their solved voltage, inductors and transformer
+    /// windings their solved current, diodes and transistors their junction
+    /// voltages, and op-amps their output voltage to slew from; the circuit also
+    /// keeps the whole solution to start the solver from. The simulator is
+    /// left at the operating point, [`reset_to_initial_conditions`](Self::reset_to_initial_conditions)
+    /// returns it there without solving again, and a simulator built from a
+    /// copy of the circuit starts there too, already biased.
+    pub fn freeze_operating_point(&mut self) -> Result<()> {
+        self.solve_operating_point()?;
+
+        let num_nodes = self.circuit.num_nodes;
+        let x = &self.matrix.x;
+        let voltage = |node: NodeId| if node.is_ground() { 0.0 } else { x[node.0 - 1] };
+        for component in &mut self.circuit.components {
+            match component {
+                Component::Capacitor(c) => {
+                    c.initial_voltage = voltage(c.nodes[0]) - voltage(c.nodes[1]);
+                }
+                Component::Inductor(l) => {
+                    l.initial_current = x[(num_nodes - 1) + l.branch.0];
+                }
+                Component::Transformer(t) => {
+                    t.initial_currents = t.branches.map(|b| x[(num_nodes - 1) + b.0]);
+                }
+                Component::Diode(d) => {
+                    d.initial_v_op = voltage(d.nodes[0]) - voltage(d.nodes[1]);
+                }
+                Component::Bjt(q) => {
+                    let v_b = voltage(q.base());
+                    q.initial_v_be_op = v_b - voltage(q.emitter());
+                    q.initial_v_bc_op = v_b - voltage(q.collector());
+                }
+                Component::Jfet(j) => {
+                    let v_s = voltage(j.source());
+                    j.initial_v_gs_op = voltage(j.gate()) - v_s;
+                    j.initial_v_ds_op = voltage(j.drain()) - v_s;
+                }
+                Component::Mosfet(m) => {
+                    let v_s = voltage(m.source());
+                    m.initial_v_gs_op = voltage(m.gate()) - v_s;
+                    m.initial_v_ds_op = voltage(m.drain()) - v_s;
+                }
+                Component::Triode(t) => {
+                    let v_k = voltage(t.cathode());
+                    t.initial_v_gk_op = voltage(t.grid()) - v_k;
+                    t.initial_v_pk_op = voltage(t.plate()) - v_k;
+                }
+                Component::OpAmp(op) => {
+                    op.initial_v_out =
+                        op.v_out_limited(voltage(op.input_pos()), voltage(op.input_neg()));
+                    // At DC the pole state is the open-loop voltage
+                    if op.pole.is_some() {
+                        op.initial_v_pole = op.initial_v_out;
+                    }
+                }
+                _ => continue,
+            }
+            component.reset_to_initial_conditions();
+        }
+        self.circuit.operating_point = Some(self.matrix.x.clone());
+        Ok(())
+    }
+
+    /// Return every capacitor, inductor, diode, transistor and op-amp to its
+    /// initial conditions, and the solution to the frozen operating point if
+    /// there is one. Digital effects, LFOs and envelope followers carry on.
+    pub fn reset_to_initial_conditions(&mut self) {
+        for component in &mut self.circuit.components {
+            component.reset_to_initial_conditions();
+        }
+        match &self.circuit.operating_point {
+            Some(op) => self.matrix.x.copy_from_slice(op),
+            None => self.matrix.x.fill(0.0),
+        }
+    }
+
+    /// Clear all simulation state between takes, as if the simulator had
+    /// just been built.
+    ///
+    /// On top of [`reset_to_initial_conditions`](Self::reset_to_initial_conditions),
+    /// this empties delay, reverb and widener buffers, restarts LFOs at
+    /// their initial phase and noise sources from their seed, releases
+    /// envelope followers, returns hysteretic resistors to zero drive and
+    /// cools self-heating transistors back to the ambient temperature. The
+    /// inputs return to zero; parameters are kept.
+    pub fn reset(&mut self) {
+        self.reset_to_initial_conditions();
+        for component in &mut self.circuit.components {
+            match component {
+                Component::Hysteretic(h) => h.reset(),
+                Component::Noise(n) => n.reset(),
+                Component::Bjt(q) => q.set_junction_temperature(q.ambient_temp),
+                _ => {}
+            }
+        }
+        for channel in 0..self.last_inputs.len() {
+            self.last_inputs[channel] = 0.0;
+            self.set_input_source(channel, 0.0);
+        }
+        for delay in &mut self.delays {
+            delay.effect.reset();
+            delay.output_voltage = 0.0;
+        }
+        for reverb in &mut self.reverbs {
+            reverb.effect.reset();
+            reverb.output_voltage = 0.0;
+        }
+        for widener in &mut self.wideners {
+            widener.effect.reset();
+            widener.output_voltages = (0.0, 0.0);
+        }
+        for lfo in self.lfos.values_mut() {
+            lfo.reset();
+        }
+        for env in &mut self.envelopes {
+            env.reset();
+        }
+        for value in self.control_values.values_mut() {
+            *value = 0.0;
+        }
+        for os in &mut self.oversamplers {
+            os.reset();
+        }
+        for blocker in &mut self.dc_blockers {
+            blocker.reset();
+        }
+    }
+
+    /// Turn the output DC blocker on or off, as with
+    /// [`SimulatorConfig::with_dc_block`]. Turning it on starts the filter
+    /// from rest.
+    pub fn set_dc_block(&mut self, enabled: bool) {
+        if !enabled {
+            self.dc_blockers.clear();
+        } else if self.dc_blockers.is_empty() {
+            self.dc_blockers = vec![DcBlocker::new(self.sample_rate as f64); self.outputs.len()];
+        }
+    }
+
+    /// Measure the large-signal magnitude response with an exponential sine sweep.
+    ///
+    /// A log sweep from `f_start` to `f_end` Hz at `amplitude` volts, lasting
+    /// `duration` seconds, is run through the full nonlinear simulation and
+    /// deconvolved against the stimulus. Unlike a small-signal analysis, the
+    /// result reflects the drive level: a clipping stage measured at a higher
+    /// amplitude shows its compressed, level-dependent response.
+    ///
+    /// Returns `(frequency, gain)` pairs, one per FFT bin in the swept band.
+    /// The simulator state is advanced by the measurement.
+    pub fn sweep_response(
+        &mut self,
+        f_start: f64,
+        f_end: f64,
+        amplitude: f64,
+        duration: f64,
+    ) -> Result<Vec<(f64, f64)>> {
+        let fs = self.sample_rate as f64;
+        if !(f_start > 0.0 && f_start < f_end && f_end <= fs / 2.0) {
+            return Err(PedalerError::InvalidSimulationParam {
+                message: format!(
+                    "sweep range {}..{} Hz must be increasing and within 0..{} Hz",
+                    f_start,
+                    f_end,
+                    fs / 2.0
+                ),
+            });
+        }
+        if duration.is_nan() || duration <= 0.0 {
+            return Err(PedalerError::InvalidSimulationParam {
+                message: format!("sweep duration must be positive, got {}", duration),
+            });
+        }
+
+        // Let the bias settle and take the resting output as the DC reference
+        let settle = (0.05 * fs) as usize;
+        let mut dc = 0.0;
+        for _ in 0..settle {
+            dc = self.process_sample(0.0)? as f64;
+        }
+
+        let sweep = log_sweep(f_start, f_end, amplitude, duration, fs);
+        let tail = ((0.1 * duration * fs) as usize).max(settle);
+        let mut response = Vec::with_capacity(sweep.len() + tail);
+        for &x in sweep.iter().chain(std::iter::repeat_n(&0.0, tail)) {
+            response.push(self.process_sample(x as f32)? as f64 - dc);
+        }
+
+        Ok(deconvolve_sweep(&sweep, &response, f_start, f_end, fs))
+    }
+
+    /// Run `input` through the circuit from a fresh start and return the
+    /// output, one sample per input sample.
+    ///
+    /// The simulator is [`reset`](Self::reset) first, so captures don't
+    /// depend on what was processed before, and is left where the input
+    /// ends.
+    pub fn transient(&mut self, input: &[f32]) -> Result<Vec<f32>> {
+        self.reset();
+        let mut output = vec![0.0; input.len()];
+        self.process_block(input, &mut output)?;
+        Ok(output)
+    }
+
+    /// Capture the response to a unit impulse: one sample of 1 V followed
+    /// by silence, `len` samples in all.
+    ///
+    /// Like [`transient`](Self::transient), this starts from a reset. The
+    /// impulse is a single sample, so the response of a linear circuit is
+    /// scaled by the sample period: an RC lowpass gives about
+    /// `exp(-t/RC) / (RC * sample_rate)`.
+    pub fn impulse_response(&mut self, len: usize) -> Result<Vec<f32>> {
+        let mut input = vec![0.0; len];
+        if let Some(first) = input.first_mut() {
+            *first = 1.0;
+        }
+        self.transient(&input)
+    }
+
+    /// List every parameter a host can change with [`Simulator::set_parameter`].
+    ///
+    /// Components come first in circuit order, followed by LFO rates.
+    /// Pots report the knob position before any modulation is applied.
+    pub fn parameters(&self) -> Vec<ParamInfo> {
+        let components = self.circuit.components.iter().filter_map(|c| {
+            let (kind, value) = component_param(c)?;
+            Some(ParamInfo::new(c.name(), kind, value))
+        });
+        let lfos = self.circuit.lfo_defs.iter().filter_map(|def| {
+            let lfo = self.lfos.get(&def.name)?;
+            Some(ParamInfo::new(&lfo.name, ParamKind::LfoRate, lfo.rate()))
+        });
+        components.chain(lfos).collect()
+    }
+
+    /// Set a parameter listed by [`Simulator::parameters`].
+    ///
+    /// Switches close for values of 0.5 and above. The change takes effect
+    /// from the next sample, which starts Newton-Raphson from the DC
+    /// operating point of the changed circuit.
+    pub fn set_parameter(&mut self, name: &str, value: f64) -> Result<()> {
+        let kind = match self.circuit.components.iter().find(|c| c.name() == name) {
+            Some(c) => component_param(c).map(|(kind, _)| kind),
+            None if self.lfos.contains_key(name) => Some(ParamKind::LfoRate),
+            None => None,
+        }
+        .ok_or_else(|| PedalerError::UnknownParameter {
+            name: name.to_string(),
+        })?;
+
+        let (min, max) = kind.range();
+        if value.is_nan() || value < min || value > max {
+            return Err(PedalerError::invalid_parameter(
+                name,
+                "value",
+                format!("must be between {} and {}, got {}", min, max, value),
+            ));
+        }
+
+        if kind == ParamKind::LfoRate {
+            let rate = self.internal_sample_rate();
+            if let Some(lfo) = self.lfos.get_mut(name) {
+                lfo.set_rate(value, rate);
+            }
+            return Ok(());
+        }
+
+        match self
+            .circuit
+            .components
+            .iter_mut()
+            .find(|c| c.name() == name)
+        {
+            Some(Component::Potentiometer(p)) => p.set_position(value),
+            Some(Component::Switch(s)) => s.set_state(value >= 0.5),
+            Some(Component::Resistor(r)) => r.set_resistance(value),
+            Some(Component::Capacitor(c)) => c.set_capacitance(value),
+            Some(Component::Inductor(l)) => l.set_inductance(value),
+            _ => {}
+        }
+        self.circuit_changed();
+        Ok(())
+    }
+
+    /// Set one field of a component or effect, addressed as `"NAME.field"`,
+    /// for host automation.
+    ///
+    /// The fields are `position` for pots, `state` for switches, `rate` for
+    /// LFOs, `value` for resistors, capacitors and inductors, all as in
+    /// [`set_parameter`](Self::set_parameter), and `mix` (0-1) for reverbs
+    /// and delays. Fields are case-insensitive. Returns `UnknownParameter`
+    /// for a name or field that does not exist, such as `"SW1.position"`.
+    pub fn set_param(&mut self, target: &str, value: f64) -> Result<()> {
+        let unknown = || PedalerError::UnknownParameter {
+            name: target.to_string(),
+        };
+        let (name, field) = target.split_once('.').ok_or_else(unknown)?;
+        let kind = match self.circuit.components.iter().find(|c| c.name() == name) {
+            Some(c) => component_param(c).map(|(kind, _)| kind),
+            None if self.lfos.contains_key(name) => Some(ParamKind::LfoRate),
+            None => None,
+        };
+
+        match (field.to_ascii_lowercase().as_str(), kind) {
+            ("position", Some(ParamKind::Potentiometer))
+            | ("state", Some(ParamKind::Switch))
+            | ("rate", Some(ParamKind::LfoRate))
+            | (
+                "value",
+                Some(ParamKind::Resistance | ParamKind::Capacitance | ParamKind::Inductance),
+            ) => self.set_parameter(name, value),
+            ("mix", None) => {
+                let reverb = self.reverbs.iter_mut().find(|r| r.effect.name == name);
+                let delay = self.delays.iter_mut().find(|d| d.effect.name == name);
+                if reverb.is_none() && delay.is_none() {
+                    return Err(unknown());
+                }
+                if !(0.0..=1.0).contains(&value) {
+                    return Err(PedalerError::invalid_parameter(
+                        name,
+                        "mix",
+                        format!("must be between 0 and 1, got {}", value),
+                    ));
+                }
+                if let Some(r) = reverb {
+                    r.effect.params.mix = value as f32;
+                }
+                if let Some(d) = delay {
+                    d.effect.set_mix(value as f32);
+                }
+                Ok(())
+            }
+            _ => Err(unknown()),
+        }
+    }
+
+    /// Turn pot `name` to `position`, clamped to its 0.001-0.999 travel.
+    ///
+    /// Unlike [`set_parameter`](Self::set_parameter), a position outside
+    /// 0-1 is clamped rather than rejected, which suits knobs driven
+    /// straight from a UI.
+    pub fn set_pot(&mut self, name: &str, position: f64) -> Result<()> {
+        if position.is_nan() {
+            return Err(PedalerError::invalid_parameter(
+                name,
+                "position",
+                "must be a number",
+            ));
+        }
+        match self
+            .circuit
+            .components
+            .iter_mut()
+            .find(|c| c.name() == name)
+        {
+            Some(Component::Potentiometer(p)) => p.set_position(position),
+            _ => {
+                return Err(PedalerError::UnknownParameter {
+                    name: name.to_string(),
+                })
+            }
+        }
+        self.circuit_changed();
+        Ok(())
+    }
+
+    /// Open or close switch `name`.
+    pub fn set_switch(&mut self, name: &str, closed: bool) -> Result<()> {
+        match self
+            .circuit
+            .components
+            .iter_mut()
+            .find(|c| c.name() == name)
+        {
+            Some(Component::Switch(s)) => s.set_state(closed),
+            _ => {
+                return Err(PedalerError::UnknownParameter {
+                    name: name.to_string(),
+                })
+            }
+        }
+        self.circuit_changed();
+        Ok(())
+    }
+
+    /// Note that a component value changed, from the next sample on.
+    fn circuit_changed(&mut self) {
+        self.factors_valid = false;
+        // The operating point may have moved far from the last sample
+        self.newton.request_dc_guess();
+    }
+
+    /// Swap the parameters of diode `name` for those of `model`, like the
+    /// clipping selector switch of an overdrive.
+    ///
+    /// `model` is a diode `.model` of the netlist or a built-in preset
+    /// (`SI`, `GE` or `LED`). The change takes effect from the next sample,
+    /// which starts Newton-Raphson from the DC operating point of the
+    /// changed circuit.
+    pub fn set_diode_model(&mut self, name: &str, model: &str) -> Result<()> {
+        let params =
+            self.circuit
+                .diode_model(model)
+                .ok_or_else(|| PedalerError::UndefinedModel {
+                    model: model.to_string(),
+                    component: name.to_string(),
+                })?;
+        match self
+            .circuit
+            .components
+            .iter_mut()
+            .find(|c| c.name() == name)
+        {
+            Some(Component::Diode(d)) => {
+                d.params = params;
+                // Rescale the new saturation current to the temperature
+                d.set_temperature(d.temperature());
+            }
+            _ => {
+                return Err(PedalerError::UnknownParameter {
+                    name: name.to_string(),
+                })
+            }
+        }
+        self.circuit_changed();
+        Ok(())
+    }
+
+    /// Get a reference to the circuit.
+    pub fn circuit(&self) -> &Circuit {
         &self.circuit
     }
 }
+
+/// Stamp digital effects as voltage sources into the MNA matrix.
+///
+/// Each holds the output computed from the previous sample's input.
+fn stamp_digital_effects(
+    num_nodes: usize,
+    delays: &[InCircuitDelay],
+    reverbs: &[InCircuitReverb],
+    wideners: &[InCircuitWidener],
+    matrix: &mut MnaMatrix,
+) {
+    // Stamp delays as voltage sources: V(out) - V(in) = output_voltage
+    for delay in delays {
+        let row = num_nodes - 1 + delay.branch.0;
+
+        // Digital effect modeled as ideal voltage source between input and output
+        // KCL: branch current enters output node, leaves input node
+        // KVL: V(out) - V(in) = output_voltage
+
+        // Add branch current to output node KCL
+        if !delay.output_node.is_ground() {
+            let out_idx = delay.output_node.0 - 1;
+            matrix.add(out_idx, row, 1.0);
+            matrix.add(row, out_idx, 1.0);
+        }
+
+        // Subtract branch current from input node KCL
+        if !delay.input_node.is_ground() {
+            let in_idx = delay.input_node.0 - 1;
+            matrix.add(in_idx, row, -1.0);
+            matrix.add(row, in_idx, -1.0);
+        }
+
+        // RHS: the processed voltage value
+        matrix.add_source(row, delay.output_voltage);
+    }
+
+    // Stamp reverbs as voltage sources
+    for reverb in reverbs {
+        let row = num_nodes - 1 + reverb.branch.0;
+
+        if !reverb.output_node.is_ground() {
+            let out_idx = reverb.output_node.0 - 1;
+            matrix.add(out_idx, row, 1.0);
+            matrix.add(row, out_idx, 1.0);
+        }
+
+        if !reverb.input_node.is_ground() {
+            let in_idx = reverb.input_node.0 - 1;
+            matrix.add(in_idx, row, -1.0);
+            matrix.add(row, in_idx, -1.0);
+        }
+
+        matrix.add_source(row, reverb.output_voltage);
+    }
+
+    // Stamp each widener output as a voltage source to ground, so the
+    // left and right nodes carry the processed voltages directly
+    for widener in wideners {
+        let outputs = [
+            (
+                widener.effect.left_node,
+                widener.left_branch,
+                widener.output_voltages.0,
+            ),
+            (
+                widener.effect.right_node,
+                widener.right_branch,
+                widener.output_voltages.1,
+            ),
+        ];
+        for (node, branch, voltage) in outputs {
+            let row = num_nodes - 1 + branch.0;
+            if !node.is_ground() {
+                let idx = node.0 - 1;
+                matrix.add(idx, row, 1.0);
+                matrix.add(row, idx, 1.0);
+            }
+            matrix.add_source(row, voltage);
+        }
+    }
+}
+
+/// The parameter kind and current value of a controllable component.
+fn component_param(component: &Component) -> Option<(ParamKind, f64)> {
+    match component {
+        Component::Potentiometer(p) => Some((ParamKind::Potentiometer, p.base_position)),
+        Component::Switch(s) => Some((ParamKind::Switch, if s.closed { 1.0 } else { 0.0 })),
+        Component::Resistor(r) => Some((ParamKind::Resistance, r.resistance)),
+        Component::Capacitor(c) => Some((ParamKind::Capacitance, c.capacitance)),
+        Component::Inductor(l) => Some((ParamKind::Inductance, l.inductance)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::ComponentId;
+    use crate::components::{Triode, TriodeParams};
+    use crate::dsl;
+
+    fn build(src: &str) -> Simulator {
+        let ast = dsl::parse(src).unwrap();
+        let circuit = Circuit::from_ast(ast).unwrap();
+        Simulator::new(circuit, 48000.0)
+    }
+
+    /// Common-emitter stage dissipating ~70mW in the transistor.
+    fn hot_stage(self_heating: bool) -> String {
+        format!(
+            ".input in\n.output c\n\
+             V_IN in 0 DC 0\n\
+             VCC vcc 0 DC 9\n\
+             VB b 0 DC 0.68\n\
+             R_C vcc c 100\n\
+             Q1 c b 0 GE\n\
+             .model GE NPN (bf=100 is=1e-14 selfheat={} rth=200 cth=1m)\n",
+            self_heating as u8
+        )
+    }
+
+    #[test]
+    fn test_self_heating_drifts_collector_voltage() {
+        let mut sim = build(&hot_stage(true));
+        let v_start = sim.step().unwrap();
+        for _ in 0..24000 {
+            sim.step().unwrap();
+        }
+        let v_end = sim.step().unwrap();
+
+        // Heating raises Is, so the collector current grows and V_C sags
+        assert!(
+            v_end < v_start - 0.05,
+            "expected drift, got {} -> {}",
+            v_start,
+            v_end
+        );
+    }
+
+    #[test]
+    fn test_no_self_heating_keeps_bias_fixed() {
+        let mut sim = build(&hot_stage(false));
+        let v_start = sim.step().unwrap();
+        for _ in 0..24000 {
+            sim.step().unwrap();
+        }
+        let v_end = sim.step().unwrap();
+        assert!((v_end - v_start).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_jfet_common_source_stage() {
+        // Self-biased: Id = beta * (Vgs - Vto)^2 with Vgs = -Id * Rs settles
+        // at Id = 1 mA, so gm = 2 mS and the gain is -gm Rd / (1 + gm Rs)
+        let mut sim = build(
+            ".input g\n.output d\n\
+             .model JX NJF (vto=-2 beta=1m)\n\
+             V_IN g 0 AC\n\
+             VDD vdd 0 DC 9\n\
+             RD vdd d 4.7k\n\
+             J1 d g s JX\n\
+             RS s 0 1k\n",
+        );
+        let out = sim.dc_sweep(&[0.0, 0.001]).unwrap();
+        assert!((out[0] - 4.3).abs() < 1e-6, "drain at {}", out[0]);
+        let gain = (out[1] - out[0]) / 0.001;
+        let expected = -2e-3 * 4.7e3 / (1.0 + 2e-3 * 1e3);
+        assert!(
+            (gain / expected - 1.0).abs() < 1e-3,
+            "gain {} vs {}",
+            gain,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_cmos_inverter_gain_stage() {
+        // Matched devices balance at half the supply, both saturated with
+        // Vgst = 2.5 V, so the gain is -(gm_n + gm_p) / (gds_n + gds_p)
+        let mut sim = build(
+            ".input in\n.output out\n\
+             .model MN NMOS (vth=2 kp=1m lambda=0.02)\n\
+             .model MP PMOS (vth=-2 kp=1m lambda=0.02)\n\
+             V_IN in 0 AC\n\
+             VDD vdd 0 DC 9\n\
+             M1 out in 0 MN\n\
+             M2 out in vdd MP\n",
+        );
+        let out = sim.dc_sweep(&[4.5, 4.5 + 1e-5, 0.0, 9.0]).unwrap();
+        assert!((out[0] - 4.5).abs() < 1e-6, "output at {}", out[0]);
+        let gain = (out[1] - out[0]) / 1e-5;
+        let gm = 1e-3 * 2.5 * (1.0 + 0.02 * 4.5);
+        let gds = 0.5e-3 * 2.5 * 2.5 * 0.02;
+        assert!(
+            (gain / (-gm / gds) - 1.0).abs() < 1e-2,
+            "gain {} vs {}",
+            gain,
+            -gm / gds
+        );
+
+        // Driven to the rails, the output swings to the opposite rail
+        assert!((out[2] - 9.0).abs() < 1e-6, "output at {}", out[2]);
+        assert!(out[3].abs() < 1e-6, "output at {}", out[3]);
+    }
+
+    #[test]
+    fn test_triode_common_cathode_stage() {
+        // 12AX7 with an unbypassed cathode resistor: the bias settles where
+        // the plate current through Rk sets Vgk, and the gain is
+        // -gm Ra / (1 + gm Rk + gp (Ra + Rk))
+        let src = ".input in\n.output p\n\
+                   .model AX7 TRIODE (mu=100 ex=1.4 kg1=1060 kp=600 kvb=300)\n\
+                   V_IN in 0 AC\n\
+                   VB b 0 DC 250\n\
+                   RG in g 68k\n\
+                   RA b p 100k\n\
+                   TRIODE1 p g k AX7\n\
+                   RK k 0 1.5k\n";
+        let mut sim = build(src);
+        let out = sim.dc_sweep(&[0.0, 1e-3]).unwrap();
+
+        let i_p = (250.0 - out[0]) / 100e3;
+        let v_k = i_p * 1.5e3;
+        let t = Triode::new(
+            ComponentId(0),
+            "T".to_string(),
+            [NodeId(1), NodeId(2), NodeId(0)],
+            TriodeParams::default(),
+        );
+        let i_model = t.i_p(-v_k, out[0] - v_k);
+        assert!(
+            (i_p / i_model - 1.0).abs() < 1e-6,
+            "Ip {} vs {}",
+            i_p,
+            i_model
+        );
+        assert!(v_k > 0.5 && v_k < 2.5, "cathode at {}", v_k);
+
+        let (gm, gp, _) = t.linearize(-v_k, out[0] - v_k);
+        let expected = -gm * 100e3 / (1.0 + gm * 1.5e3 + gp * 101.5e3);
+        let gain = (out[1] - out[0]) / 1e-3;
+        assert!(
+            (gain / expected - 1.0).abs() < 1e-2,
+            "gain {} vs {}",
+            gain,
+            expected
+        );
+
+        // Overdriven hard enough to pull grid current, it still converges
+        let mut sim = build(src);
+        for n in 0..480 {
+            let x = 5.0 * (2.0 * std::f32::consts::PI * 1000.0 * n as f32 / 48000.0).sin();
+            let y = sim.process_sample(x).unwrap();
+            assert!(
+                y.is_finite() && (0.0..=250.0).contains(&y),
+                "plate at {}",
+                y
+            );
+        }
+    }
+
+    #[test]
+    fn test_step_up_transformer_doubles_the_signal() {
+        // 1:2 turns ratio: Ls = 4 Lp. At 200 Hz the 1 H primary is ~1.3k,
+        // far above the source resistance, and the leakage is negligible
+        let mut sim = build(
+            ".input in\n.output out\n\
+             V_IN in 0 AC\n\
+             R1 in p 1\n\
+             XFMR1 p 0 out 0 1 4 0.999\n\
+             RL out 0 10k\n",
+        );
+        let (mut peak_in, mut out_at_peak) = (0.0f32, 0.0f32);
+        for n in 0..4800 {
+            let x = 0.1 * (2.0 * std::f32::consts::PI * 200.0 * n as f32 / 48000.0).sin();
+            let y = sim.process_sample(x).unwrap();
+            // Dotted ends together: the output peaks with the input
+            if n >= 2400 && x > peak_in {
+                peak_in = x;
+                out_at_peak = y;
+            }
+        }
+        let ratio = out_at_peak / peak_in;
+        assert!((ratio - 2.0).abs() < 0.02, "secondary/primary {}", ratio);
+    }
+
+    #[test]
+    fn test_noise_amplitude_scales_rms() {
+        let rms = |amplitude: &str| {
+            let mut sim = build(&format!(
+                ".input in\n.output out\nV_IN in 0 DC 0\nR1 in out 1k\nR2 out 0 1k\nN1 0 out {} 42\n",
+                amplitude
+            ));
+            let sum: f64 = (0..4800)
+                .map(|_| (sim.step().unwrap() as f64).powi(2))
+                .sum();
+            (sum / 4800.0).sqrt()
+        };
+
+        // Uniform in +-1 mA into 500 ohms: 0.5 V / sqrt(3) RMS
+        let quiet = rms("1m");
+        assert!(
+            (quiet / (0.5 / 3f64.sqrt()) - 1.0).abs() < 0.05,
+            "rms {}",
+            quiet
+        );
+        // Same seed, same sequence: exactly twice the RMS
+        assert!((rms("2m") / quiet - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_monte_carlo_draws_values_within_tolerance() {
+        let src = ".input in\n.output out\nV_IN in 0 AC\nR_IN in 0 1k\n\
+                   VCC vcc 0 DC 1\nR1 vcc out 10k tol=5%\nR2 out 0 10k\n";
+        let circuit = Circuit::from_ast(dsl::parse(src).unwrap()).unwrap();
+        let resistance = |sim: &Simulator, name: &str| match sim
+            .circuit()
+            .components
+            .iter()
+            .find(|c| c.name() == name)
+        {
+            Some(Component::Resistor(r)) => r.resistance,
+            _ => panic!("{} missing", name),
+        };
+
+        let mut sims = Simulator::monte_carlo(&circuit, 48000.0, 200, 7);
+        let values: Vec<f64> = sims.iter().map(|sim| resistance(sim, "R1")).collect();
+        for (sim, &r1) in sims.iter_mut().zip(&values) {
+            assert!((9_500.0..=10_500.0).contains(&r1), "R1 {}", r1);
+            assert_eq!(resistance(sim, "R2"), 10_000.0);
+            // Each unit simulates with its own value
+            let out = sim.step().unwrap() as f64;
+            assert!(
+                (out - 10_000.0 / (r1 + 10_000.0)).abs() < 1e-4,
+                "out {} with R1 {}",
+                out,
+                r1
+            );
+        }
+        // Spread around the nominal value with a standard deviation of
+        // about a third of the tolerance
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let sd =
+            (values.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / values.len() as f64).sqrt();
+        assert!((mean - 10_000.0).abs() < 50.0, "mean {}", mean);
+        assert!(sd > 100.0 && sd < 250.0, "sd {}", sd);
+
+        // The same seed draws the same unit
+        let again = Simulator::with_randomized(circuit.clone(), 48000.0, 10);
+        assert_eq!(resistance(&again, "R1"), values[3]);
+        assert_ne!(values[3], values[4]);
+    }
+
+    #[test]
+    fn test_leaky_capacitor_discharges_through_leakage() {
+        // The diode charges the cap, then blocks once the input goes negative
+        let mut sim = build(
+            ".input in\n.output c\n\
+             V_IN in 0 AC\n\
+             D1 in c\n\
+             C1 c 0 1u rleak=100k\n",
+        );
+        sim.set_input(5.0);
+        for _ in 0..4800 {
+            sim.step().unwrap();
+        }
+        sim.set_input(-5.0);
+        let v_start = sim.step().unwrap() as f64;
+
+        // tau = 100k * 1u = 0.1s = 4800 samples
+        let mut v_end = v_start;
+        for _ in 0..4800 {
+            v_end = sim.step().unwrap() as f64;
+        }
+        let ratio = v_end / v_start;
+        assert!(v_start > 3.0, "cap should have charged, got {}", v_start);
+        assert!(
+            (ratio - (-1.0f64).exp()).abs() < 0.01,
+            "decay ratio {}",
+            ratio
+        );
+    }
+
+    /// Clipper fed through 1k, driven with a constant input.
+    fn clip_level(clipper: &str, input: f32) -> f64 {
+        let mut sim = build(&format!(
+            ".input in\n.output out\n\
+             V_IN in 0 AC\n\
+             R1 in out 1k\n\
+             {}\n\
+             .model SI D (is=2.52e-9 n=1.752)\n\
+             .model GE D (is=1e-6 n=1.3)\n",
+            clipper
+        ));
+        sim.set_input(input);
+        let mut out = 0.0;
+        for _ in 0..10 {
+            out = sim.step().unwrap();
+        }
+        out as f64
+    }
+
+    #[test]
+    fn test_diode_stack_clips_asymmetrically() {
+        let stack = "DSTACK1 out 0 fwd=SI,SI rev=GE";
+        let positive = clip_level(stack, 5.0);
+        let negative = clip_level(stack, -5.0);
+
+        // Two silicon drops one way, a single germanium drop the other
+        let si = clip_level("D1 out 0 SI", 5.0);
+        let ge = clip_level("D1 0 out GE", -5.0);
+        assert!(
+            (positive - 2.0 * si).abs() < 0.03 * si,
+            "{} vs 2 x {}",
+            positive,
+            si
+        );
+        assert!((negative - ge).abs() < 0.01, "{} vs {}", negative, ge);
+        assert!(
+            positive > 1.0 && negative > -0.4,
+            "{} / {}",
+            positive,
+            negative
+        );
+    }
+
+    #[test]
+    fn test_zener_clamps_reverse_voltage() {
+        let zener = "D1 out 0 Z51\n.model Z51 D (vz=5.1)";
+        // 4.9 mA of breakdown current sits within a few n Vt of Vz
+        let clamped = clip_level(zener, -10.0);
+        assert!((clamped + 5.1).abs() < 0.1, "clamped at {}", clamped);
+        // Below Vz it blocks, and forward it is an ordinary diode
+        assert!((clip_level(zener, -4.0) + 4.0).abs() < 1e-6);
+        assert!((clip_level(zener, 10.0) - clip_level("D1 out 0", 10.0)).abs() < 1e-9);
+    }
+
+    /// Drive an impulse through a buffered widener and record both outputs.
+    fn widen_impulse(params: &str) -> (Vec<f64>, Vec<f64>) {
+        let mut sim = build(&format!(
+            ".input in\n.output l\n\
+             V_IN in 0 AC\n\
+             R1 in 0 10k\n\
+             WIDEN W1 in l r {}\n\
+             R_L l 0 10k\n\
+             R_R r 0 10k\n",
+            params
+        ));
+        let mut left = Vec::new();
+        let mut right = Vec::new();
+        for i in 0..100 {
+            sim.set_input(if i == 10 { 1.0 } else { 0.0 });
+            sim.step().unwrap();
+            left.push(sim.node_voltage("l").unwrap());
+            right.push(sim.node_voltage("r").unwrap());
+        }
+        (left, right)
+    }
+
+    fn peak_index(signal: &[f64]) -> usize {
+        (0..signal.len())
+            .max_by(|&a, &b| signal[a].total_cmp(&signal[b]))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_widener_outputs_stereo_pair() {
+        let (left, right) = widen_impulse("width=0 delay=1m");
+        assert_eq!(left, right);
+        assert_eq!(peak_index(&left), 11); // one sample of digital latency
+
+        // 1ms at 48kHz is 48 samples between the channels
+        let (left, right) = widen_impulse("width=1 delay=1m");
+        assert_eq!(peak_index(&left), 11);
+        assert_eq!(peak_index(&right), 11 + 48);
+    }
+
+    #[test]
+    fn test_self_driven_hysteretic_resistor_settles() {
+        let mut sim = build(
+            ".input in\n.output out\n\
+             V_IN in 0 AC\n\
+             HYST H1 in out rmin=1k rmax=1M tau_rise=5m tau_fall=50m\n\
+             R1 out 0 10k\n",
+        );
+        let input = vec![1.0f32; 4800];
+        let mut output = vec![0.0f32; input.len()];
+        sim.process_block(&input, &mut output).unwrap();
+
+        // Starts near rmax, then conducts until the voltage across it (its
+        // own drive) balances its resistance at about s = 0.6
+        assert!(output[0] < 0.01);
+        assert!(output.windows(2).all(|w| w[1] >= w[0]));
+        let settled = output[output.len() - 1];
+        assert!(settled > 0.3 && settled < 0.5, "settled at {}", settled);
+    }
+
+    #[test]
+    fn test_nonlinear_resistor_follows_curve_of_control() {
+        // An LDR on an envelope follower, into a 10k load
+        for (control, r_ldr) in [(0.0, 1e6), (0.25, 1e5), (0.5, 1e4), (1.0, 1e3)] {
+            let mut sim = build(&format!(
+                ".input in\n.output out\n\
+                 V_IN in 0 AC\n\
+                 VCTL ctl 0 DC {}\n\
+                 R_CTL ctl 0 10k\n\
+                 ENV ENV1 ctl attack=1m release=10m\n\
+                 NLR R_LDR in out mod=ENV1 ctl=0,0.5,1 r=1M,10k,1k\n\
+                 R1 out 0 10k\n",
+                control
+            ));
+            let input = vec![1.0f32; 4800];
+            let mut output = vec![0.0f32; input.len()];
+            sim.process_block(&input, &mut output).unwrap();
+
+            let expected = 1e4 / (1e4 + r_ldr);
+            let settled = output[output.len() - 1] as f64;
+            assert!(
+                (settled / expected - 1.0).abs() < 1e-3,
+                "control {}: got {} expected {}",
+                control,
+                settled,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_parameters_cover_pot_switch_and_lfo() {
+        let mut sim = build(
+            ".input in\n.output out\n\
+             V_IN in 0 AC\n\
+             POT1 in out 0 100k 0.25\n\
+             SW1 out 0 state=0\n\
+             LFO LFO1 2 sine\n\
+             R1 out 0 10k LFO1 depth=0.5\n",
+        );
+        let params = sim.parameters();
+        let find = |name: &str| params.iter().find(|p| p.name == name).unwrap().clone();
+
+        let pot = find("POT1");
+        assert_eq!(pot.kind, ParamKind::Potentiometer);
+        assert_eq!((pot.min, pot.max, pot.value), (0.0, 1.0, 0.25));
+        let switch = find("SW1");
+        assert_eq!(switch.kind, ParamKind::Switch);
+        assert_eq!((switch.min, switch.max, switch.value), (0.0, 1.0, 0.0));
+        let lfo = find("LFO1");
+        assert_eq!(lfo.kind, ParamKind::LfoRate);
+        assert_eq!(
+            (lfo.min, lfo.max, lfo.value),
+            (ParamKind::LFO_RATE_MIN, ParamKind::LFO_RATE_MAX, 2.0)
+        );
+        assert_eq!(find("R1").kind, ParamKind::Resistance);
+        assert!(params.iter().all(|p| p.name != "V_IN"));
+
+        sim.set_parameter("POT1", 0.75).unwrap();
+        sim.set_parameter("SW1", 1.0).unwrap();
+        sim.set_parameter("LFO1", 5.0).unwrap();
+        let values: Vec<f64> = sim.parameters().iter().map(|p| p.value).collect();
+        assert_eq!(values, vec![0.75, 1.0, 10e3, 5.0]);
+
+        assert!(matches!(
+            sim.set_parameter("POT1", 1.5),
+            Err(PedalerError::InvalidParameter { .. })
+        ));
+        assert!(matches!(
+            sim.set_parameter("V_IN", 1.0),
+            Err(PedalerError::UnknownParameter { .. })
+        ));
+    }
+
+    #[test]
+    fn test_set_param_dispatches_by_field() {
+        let mut sim = build(
+            ".input in\n.output out\n\
+             V_IN in 0 AC\n\
+             POT1 in mid 0 100k 0.25\n\
+             SW1 mid 0 state=0\n\
+             LFO LFO1 2 sine\n\
+             R1 mid wet 10k LFO1 depth=0.5\n\
+             REVERB RV1 wet out mix=0.3\n",
+        );
+        sim.set_param("POT1.position", 0.75).unwrap();
+        sim.set_param("SW1.state", 1.0).unwrap();
+        sim.set_param("LFO1.rate", 5.0).unwrap();
+        sim.set_param("R1.Value", 22e3).unwrap();
+        sim.set_param("RV1.mix", 0.8).unwrap();
+        let values: Vec<f64> = sim.parameters().iter().map(|p| p.value).collect();
+        assert_eq!(values, vec![0.75, 1.0, 22e3, 5.0]);
+        assert_eq!(sim.reverbs[0].effect.params.mix, 0.8);
+
+        // Each field only applies to its own kind of component
+        for target in [
+            "POT1.state",
+            "SW1.position",
+            "RV1.rate",
+            "LFO1.mix",
+            "NOPE.mix",
+            "POT1",
+        ] {
+            let err = sim.set_param(target, 0.5).unwrap_err();
+            assert!(
+                matches!(err, PedalerError::UnknownParameter { ref name } if name == target),
+                "{}",
+                target
+            );
+        }
+        assert!(matches!(
+            sim.set_param("RV1.mix", 1.5),
+            Err(PedalerError::InvalidParameter { .. })
+        ));
+    }
+
+    /// Output level of a sidechain ducker with a steady input, after the
+    /// control input has been held at `sidechain` volts.
+    fn ducked_level(sidechain: f32) -> f64 {
+        let mut sim = build(
+            ".input in\n.output out\n.control sc\n\
+             V_IN in 0 AC\n\
+             V_SC sc 0 AC\n\
+             R_SC sc 0 10k\n\
+             ENV ENV1 sc attack=1m release=50m gain=1\n\
+             POT1 in out 0 10k position=0.1 mod=ENV1 depth=0.8\n",
+        );
+        sim.set_input(1.0);
+        sim.set_control_input("sc", sidechain).unwrap();
+        let mut out = 0.0;
+        for _ in 0..4800 {
+            out = sim.step().unwrap();
+        }
+        out as f64
+    }
+
+    #[test]
+    fn test_control_input_ducks_output() {
+        // Same audio input; only the sidechain differs
+        let open = ducked_level(0.0);
+        let ducked = ducked_level(1.0);
+        assert!((open - 0.9).abs() < 0.01, "open level {}", open);
+        assert!((ducked - 0.1).abs() < 0.01, "ducked level {}", ducked);
+
+        let mut sim = build(".input in\n.output out\nV_IN in 0 AC\nR1 in out 1k\nR2 out 0 1k\n");
+        assert!(sim.set_control_input("in", 1.0).is_err());
+    }
+
+    #[test]
+    fn test_load_impedance_attenuates_high_impedance_output() {
+        let level = |source: Option<f64>, load: Option<f64>| {
+            let mut ast =
+                dsl::parse(".input in\n.output out\nV_IN in 0 AC\nR1 in out 100k\nC1 out 0 100p\n")
+                    .unwrap();
+            if let Some(r) = source {
+                ast.add_source_impedance(r).unwrap();
+            }
+            if let Some(r) = load {
+                ast.add_load_impedance(r).unwrap();
+            }
+            let mut sim = Simulator::new(Circuit::from_ast(ast).unwrap(), 48000.0);
+            let mut out = vec![0.0f32; 4800];
+            sim.process_block(&[1.0; 4800], &mut out).unwrap();
+            out[out.len() - 1]
+        };
+
+        // Unloaded, the 100k output resistance drops nothing at DC
+        assert!((level(None, None) - 1.0).abs() < 1e-4);
+        // A 100k load halves it, and a 100k source on top leaves a third
+        assert!((level(None, Some(100e3)) - 0.5).abs() < 1e-4);
+        assert!((level(Some(100e3), Some(100e3)) - 1.0 / 3.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_invalid_input_samples_hold_last_valid_sample() {
+        let clipper = ".input in\n.output out\nV_IN in 0 AC\nR1 in out 1k\nC1 out 0 100n\nD1 out 0\nD2 0 out\n";
+        let clean: Vec<f32> = (0..480).map(|i| (i as f32 * 0.1).sin()).collect();
+        let mut corrupt = clean.clone();
+        corrupt[100] = f32::NAN;
+        corrupt[200] = f32::INFINITY;
+        corrupt[201] = f32::NEG_INFINITY;
+        // What the simulator should have seen instead
+        let mut held = clean.clone();
+        held[100] = held[99];
+        held[200] = held[199];
+        held[201] = held[199];
+
+        let render = |input: &[f32]| {
+            let mut sim = build(clipper);
+            let mut output = vec![0.0f32; input.len()];
+            sim.process_block(input, &mut output).unwrap();
+            (output, sim.invalid_input_count())
+        };
+        let (output, invalid) = render(&corrupt);
+        assert!(output.iter().all(|v| v.is_finite()));
+        assert_eq!(invalid, 3);
+        assert_eq!(output, render(&held).0);
+    }
+
+    #[test]
+    fn test_stats_count_iterations_per_sample() {
+        let input: Vec<f32> = (0..480).map(|i| 2.0 * (i as f32 * 0.05).sin()).collect();
+        let mut output = vec![0.0f32; input.len()];
+
+        let mut linear =
+            build(".input in\n.output out\nV_IN in 0 AC\nR1 in out 1k\nC1 out 0 100n\n");
+        linear.process_block(&input, &mut output).unwrap();
+        let stats = linear.stats();
+        assert_eq!(stats.samples, 480);
+        assert_eq!(stats.max_iterations, 1);
+        assert_eq!(stats.avg_iterations(), 1.0);
+        assert_eq!(stats.worst_residual, 0.0);
+
+        let mut clipper =
+            build(".input in\n.output out\nV_IN in 0 AC\nR1 in out 1k\nD1 out 0\nD2 0 out\n");
+        clipper.process_block(&input, &mut output).unwrap();
+        let stats = clipper.stats().clone();
+        assert_eq!(stats.samples, 480);
+        assert!(stats.avg_iterations() > 1.0, "{:?}", stats);
+        assert!(stats.max_iterations > 1);
+        assert!(stats.worst_residual > 0.0 && stats.worst_residual < DEFAULT_TOLERANCE);
+        assert_eq!(stats.gmin_fallbacks + stats.source_fallbacks, 0);
+        assert_eq!(stats.overruns, 0);
+
+        clipper.reset_stats();
+        assert_eq!(*clipper.stats(), SolverStats::default());
+    }
+
+    #[test]
+    fn test_stats_count_dc_guess_and_overruns() {
+        let clipper = ".input in\n.output out\nV_IN in 0 AC\nR1 in out 1k\nD1 out 0\nD2 0 out\n";
+        let input: Vec<f32> = (0..480).map(|i| 2.0 * (i as f32 * 0.05).sin()).collect();
+        let run = |config: SimulatorConfig| {
+            let circuit = Circuit::from_ast(dsl::parse(clipper).unwrap()).unwrap();
+            let mut sim = Simulator::with_config(circuit, 48000.0, config).unwrap();
+            let mut output = vec![0.0f32; input.len()];
+            sim.process_block(&input, &mut output).unwrap();
+            (sim.stats().clone(), sim.budget_overrun_count())
+        };
+
+        // The DC solve is one iteration of each sample, and the sample
+        // starting from it at least another
+        let (stats, _) =
+            run(SimulatorConfig::new().with_initial_guess(InitialGuess::DcOperatingPoint));
+        assert!(stats.total_iterations >= 2 * stats.samples, "{:?}", stats);
+
+        let (stats, overruns) = run(SimulatorConfig::new().with_time_budget(Some(1)));
+        assert!(stats.overruns > 0, "{:?}", stats);
+        assert_eq!(stats.overruns, overruns);
+        assert_eq!(stats.max_iterations, 1);
+    }
+
+    #[test]
+    fn test_lfo_value_traces_waveform() {
+        let mut sim = build(
+            ".input in\n.output out\n\
+             V_IN in 0 AC\n\
+             LFO LFO1 100 triangle\n\
+             R1 in out 10k LFO1 depth=0.5\n\
+             R2 out 0 10k\n\
+             ENV ENV1 in\n",
+        );
+        assert_eq!(sim.lfo_value("LFO1"), Some(0.5));
+        assert_eq!(sim.lfo_value("LFO2"), None);
+        assert_eq!(sim.envelope_value("ENV1"), Some(0.0));
+
+        // 100 Hz at 48 kHz: one cycle every 480 samples, peaking half way
+        let mut values = Vec::new();
+        for _ in 0..480 {
+            sim.step().unwrap();
+            values.push(sim.lfo_value("LFO1").unwrap());
+        }
+        for (i, v) in values.iter().enumerate() {
+            let phase = i as f64 / 480.0;
+            let expected = if phase < 0.5 {
+                2.0 * phase
+            } else {
+                2.0 * (1.0 - phase)
+            };
+            assert!(
+                (v - expected).abs() < 1e-9,
+                "sample {}: {} vs {}",
+                i,
+                v,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_frozen_operating_point_restores_without_solving() {
+        // Diode-string bias with a filtered supply, which takes a good part
+        // of a second to come up from rest
+        let mut sim = build(
+            ".input in\n.output a\n\
+             V_IN in 0 AC\n\
+             VCC vcc 0 DC 9\n\
+             R_F vcc f 100\n\
+             C_F f 0 100u\n\
+             R_B f a 10k\n\
+             D1 a k\n\
+             D2 k 0\n\
+             C_B a 0 10u\n\
+             C_IN in a 100n\n",
+        );
+        let cold = sim.step().unwrap();
+        sim.freeze_operating_point().unwrap();
+        let v_a = sim.node_voltage("a").unwrap();
+        let v_f = sim.node_voltage("f").unwrap();
+        assert!(v_a > 1.0 && v_f > 8.5, "not biased: a {} f {}", v_a, v_f);
+        assert!(cold.abs() < 1e-3);
+
+        // Frozen, the stage sits at its operating point from the first sample
+        let settled = |sim: &mut Simulator| {
+            let out = sim.step().unwrap() as f64;
+            assert!((out - v_a).abs() < 1e-4, "got {} expected {}", out, v_a);
+            assert!((sim.node_voltage("f").unwrap() - v_f).abs() < 1e-4);
+        };
+        settled(&mut sim);
+
+        let input: Vec<f32> = (0..2400).map(|i| 0.5 * (i as f32 * 0.05).sin()).collect();
+        let mut output = vec![0.0f32; input.len()];
+        sim.process_block(&input, &mut output).unwrap();
+        sim.set_input(0.0);
+        sim.reset_to_initial_conditions();
+        settled(&mut sim);
+
+        // Copies of the frozen circuit start biased too
+        let mut copy = Simulator::new(sim.circuit().clone(), 48000.0);
+        settled(&mut copy);
+    }
+
+    #[test]
+    fn test_dc_sweep_of_symmetric_clipper() {
+        let mut sim = build(
+            ".input in\n.output out\n\
+             V_IN in 0 AC\n\
+             C_IN in a 100n\n\
+             R1 a out 1k\n\
+             D1 out 0 SI\n\
+             D2 0 out SI\n\
+             C1 out 0 10n\n\
+             .model SI D (is=2.52e-9 n=1.752)\n",
+        );
+        let inputs: Vec<f64> = (-50..=50).map(|i| i as f64 * 0.1).collect();
+        let curve = sim.dc_sweep(&inputs).unwrap();
+
+        // The coupling cap is open at DC, so nothing gets through
+        assert!(curve.iter().all(|v| v.abs() < 1e-6), "{:?}", curve);
+
+        let mut sim = build(
+            ".input in\n.output out\n\
+             V_IN in 0 AC\n\
+             R1 in out 1k\n\
+             D1 out 0 SI\n\
+             D2 0 out SI\n\
+             C1 out 0 10n\n\
+             .model SI D (is=2.52e-9 n=1.752)\n",
+        );
+        let curve = sim.dc_sweep(&inputs).unwrap();
+
+        // Odd-symmetric and monotonic
+        for i in 0..inputs.len() {
+            assert!((curve[i] + curve[inputs.len() - 1 - i]).abs() < 1e-6);
+        }
+        assert!(curve.windows(2).all(|w| w[1] > w[0]));
+
+        // Nearly unity gain while the diodes are off, flat past the knee
+        assert!((curve[51] - 0.1).abs() < 0.01, "small-signal {}", curve[51]);
+        let top = curve[100];
+        assert!(top > 0.5 && top < 0.9, "clip level {}", top);
+        assert!(
+            top - curve[80] < 0.1,
+            "not flattening: {} -> {}",
+            curve[80],
+            top
+        );
+
+        // The sweep leaves the transient state alone
+        sim.set_input(0.0);
+        assert!(sim.step().unwrap().abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_linear_circuit_reuses_lu_factors() {
+        let src = ".input in\n.output out\nV_IN in 0 AC\nR1 in a 10k\nC1 a 0 10n\n\
+                   L1 a b 100m\nPOT1 b out 0 100k 0.5\nC2 out 0 1n\n";
+        let mut cached = build(src);
+        let mut naive = build(src);
+        naive.time_invariant = false;
+        assert!(cached.time_invariant);
+
+        let run = |sim: &mut Simulator, from: usize| -> Vec<f32> {
+            (from..from + 1000)
+                .map(|i| {
+                    sim.set_input((i as f32 * 0.07).sin());
+                    sim.step().unwrap()
+                })
+                .collect()
+        };
+        let (a, b) = (run(&mut cached, 0), run(&mut naive, 0));
+        assert_eq!(a, b);
+        assert_eq!(cached.matrix.factorization_count(), 1);
+        assert_eq!(naive.matrix.factorization_count(), 1000);
+
+        // A new pot position needs new factors, once
+        cached.set_parameter("POT1", 0.9).unwrap();
+        naive.set_parameter("POT1", 0.9).unwrap();
+        assert_eq!(run(&mut cached, 1000), run(&mut naive, 1000));
+        assert_eq!(cached.matrix.factorization_count(), 2);
+    }
+
+    #[test]
+    fn test_reset_flushes_reverb_tail() {
+        let src = ".input in\n.output out\nV_IN in 0 AC\nR1 in a 1k\nC1 a 0 100n\n\
+                   REVERB RV1 a out decay=0.9 mix=1\nR2 out 0 10k\n\
+                   LFO LFO1 3 sine\nR3 out 0 10k LFO1\n";
+        let mut sim = build(src);
+        let mut impulse = vec![0.0f32; 4800];
+        impulse[0] = 1.0;
+        let mut output = vec![0.0f32; 4800];
+        sim.process_block(&impulse, &mut output).unwrap();
+        assert!(
+            output[2400..].iter().any(|&v| v.abs() > 1e-6),
+            "no tail to flush"
+        );
+
+        // The LFO restarts too, keeping pace with a fresh simulator
+        sim.reset();
+        let silence = vec![0.0f32; 4800];
+        sim.process_block(&silence, &mut output).unwrap();
+        assert!(output.iter().all(|&v| v == 0.0));
+        let mut fresh = build(src);
+        fresh.process_block(&silence, &mut output).unwrap();
+        assert_eq!(sim.lfo_value("LFO1"), fresh.lfo_value("LFO1"));
+    }
+
+    #[test]
+    fn test_reset_matches_fresh_simulator() {
+        // A self-heating stage driven through its base, oversampled
+        let src = ".input in\n.output c\n\
+                   V_IN in 0 AC\n\
+                   VCC vcc 0 DC 9\n\
+                   VB bias 0 DC 0.68\n\
+                   R_B bias b 10\n\
+                   R_IN in b 1k\n\
+                   R_C vcc c 100\n\
+                   Q1 c b 0 GE\n\
+                   .model GE NPN (bf=100 is=1e-14 selfheat=1 rth=200 cth=1m)\n";
+        let simulator = || {
+            let circuit = Circuit::from_ast(dsl::parse(src).unwrap()).unwrap();
+            Simulator::with_config(circuit, 48000.0, SimulatorConfig::new().with_oversample(2))
+                .unwrap()
+        };
+        let input: Vec<f32> = (0..480).map(|i| 0.5 * (i as f32 * 0.1).sin()).collect();
+        let expected = simulator().transient(&input).unwrap();
+
+        // Heat the junction up and leave an input behind
+        let mut sim = simulator();
+        let warm_up = vec![0.5f32; 24000];
+        sim.transient(&warm_up).unwrap();
+        assert!(sim.circuit().components.iter().any(|c| match c {
+            Component::Bjt(q) => q.junction_temperature() > q.ambient_temp + 1.0,
+            _ => false,
+        }));
+        assert_eq!(sim.transient(&input).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_block_boundaries_do_not_change_output() {
+        let src = include_str!("../../examples/circuits/fuzz.ped");
+        let input: Vec<f32> = (0..512)
+            .map(|i| 0.8 * (2.0 * std::f32::consts::PI * 330.0 * i as f32 / 48000.0).sin())
+            .collect();
+
+        let mut whole = vec![0.0f32; 512];
+        build(src).process_block(&input, &mut whole).unwrap();
+
+        let mut split = vec![0.0f32; 512];
+        let mut sim = build(src);
+        sim.process_block(&input[..256], &mut split[..256]).unwrap();
+        sim.process_block(&input[256..], &mut split[256..]).unwrap();
+
+        assert!(whole.iter().any(|&v| v.abs() > 1e-3));
+        assert_eq!(
+            whole.iter().map(|v| v.to_bits()).collect::<Vec<_>>(),
+            split.iter().map(|v| v.to_bits()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_ammeter_reads_series_current() {
+        let mut sim = build(
+            ".input in\n.output out\n\
+             V_IN in 0 DC 0\n\
+             VB bias 0 DC 5\n\
+             AM1 bias mid\n\
+             R1 mid out 1k\n\
+             R2 out 0 1k\n\
+             R_IN in out 1M\n",
+        );
+        sim.step().unwrap();
+
+        // The ammeter current must match Ohm's law across R1
+        let i = sim.probe_current("AM1").unwrap();
+        let v_r1 = sim.node_voltage("mid").unwrap() - sim.node_voltage("out").unwrap();
+        assert!((i - v_r1 / 1000.0).abs() < 1e-9, "got {}", i);
+
+        // 5V across ~2k (the 1M input leg barely loads the divider)
+        assert!((i - 2.5e-3).abs() < 1e-5);
+        assert!(sim.probe_current("AM2").is_none());
+    }
+
+    #[test]
+    fn test_overflow_names_the_node() {
+        // 1e306 A into 1k is far past the range of f64
+        let mut sim = build(
+            ".input in\n.output out\n\
+             V_IN in 0 AC\n\
+             R1 in out 1k\n\
+             R2 out 0 1k\n\
+             I1 0 hot 1e306\n\
+             R3 hot 0 1k\n",
+        );
+        match sim.step() {
+            Err(PedalerError::NumericalOverflow { node, value }) => {
+                assert_eq!(node, "hot");
+                assert!(value.is_infinite());
+            }
+            other => panic!("expected an overflow, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_diode_drop_falls_as_temperature_rises() {
+        let drop = |celsius: f64| {
+            let ast =
+                dsl::parse(".input in\n.output out\nV_IN in 0 DC 9\nR1 in out 10k\nD1 out 0\n")
+                    .unwrap();
+            let config = SimulatorConfig::new().with_temperature(celsius);
+            let mut sim =
+                Simulator::with_config(Circuit::from_ast(ast).unwrap(), 48000.0, config).unwrap();
+            sim.step().unwrap();
+            sim.node_voltage("out").unwrap()
+        };
+        let (cold, room, hot) = (drop(-10.0), drop(27.0), drop(70.0));
+        assert!(cold > room && room > hot, "{} {} {}", cold, room, hot);
+
+        // Silicon's forward drop falls about 2mV/°C
+        let slope = (hot - cold) / 80.0;
+        assert!((-2.5e-3..-1.5e-3).contains(&slope), "{} V/°C", slope);
+
+        for celsius in [-273.15, -300.0, f64::NAN] {
+            let ast =
+                dsl::parse(".input in\n.output out\nV_IN in 0 DC 9\nR1 in out 10k\nD1 out 0\n")
+                    .unwrap();
+            let config = SimulatorConfig::new().with_temperature(celsius);
+            match Simulator::with_config(Circuit::from_ast(ast).unwrap(), 48000.0, config) {
+                Err(PedalerError::InvalidParameter { param, .. }) => {
+                    assert_eq!(param, "temperature")
+                }
+                other => panic!(
+                    "{} °C: expected InvalidParameter, got {:?}",
+                    celsius,
+                    other.map(|_| ())
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_branch_current_follows_ohms_law() {
+        let mut sim = build(
+            ".input in\n.output out\n\
+             V_IN in 0 DC 0\n\
+             VB bias 0 DC 9\n\
+             R1 bias out 4.7k\n\
+             R2 out 0 2.2k\n\
+             R_IN in out 1M\n",
+        );
+        sim.step().unwrap();
+
+        // VB supplies R1's current, which reads negative through the source
+        let v_r1 = sim.node_voltage("bias").unwrap() - sim.node_voltage("out").unwrap();
+        let i = sim.branch_current("VB").unwrap();
+        assert!((i + v_r1 / 4.7e3).abs() < 1e-12, "got {}", i);
+
+        // The raw solution holds the same value after the node voltages
+        let nodes = sim.circuit().num_nodes - 1;
+        assert!(sim.solution()[nodes..].contains(&i));
+        assert!(sim.branch_current("R1").is_none());
+    }
+
+    #[test]
+    fn test_dc_block_removes_output_offset() {
+        let src = ".input in\n.output out\nV_IN in 0 AC\nVB bias 0 DC 2\nR1 bias out 10k\nR2 in out 10k\n";
+        let circuit = Circuit::from_ast(dsl::parse(src).unwrap()).unwrap();
+        let mut sim =
+            Simulator::with_config(circuit, 48000.0, SimulatorConfig::new().with_dc_block(true))
+                .unwrap();
+
+        // A 1 kHz tone riding on 1 V of input offset, plus 1 V from the bias
+        let input: Vec<f32> = (0..48000)
+            .map(|n| 1.0 + (2.0 * std::f32::consts::PI * 1000.0 * n as f32 / 48000.0).sin())
+            .collect();
+        let mut output = vec![0.0; input.len()];
+        sim.process_block(&input, &mut output).unwrap();
+        let mean = |s: &[f32]| s.iter().sum::<f32>() / s.len() as f32;
+        assert!(
+            mean(&output[24000..]).abs() < 1e-3,
+            "mean {}",
+            mean(&output[24000..])
+        );
+        // The tone itself passes at half amplitude
+        let peak = output[24000..].iter().fold(0.0f32, |m, v| m.max(v.abs()));
+        assert!((peak - 0.5).abs() < 0.01, "peak {}", peak);
+
+        // Without the blocker the offset is still there
+        sim.set_dc_block(false);
+        sim.process_block(&input, &mut output).unwrap();
+        assert!((mean(&output) - 1.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_current_source_input_injects_sample_current() {
+        // A 100µA pickup current into 10k, divided down to the output
+        let mut sim = build(".input in\n.output out\nI_IN 0 in 0\nR1 in out 10k\nR2 out 0 10k\n");
+        assert!(matches!(
+            sim.circuit().components[sim.circuit().input_source_idx.unwrap()],
+            Component::CurrentSource(_)
+        ));
+        let out = sim.process_sample(100e-6).unwrap();
+        assert!((sim.node_voltage("in").unwrap() - 2.0).abs() < 1e-6);
+        assert!((out - 1.0).abs() < 1e-6);
+        assert_eq!(sim.dc_sweep(&[-1e-4, 2e-4]).unwrap(), vec![-1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_double_throw_switch_follows_selected_throw() {
+        // True bypass: the output is either the dry input or the effect
+        let mut sim = build(
+            ".input in\n.output out\n\
+             V_IN in 0 DC 1\n\
+             VFX fx 0 DC 2\n\
+             SW1 out in fx OPEN\n\
+             R_LOAD out 0 10k\n",
+        );
+        sim.step().unwrap();
+        let out = sim.node_voltage("out").unwrap();
+        assert!((out - 1.0).abs() < 1e-5, "got {}", out);
+
+        sim.set_switch("SW1", true).unwrap();
+        sim.step().unwrap();
+        let out = sim.node_voltage("out").unwrap();
+        assert!((out - 2.0).abs() < 1e-5, "got {}", out);
+        assert!(sim.branch_current("V_IN").unwrap().abs() < 1e-8);
+    }
+
+    #[test]
+    fn test_diode_swap_raises_clipping_threshold() {
+        let mut sim = build(
+            ".input in\n.output out\nV_IN in 0 AC\nR1 in out 1k\nD1 out 0 SOFT\nD2 0 out SOFT\n\
+             .model SOFT D (is=1e-6 n=1.3)\n",
+        );
+        let input: Vec<f32> = (0..480).map(|i| 4.0 * (i as f32 * 0.13).sin()).collect();
+        let peak = |sim: &mut Simulator| {
+            let mut output = vec![0.0f32; input.len()];
+            sim.process_block(&input, &mut output).unwrap();
+            output.iter().fold(0.0f32, |m, v| m.max(v.abs()))
+        };
+
+        sim.set_diode_model("D1", "SI").unwrap();
+        sim.set_diode_model("D2", "SI").unwrap();
+        let silicon = peak(&mut sim);
+        sim.set_diode_model("D1", "LED").unwrap();
+        sim.set_diode_model("D2", "led").unwrap();
+        let led = peak(&mut sim);
+        sim.set_diode_model("D1", "SOFT").unwrap();
+        sim.set_diode_model("D2", "SOFT").unwrap();
+        let soft = peak(&mut sim);
+
+        assert!(silicon > 0.5 && silicon < 0.9, "silicon peak {}", silicon);
+        assert!(
+            led > silicon + 0.8,
+            "LED peak {} vs silicon {}",
+            led,
+            silicon
+        );
+        assert!(soft < silicon, "soft peak {} vs silicon {}", soft, silicon);
+        assert!(matches!(
+            sim.set_diode_model("D1", "ZENER"),
+            Err(PedalerError::UndefinedModel { .. })
+        ));
+        assert!(matches!(
+            sim.set_diode_model("R1", "SI"),
+            Err(PedalerError::UnknownParameter { .. })
+        ));
+    }
+
+    fn inverting_amp(model: &str) -> Simulator {
+        build(&format!(
+            ".input in\n.output out\n\
+             .model OPX OP ({})\n\
+             V_IN in 0 DC 0\n\
+             R_IN in inv 10k\n\
+             R_F inv out 47k\n\
+             OP1 out 0 inv OPX\n",
+            model
+        ))
+    }
+
+    #[test]
+    fn test_nullor_inverting_gain_is_exact() {
+        let mut sim = inverting_amp("nullor=1");
+        for &vin in &[0.1f32, -0.25, 0.5] {
+            sim.set_input(vin);
+            let out = sim.step().unwrap() as f64;
+            let expected = -(47e3 / 10e3) * vin as f64;
+            assert!(
+                (out - expected).abs() < 1e-6,
+                "got {} expected {}",
+                out,
+                expected
+            );
+            assert!(sim.node_voltage("inv").unwrap().abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_vccs_inverting_gain_is_finite() {
+        let mut sim = inverting_amp("gain=1000");
+        sim.set_input(0.1);
+        let out = sim.step().unwrap() as f64;
+        // Finite open-loop gain leaves a visible error against -Rf/Rin
+        assert!(out < -0.46 && out > -0.47, "got {}", out);
+    }
+
+    #[test]
+    fn test_digital_effects_stay_stamped_during_iteration() {
+        // The clipper needs Newton-Raphson, which restamps every iteration
+        let mut sim = build(
+            ".input in\n.output out\nV_IN in 0 AC\nR1 in clip 1k\nD1 clip 0\nD2 0 clip\n\
+             DELAY d1 clip out 1m mix=1\nR_LOAD out 0 10k\n",
+        );
+        let input: Vec<f32> = (0..480).map(|i| 2.0 * (i as f32 * 0.1).sin()).collect();
+        let mut output = vec![0.0f32; input.len()];
+        sim.process_block(&input, &mut output).unwrap();
+        // The delay adds its echo on top of the clipped input
+        let peak = output.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+        assert!(peak > 1.0 && peak < 1.5, "peak {}", peak);
+    }
+
+    #[test]
+    fn test_opamp_output_saturates_at_rails() {
+        // Gain -4.7 with 9 V rails: the output clips at +/-8.5 V
+        // The dominant pole's state must not wind up while clipped
+        for model in [
+            "vpos=9 vneg=-9",
+            "gain=2e5 rout=75 vpos=9 vneg=-9",
+            "gbw=1e7 vpos=9 vneg=-9",
+            "gain=2e5 rout=75 gbw=1e7 vpos=9 vneg=-9",
+        ] {
+            let mut sim = inverting_amp(model);
+            let mut peak = (0.0f64, 0.0f64);
+            for i in 0..480 {
+                let vin = 3.0 * (i as f32 * 0.05).sin();
+                sim.set_input(vin);
+                let out = sim.step().unwrap() as f64;
+                peak = (peak.0.min(out), peak.1.max(out));
+                if vin.abs() < 1.5 {
+                    assert!(
+                        (out + 4.7 * vin as f64).abs() < 0.01,
+                        "{}: {} at {}",
+                        model,
+                        out,
+                        vin
+                    );
+                }
+            }
+            // Rout drops a little of the rail into the feedback network
+            assert!(
+                (peak.0 + 8.5).abs() < 0.05 && (peak.1 - 8.5).abs() < 0.05,
+                "{}: {:?}",
+                model,
+                peak
+            );
+        }
+    }
+
+    #[test]
+    fn test_opamp_output_is_slew_limited() {
+        // 0.01 V/us ramps the -4.7 V/V step response over about 23 samples
+        for model in ["slew=0.01", "gain=2e5 rout=75 slew=0.01"] {
+            let mut sim = inverting_amp(model);
+            let max_rise = 0.01 * 1e6 * sim.dt;
+            sim.set_input(-1.0);
+            let mut prev = 0.0;
+            let mut slewing = 0;
+            for _ in 0..48 {
+                let out = sim.step().unwrap() as f64;
+                let rise = out - prev;
+                assert!(
+                    rise >= -1e-6 && rise <= max_rise * 1.001,
+                    "{}: rise {} > {}",
+                    model,
+                    rise,
+                    max_rise
+                );
+                if rise > max_rise * 0.99 {
+                    slewing += 1;
+                }
+                prev = out;
+            }
+            assert!(slewing >= 20, "{}: slewed for {} samples", model, slewing);
+            assert!((prev - 4.7).abs() < 0.01, "{}: settled at {}", model, prev);
+        }
+    }
+
+    #[test]
+    fn test_opamp_gbw_limits_closed_loop_bandwidth() {
+        // A noise gain of 5.7 on a 100 kHz GBW puts the closed-loop pole at
+        // 17.5 kHz; at 5 kHz (5.18 kHz once the trapezoidal rule warps it)
+        // the -4.7 V/V gain is down to 4.51 and lags by 16.5 degrees
+        for model in ["gbw=1e5", "gain=2e5 rout=75 gbw=1e5"] {
+            let mut sim = inverting_amp(model);
+            let w = 2.0 * std::f64::consts::PI * 5000.0 * sim.dt;
+            let (mut re, mut im) = (0.0, 0.0);
+            for n in 0..960 {
+                sim.set_input((0.1 * (w * n as f64).sin()) as f32);
+                let out = sim.step().unwrap() as f64;
+                // Correlate over the last 50 cycles, once the start has settled
+                if n >= 480 {
+                    re += out * (w * n as f64).sin();
+                    im += out * (w * n as f64).cos();
+                }
+            }
+            let gain = (re * re + im * im).sqrt() / 240.0 / 0.1;
+            let expected = 4.7 / (1.0 + (5.7 * 5180.0 / 1e5f64).powi(2)).sqrt();
+            assert!(
+                (gain - expected).abs() < 0.02,
+                "{}: gain {} vs {}",
+                model,
+                gain,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_ideal_opamp_high_gain_stays_well_conditioned() {
+        // Closed-loop gain of -1e4 around the default ideal op-amp (A = 1e9)
+        let mut sim = build(
+            ".input in\n.output out\n\
+             V_IN in 0 DC 0\n\
+             R_IN in inv 100\n\
+             R_F inv out 1M\n\
+             OP1 out 0 inv\n",
+        );
+        let (gain, a) = (1e6 / 100.0, 1e9);
+        for &vin in &[1e-4f32, -5e-4, 1e-3] {
+            sim.set_input(vin);
+            let out = sim.step().unwrap() as f64;
+            let expected = -gain * vin as f64 / (1.0 + (1.0 + gain) / a);
+            assert!(
+                (out / expected - 1.0).abs() < 1e-6,
+                "got {} expected {}",
+                out,
+                expected
+            );
+            // A transconductance of A / Rout = 1e10 next to the 1 Mohm
+            // feedback would leave 16 decades between entries and pivots
+            let largest = sim.matrix.a.iter().fold(0.0f64, |m, v| m.max(v.abs()));
+            let spread = largest / sim.matrix.min_pivot();
+            assert!(spread < 1e8, "pivot spread {:e}", spread);
+        }
+    }
+
+    /// Rheostat-wired pot into a cap, with the wiper driven by ENV1 following `ctl`.
+    fn env_swept_lowpass(control_volts: f64) -> Simulator {
+        build(&format!(
+            ".input in\n.output out\n\
+             V_IN in 0 DC 0\n\
+             VCTL ctl 0 DC {}\n\
+             R_CTL ctl 0 10k\n\
+             ENV ENV1 ctl attack=1m release=10m\n\
+             POT1 in out out 100k position=0.2 mod=ENV1 depth=0.6\n\
+             C1 out 0 1n\n",
+            control_volts
+        ))
+    }
+
+    fn pot_position(sim: &Simulator, name: &str) -> f64 {
+        sim.circuit()
+            .components
+            .iter()
+            .find_map(|c| match c {
+                Component::Potentiometer(p) if p.name == name => Some(p.position),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    fn sine_rms(sim: &mut Simulator, freq: f64, samples: usize) -> f64 {
+        let mut sum = 0.0;
+        for n in 0..samples {
+            let t = n as f64 / 48000.0;
+            sim.set_input((2.0 * std::f64::consts::PI * freq * t).sin() as f32);
+            let y = sim.step().unwrap() as f64;
+            if n >= samples / 2 {
+                sum += y * y;
+            }
+        }
+        (sum / (samples / 2) as f64).sqrt()
+    }
+
+    #[test]
+    fn test_envelope_sweeps_pot_cutoff() {
+        let mut quiet = env_swept_lowpass(0.0);
+        let mut loud = env_swept_lowpass(1.0);
+
+        let rms_quiet = sine_rms(&mut quiet, 4000.0, 4800);
+        let rms_loud = sine_rms(&mut loud, 4000.0, 4800);
+
+        // The envelope pushes the wiper from 0.2 to 0.8
+        assert!((pot_position(&quiet, "POT1") - 0.2).abs() < 1e-9);
+        assert!((pot_position(&loud, "POT1") - 0.8).abs() < 1e-3);
+
+        // 20k vs 80k into 1nF: cutoff drops from ~8kHz to ~2kHz
+        assert!(
+            rms_loud < rms_quiet * 0.6,
+            "quiet {} loud {}",
+            rms_quiet,
+            rms_loud
+        );
+    }
+
+    #[test]
+    fn test_lfo_sweeps_capacitance() {
+        let mut sim = build(
+            ".input in\n.output out\n\
+             V_IN in 0 DC 0\n\
+             LFO LFO1 100 sine\n\
+             R1 in out 10k\n\
+             C1 out 0 10n mod=LFO1 depth=0.5 range=2\n",
+        );
+        let (mut lowest, mut highest) = (f64::INFINITY, 0.0f64);
+        for _ in 0..480 {
+            sim.step().unwrap();
+            let c = sim
+                .circuit()
+                .components
+                .iter()
+                .find_map(|c| match c {
+                    Component::Capacitor(c) => Some(c.effective_capacitance),
+                    _ => None,
+                })
+                .unwrap();
+            lowest = lowest.min(c);
+            highest = highest.max(c);
+        }
+        // One LFO cycle sweeps 10n up to 10n * (1 + 0.5 * 2)
+        assert!((lowest - 10e-9).abs() < 1e-12, "lowest {}", lowest);
+        assert!((highest - 20e-9).abs() < 1e-11, "highest {}", highest);
+    }
+
+    #[test]
+    fn test_pot_position_tracks_lfo() {
+        let mut sim = build(
+            ".input in\n.output out\n\
+             V_IN in 0 DC 0\n\
+             LFO LFO1 100 triangle\n\
+             POT1 in out 0 100k position=0.2 mod=LFO1 depth=0.5\n",
+        );
+        let (mut lowest, mut highest) = (1.0f64, 0.0f64);
+        for _ in 0..480 {
+            sim.step().unwrap();
+            let position = pot_position(&sim, "POT1");
+            lowest = lowest.min(position);
+            highest = highest.max(position);
+        }
+        assert!((lowest - 0.2).abs() < 1e-3, "lowest {}", lowest);
+        assert!((highest - 0.7).abs() < 1e-3, "highest {}", highest);
+    }
+
+    /// Magnitude at the sweep bin closest to `freq`.
+    fn gain_at(bins: &[(f64, f64)], freq: f64) -> f64 {
+        bins.iter()
+            .min_by(|a, b| (a.0 - freq).abs().total_cmp(&(b.0 - freq).abs()))
+            .unwrap()
+            .1
+    }
+
+    #[test]
+    fn test_sweep_response_matches_rc_lowpass() {
+        let mut sim =
+            build(".input in\n.output out\nV_IN in 0 DC 0\nR1 in out 1k\nC1 out 0 100n\n");
+        let bins = sim.sweep_response(20.0, 20000.0, 0.5, 1.0).unwrap();
+
+        let fc = 1.0 / (2.0 * std::f64::consts::PI * 1e3 * 100e-9);
+        for &f in &[100.0, 1000.0, fc, 5000.0] {
+            let expected = 1.0 / (1.0 + (f / fc).powi(2)).sqrt();
+            let db_error = 20.0 * (gain_at(&bins, f) / expected).log10();
+            assert!(db_error.abs() < 0.5, "{} Hz off by {} dB", f, db_error);
+        }
+    }
+
+    #[test]
+    fn test_impulse_response_decays_with_rc_time_constant() {
+        let mut sim =
+            build(".input in\n.output out\nV_IN in 0 DC 0\nR1 in out 1k\nC1 out 0 100n\n");
+        // Processing beforehand doesn't change the capture
+        sim.process_sample(0.7).unwrap();
+        let ir = sim.impulse_response(480).unwrap();
+        assert_eq!(ir, sim.impulse_response(480).unwrap());
+
+        let (rc, fs) = (1e3 * 100e-9, 48000.0);
+        for n in [5, 10, 20, 40] {
+            let expected = (-(n as f64) / fs / rc).exp() / (rc * fs);
+            let error = ir[n] as f64 / expected - 1.0;
+            assert!(
+                error.abs() < 0.02,
+                "sample {} off by {:.1}%",
+                n,
+                100.0 * error
+            );
+        }
+    }
+
+    #[test]
+    fn test_sweep_response_shows_clipping_compression() {
+        let src = ".model DCLIP D (is=1e-9 n=1.8)\n.input in\n.output out\n\
+                   V_IN in 0 DC 0\nR1 in out 10k\nD1 out 0 DCLIP\nD2 0 out DCLIP\n";
+        let quiet = build(src).sweep_response(100.0, 5000.0, 0.01, 0.5).unwrap();
+        let loud = build(src).sweep_response(100.0, 5000.0, 2.0, 0.5).unwrap();
+
+        // Small signals pass almost untouched; large ones are clipped down
+        assert!(gain_at(&quiet, 1000.0) > 0.8);
+        assert!(gain_at(&loud, 1000.0) < 0.6 * gain_at(&quiet, 1000.0));
+    }
+
+    /// Fraction of the output power that aliased: power at the folded
+    /// images of harmonics above Nyquist, over all power.
+    fn aliased_power(oversample: usize) -> f64 {
+        use crate::analysis::{fft, Complex};
+
+        // 343 cycles in 4096 samples (about 4 kHz), so every harmonic and
+        // every alias lands exactly on a bin
+        let (n, cycles) = (4096, 343);
+        let src = ".input in\n.output out\nV_IN in 0 AC\nR1 in out 1k\nD1 out 0\nD2 0 out\n";
+        let circuit = Circuit::from_ast(dsl::parse(src).unwrap()).unwrap();
+        let config = SimulatorConfig::new().with_oversample(oversample);
+        let mut sim = Simulator::with_config(circuit, 48000.0, config).unwrap();
+        let x =
+            |i: usize| 5.0 * (2.0 * std::f64::consts::PI * (cycles * i) as f64 / n as f64).sin();
+        for i in 0..n {
+            sim.process_sample(x(i) as f32).unwrap();
+        }
+        let mut spectrum: Vec<Complex> = (0..n)
+            .map(|i| Complex::new(sim.process_sample(x(i) as f32).unwrap() as f64, 0.0))
+            .collect();
+        fft(&mut spectrum);
+
+        let power = |bin: usize| spectrum[bin].norm_sqr();
+        let total: f64 = (1..n / 2).map(power).sum();
+        let aliases: std::collections::HashSet<usize> = (1..200)
+            .map(|k| k * cycles)
+            .filter(|&h| h > n / 2)
+            .map(|h| h % n)
+            .map(|b| if b > n / 2 { n - b } else { b })
+            .filter(|b| b % cycles != 0)
+            .collect();
+        aliases.iter().map(|&b| power(b)).sum::<f64>() / total
+    }
+
+    #[test]
+    fn test_oversampling_reduces_clipping_aliases() {
+        let (plain, oversampled) = (aliased_power(1), aliased_power(4));
+        assert!(plain > 1e-3, "hard clipping should alias at 1x: {}", plain);
+        assert!(
+            oversampled < plain / 100.0,
+            "1x {:.3e} vs 4x {:.3e}",
+            plain,
+            oversampled
+        );
+    }
+
+    #[test]
+    fn test_stereo_channels_are_isolated() {
+        // Independent RC lowpasses on the left and right channels
+        let stereo = ".input inL\n.input inR\n.output outL\n.output outR\n\
+                      V_L inL 0 AC\nV_R inR 0 AC\n\
+                      R1 inL outL 10k\nC1 outL 0 10n\n\
+                      R2 inR outR 1k\nC2 outR 0 100n\n";
+        let mut sim = build(stereo);
+        assert_eq!((sim.input_channels(), sim.output_channels()), (2, 2));
+
+        // An impulse on the left only leaves the right silent
+        let impulse: Vec<f32> = (0..64).map(|i| if i == 0 { 1.0 } else { 0.0 }).collect();
+        let silence = vec![0.0f32; 64];
+        let (mut left, mut right) = (vec![0.0f32; 64], vec![0.0f32; 64]);
+        sim.process_block_multi(&[&impulse, &silence], &mut [&mut left, &mut right])
+            .unwrap();
+        assert!(left[1..].iter().all(|&v| v > 0.0));
+        assert!(right.iter().all(|&v| v == 0.0));
+
+        // Each channel matches its filter simulated on its own
+        let mut sim = build(stereo);
+        let mut mono = build(".input in\n.output out\nV_IN in 0 AC\nR2 in out 1k\nC2 out 0 100n\n");
+        let mut expected = vec![0.0f32; 64];
+        mono.process_block(&impulse, &mut expected).unwrap();
+        sim.process_block_multi(&[&silence, &impulse], &mut [&mut left, &mut right])
+            .unwrap();
+        assert!(left.iter().all(|&v| v == 0.0));
+        for (y, x) in right.iter().zip(&expected) {
+            assert!((y - x).abs() < 1e-6, "{} vs {}", y, x);
+        }
+
+        // The channel counts must match the circuit
+        let err = sim
+            .process_block_multi(&[&impulse], &mut [&mut left, &mut right])
+            .unwrap_err();
+        assert!(matches!(err, PedalerError::InvalidSimulationParam { .. }));
+    }
+}
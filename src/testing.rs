@@ -0,0 +1,163 @@
+//! Golden-output testing helpers (`testing` feature).
+//!
+//! The usual way to check that a circuit has not changed behavior is to
+//! render a known input, and compare the result against a stored reference:
+//!
+//! ```ignore
+//! use pedaler_core::testing::{fingerprint, render, test_stimulus};
+//!
+//! let output = render(include_str!("fuzz.ped"), 48000.0, &test_stimulus(48000.0))?;
+//! assert_eq!(fingerprint(&output), 0x1f2e_3d4c_5b6a_7988);
+//! ```
+//!
+//! A [`fingerprint`] is exact and cheap to store, but any change to the
+//! quantized output changes it. [`compare_with_tolerance`] against a stored
+//! rendering says how far apart two outputs are, which is the better check
+//! across platforms whose floating point differs in the last bits.
+
+use std::f64::consts::PI;
+
+use crate::error::Result;
+use crate::{circuit, dsl, Circuit, Simulator};
+
+/// Decimal places samples are rounded to before fingerprinting.
+pub const FINGERPRINT_DECIMALS: i32 = 6;
+
+/// Length of [`test_stimulus`] in seconds.
+pub const STIMULUS_DURATION: f64 = 0.5;
+
+/// The standard test stimulus at `sample_rate`.
+///
+/// The first half is an exponential sine sweep from 20 Hz to 20 kHz at
+/// 0.1 V, which probes the small-signal response. The second half is a
+/// decaying 110 Hz tone starting at 1 V, which drives clipping stages hard
+/// and then lets them recover. The signal is computed in `f64`, so it is
+/// identical on every platform.
+pub fn test_stimulus(sample_rate: f32) -> Vec<f32> {
+    let fs = sample_rate as f64;
+    let n = (STIMULUS_DURATION * fs).round() as usize;
+    let half = n / 2;
+    let sweep_len = half as f64 / fs;
+    let (f_start, f_end) = (20.0, (20_000.0f64).min(fs / 2.0));
+    let l = sweep_len / (f_end / f_start).ln();
+
+    (0..n)
+        .map(|i| {
+            if i < half {
+                let t = i as f64 / fs;
+                0.1 * (2.0 * PI * f_start * l * ((t / l).exp() - 1.0)).sin()
+            } else {
+                let t = (i - half) as f64 / fs;
+                (-t / 0.05).exp() * (2.0 * PI * 110.0 * t).sin()
+            }
+        })
+        .map(|v| v as f32)
+        .collect()
+}
+
+/// Parse, build and validate a circuit, and render `input` through it.
+pub fn render(source: &str, sample_rate: f32, input: &[f32]) -> Result<Vec<f32>> {
+    let circuit = Circuit::from_ast(dsl::parse(source)?)?;
+    circuit::validate_circuit(&circuit)?;
+    let mut simulator = Simulator::new(circuit, sample_rate);
+    let mut output = vec![0.0f32; input.len()];
+    simulator.process_block(input, &mut output)?;
+    Ok(output)
+}
+
+/// A stable 64-bit fingerprint of a rendered output.
+///
+/// Samples are rounded to [`FINGERPRINT_DECIMALS`] places and hashed with
+/// FNV-1a, which, unlike the standard library hasher, is fixed across Rust
+/// releases, so stored fingerprints stay valid.
+pub fn fingerprint(output: &[f32]) -> u64 {
+    const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let scale = 10f64.powi(FINGERPRINT_DECIMALS);
+    let mut hash = FNV_OFFSET;
+    for &sample in output {
+        // Rounding first folds -0.0 into 0 and last-bit noise into one step
+        let quantized = (sample as f64 * scale).round() as i64;
+        for byte in quantized.to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+    hash
+}
+
+/// Result of comparing an output against a reference rendering.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoldenComparison {
+    /// Largest absolute per-sample difference
+    pub max_error: f32,
+    /// Index of the first sample differing by more than the tolerance, or
+    /// of the first missing sample if the lengths differ
+    pub first_mismatch: Option<usize>,
+}
+
+impl GoldenComparison {
+    /// Whether the output matches the reference within the tolerance.
+    pub fn is_match(&self) -> bool {
+        self.first_mismatch.is_none()
+    }
+}
+
+/// Compare `output` against `reference`, sample by sample.
+///
+/// Outputs of different lengths never match.
+pub fn compare_with_tolerance(
+    output: &[f32],
+    reference: &[f32],
+    tolerance: f32,
+) -> GoldenComparison {
+    let mut max_error = 0.0f32;
+    let mut first_mismatch = None;
+    for (i, (&a, &b)) in output.iter().zip(reference).enumerate() {
+        let error = (a - b).abs();
+        max_error = max_error.max(error);
+        if first_mismatch.is_none() && (error > tolerance || error.is_nan()) {
+            first_mismatch = Some(i);
+        }
+    }
+    if first_mismatch.is_none() && output.len() != reference.len() {
+        first_mismatch = Some(output.len().min(reference.len()));
+    }
+
+    GoldenComparison {
+        max_error,
+        first_mismatch,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clipper(r1: &str) -> String {
+        format!(
+            ".input in\n.output out\nV_IN in 0 AC\nR1 in out {}\nC1 out 0 10n\nD1 out 0\nD2 0 out\n",
+            r1
+        )
+    }
+
+    #[test]
+    fn test_harness_matches_identical_and_flags_changed_circuit() {
+        let stimulus = test_stimulus(48000.0);
+        assert_eq!(stimulus.len(), 24000);
+
+        let reference = render(&clipper("10k"), 48000.0, &stimulus).unwrap();
+        let same = render(&clipper("10k"), 48000.0, &stimulus).unwrap();
+        let changed = render(&clipper("12k"), 48000.0, &stimulus).unwrap();
+
+        assert_eq!(fingerprint(&same), fingerprint(&reference));
+        assert!(compare_with_tolerance(&same, &reference, 1e-6).is_match());
+
+        assert_ne!(fingerprint(&changed), fingerprint(&reference));
+        let cmp = compare_with_tolerance(&changed, &reference, 1e-4);
+        assert!(!cmp.is_match());
+        assert!(cmp.max_error > 1e-4);
+        assert!(!compare_with_tolerance(&same[1..], &reference, 1.0).is_match());
+    }
+}
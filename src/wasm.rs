@@ -18,13 +18,21 @@
 //!   R2 out 0 10k
 //! `;
 //!
-//! const sim = new WasmPedalSim(circuitDsl, 48000);
+//! // AudioWorklet render quanta are 128 samples
+//! const sim = new WasmPedalSim(circuitDsl, 48000, 128);
 //!
 //! // In AudioWorkletProcessor.process():
 //! const input = inputBuffer.getChannelData(0);
 //! const output = outputBuffer.getChannelData(0);
 //! sim.process_block(input, output);
 //! ```
+//!
+//! ## Real-Time Safety
+//!
+//! All working storage is allocated when the simulator is constructed, sized
+//! for the declared maximum block size. `process_block` does not allocate,
+//! so it is safe to call from the audio thread. Blocks longer than the
+//! declared maximum are processed in chunks of that size.
 
 use wasm_bindgen::prelude::*;
 
@@ -45,6 +53,10 @@ pub fn init_panic_hook() {
 #[wasm_bindgen]
 pub struct WasmPedalSim {
     simulator: Simulator,
+    /// Staged input samples, `max_block_size` long
+    input_scratch: Vec<f32>,
+    /// Processed output samples, `max_block_size` long
+    output_scratch: Vec<f32>,
 }
 
 #[wasm_bindgen]
@@ -54,17 +66,22 @@ impl WasmPedalSim {
     /// # Arguments
     /// * `circuit_dsl` - The circuit description in Pedaler DSL format
     /// * `sample_rate` - Audio sample rate in Hz (typically 44100 or 48000)
+    /// * `max_block_size` - Largest block that will be passed to `process_block`
     ///
     /// # Returns
     /// A new `WasmPedalSim` instance or an error if the circuit is invalid.
     ///
     /// # Example
     /// ```javascript
-    /// const sim = new WasmPedalSim(circuitDsl, 48000);
+    /// const sim = new WasmPedalSim(circuitDsl, 48000, 128);
     /// ```
     #[wasm_bindgen(constructor)]
-    pub fn new(circuit_dsl: &str, sample_rate: f32) -> Result<WasmPedalSim, JsValue> {
-        Self::with_config(circuit_dsl, sample_rate, 50, 1e-4)
+    pub fn new(
+        circuit_dsl: &str,
+        sample_rate: f32,
+        max_block_size: usize,
+    ) -> Result<WasmPedalSim, JsValue> {
+        Self::with_config(circuit_dsl, sample_rate, max_block_size, 50, 1e-4)
     }
 
     /// Create a new simulator with custom Newton-Raphson configuration.
@@ -72,21 +89,26 @@ impl WasmPedalSim {
     /// # Arguments
     /// * `circuit_dsl` - The circuit description in Pedaler DSL format
     /// * `sample_rate` - Audio sample rate in Hz
+    /// * `max_block_size` - Largest block that will be passed to `process_block`
     /// * `max_iterations` - Maximum Newton-Raphson iterations (default: 50)
     /// * `tolerance` - Convergence tolerance in volts (default: 1e-4)
     #[wasm_bindgen]
     pub fn with_config(
         circuit_dsl: &str,
         sample_rate: f32,
+        max_block_size: usize,
         max_iterations: usize,
         tolerance: f64,
     ) -> Result<WasmPedalSim, JsValue> {
+        if max_block_size == 0 {
+            return Err(JsValue::from_str("max_block_size must be at least 1"));
+        }
+
         // Parse the DSL
         let ast = dsl::parse(circuit_dsl).map_err(|e| JsValue::from_str(&e.to_string()))?;
 
         // Build the circuit
-        let circuit =
-            Circuit::from_ast(ast).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let circuit = Circuit::from_ast(ast).map_err(|e| JsValue::from_str(&e.to_string()))?;
 
         // Validate
         crate::circuit::validate_circuit(&circuit)
@@ -96,15 +118,21 @@ impl WasmPedalSim {
         let config = SimulatorConfig::new()
             .with_max_iterations(max_iterations)
             .with_tolerance(tolerance);
-        let simulator = Simulator::with_config(circuit, sample_rate, config);
+        let simulator = Simulator::with_config(circuit, sample_rate, config)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
 
-        Ok(WasmPedalSim { simulator })
+        Ok(WasmPedalSim {
+            simulator,
+            input_scratch: vec![0.0; max_block_size],
+            output_scratch: vec![0.0; max_block_size],
+        })
     }
 
     /// Process a block of audio samples.
     ///
     /// This is the main processing function, designed to be called from
-    /// an AudioWorkletProcessor's `process()` method.
+    /// an AudioWorkletProcessor's `process()` method. It never allocates;
+    /// blocks longer than `max_block_size` are processed in chunks.
     ///
     /// # Arguments
     /// * `input` - Input audio samples (mono, f32)
@@ -125,11 +153,50 @@ impl WasmPedalSim {
     /// ```
     #[wasm_bindgen]
     pub fn process_block(&mut self, input: &[f32], output: &mut [f32]) {
-        // Process each sample
         let len = input.len().min(output.len());
-        for i in 0..len {
-            self.simulator.set_input(input[i]);
-            output[i] = self.simulator.step().unwrap_or(0.0);
+        let chunk_size = self.input_scratch.len();
+
+        for start in (0..len).step_by(chunk_size) {
+            let n = chunk_size.min(len - start);
+            let staged = &mut self.input_scratch[..n];
+            staged.copy_from_slice(&input[start..start + n]);
+
+            for (sample, out) in staged.iter().zip(&mut self.output_scratch[..n]) {
+                *out = self.simulator.process_sample(*sample).unwrap_or(0.0);
+            }
+
+            output[start..start + n].copy_from_slice(&self.output_scratch[..n]);
+        }
+    }
+
+    /// Process interleaved multi-channel audio, for circuits with several
+    /// `.input` or `.output` directives.
+    ///
+    /// `input` holds one sample per input channel for each frame, and
+    /// `output` receives one sample per output channel for each frame, in
+    /// declaration order. Frames that do not fit in `output` are skipped.
+    /// Like `process_block`, this never allocates.
+    ///
+    /// # Example (AudioWorklet, stereo)
+    /// ```javascript
+    /// const [left, right] = inputs[0];
+    /// for (let i = 0; i < left.length; i++) {
+    ///   this.frames[2 * i] = left[i];
+    ///   this.frames[2 * i + 1] = right[i];
+    /// }
+    /// this.sim.process_interleaved(this.frames, this.result);
+    /// ```
+    #[wasm_bindgen]
+    pub fn process_interleaved(&mut self, input: &[f32], output: &mut [f32]) {
+        let inputs = self.simulator.input_channels();
+        let outputs = self.simulator.output_channels();
+        for (frame_in, frame_out) in input
+            .chunks_exact(inputs)
+            .zip(output.chunks_exact_mut(outputs))
+        {
+            if self.simulator.process_frame(frame_in, frame_out).is_err() {
+                frame_out.fill(0.0);
+            }
         }
     }
 
@@ -150,12 +217,177 @@ impl WasmPedalSim {
         output
     }
 
+    /// Get the largest block size scratch storage was allocated for.
+    #[wasm_bindgen(getter)]
+    pub fn max_block_size(&self) -> usize {
+        self.input_scratch.len()
+    }
+
     /// Get the sample rate this simulator was configured with.
     #[wasm_bindgen(getter)]
     pub fn sample_rate(&self) -> f32 {
         self.simulator.sample_rate()
     }
 
+    /// Get the number of audio input channels, one per `.input`.
+    #[wasm_bindgen(getter)]
+    pub fn input_channels(&self) -> usize {
+        self.simulator.input_channels()
+    }
+
+    /// Get the number of audio output channels, one per `.output`.
+    #[wasm_bindgen(getter)]
+    pub fn output_channels(&self) -> usize {
+        self.simulator.output_channels()
+    }
+
+    /// Get the number of NaN or infinite input samples replaced so far.
+    #[wasm_bindgen(getter)]
+    pub fn invalid_input_count(&self) -> u64 {
+        self.simulator.invalid_input_count()
+    }
+
+    /// Get the number of samples that ran out of their time budget.
+    #[wasm_bindgen(getter)]
+    pub fn budget_overrun_count(&self) -> u64 {
+        self.simulator.budget_overrun_count()
+    }
+
+    /// Get the number of nonlinear components (diodes, transistors, and
+    /// op-amps, which clip at their rails).
+    ///
+    /// Nonlinear circuits need Newton-Raphson iteration every sample, so
+    /// hosts can use this to predict CPU load.
+    #[wasm_bindgen(getter)]
+    pub fn nonlinear_component_count(&self) -> usize {
+        self.simulator.circuit().nonlinear_component_count()
+    }
+
+    /// Whether the circuit has any nonlinear components.
+    #[wasm_bindgen(getter)]
+    pub fn has_nonlinear(&self) -> bool {
+        self.simulator.circuit().has_nonlinear()
+    }
+
+    /// Whether the circuit has capacitors or inductors.
+    #[wasm_bindgen(getter)]
+    pub fn has_reactive(&self) -> bool {
+        self.simulator.circuit().has_reactive()
+    }
+
+    /// Whether any component is modulated by an LFO or envelope follower.
+    #[wasm_bindgen(getter)]
+    pub fn has_modulation(&self) -> bool {
+        self.simulator.circuit().has_modulation()
+    }
+
+    /// Set a pot position, switch state, LFO rate or component value by name.
+    ///
+    /// Pots take 0-1, switches close at 0.5 and above, LFO rates are in Hz
+    /// and component values are in ohms, farads or henries.
+    #[wasm_bindgen]
+    pub fn set_parameter(&mut self, name: &str, value: f64) -> Result<(), JsValue> {
+        self.simulator
+            .set_parameter(name, value)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Set a parameter by a `"NAME.field"` target, e.g. `"POT1.position"`,
+    /// `"SW1.state"`, `"LFO1.rate"` or `"RV1.mix"`, for automation.
+    #[wasm_bindgen]
+    pub fn set_param(&mut self, target: &str, value: f64) -> Result<(), JsValue> {
+        self.simulator
+            .set_param(target, value)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Turn a pot, from 0.0 to 1.0; positions outside are clamped to the
+    /// pot's 0.001-0.999 travel. Cheap enough to call from every knob event.
+    ///
+    /// # Example
+    /// ```javascript
+    /// knob.addEventListener('input', (e) => sim.set_pot('POT_GAIN', e.target.value / 100));
+    /// ```
+    #[wasm_bindgen]
+    pub fn set_pot(&mut self, name: &str, position: f64) -> Result<(), JsValue> {
+        self.simulator
+            .set_pot(name, position)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Open or close a switch.
+    ///
+    /// # Example
+    /// ```javascript
+    /// toggle.addEventListener('change', (e) => sim.set_switch('SW_BYPASS', e.target.checked));
+    /// ```
+    #[wasm_bindgen]
+    pub fn set_switch(&mut self, name: &str, closed: bool) -> Result<(), JsValue> {
+        self.simulator
+            .set_switch(name, closed)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Swap a diode for a `.model` of the netlist or a built-in preset
+    /// (`SI`, `GE` or `LED`), like a clipping selector switch.
+    #[wasm_bindgen]
+    pub fn set_diode_model(&mut self, name: &str, model: &str) -> Result<(), JsValue> {
+        self.simulator
+            .set_diode_model(name, model)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Cap the Newton-Raphson iterations of each sample, so a pathological
+    /// sample cannot blow the audio deadline; `undefined` removes the limit.
+    #[wasm_bindgen]
+    pub fn set_time_budget(&mut self, iterations: Option<usize>) {
+        self.simulator.set_time_budget(iterations);
+    }
+
+    /// Set the voltage on a control input declared with `.control`, such as
+    /// a sidechain. The value holds until it is set again.
+    #[wasm_bindgen]
+    pub fn set_control_input(&mut self, name: &str, value: f32) -> Result<(), JsValue> {
+        self.simulator
+            .set_control_input(name, value)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Clear all simulation state between takes: capacitor and inductor
+    /// history, delay and reverb buffers, LFO phase and envelopes.
+    /// Parameters are kept.
+    ///
+    /// # Example
+    /// ```javascript
+    /// sim.reset(); // before replaying a loop, so no reverb tail leaks in
+    /// ```
+    #[wasm_bindgen]
+    pub fn reset(&mut self) {
+        self.simulator.reset();
+    }
+
+    /// Turn the 20 Hz DC blocker on the output on or off (off by default).
+    ///
+    /// # Example
+    /// ```javascript
+    /// sim.set_dc_block(true); // no offset thump when the effect engages
+    /// ```
+    #[wasm_bindgen]
+    pub fn set_dc_block(&mut self, enabled: bool) {
+        self.simulator.set_dc_block(enabled);
+    }
+
+    /// Compute the static transfer curve: the output voltage for each DC
+    /// input voltage, e.g. to plot a clipper's knee.
+    ///
+    /// The audio state is left untouched.
+    #[wasm_bindgen]
+    pub fn dc_sweep(&mut self, input_voltages: &[f64]) -> Result<Vec<f64>, JsValue> {
+        self.simulator
+            .dc_sweep(input_voltages)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
     /// Get the current voltage at a named node.
     ///
     /// Useful for debugging or visualization.
@@ -169,6 +401,44 @@ impl WasmPedalSim {
     pub fn node_voltage(&self, node_name: &str) -> Option<f64> {
         self.simulator.node_voltage(node_name)
     }
+
+    /// Get the names of every node except ground, for `node_voltage`.
+    #[wasm_bindgen]
+    pub fn node_names(&self) -> Vec<String> {
+        self.simulator.circuit().signal_node_names().to_vec()
+    }
+
+    /// Get the names of every component, in circuit order.
+    #[wasm_bindgen]
+    pub fn component_names(&self) -> Vec<String> {
+        self.simulator
+            .circuit()
+            .component_names()
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    /// Get the current value (0.0 to 1.0) of a named LFO.
+    ///
+    /// Meant for modulation indicators, polled once per block.
+    ///
+    /// # Returns
+    /// The LFO value, or `undefined` if there is no LFO with that name.
+    #[wasm_bindgen]
+    pub fn lfo_value(&self, name: &str) -> Option<f64> {
+        self.simulator.lfo_value(name)
+    }
+
+    /// Get the current value (0.0 to 1.0) of a named envelope follower.
+    ///
+    /// # Returns
+    /// The envelope value, or `undefined` if there is no envelope follower
+    /// with that name.
+    #[wasm_bindgen]
+    pub fn envelope_value(&self, name: &str) -> Option<f64> {
+        self.simulator.envelope_value(name)
+    }
 }
 
 /// Get the library version.
@@ -182,3 +452,138 @@ pub fn version() -> String {
 pub fn default_sample_rate() -> f32 {
     crate::DEFAULT_SAMPLE_RATE
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Component;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    /// System allocator that counts allocations made on the current thread.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: CountingAllocator = CountingAllocator;
+
+    fn allocations() -> usize {
+        ALLOCATIONS.with(|count| count.get())
+    }
+
+    #[test]
+    fn test_process_block_does_not_allocate() {
+        // Nonlinear and LFO-modulated, so both Newton and modulation run
+        let dsl = ".input in\n.output out\nVIN in 0 AC 1.0\nLFO LFO1 2 sine\n\
+                   R1 in out 10k LFO1 depth=0.8\nC1 out 0 10n\nD1 out 0\nD2 0 out\n";
+        let mut sim = WasmPedalSim::new(dsl, 48000.0, 128).unwrap();
+
+        let input: Vec<f32> = (0..128)
+            .map(|i| (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 48000.0).sin())
+            .collect();
+        let mut output = vec![0.0f32; 128];
+
+        let before = allocations();
+        for len in [128, 64, 1, 127] {
+            sim.process_block(&input[..len], &mut output[..len]);
+        }
+        assert_eq!(allocations() - before, 0);
+        assert!(output.iter().any(|&v| v != 0.0));
+
+        // Interleaved stereo frames do not allocate either
+        let dsl = ".input inL\n.input inR\n.output outL\n.output outR\n\
+                   VL inL 0 AC 1.0\nVR inR 0 AC 1.0\n\
+                   R1 inL outL 10k\nD1 outL 0\nR2 inR outR 10k\nD2 0 outR\n";
+        let mut sim = WasmPedalSim::new(dsl, 48000.0, 128).unwrap();
+        assert_eq!((sim.input_channels(), sim.output_channels()), (2, 2));
+        output.fill(0.0);
+        let before = allocations();
+        sim.process_interleaved(&input, &mut output);
+        assert_eq!(allocations() - before, 0);
+        assert!(output.iter().any(|&v| v != 0.0));
+    }
+
+    #[test]
+    fn test_set_pot_and_switch_change_the_circuit() {
+        let dsl = ".input in\n.output out\nVIN in 0 AC 1.0\nR1 in a 10k\n\
+                   POT1 a out 0 100k 0.5\nSW1 out 0 OPEN\n";
+        let mut sim = WasmPedalSim::new(dsl, 48000.0, 128).unwrap();
+        let pot = |sim: &WasmPedalSim| match sim
+            .simulator
+            .circuit()
+            .components
+            .iter()
+            .find(|c| c.name() == "POT1")
+        {
+            Some(Component::Potentiometer(p)) => (p.g1(), p.g2()),
+            _ => panic!("POT1 missing"),
+        };
+
+        let before = pot(&sim);
+        sim.set_pot("POT1", 0.2).unwrap();
+        let after = pot(&sim);
+        assert!(after.0 > before.0 && after.1 < before.1);
+
+        // Out of range clamps to the end of the travel
+        sim.set_pot("POT1", 1.5).unwrap();
+        let Some(Component::Potentiometer(p)) = sim
+            .simulator
+            .circuit()
+            .components
+            .iter()
+            .find(|c| c.name() == "POT1")
+        else {
+            panic!("POT1 missing");
+        };
+        assert_eq!(p.position, 0.999);
+
+        sim.set_switch("SW1", true).unwrap();
+        assert!(matches!(
+            sim.simulator.circuit().components.iter().find(|c| c.name() == "SW1"),
+            Some(Component::Switch(s)) if s.closed
+        ));
+    }
+
+    #[test]
+    fn test_names_list_signal_nodes_and_components() {
+        let dsl = ".input in\n.output out\nVIN in 0 AC 1.0\nR1 in mid 10k\nC1 mid GND 10n\nR2 mid out 1k\n";
+        let sim = WasmPedalSim::new(dsl, 48000.0, 128).unwrap();
+        assert_eq!(sim.node_names(), ["in", "mid", "out"]);
+        assert!(!sim.node_names().iter().any(|n| n == "0"));
+        assert_eq!(sim.component_names(), ["VIN", "R1", "C1", "R2"]);
+    }
+
+    #[test]
+    fn test_oversized_block_is_chunked() {
+        let dsl = ".input in\n.output out\nVIN in 0 AC 1.0\nR1 in out 10k\nC1 out 0 10n\n";
+        let input: Vec<f32> = (0..300).map(|i| ((i % 50) as f32 / 25.0) - 1.0).collect();
+
+        let mut chunked = WasmPedalSim::new(dsl, 48000.0, 64).unwrap();
+        let mut whole = WasmPedalSim::new(dsl, 48000.0, 512).unwrap();
+        let mut out_chunked = vec![0.0f32; 300];
+        let mut out_whole = vec![0.0f32; 300];
+        chunked.process_block(&input, &mut out_chunked);
+        whole.process_block(&input, &mut out_whole);
+
+        assert_eq!(out_chunked, out_whole);
+    }
+}
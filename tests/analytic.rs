@@ -0,0 +1,248 @@
+//! Transient simulations checked against closed-form solutions.
+//!
+//! Reactive components use trapezoidal companion models, which are exact for
+//! the bilinear transform of the continuous circuit. Where that matters the
+//! expected values account for its frequency warping,
+//! `w_d = (2 / dt) * atan(w * dt / 2)`, so tolerances can stay tight.
+//! Backward Euler is checked against the same solutions, with tolerances
+//! for its first-order error.
+
+use std::f64::consts::PI;
+
+use pedaler_core::{dsl, Circuit, IntegrationMethod, Simulator, SimulatorConfig, THERMAL_VOLTAGE};
+
+fn simulator(src: &str, sample_rate: f32) -> Simulator {
+    simulator_with(src, sample_rate, IntegrationMethod::Trapezoidal)
+}
+
+fn simulator_with(src: &str, sample_rate: f32, method: IntegrationMethod) -> Simulator {
+    let circuit = Circuit::from_ast(dsl::parse(src).unwrap()).unwrap();
+    pedaler_core::circuit::validate_circuit(&circuit).unwrap();
+    let config = SimulatorConfig::new()
+        .with_tolerance(1e-9)
+        .with_max_iterations(100)
+        .with_integration_method(method);
+    Simulator::with_config(circuit, sample_rate, config).unwrap()
+}
+
+/// Run `input` through the circuit, returning the output as `f64`.
+fn render(src: &str, sample_rate: f32, input: impl IntoIterator<Item = f64>) -> Vec<f64> {
+    let mut sim = simulator(src, sample_rate);
+    input
+        .into_iter()
+        .map(|x| {
+            sim.set_input(x as f32);
+            sim.step().unwrap() as f64
+        })
+        .collect()
+}
+
+/// Amplitude and phase of the `freq` component of `signal`, fitted over a
+/// whole number of periods at its end.
+fn tone(signal: &[f64], freq: f64, fs: f64, periods: usize) -> (f64, f64) {
+    let n = (periods as f64 * fs / freq).round() as usize;
+    let start = signal.len() - n;
+    let (mut s, mut c) = (0.0, 0.0);
+    for (i, y) in signal[start..].iter().enumerate() {
+        let phase = 2.0 * PI * freq * (start + i) as f64 / fs;
+        s += y * phase.sin();
+        c += y * phase.cos();
+    }
+    let (s, c) = (2.0 * s / n as f64, 2.0 * c / n as f64);
+    (s.hypot(c), c.atan2(s))
+}
+
+/// Frequency of a ringing signal, from the interpolated times of its first
+/// and last upward zero crossings.
+fn ringing_frequency(signal: &[f64], fs: f64) -> f64 {
+    let crossings: Vec<f64> = signal
+        .windows(2)
+        .enumerate()
+        .filter(|(_, w)| w[0] < 0.0 && w[1] >= 0.0)
+        .map(|(i, w)| (i as f64 + w[0] / (w[0] - w[1])) / fs)
+        .collect();
+    assert!(crossings.len() > 10, "only {} crossings", crossings.len());
+    (crossings.len() - 1) as f64 / (crossings[crossings.len() - 1] - crossings[0])
+}
+
+/// Frequency the trapezoidal rule maps an analog frequency to.
+fn warped(freq: f64, fs: f64) -> f64 {
+    fs / PI * (PI * freq / fs).atan()
+}
+
+#[test]
+fn test_rc_step_response() {
+    // tau = 10 ms
+    let fs = 48000.0;
+    let tau = 10e3 * 1e-6;
+    let src = ".input in\n.output out\nV_IN in 0 AC\nR1 in out 10k\nC1 out 0 1u\n";
+    let out = render(src, fs as f32, std::iter::repeat_n(1.0, 4800));
+
+    // A step between samples reaches the trapezoidal rule as a ramp over the
+    // first interval, which delays the response by half a sample
+    for (n, &v) in out.iter().enumerate() {
+        let t = (n as f64 + 0.5) / fs;
+        let expected = 1.0 - (-t / tau).exp();
+        assert!(
+            (v - expected).abs() < 1e-4,
+            "t = {}: {} vs {}",
+            t,
+            v,
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_rc_lowpass_sine_response() {
+    // Corner 1.59 kHz, driven at 1 kHz
+    let fs = 48000.0;
+    let (r, c, f) = (1e3, 100e-9, 1000.0);
+    let src = ".input in\n.output out\nV_IN in 0 AC\nR1 in out 1k\nC1 out 0 100n\n";
+    let input = (0..9600).map(|n| (2.0 * PI * f * n as f64 / fs).sin());
+    let out = render(src, fs as f32, input);
+
+    let w = 2.0 * PI * f;
+    let wd = 2.0 * fs * (w / (2.0 * fs)).tan();
+    let gain = 1.0 / (1.0 + (wd * r * c).powi(2)).sqrt();
+    let phase = -(wd * r * c).atan();
+    let (amplitude, measured_phase) = tone(&out, f, fs, 20);
+    assert!(
+        (amplitude - gain).abs() < 1e-5,
+        "gain {} vs {}",
+        amplitude,
+        gain
+    );
+    assert!(
+        (measured_phase - phase).abs() < 1e-4,
+        "phase {} vs {}",
+        measured_phase,
+        phase
+    );
+}
+
+/// Charge an RC with time constant `tau` to 1 V, then let it discharge
+/// through the resistor, returning the discharge.
+fn rc_discharge(method: IntegrationMethod, tau: f64, fs: f64, samples: usize) -> Vec<f64> {
+    let src = format!(
+        ".input in\n.output out\nV_IN in 0 AC\nR1 in out 1k\nC1 out 0 {}\n",
+        tau / 1e3
+    );
+    let mut sim = simulator_with(&src, fs as f32, method);
+    sim.set_input(1.0);
+    for _ in 0..(100.0 * tau * fs).max(100.0) as usize {
+        sim.step().unwrap();
+    }
+    sim.set_input(0.0);
+    (0..samples).map(|_| sim.step().unwrap() as f64).collect()
+}
+
+#[test]
+fn test_rc_discharge_with_both_integration_methods() {
+    // tau = 1 ms, 48 samples
+    let (fs, tau) = (48000.0, 1e-3);
+    let trap = rc_discharge(IntegrationMethod::Trapezoidal, tau, fs, 480);
+    let euler = rc_discharge(IntegrationMethod::BackwardEuler, tau, fs, 480);
+
+    // The trapezoidal rule sees the falling edge half a sample late, as in
+    // the step response; backward Euler sees it at once
+    let (mut trap_err, mut euler_err) = (0.0f64, 0.0f64);
+    for (n, (&vt, &ve)) in trap.iter().zip(&euler).enumerate() {
+        let t = n as f64 / fs;
+        trap_err = trap_err.max((vt - (-(t + 0.5 / fs) / tau).exp()).abs());
+        euler_err = euler_err.max((ve - (-(t + 1.0 / fs) / tau).exp()).abs());
+    }
+    assert!(trap_err < 1e-4, "trapezoidal error {}", trap_err);
+    assert!(euler_err < 1e-2, "backward Euler error {}", euler_err);
+    assert!(trap_err < euler_err / 10.0);
+
+    // With a time constant of a tenth of a sample the trapezoidal rule
+    // alternates in sign as it decays, while backward Euler decays
+    // monotonically as the real circuit does
+    let tau = 0.1 / fs;
+    let trap = rc_discharge(IntegrationMethod::Trapezoidal, tau, fs, 8);
+    let euler = rc_discharge(IntegrationMethod::BackwardEuler, tau, fs, 8);
+    assert!(trap.windows(2).all(|w| w[0] * w[1] < 0.0), "{:?}", trap);
+    assert!(euler.iter().all(|&v| v >= 0.0));
+    assert!(euler.windows(2).all(|w| w[1] <= w[0]));
+}
+
+#[test]
+fn test_rl_step_response() {
+    // tau = L / R = 10 ms; the output is across the resistor
+    let fs = 48000.0;
+    let tau = 100e-3 / 10.0;
+    let src = ".input in\n.output out\nV_IN in 0 AC\nL1 in out 100m\nR1 out 0 10\n";
+    let out = render(src, fs as f32, std::iter::repeat_n(1.0, 4800));
+
+    for (n, &v) in out.iter().enumerate() {
+        let t = (n as f64 + 0.5) / fs;
+        let expected = 1.0 - (-t / tau).exp();
+        assert!(
+            (v - expected).abs() < 1e-4,
+            "t = {}: {} vs {}",
+            t,
+            v,
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_lc_ringing_frequency() {
+    // f0 = 1 / (2 pi sqrt(LC)) = 1591.5 Hz, damping ratio 0.005
+    let fs = 48000.0;
+    let (l, c): (f64, f64) = (10e-3, 1e-6);
+    let src = ".input in\n.output out\nV_IN in 0 AC\nR1 in a 1\nL1 a out 10m\nC1 out 0 1u\n";
+    let out = render(src, fs as f32, std::iter::repeat_n(1.0, 4800));
+
+    // The step rings around its final value of 1 V
+    let ringing: Vec<f64> = out.iter().map(|v| v - 1.0).collect();
+    let f0 = 1.0 / (2.0 * PI * (l * c).sqrt());
+    let expected = warped(f0, fs);
+    let measured = ringing_frequency(&ringing, fs);
+    assert!(
+        (measured / expected - 1.0).abs() < 1e-4,
+        "{} Hz vs {} Hz",
+        measured,
+        expected
+    );
+}
+
+#[test]
+fn test_ideal_opamp_inverting_gain() {
+    let src =
+        ".input in\n.output out\nV_IN in 0 AC\nR1 in inv 10k\nR2 inv out 47k\nOP1 out 0 inv\n";
+    let out = render(src, 48000.0, [0.1, -0.05, 0.01, 0.2]);
+    for (v, x) in out.iter().zip([0.1, -0.05, 0.01, 0.2]) {
+        let expected = -4.7 * x;
+        assert!((v - expected).abs() < 1e-6, "{} vs {}", v, expected);
+    }
+}
+
+#[test]
+fn test_diode_clipping_threshold() {
+    // Resistor into a diode to ground: solve (vin - v) / R = Is (e^(v / nVt) - 1)
+    let (r, is, n) = (1e3, 1e-14, 1.0);
+    let src = ".input in\n.output out\nV_IN in 0 AC\nR1 in out 1k\nD1 out 0\n";
+    let inputs: Vec<f64> = (-10..=50).map(|i| i as f64 * 0.1).collect();
+    let out = simulator(src, 48000.0).dc_sweep(&inputs).unwrap();
+
+    let diode = |v: f64| is * ((v / (n * THERMAL_VOLTAGE)).exp() - 1.0);
+    for (&vin, &v) in inputs.iter().zip(&out) {
+        // The left side falls and the right side rises with v, so bisect
+        let (mut lo, mut hi) = (vin.min(0.0), vin.max(0.0));
+        for _ in 0..100 {
+            let mid = 0.5 * (lo + hi);
+            if (vin - mid) / r > diode(mid) {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        assert!((v - lo).abs() < 1e-6, "vin = {}: {} vs {}", vin, v, lo);
+    }
+
+    // Past the knee the output barely moves: 60 mV per decade of current
+    assert!(out[out.len() - 1] > 0.6 && out[out.len() - 1] < 0.7);
+}